@@ -0,0 +1,209 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Throughput of parsing, filtering and formatting 100k records of the
+//! bundled sample, and of parsing them as csv on multiple tasks. Run with
+//! `cargo bench`.
+//!
+//! The human readable format is implemented by the binary and measured by
+//! running it on the sample. The measurement includes the startup of the
+//! process.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use futures::stream::iter;
+use rogcat::{
+    filter::FilterSpec,
+    parser::Parser,
+    pipeline::{Pipeline, Source},
+    record::{Format, Level},
+    sink::FormatSink,
+    StreamData,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tokio::runtime::Runtime;
+
+/// Number of lines read per iteration
+const LINES: usize = 100_000;
+
+/// The bundled sample repeated to `LINES` lines
+fn lines() -> Vec<String> {
+    include_str!("sample.log")
+        .lines()
+        .cycle()
+        .take(LINES)
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Source streaming `lines`. Created outside of the measurement.
+fn source(lines: &[String]) -> Source {
+    Source::Stream(Box::new(iter(
+        lines
+            .iter()
+            .map(|line| StreamData::Line(line.clone()))
+            .collect::<Vec<_>>(),
+    )))
+}
+
+/// `lines` as csv rows
+fn csv_lines(lines: &[String]) -> Vec<String> {
+    let mut parser = Parser::default();
+    lines
+        .iter()
+        .filter_map(|line| parser.parse(line))
+        .map(|record| {
+            Format::Csv
+                .fmt_record(&record)
+                .expect("Failed to format csv")
+        })
+        .collect()
+}
+
+/// Run the pipeline over `source` parsing on `workers` tasks and write the
+/// records in `format`
+fn run_parallel(
+    runtime: &Runtime,
+    source: Source,
+    filter: &FilterSpec,
+    format: Format,
+    workers: usize,
+) {
+    runtime
+        .block_on(
+            Pipeline::new()
+                .source(source)
+                .filter(filter.clone())
+                .parallel(workers)
+                .run(FormatSink::new(format, io::sink())),
+        )
+        .expect("Failed to run pipeline");
+}
+
+/// Run the pipeline over `source` and write the records in `format`
+fn run(runtime: &Runtime, source: Source, filter: &FilterSpec, format: Format) {
+    run_parallel(runtime, source, filter, format, 1)
+}
+
+/// Write `lines` to a file in the temporary directory of the benchmarks
+fn input(lines: &[String]) -> PathBuf {
+    let file = Path::new(env!("CARGO_TARGET_TMPDIR")).join("sample.log");
+    fs::write(&file, lines.join("\n")).expect("Failed to write input");
+    file
+}
+
+/// Run the rogcat binary over `input` and write the records in the human
+/// readable format
+fn run_human(input: &Path) {
+    let status = Command::new(env!("CARGO_BIN_EXE_rogcat"))
+        .args(["--color", "never", "--input"])
+        .arg(input)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("Failed to run rogcat");
+    assert!(status.success(), "rogcat failed with {status}");
+}
+
+fn parse(c: &mut Criterion) {
+    let lines = lines();
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(LINES as u64));
+    group.bench_function("default", |b| {
+        b.iter(|| {
+            let mut parser = Parser::default();
+            lines.iter().filter_map(|l| parser.parse(l)).count()
+        })
+    });
+    group.finish();
+}
+
+fn pipeline(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create runtime");
+    let lines = lines();
+    let no_filter = FilterSpec::default();
+    let filter = FilterSpec {
+        level: Level::Info,
+        tag: vec!["Manager$".to_owned()],
+        message: vec!["!frames".to_owned()],
+        ..Default::default()
+    };
+
+    let mut group = c.benchmark_group("pipeline");
+    group.throughput(Throughput::Elements(LINES as u64));
+    group.sample_size(20);
+    group.bench_function("raw", |b| {
+        b.iter_batched(
+            || source(&lines),
+            |source| run(&runtime, source, &no_filter, Format::Raw),
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("csv", |b| {
+        b.iter_batched(
+            || source(&lines),
+            |source| run(&runtime, source, &no_filter, Format::Csv),
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("json", |b| {
+        b.iter_batched(
+            || source(&lines),
+            |source| run(&runtime, source, &no_filter, Format::Json),
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("filtered", |b| {
+        b.iter_batched(
+            || source(&lines),
+            |source| run(&runtime, source, &filter, Format::Raw),
+            BatchSize::PerIteration,
+        )
+    });
+    let input = input(&lines);
+    group.bench_function("human", |b| b.iter(|| run_human(&input)));
+    group.finish();
+}
+
+fn parallel(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create runtime");
+    let lines = csv_lines(&lines());
+    let filter = FilterSpec::default();
+
+    let mut group = c.benchmark_group("parallel");
+    group.throughput(Throughput::Elements(LINES as u64));
+    group.sample_size(20);
+    for workers in [1, 2, 4, 8] {
+        group.bench_function(format!("csv input {workers}"), |b| {
+            b.iter_batched(
+                || source(&lines),
+                |source| run_parallel(&runtime, source, &filter, Format::Raw, workers),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, pipeline, parallel);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::Error;
+use std::{env, path::PathBuf};
+use tokio::process::Command;
+use which::which_in;
+
+/// Find adb binary
+pub fn adb() -> Result<PathBuf, Error> {
+    let cwd = env::current_dir().map_err(|e| Error::io("Failed to get current directory", e))?;
+    which_in("adb", env::var_os("PATH"), cwd).map_err(Error::Adb)
+}
+
+/// Command running adb on `device` or the only connected device
+pub fn adb_command(device: Option<&str>) -> Result<Command, Error> {
+    Ok(device_command(adb()?, device))
+}
+
+fn device_command(adb: PathBuf, device: Option<&str>) -> Command {
+    let mut command = Command::new(adb);
+    if let Some(device) = device {
+        command.arg("-s").arg(device);
+    }
+    command
+}
+
+#[test]
+fn adb_device_command() {
+    let args = |device| {
+        device_command(PathBuf::from("adb"), device)
+            .arg("devices")
+            .as_std()
+            .get_args()
+            .map(|a| a.to_str().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(args(None), ["devices"]);
+    assert_eq!(
+        args(Some("emulator-5554")),
+        ["-s", "emulator-5554", "devices"]
+    );
+}
@@ -0,0 +1,192 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Bounded buffer between the reading and parsing of records and a
+//! potentially slow consumer like a terminal.
+
+use crate::{pipeline::RecordStream, record::Record};
+use clap::ValueEnum;
+use futures::{
+    future::poll_fn,
+    task::{Context, Poll, Waker},
+    Stream, StreamExt,
+};
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+/// Default number of buffered records
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Behaviour if the buffer is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum DropPolicy {
+    /// Stop reading until the consumer catches up
+    #[default]
+    Block,
+    /// Discard the oldest buffered record
+    DropOldest,
+}
+
+#[derive(Default)]
+struct Queue {
+    records: VecDeque<Record>,
+    /// Number of records discarded by the drop oldest policy
+    dropped: u64,
+    /// The producer finished
+    done: bool,
+    /// The consumer is gone
+    closed: bool,
+    producer: Option<Waker>,
+    consumer: Option<Waker>,
+}
+
+/// Handle to the number of records dropped by a buffer
+#[derive(Clone)]
+pub struct Dropped(Arc<Mutex<Queue>>);
+
+impl Dropped {
+    pub fn count(&self) -> u64 {
+        self.0.lock().expect("Poisoned lock").dropped
+    }
+}
+
+/// Consuming end of a buffer
+struct Buffered(Arc<Mutex<Queue>>);
+
+impl Stream for Buffered {
+    type Item = Record;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Record>> {
+        let mut queue = self.0.lock().expect("Poisoned lock");
+        if let Some(record) = queue.records.pop_front() {
+            if let Some(producer) = queue.producer.take() {
+                producer.wake();
+            }
+            Poll::Ready(Some(record))
+        } else if queue.done {
+            Poll::Ready(None)
+        } else {
+            queue.consumer = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Buffered {
+    fn drop(&mut self) {
+        let mut queue = self.0.lock().expect("Poisoned lock");
+        queue.closed = true;
+        if let Some(producer) = queue.producer.take() {
+            producer.wake();
+        }
+    }
+}
+
+/// Poll `stream` in a separate task and buffer up to `capacity` records for
+/// the returned stream. If the buffer is full the task either pauses or
+/// discards the oldest record according to `policy`.
+pub fn bounded(
+    mut stream: RecordStream,
+    capacity: usize,
+    policy: DropPolicy,
+) -> (RecordStream, Dropped) {
+    let capacity = capacity.max(1);
+    let queue = Arc::new(Mutex::new(Queue::default()));
+
+    let producer = queue.clone();
+    tokio::spawn(async move {
+        while let Some(record) = stream.next().await {
+            // Wait for space or drop the oldest record
+            let closed = poll_fn(|cx| {
+                let mut queue = producer.lock().expect("Poisoned lock");
+                if queue.closed {
+                    return Poll::Ready(true);
+                }
+                if queue.records.len() >= capacity {
+                    if policy == DropPolicy::Block {
+                        queue.producer = Some(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                    queue.records.pop_front();
+                    queue.dropped += 1;
+                }
+                Poll::Ready(false)
+            })
+            .await;
+            if closed {
+                return;
+            }
+
+            let mut queue = producer.lock().expect("Poisoned lock");
+            queue.records.push_back(record);
+            if let Some(consumer) = queue.consumer.take() {
+                consumer.wake();
+            }
+        }
+
+        let mut queue = producer.lock().expect("Poisoned lock");
+        queue.done = true;
+        if let Some(consumer) = queue.consumer.take() {
+            consumer.wake();
+        }
+    });
+
+    (Box::pin(Buffered(queue.clone())), Dropped(queue))
+}
+
+#[cfg(test)]
+fn records(n: usize) -> RecordStream {
+    Box::pin(futures::stream::iter((0..n).map(|i| Record {
+        message: i.to_string(),
+        ..Default::default()
+    })))
+}
+
+#[tokio::test]
+async fn bounded_block() {
+    let (mut stream, dropped) = bounded(records(100_000), 100, DropPolicy::Block);
+    let mut expected = 0;
+    while let Some(record) = stream.next().await {
+        assert_eq!(record.message, expected.to_string());
+        expected += 1;
+    }
+    assert_eq!(expected, 100_000);
+    assert_eq!(dropped.count(), 0);
+}
+
+#[tokio::test]
+async fn bounded_drop_oldest() {
+    use std::time::Duration;
+
+    let (stream, dropped) = bounded(records(100_000), 1_000, DropPolicy::DropOldest);
+    // Don't consume until the producer is done. The buffer holds at most
+    // its capacity and all other records are counted as dropped.
+    while dropped.count() < 99_000 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let records = stream.collect::<Vec<_>>().await;
+    assert_eq!(dropped.count(), 99_000);
+    assert_eq!(records.len(), 1_000);
+    assert_eq!(records[0].message, "99000");
+    assert_eq!(records[999].message, "99999");
+}
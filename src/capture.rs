@@ -0,0 +1,202 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Capture windows around trigger records. Records are held in a ring until
+//! a trigger fires. The ring, the trigger and the following records are
+//! passed on and the ring fills again after the window.
+
+use crate::diagnostics::Reporter;
+use anyhow::{anyhow, Result};
+use futures::{
+    sink::Sink,
+    task::{Context, Poll},
+};
+use regex::Regex;
+use rogcat::{record::Record, sink::Queued, Error, LogSink};
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+/// Number of capture windows opened so far and the time of the trigger of
+/// the current window. Output files are split at the start of a window and
+/// named after the trigger.
+#[derive(Clone, Default)]
+pub struct Windows(Arc<Mutex<(usize, Option<String>)>>);
+
+impl Windows {
+    pub fn count(&self) -> usize {
+        self.0.lock().expect("Poisoned lock").0
+    }
+
+    /// Time of the record that triggered the current window
+    pub fn time(&self) -> Option<String> {
+        self.0.lock().expect("Poisoned lock").1.clone()
+    }
+
+    /// Start a new window triggered by a record at `time`
+    pub fn open(&self, time: Option<&str>) {
+        let mut windows = self.0.lock().expect("Poisoned lock");
+        windows.0 += 1;
+        windows.1 = time.map(ToOwned::to_owned);
+    }
+}
+
+struct CaptureSink {
+    sink: Queued<Pin<LogSink>>,
+    trigger: Regex,
+    /// Most recent records outside of a window
+    ring: VecDeque<Record>,
+    before: usize,
+    after: usize,
+    /// Records left in the current window
+    remaining: usize,
+    windows: Windows,
+    reporter: Reporter,
+}
+
+/// Wrap `sink` and pass on only the `before` records preceding a record with
+/// a tag or message matching `trigger`, the record and the `after` records
+/// following it. A trigger within a window extends the window.
+pub fn wrap(
+    sink: Pin<LogSink>,
+    trigger: &str,
+    before: usize,
+    after: usize,
+    windows: Windows,
+    reporter: Reporter,
+) -> Result<Pin<LogSink>> {
+    let trigger =
+        Regex::new(trigger).map_err(|e| anyhow!("Invalid trigger regex {trigger}: {e}"))?;
+    Ok(Box::pin(CaptureSink {
+        sink: Queued::new(sink),
+        trigger,
+        ring: VecDeque::with_capacity(before),
+        before,
+        after,
+        remaining: 0,
+        windows,
+        reporter,
+    }))
+}
+
+impl CaptureSink {
+    fn is_trigger(&self, record: &Record) -> bool {
+        self.trigger.is_match(&record.tag) || self.trigger.is_match(&record.message)
+    }
+}
+
+impl Sink<Record> for CaptureSink {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sink.poll_drain(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Record) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        if this.is_trigger(&item) {
+            if this.remaining == 0 {
+                this.windows.open(item.time.as_deref());
+                let message = format!(
+                    "Trigger fired at {}: {}: {}",
+                    item.time.as_deref().unwrap_or("-"),
+                    item.tag,
+                    item.message
+                );
+                this.reporter
+                    .report(&message)
+                    .map_err(|e| Error::io("Failed to write diagnostic", e))?;
+                this.sink.extend(this.ring.drain(..));
+            }
+            this.remaining = this.after;
+            this.sink.push(item);
+        } else if this.remaining > 0 {
+            this.remaining -= 1;
+            this.sink.push(item);
+        } else if this.before > 0 {
+            if this.ring.len() == this.before {
+                this.ring.pop_front();
+            }
+            this.ring.push_back(item);
+        }
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sink.poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sink.poll_close(cx)
+    }
+}
+
+#[tokio::test]
+async fn capture_windows() {
+    use crate::cli::CliArguments;
+    use clap::Parser;
+    use futures::{channel::mpsc, stream, SinkExt, StreamExt};
+
+    let (tx, rx) = mpsc::unbounded();
+    let args = CliArguments::parse_from(["rogcat", "--color", "never"]);
+    let windows = Windows::default();
+    let sink = wrap(
+        Box::pin(tx.sink_map_err(|e| Error::Invalid(e.to_string()))),
+        "^boom$",
+        2,
+        3,
+        windows.clone(),
+        Reporter::new(&args),
+    )
+    .unwrap();
+
+    // The trigger at 8 is within the window of 5 and extends it. The window of
+    // 16 starts with the ring filled after the first window.
+    let records = (0..25).map(|i| Record {
+        message: if [5, 8, 16].contains(&i) {
+            "boom".to_owned()
+        } else {
+            i.to_string()
+        },
+        ..Default::default()
+    });
+    stream::iter(records).map(Ok).forward(sink).await.unwrap();
+    let messages = rx.map(|r| r.message).collect::<Vec<_>>().await;
+    assert_eq!(
+        messages,
+        [
+            "3", "4", "boom", "6", "7", "boom", "9", "10", "11", "14", "15", "boom", "17", "18",
+            "19"
+        ]
+    );
+    assert_eq!(windows.count(), 2);
+}
+
+#[test]
+fn window_trigger_time() {
+    let windows = Windows::default();
+    assert_eq!(windows.time(), None);
+    windows.open(Some("01-10 23:02:59.028"));
+    assert_eq!(windows.time().as_deref(), Some("01-10 23:02:59.028"));
+    windows.open(None);
+    assert_eq!((windows.count(), windows.time()), (2, None));
+}
@@ -1,16 +1,32 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
-use crate::utils;
+use crate::{
+    diff::Key,
+    utils::{config_get, same_file},
+};
+use anyhow::{anyhow, Result};
 use clap::{crate_authors, Args, Parser, Subcommand, ValueHint};
 use clap_complete::Shell;
 use lazy_static::lazy_static;
-use rogcat::record::{Format, Level};
+use rogcat::{
+    buffer::{DropPolicy, DEFAULT_CAPACITY},
+    config_dir,
+    filter::FilterSpec,
+    parser::InputFormat,
+    pipeline::Source,
+    profiles::Profile,
+    reader::{self, LogcatOptions},
+    record::{Column, Format, Level, LevelStyle},
+    rewrite::Rewrite,
+    DEFAULT_BUFFER,
+};
+use std::str::FromStr;
 
 lazy_static! {
     static ref ABOUT: String = {
         format!(
             "A 'adb logcat' wrapper and log processor. Your config directory is \"{}\".",
-            utils::config_dir().display()
+            config_dir().display()
         )
     };
 }
@@ -22,18 +38,116 @@ pub(crate) struct CliArguments {
     #[clap(long, long, conflicts_with_all = &["input", "COMMAND"])]
     pub(crate) buffer: Option<Vec<String>>,
 
+    /// Filter by log buffer like crash or events. The prefix '!' inverts the match. Implies --split-buffers and cannot be used with buffers selected by --logcat-args.
+    #[clap(long, conflicts_with_all = &["input", "COMMAND"])]
+    pub(crate) buffer_filter: Vec<String>,
+
+    /// Number of records buffered between reading and output.
+    #[clap(long, default_value_t = DEFAULT_CAPACITY)]
+    pub(crate) buffer_size: usize,
+
     // Terminal coloring option
-    #[clap(long, conflicts_with_all = &["highlight", "output"], value_parser = ["always", "auto", "never"])]
+    #[clap(long, conflicts_with = "highlight", value_parser = ["always", "auto", "never"])]
     pub(crate) color: Option<String>,
 
+    /// Compress output files with gzip. Implied by an output filename ending in '.gz'.
+    #[clap(long, requires = "output")]
+    pub(crate) compress: bool,
+
+    /// Accept commands like "tag +MyTag" or "level warn" that change the filters while running. Takes a tcp port on localhost or on unix the path of a socket. Port 0 picks a free port that is printed on stderr. See "rogcat ctl".
+    #[clap(long, value_name = "ADDRESS")]
+    pub(crate) control_socket: Option<String>,
+
+    /// Print the number of matching records instead of the records. Exits with 1 if no record matched.
+    #[clap(long, conflicts_with_all = &["output", "output-command"])]
+    pub(crate) count: bool,
+
+    /// Print a table of the matching records per column like tag or level, or per extracted field. Implies --count.
+    #[clap(long, value_name = "KEY", conflicts_with_all = &["output", "output-command"])]
+    pub(crate) count_by: Option<String>,
+
+    /// Write buffered records to the output file at most every <MS> milliseconds while the input is idle. Defaults to 1000.
+    #[clap(long, value_name = "MS", requires = "output")]
+    pub(crate) flush_interval: Option<u64>,
+
     /// Dump the log and then exit (don't block)
     #[clap(long, short, conflicts_with_all = &["input", "COMMAND", "restart"])]
     pub(crate) dump: bool,
 
+    /// Collapse consecutive records with identical tag, level and message. Repeat counts are emitted after at most N seconds.
+    #[clap(
+        long,
+        value_name = "N",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "1"
+    )]
+    pub(crate) dedupe: Option<u64>,
+
+    /// Behaviour if the output cannot keep up and the buffer is full. 'block' pauses reading, 'drop-oldest' discards the oldest buffered records.
+    #[clap(long, value_enum, default_value_t)]
+    pub(crate) drop_policy: DropPolicy,
+
+    /// Drop records without a parseable timestamp when filtering with --since or --until.
+    #[clap(long)]
+    pub(crate) drop_untimed: bool,
+
+    /// Name the values of known events of the events buffer, e.g. am_proc_start, and rewrite their messages to key=value pairs. The event definitions of the device are used when reading from adb.
+    #[clap(long)]
+    pub(crate) decode_events: bool,
+
+    /// Print which filter dropped a record to stderr. Up to 10 records per second are explained.
+    #[clap(long)]
+    pub(crate) explain: bool,
+
+    /// Explain all dropped records matching this regex instead of rate limiting.
+    #[clap(long, value_name = "REGEX", requires = "explain")]
+    pub(crate) explain_filter: Option<String>,
+
+    /// Extract key=value pairs from messages into the record fields.
+    #[clap(long)]
+    pub(crate) extract_fields: bool,
+
+    /// Filter on extracted fields in the form key=regex. The regex prefix '!' inverts the match.
+    #[clap(long)]
+    pub(crate) field: Vec<String>,
+
+    /// Exit with 1 if records at or above this level passed the filters and print their number when the input ends, e.g. to gate CI runs on replayed logs. See --max-count.
+    #[clap(long, value_name = "LEVEL", value_parser = Level::from_str)]
+    pub(crate) fail_on_level: Option<Level>,
+
+    /// Append input lines that cannot be parsed, e.g. continuation lines of kernel oopses, to the message of the previous record. Up to 100 lines are folded into a record.
+    #[clap(long)]
+    pub(crate) fold_continuations: bool,
+
     /// Output format. Defaults to human on stdout and raw on file output
     #[clap(long, short, value_enum)]
     pub(crate) format: Option<Format>,
 
+    /// Write records with a line layout like "{time} {level} {tag:<20.20} | {message}". Placeholders are time, date, level, tag, process, thread, message and raw. Write braces as {{ and }}.
+    #[clap(long, value_name = "TEMPLATE", conflicts_with_all = &["format", "columns"])]
+    pub(crate) format_template: Option<String>,
+
+    /// Tint the lines of --format-template by level on terminals.
+    #[clap(long, requires = "format-template", conflicts_with = "output")]
+    pub(crate) template_color: bool,
+
+    /// Select and order the columns of csv and json output, e.g. time,level,tag,message
+    #[clap(long = "fields", value_enum, value_delimiter = ',')]
+    pub(crate) columns: Vec<Column>,
+
+    /// Write the levels of csv and json output as letters like "I" or words like "Info".
+    #[clap(long, value_enum, default_value_t)]
+    pub(crate) level_style: LevelStyle,
+
+    /// Write a self-contained html page with search, level and tag filters. Requires the html format.
+    #[clap(long, requires = "output")]
+    pub(crate) html_interactive: bool,
+
+    /// Write a JSON array per output file instead of one object per line. Requires the json or json-pretty format.
+    #[clap(long, requires = "output")]
+    pub(crate) json_array: bool,
+
     /// Select a format for output file names.
     /// By passing 'single' the filename provided with the '-o' option is used (default).
     /// 'enumerate' appends a file sequence number after the filename passed
@@ -42,6 +156,14 @@ pub(crate) struct CliArguments {
     #[clap(long, short = 'a', requires = "output", value_parser = ["single", "enumerate", "date"])]
     pub(crate) filename_format: Option<String>,
 
+    /// Group the lines of Java stack traces under the record of the exception in the terminal output.
+    #[clap(long, conflicts_with = "output")]
+    pub(crate) group_traces: bool,
+
+    /// Print a dimmed header before records of another process than the previous record in the terminal output. The header names the process if its start is read from the events buffer.
+    #[clap(long, value_name = "KEY", conflicts_with = "output", value_parser = ["process"])]
+    pub(crate) group_by: Option<String>,
+
     /// Read n records and exit.
     #[clap(short = 'H', long, conflicts_with_all = &["tail", "restart"])]
     pub(crate) head: Option<usize>,
@@ -51,18 +173,64 @@ pub(crate) struct CliArguments {
     #[clap(short, long, conflicts_with = "output")]
     pub(crate) highlight: Vec<String>,
 
-    /// Read from file instead of a adb command.
-    /// Use 'serial://COM0@115200,8N1 or similiar for reading a serial port.
+    /// Style of highlighted matches within tags and messages.
+    #[clap(long, conflicts_with = "output", value_parser = ["bold", "inverse", "underline"])]
+    pub(crate) highlight_style: Option<String>,
+
+    /// Color of messages: the color of the level, the default color of the terminal or the dimm color.
+    #[clap(long, conflicts_with = "output", value_parser = ["level", "default", "dimm"])]
+    pub(crate) message_color: Option<String>,
+
+    /// Read from file instead of a adb command. Serial ports are read from 'serial://COM0@115200,8N1' or similar. The baud rate defaults to 115200 and the framing to 8N1.
     #[clap(short, long, value_hint = ValueHint::FilePath)]
     pub(crate) input: Vec<PathBuf>,
 
+    /// Format of the input lines. 'auto' detects the format of each line, the others skip the detection.
+    #[clap(long, value_enum, default_value_t)]
+    pub(crate) input_format: InputFormat,
+
+    /// Read only the last N lines of each input file. The files are searched from the end instead of read as a whole. Compressed files are not supported.
+    #[clap(long, value_name = "N", requires = "input")]
+    pub(crate) last_records: Option<usize>,
+
     /// Dump the logs prior to the last reboot.
     #[clap(short = 'L', long, conflicts_with_all = &["input", "COMMAND"])]
     pub(crate) last: bool,
 
-    /// Minimum level
-    #[clap(short, long, value_parser = Level::values())]
-    pub(crate) level: Option<String>,
+    /// Keep only the most recent n records in output files and delete older files.
+    /// Files are split according to 'records-per-file' which is required.
+    /// Use k, M, G suffixes or a plain number.
+    #[clap(long, requires_all = &["output", "records-per-file"])]
+    pub(crate) keep_last: Option<String>,
+
+    /// Minimum level. One of trace, verbose, debug, info, warn, error, fatal, assert or the first letter in upper case. Trace is lower than verbose.
+    #[clap(short, long, value_parser = Level::from_str)]
+    pub(crate) level: Option<Level>,
+
+    /// Write every record to the output file immediately, e.g. for following the file with tail -f.
+    #[clap(long, requires = "output", conflicts_with = "flush-interval")]
+    pub(crate) line_buffered: bool,
+
+    /// Additional arguments of adb logcat like "-v uid --pid 1234". Flags given here replace the ones set by rogcat.
+    #[clap(long, conflicts_with_all = &["input", "COMMAND"])]
+    pub(crate) logcat_args: Option<String>,
+
+    /// Number of records at or above --fail-on-level that are tolerated.
+    #[clap(
+        long,
+        value_name = "N",
+        requires = "fail-on-level",
+        default_value_t = 0
+    )]
+    pub(crate) max_count: usize,
+
+    /// Truncate messages longer than n chars in terminal output instead of wrapping them.
+    #[clap(long, conflicts_with = "output")]
+    pub(crate) max_message_width: Option<usize>,
+
+    /// Merge multiple input files in the order of the record timestamps instead of reading them one after another.
+    #[clap(long, requires = "input")]
+    pub(crate) merge_by_time: bool,
 
     /// Message filters in RE2. The prefix '!' inverts the match.
     #[clap(short, long)]
@@ -72,10 +240,18 @@ pub(crate) struct CliArguments {
     #[clap(short = 'M', long = "Message")]
     pub(crate) message_ignore_case: Vec<String>,
 
+    /// Don't wait for the device before starting adb logcat.
+    #[clap(long, conflicts_with_all = &["input", "COMMAND"])]
+    pub(crate) no_wait_for_device: bool,
+
     /// Use white as dimm color.
     #[clap(long, conflicts_with = "output")]
     pub(crate) no_dimm: bool,
 
+    /// Pick the colors of tags and pids for a dark or light terminal background.
+    #[clap(long, conflicts_with = "output", value_parser = ["dark", "light"])]
+    pub(crate) theme: Option<String>,
+
     /// Use intense colors in terminal output.
     #[clap(long, conflicts_with = "output")]
     pub(crate) bright_colors: bool,
@@ -84,14 +260,42 @@ pub(crate) struct CliArguments {
     #[clap(long, conflicts_with = "output")]
     pub(crate) hide_timestamp: bool,
 
-    /// Write output to file.
-    #[clap(long, short, conflicts_with = "color", value_hint = ValueHint::FilePath)]
+    /// Print a separator before records following the previous record after more than THRESHOLD, e.g. 500ms, 2s or 1m. Takes the units of durations of --since.
+    #[clap(long, value_name = "THRESHOLD", conflicts_with = "output")]
+    pub(crate) show_gaps: Option<String>,
+
+    /// Show the time since the previous record in terminal output.
+    #[clap(long, conflicts_with = "output")]
+    pub(crate) show_delta: bool,
+
+    /// Show the time since the start of the process of each record in terminal output. Processes are known from their start in the events buffer.
+    #[clap(long, conflicts_with = "output")]
+    pub(crate) show_uptime: bool,
+
+    /// Command to run for records matching --watch. The record is passed in the environment variables ROGCAT_TAG, ROGCAT_MESSAGE, ROGCAT_PID, ROGCAT_TID, ROGCAT_LEVEL and ROGCAT_TIME. Invocations are limited to one per second and pattern.
+    #[clap(long, value_name = "COMMAND", requires = "watch", value_hint = ValueHint::CommandString)]
+    pub(crate) on_match: Option<String>,
+
+    /// Run the --on-match command at most once per --watch pattern.
+    #[clap(long, requires = "watch")]
+    pub(crate) once: bool,
+
+    /// Write output to file. Records are forwarded to a socket if the output is tcp://HOST:PORT or unix:///PATH.
+    #[clap(long, short, value_hint = ValueHint::FilePath)]
     pub(crate) output: Option<PathBuf>,
 
+    /// Pipe the records into the stdin of a command, e.g. "grep --line-buffered Tag". The format defaults to raw. rogcat exits when the command exits.
+    #[clap(long, conflicts_with = "output", value_hint = ValueHint::CommandString)]
+    pub(crate) output_command: Option<String>,
+
     /// Overwrite output file if present.
     #[clap(long, requires = "output")]
     pub(crate) overwrite: bool,
 
+    /// Parse the lines of input files or stdin on N tasks. The order of the records is kept. Records spanning multiple lines like of "logcat -v long" may be split.
+    #[clap(long, value_name = "N", conflicts_with = "fold-continuations")]
+    pub(crate) parallel: Option<usize>,
+
     /// Filter by process ID.
     #[clap(long)]
     pub(crate) pid: Vec<String>,
@@ -100,33 +304,97 @@ pub(crate) struct CliArguments {
     #[clap(long, short = 'N')]
     pub(crate) process_name: Option<Vec<String>>,
 
+    /// Show the bytes read of the input files with throughput and ETA on stderr and the number of parsed records at the end. Shown by default if stderr is a terminal and the records aren't printed on it.
+    #[clap(long, requires = "input", conflicts_with_all = &["quiet", "last-records"])]
+    pub(crate) progress: bool,
+
+    /// Run COMMAND again every n seconds and pass only the lines that weren't printed by the former runs, e.g. for "adb shell su -c dmesg". All lines are passed again if the output changed completely.
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        requires = "COMMAND",
+        conflicts_with = "restart"
+    )]
+    pub(crate) poll: Option<f64>,
+
+    /// Number of lines of a run a polled command remembers to find the new lines of the next run.
+    #[clap(long, value_name = "LINES", requires = "poll", default_value_t = reader::POLL_WINDOW)]
+    pub(crate) poll_window: usize,
+
+    /// Seconds between queries of the pids of the process names on the device. 0 disables the refresh. Defaults to 3.
+    #[clap(long)]
+    pub(crate) process_refresh: Option<u64>,
+
     /// Manually specify profile file (overrules ROGCAT_PROFILES).
     #[clap(short = 'P', long, value_hint = ValueHint::FilePath)]
     pub(crate) profiles_path: Option<PathBuf>,
 
-    /// Select profile.
+    /// Select profile by name or unique prefix. Pass multiple times to merge several profiles. Earlier profiles take precedence.
     #[clap(short, long)]
-    pub(crate) profile: Option<String>,
+    pub(crate) profile: Vec<String>,
 
     /// Write n records per file. Use k, M, G suffixes or a plain number.
     #[clap(long, short = 'n', requires = "output")]
     pub(crate) records_per_file: Option<String>,
 
+    /// Print nothing and exit with 0 if a record matched and 1 otherwise.
+    #[clap(long, conflicts_with_all = &["output", "output-command", "count", "count-by"])]
+    pub(crate) quiet: bool,
+
     /// Regex filter on tag, pid, thread and message.
     #[clap(long = "regex", short)]
     pub(crate) regex_filter: Vec<String>,
 
-    /// Restart command on exit.
-    #[clap(long, conflicts_with_all = &["dump", "input", "tail"])]
+    /// Show thread names instead of ids. The names are read from /proc of the device in the background, records show the id until the name is known. Skipped if the source is not adb.
+    #[clap(long)]
+    pub(crate) resolve_threads: bool,
+
+    /// Restart command on exit. Serial ports are reopened after a disconnect.
+    #[clap(long, conflicts_with_all = &["dump", "tail"])]
     pub(crate) restart: bool,
 
+    /// Rewrite messages and field values with sed like rules such as 's/Bearer [A-Za-z0-9._-]+/Bearer ***/' before filtering and output. Capture groups are referenced with $1 or ${name}, the flag g replaces all matches and i ignores the case. Pass multiple times to apply several rules in order after the rules of the profile.
+    #[clap(long, value_name = "RULE")]
+    pub(crate) rewrite: Vec<String>,
+
     /// Show month and day in terminal output.
     #[clap(long, conflicts_with = "output")]
     pub(crate) show_date: bool,
 
-    /// Forwards the device selector to adb.
+    /// Forwards the device selector to adb. Pass multiple times to follow
+    /// several devices at once.
     #[clap(long = "serial", short = 's')]
-    pub(crate) device: Option<String>,
+    pub(crate) device: Vec<String>,
+
+    /// Show only records at or after a time like "01-10 23:02:59.000" or a duration like 5m or 2h. Durations are relative to the newest record of the input files or to the current time.
+    #[clap(long)]
+    pub(crate) since: Option<String>,
+
+    /// Run one adb logcat per buffer to show the buffer of each record. Records of different buffers may appear out of order.
+    #[clap(long, conflicts_with_all = &["input", "COMMAND"])]
+    pub(crate) split_buffers: bool,
+
+    /// Start output files with a line describing the recording: rogcat version, device, start time and arguments. Requires the csv, json, logcat or raw format.
+    #[clap(long, requires = "output")]
+    pub(crate) session: bool,
+
+    /// Stamp device properties into the output: a header line of files, the page header of html files and a banner on the terminal. Defaults to ro.product.model, ro.build.fingerprint and ro.build.version.release. Skipped if the source is not adb.
+    #[clap(
+        long,
+        value_name = "PROPS",
+        min_values = 0,
+        require_equals = true,
+        value_delimiter = ','
+    )]
+    pub(crate) stamp_props: Option<Vec<String>>,
+
+    /// Print statistics about the records per level, tag and process on exit.
+    #[clap(long)]
+    pub(crate) stats: bool,
+
+    /// Drop input lines that cannot be parsed instead of passing them as message. Fails if any line was dropped.
+    #[clap(long)]
+    pub(crate) strict: bool,
 
     /// Tag filters in RE2. The prefix '!' inverts the match.
     #[clap(long, short)]
@@ -140,9 +408,38 @@ pub(crate) struct CliArguments {
     #[clap(long, conflicts_with_all = &["input", "COMMAND", "restart"])]
     pub(crate) tail: Option<usize>,
 
+    /// Print the records on the terminal in addition to the output. The format option applies to the output.
+    #[clap(long, requires = "output")]
+    pub(crate) tee: bool,
+
+    /// Filter by thread ID. The prefix '!' inverts the match.
+    #[clap(long)]
+    pub(crate) tid: Vec<String>,
+
+    /// Write only capture windows around records with a tag or message matching this RE2 pattern. A trigger within a window extends it. The date and enumerate filename formats start a new file for every window named after the time of the trigger.
+    #[clap(long, value_name = "REGEX")]
+    pub(crate) trigger: Option<String>,
+
+    /// Records preceding a trigger that are kept in memory and written with it.
+    #[clap(long, value_name = "N", default_value_t = 100, requires = "trigger")]
+    pub(crate) before: usize,
+
+    /// Records following a trigger that are written with it.
+    #[clap(long, value_name = "N", default_value_t = 100, requires = "trigger")]
+    pub(crate) after: usize,
+
+    /// Show only records at or before a time or duration. See --since.
+    #[clap(long)]
+    pub(crate) until: Option<String>,
+
+    /// Run the --on-match command for records with a tag or message matching this RE2 pattern.
+    #[clap(long, requires = "on-match")]
+    pub(crate) watch: Vec<String>,
+
     /// Optional command to run and capture stdout and stdderr from.
     /// Pass "-" to capture stdin. If omitted, rogcat will run
     /// "adb logcat -b all" and restarts this commmand if 'adb' terminates.
+    /// Use 'serial://COM0@115200,8N1' or similiar for reading a serial port.
     #[clap(name = "COMMAND")]
     pub(crate) command: Option<String>,
 
@@ -150,29 +447,320 @@ pub(crate) struct CliArguments {
     pub(crate) subcommands: Option<SubCommands>,
 }
 
+impl CliArguments {
+    /// Use the settings of the profile for all options not passed on the command line
+    pub(crate) fn apply(&mut self, profile: &Profile) -> Result<()> {
+        if self.level.is_none() {
+            if let Some(level) = profile.level.as_ref() {
+                self.level = Some(
+                    level
+                        .parse()
+                        .map_err(|e| anyhow!("Invalid level in profile: {e}"))?,
+                );
+            }
+        }
+        if self.format.is_none() {
+            if let Some(format) = profile.format.as_ref() {
+                self.format = Some(
+                    Format::from_str(format)
+                        .map_err(|_| anyhow!("Invalid format {} in profile", format))?,
+                );
+            }
+        }
+        if self.buffer.is_none() {
+            self.buffer = profile.buffer.clone();
+        }
+        if self.head.is_none() && self.tail.is_none() {
+            if profile.head.is_some() && profile.tail.is_some() {
+                return Err(anyhow!("Profile cannot set both head and tail"));
+            }
+            self.head = profile.head;
+            self.tail = profile.tail;
+        }
+        Ok(())
+    }
+
+    /// Show the progress of reading the input files. The progress bar is
+    /// not drawn over records printed on the terminal unless forced.
+    pub(crate) fn show_progress(&self) -> bool {
+        if !matches!(self.source(), Ok(Source::Files(_)))
+            || self.quiet
+            || self.last_records.is_some()
+        {
+            return false;
+        }
+        let counting = self.count || self.count_by.is_some();
+        let terminal = !counting
+            && ((self.output.is_none() && self.output_command.is_none()) || self.tee)
+            && atty::is(atty::Stream::Stdout);
+        self.progress || (!terminal && atty::is(atty::Stream::Stderr))
+    }
+
+    /// Source selected by the input files, the command or adb logcat
+    pub(crate) fn source(&self) -> Result<Source> {
+        if self.parallel.is_some() && self.input.is_empty() && self.command.as_deref() != Some("-")
+        {
+            return Err(anyhow!("--parallel requires input files or stdin"));
+        }
+        if !self.input.is_empty() {
+            let serial = self
+                .input
+                .iter()
+                .find_map(|input| input.to_str().and_then(reader::serial_port));
+            return match serial {
+                Some(_) if self.last_records.is_some() => {
+                    Err(anyhow!("--last-records requires input files"))
+                }
+                Some(_) if self.input.len() > 1 => Err(anyhow!(
+                    "Serial ports cannot be read together with other inputs"
+                )),
+                Some(port) => Ok(Source::Serial {
+                    port: port.to_owned(),
+                    restart: self.restart,
+                }),
+                None if self.restart => Err(anyhow!("Input files cannot be restarted")),
+                None => Ok(Source::Files(self.input.clone())),
+            };
+        }
+        let args = match self.logcat_args.as_deref() {
+            Some(args) => shell_words::split(args)
+                .map_err(|e| anyhow!("Invalid logcat arguments \"{args}\": {e}"))?,
+            None => Vec::new(),
+        };
+        if let Some(poll) = self.poll {
+            return match self.command.clone() {
+                Some(command) if command == "-" || reader::serial_port(&command).is_some() => {
+                    Err(anyhow!("Only commands can be polled"))
+                }
+                Some(command) => match Duration::try_from_secs_f64(poll) {
+                    Ok(interval) if !interval.is_zero() => Ok(Source::Poll {
+                        command,
+                        interval,
+                        window: self.poll_window,
+                    }),
+                    _ => Err(anyhow!("Invalid poll interval {poll}")),
+                },
+                None => Err(anyhow!("--poll requires a command")),
+            };
+        }
+        // Only a split adb logcat knows the buffer of the records
+        let logcat_buffers = |options: LogcatOptions| {
+            if !self.buffer_filter.is_empty() && options.args_select_buffers() {
+                Err(anyhow!(
+                    "--buffer-filter cannot be used with buffers selected by --logcat-args"
+                ))
+            } else {
+                Ok(Source::Logcat(options))
+            }
+        };
+        Ok(match self.command.clone() {
+            Some(command) if command == "-" => Source::Stdin,
+            Some(command) => match reader::serial_port(&command) {
+                Some(port) => Source::Serial {
+                    port: port.to_owned(),
+                    restart: self.restart,
+                },
+                None => Source::Command {
+                    command,
+                    restart: self.restart,
+                },
+            },
+            None => logcat_buffers(LogcatOptions {
+                devices: self.device.clone(),
+                buffers: self
+                    .buffer
+                    .clone()
+                    .or_else(|| config_get("buffer"))
+                    .unwrap_or_else(|| DEFAULT_BUFFER.iter().map(|&s| s.to_owned()).collect()),
+                tail: self.tail,
+                dump: self.dump,
+                last: self.last,
+                restart: self.restart | config_get::<bool>("restart").unwrap_or(true),
+                wait_for_device: !self.no_wait_for_device,
+                since: self.since.clone(),
+                split_buffers: self.split_buffers || !self.buffer_filter.is_empty(),
+                args,
+            })?,
+        })
+    }
+
+    /// Filters of the command line merged with the filters of the profile
+    pub(crate) fn filter_spec(&self, profile: &Profile) -> FilterSpec {
+        let merge = |args: &[String], profile: &[String]| [args, profile].concat();
+        FilterSpec {
+            level: self.level.clone().unwrap_or_default(),
+            tag: merge(&self.tag, &profile.tag),
+            tag_ignore_case: merge(&self.tag_ignore_case, &profile.tag_ignore_case),
+            message: merge(&self.message, &profile.message),
+            message_ignore_case: merge(&self.message_ignore_case, &profile.message_ignore_case),
+            pid: merge(&self.pid, &profile.pid),
+            tid: merge(&self.tid, &profile.tid),
+            process_name: merge(
+                self.process_name.as_deref().unwrap_or_default(),
+                &profile.process_name,
+            ),
+            regex: merge(&self.regex_filter, &profile.regex),
+            fields: self.field.clone(),
+            buffer: self.buffer_filter.clone(),
+            since: self.since.clone(),
+            until: self.until.clone(),
+            drop_untimed: self.drop_untimed,
+            process_refresh: self.process_refresh,
+        }
+    }
+
+    /// Read the inputs of `opts` and write them to its output like
+    /// `-i INPUT -o OUTPUT -f FORMAT`
+    pub(crate) fn convert(&mut self, opts: ConvertOpts) -> Result<()> {
+        if !self.buffer_filter.is_empty() {
+            return Err(anyhow!("--buffer-filter requires adb logcat as source"));
+        }
+        if opts
+            .input
+            .iter()
+            .any(|input| same_file(input, &opts.output))
+        {
+            return Err(anyhow!("Cannot convert {} in place", opts.output.display()));
+        }
+        // The extension before .gz selects the format
+        let name = opts.output.with_extension("");
+        let name = match opts.output.extension() {
+            Some(e) if e == "gz" => name.as_path(),
+            _ => opts.output.as_path(),
+        };
+        let format = match name.extension().and_then(|e| e.to_str()) {
+            Some("csv") => Some(Format::Csv),
+            Some("html") => Some(Format::Html),
+            Some("json") => Some(Format::Json),
+            _ => None,
+        };
+        self.format = opts.format.or(format);
+        self.input = opts.input;
+        self.input_format = opts.input_format;
+        self.output = Some(opts.output);
+        Ok(())
+    }
+
+    /// Rewrite rules of the profile followed by the ones of the command line
+    pub(crate) fn rewrite(&self, profile: &Profile) -> Result<Rewrite> {
+        Ok(Rewrite::parse(
+            &[&profile.rewrite[..], &self.rewrite].concat(),
+        )?)
+    }
+}
+
 #[derive(Subcommand, Clone, Debug, PartialEq)]
 pub(crate) enum SubCommands {
+    /// Captures a bugreport and writes it to a file.
+    #[clap(name = "bugreport")]
+    BugReport(BugReportOpts),
+
     /// Generates completion scripts.
     Completions(CompletionsOpts),
 
+    /// Sends a command like "tag +MyTag" to the control socket of a running rogcat.
+    Ctl(CtlOpts),
+
     /// Clears logd buffers.
     Clear(ClearOpts),
 
-    /// Lists available devices.
-    Devices,
+    /// Finds Java crashes, native crashes and ANRs in files or adb logcat and prints a summary or writes each one to a file.
+    Crashes(CrashesOpts),
+
+    /// Reads and writes the settings of the configuration file.
+    Config(ConfigOpts),
+
+    /// Converts recorded files to another format, e.g. old csv captures to json. Filters apply.
+    Convert(ConvertOpts),
+
+    /// Lists available devices with their state, product and model.
+    Devices(DevicesOpts),
+
+    /// Shows the records removed and added between two files. Timestamps and pids are ignored. Exits with 1 if the files differ.
+    Diff(DiffOpts),
 
     /// Add log message to logcat buffer.
     Log(LogOpts),
 
     /// Lists existing profiles
     Profiles(ProfilesOpts),
+
+    /// Replays a recorded file at the pace of its timestamps. Filters and terminal options apply.
+    Replay(ReplayOpts),
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+pub(crate) struct BugReportOpts {
+    /// Zip the report.
+    #[clap(long, short)]
+    pub(crate) zip: bool,
+
+    /// Overwrite report file if present.
+    #[clap(long)]
+    pub(crate) overwrite: bool,
+
+    /// Output file name. Defaults to <now>-bugreport.txt
+    #[clap(name = "FILE", value_hint = ValueHint::FilePath)]
+    pub(crate) file: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+pub(crate) struct ConfigOpts {
+    #[clap(subcommand)]
+    pub(crate) action: ConfigAction,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub(crate) enum ConfigAction {
+    /// Prints the value of a setting.
+    Get { key: String },
+    /// Writes a setting. Lists are separated by commas and colors are passed as NAME=COLOR pairs.
+    Set { key: String, value: String },
+    /// Removes a setting.
+    Unset { key: String },
+    /// Lists the settings of the configuration file.
+    List,
 }
 
 #[derive(Args, Debug, Clone, PartialEq)]
 pub(crate) struct CompletionsOpts {
     /// The shell to generate the script for.
-    #[clap(required = true, value_enum)]
-    pub(crate) shell: Shell,
+    #[clap(required_unless_present = "list", value_enum)]
+    pub(crate) shell: Option<Shell>,
+
+    /// Complete profile names and device serials by calling rogcat when completing. Supported for bash, zsh and fish.
+    #[clap(long, requires = "shell")]
+    pub(crate) dynamic: bool,
+
+    /// Print the candidates of profile names or device serials, one per line.
+    #[clap(long, hide = true, value_enum, conflicts_with = "shell")]
+    pub(crate) list: Option<CompletionList>,
+}
+
+/// Candidates listed for dynamic completions
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CompletionList {
+    Devices,
+    Profiles,
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+pub(crate) struct DevicesOpts {
+    /// Print a table or a JSON array.
+    #[clap(long, value_enum, default_value_t)]
+    pub(crate) format: ListFormat,
+
+    /// Exit with 1 if no device is in the state "device", e.g. all are unauthorized or offline.
+    #[clap(long)]
+    pub(crate) require_device: bool,
+}
+
+/// Output of the devices and profiles subcommands
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) enum ListFormat {
+    #[default]
+    Table,
+    Json,
 }
 
 #[derive(Args, Debug, Clone, PartialEq)]
@@ -189,16 +777,253 @@ pub(crate) struct LogOpts {
     pub(crate) tag: Option<String>,
 
     /// Log level.
-    #[clap(short, long, value_parser = Level::values())]
-    pub(crate) level: Option<String>,
+    #[clap(short, long, value_parser = Level::from_str)]
+    pub(crate) level: Option<Level>,
+
+    /// Read level and tag of stdin lines from a prefix like "W/Tag: message".
+    /// Lines without prefix use --level and --tag.
+    #[clap(long)]
+    pub(crate) prefixed: bool,
 
     #[clap(name = "MESSAGE", required = true)]
     pub(crate) message: String,
 }
 
+#[derive(Args, Debug, Clone, PartialEq)]
+pub(crate) struct CrashesOpts {
+    /// Read from files instead of adb logcat.
+    #[clap(short, long, value_hint = ValueHint::FilePath)]
+    pub(crate) input: Vec<PathBuf>,
+
+    /// Write each crash to a file in this directory instead of printing a summary.
+    #[clap(short, long, value_hint = ValueHint::DirPath)]
+    pub(crate) output: Option<PathBuf>,
+
+    /// Dump the log of the device and then exit.
+    #[clap(long, short, conflicts_with = "input")]
+    pub(crate) dump: bool,
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+pub(crate) struct ConvertOpts {
+    /// Files to convert. The records of all files are written to the output.
+    #[clap(short, long, required = true, value_hint = ValueHint::FilePath)]
+    pub(crate) input: Vec<PathBuf>,
+
+    /// Format of the input lines.
+    #[clap(long, value_enum, default_value_t)]
+    pub(crate) input_format: InputFormat,
+
+    /// Output file. Files ending in .gz are compressed.
+    #[clap(short, long, value_hint = ValueHint::FilePath)]
+    pub(crate) output: PathBuf,
+
+    /// Output format. Defaults to the extension of the output file: csv, html or json. Other files are written raw.
+    #[clap(long, short, value_enum)]
+    pub(crate) format: Option<Format>,
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+pub(crate) struct CtlOpts {
+    /// Port or socket path passed to --control-socket of the running rogcat.
+    #[clap(long, value_name = "ADDRESS")]
+    pub(crate) control_socket: String,
+
+    /// Command like "tag +MyTag", "tag -MyTag", "message !noisy", "level warn" or "clear tag".
+    #[clap(name = "COMMAND", required = true, allow_hyphen_values = true)]
+    pub(crate) command: Vec<String>,
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+pub(crate) struct DiffOpts {
+    /// Fields of the records that are compared.
+    #[clap(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "tag,level,message"
+    )]
+    pub(crate) key: Vec<Key>,
+
+    /// Number of unchanged records shown around changes.
+    #[clap(short = 'C', long, default_value_t = 3)]
+    pub(crate) context: usize,
+
+    /// Replace numbers in messages before comparing, e.g. pids and durations.
+    #[clap(long)]
+    pub(crate) ignore_numbers: bool,
+
+    /// Color the removed and added records.
+    #[clap(long, value_parser = ["always", "auto", "never"])]
+    pub(crate) color: Option<String>,
+
+    /// Write the diff to a file. Colors are written only with --color always.
+    #[clap(short, long, value_hint = ValueHint::FilePath)]
+    pub(crate) output: Option<PathBuf>,
+
+    /// Overwrite the output file if present.
+    #[clap(long, requires = "output")]
+    pub(crate) overwrite: bool,
+
+    /// File of the former run.
+    #[clap(name = "OLD", required = true, value_hint = ValueHint::FilePath)]
+    pub(crate) old: PathBuf,
+
+    /// File of the latter run.
+    #[clap(name = "NEW", required = true, value_hint = ValueHint::FilePath)]
+    pub(crate) new: PathBuf,
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+pub(crate) struct ReplayOpts {
+    /// Speed factor of the replay, e.g. 2.0 for twice the original speed.
+    #[clap(long, short, default_value_t = 1.0)]
+    pub(crate) speed: f64,
+
+    /// Replay the records without delay.
+    #[clap(long, conflicts_with = "speed")]
+    pub(crate) no_delay: bool,
+
+    /// Recorded file.
+    #[clap(name = "FILE", required = true, value_hint = ValueHint::FilePath)]
+    pub(crate) file: PathBuf,
+}
+
 #[derive(Args, Debug, Clone, PartialEq)]
 pub(crate) struct ProfilesOpts {
     /// Manually specify profile file (overrules ROGCAT_PROFILES).
     #[clap(short = 'P', long, value_hint = ValueHint::FilePath)]
     pub(crate) profiles_path: Option<PathBuf>,
+
+    /// Print a table or a JSON array of names, comments and extended profiles.
+    #[clap(long, value_enum, default_value_t)]
+    pub(crate) format: ListFormat,
+}
+
+#[test]
+fn config_actions() {
+    let args = CliArguments::parse_from(["rogcat", "config", "set", "terminal_tag_width", "30"]);
+    assert_eq!(
+        args.subcommands,
+        Some(SubCommands::Config(ConfigOpts {
+            action: ConfigAction::Set {
+                key: "terminal_tag_width".to_owned(),
+                value: "30".to_owned()
+            }
+        }))
+    );
+    assert!(CliArguments::try_parse_from(["rogcat", "config", "set", "restart"]).is_err());
+}
+
+#[test]
+fn keep_last_requires_records_per_file() {
+    let args = |extra: &[&str]| {
+        let args = ["rogcat", "-o", "log.txt", "--keep-last", "10k"];
+        CliArguments::try_parse_from(args.iter().chain(extra))
+    };
+    assert!(args(&[]).is_err());
+    assert!(args(&["-n", "1k"]).is_ok());
+}
+
+#[test]
+fn buffer_filter_requires_logcat() {
+    let source = |args: &[&str]| {
+        CliArguments::try_parse_from(["rogcat", "--buffer-filter", "crash"].iter().chain(args))
+            .map_err(anyhow::Error::from)
+            .and_then(|args| args.source())
+    };
+    assert!(source(&["-i", "capture.log"]).is_err());
+    assert!(source(&["-"]).is_err());
+    assert!(source(&["--logcat-args=-b radio"]).is_err());
+    assert!(source(&["--logcat-args=--buffer=radio"]).is_err());
+    match source(&["--logcat-args=-v uid"]).unwrap() {
+        Source::Logcat(options) => assert!(options.split_buffers),
+        _ => panic!("Expected adb logcat"),
+    }
+}
+
+#[test]
+fn profile_settings() {
+    let profiles = rogcat::profiles::parse(
+        r#"
+        [profile.kernel]
+        buffer = ["kernel"]
+        level = "warn"
+
+        [profile.crash]
+        extends = ["kernel"]
+        buffer = ["crash"]
+        format = "json"
+        "#,
+    )
+    .unwrap();
+
+    let kernel = rogcat::profiles::select(&profiles, &["kernel"]).unwrap();
+    let mut args = CliArguments::parse_from(["rogcat"]);
+    args.apply(&kernel).unwrap();
+    assert_eq!(args.level, Some(Level::Warn));
+    assert_eq!(args.buffer, Some(vec!["kernel".to_owned()]));
+    assert_eq!(args.format, None);
+
+    // Command line wins
+    let mut args = CliArguments::parse_from(["rogcat", "-l", "debug"]);
+    args.apply(&kernel).unwrap();
+    assert_eq!(args.level, Some(Level::Debug));
+
+    // Extending profile overrides conflicting buffers
+    let crash = rogcat::profiles::select(&profiles, &["crash"]).unwrap();
+    let mut args = CliArguments::parse_from(["rogcat"]);
+    args.apply(&crash).unwrap();
+    assert_eq!(args.level, Some(Level::Warn));
+    assert_eq!(args.buffer, Some(vec!["crash".to_owned()]));
+    assert_eq!(args.format, Some(Format::Json));
+
+    let invalid = Profile {
+        level: Some("loud".to_owned()),
+        ..Default::default()
+    };
+    assert!(CliArguments::parse_from(["rogcat"])
+        .apply(&invalid)
+        .is_err());
+}
+
+#[tokio::test]
+async fn filter_spec_merge() {
+    use rogcat::{
+        filter::{Filter, FilterDecision, Rejection},
+        parser::Parser,
+        record::Record,
+    };
+
+    let strings = |values: &[&str]| values.iter().map(|&v| v.to_owned()).collect::<Vec<_>>();
+    let profile = Profile {
+        tag: strings(&["^b$", "!x$", "^a$"]),
+        ..Default::default()
+    };
+    let args = CliArguments::parse_from(["rogcat", "-t", "^a$", "-t", "!^x", "-i", "capture.log"]);
+    let spec = args.filter_spec(&profile);
+    // Command line filters come first
+    assert_eq!(spec.tag, ["^a$", "!^x", "^b$", "!x$", "^a$"]);
+
+    let source = Source::Files(args.input.clone());
+    let mut filter = Filter::new(spec, &source, &mut Parser::default())
+        .await
+        .unwrap();
+    let tag = |tag: &str| Record {
+        tag: tag.to_owned(),
+        ..Default::default()
+    };
+    let drop = |pattern| {
+        FilterDecision::Drop(Rejection {
+            filter: "tag",
+            field: false,
+            pattern,
+        })
+    };
+    for passing in ["a", "b"] {
+        assert_eq!(filter.decide(&tag(passing)), FilterDecision::Pass);
+    }
+    assert_eq!(filter.decide(&tag("c")), drop(None));
+    // The negative filter of the command line is checked first
+    assert_eq!(filter.decide(&tag("x")), drop(Some("^x")));
 }
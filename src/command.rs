@@ -0,0 +1,207 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Sink that pipes the formatted records into the stdin of a child process
+
+use crate::{cli::CliArguments, diagnostics::Reporter, terminal::Human};
+use anyhow::{anyhow, Result};
+use futures::{
+    ready,
+    sink::Sink,
+    task::{Context, Poll},
+    Future,
+};
+use rogcat::{
+    profiles::Profile,
+    record::{Column, Format, LevelStyle, Record},
+    Error, LogSink,
+};
+use std::{io::ErrorKind, pin::Pin, process::Stdio};
+use termcolor::Buffer;
+use tokio::{
+    io::AsyncWrite,
+    process::{Child, ChildStdin, Command},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Number of bytes buffered before waiting for the child to read them
+const BUFFER_SIZE: usize = 64 * 1024;
+
+enum Render {
+    Format(Format, Option<Vec<Column>>, LevelStyle),
+    Human(Box<Human>),
+}
+
+struct CommandSink {
+    render: Render,
+    child: Child,
+    /// Closed when the child exited
+    stdin: Option<ChildStdin>,
+    buffer: Vec<u8>,
+    /// Cancelled when the child stopped reading
+    shutdown: CancellationToken,
+    /// Notes a failed exit of the child
+    stderr: Reporter,
+}
+
+/// Spawn `args.output_command` and create a sink writing the records to its
+/// stdin. The output defaults to the raw format. Reading stops when the child
+/// exits.
+pub fn try_from(
+    args: &CliArguments,
+    profile: &Profile,
+    shutdown: CancellationToken,
+) -> Result<LogSink> {
+    let command = args.output_command.as_deref().unwrap_or_default();
+    let mut cmd = command.split_whitespace();
+    let program = cmd
+        .next()
+        .ok_or_else(|| anyhow!("Invalid output command \"{command}\""))?;
+    let mut child = Command::new(program)
+        .args(cmd)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| anyhow!("Failed to spawn output command \"{command}\": {e}"))?;
+    let stdin = child.stdin.take();
+
+    let render = match args.format.clone().unwrap_or(Format::Raw) {
+        Format::Human => Render::Human(Box::new(Human::from(args, profile, Format::Human)?)),
+        format => Render::Format(
+            format,
+            (!args.columns.is_empty()).then(|| args.columns.clone()),
+            args.level_style,
+        ),
+    };
+
+    Ok(Box::new(CommandSink {
+        render,
+        child,
+        stdin,
+        buffer: Vec::new(),
+        shutdown,
+        stderr: Reporter::new(args),
+    }))
+}
+
+impl CommandSink {
+    /// Write the buffer to the child. Records are discarded once the child
+    /// stopped reading.
+    fn poll_write_buffer(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        while !self.buffer.is_empty() {
+            let Some(stdin) = self.stdin.as_mut() else {
+                self.buffer.clear();
+                break;
+            };
+            match ready!(Pin::new(stdin).poll_write(cx, &self.buffer)) {
+                Ok(n) => {
+                    self.buffer.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::BrokenPipe => self.closed(),
+                Err(e) => return Poll::Ready(Err(Error::io("Failed to write to command", e))),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// The child exited. Stop reading records.
+    fn closed(&mut self) {
+        self.stdin = None;
+        self.buffer.clear();
+        self.shutdown.cancel();
+    }
+}
+
+impl Sink<Record> for CommandSink {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.buffer.len() >= BUFFER_SIZE {
+            ready!(this.poll_write_buffer(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, record: Record) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        if this.stdin.is_none() {
+            return Ok(());
+        }
+        match &mut this.render {
+            Render::Format(format, columns, style) => {
+                let line = format.fmt_styled(&record, columns.as_deref(), *style)?;
+                this.buffer.extend(line.as_bytes());
+                this.buffer.push(b'\n');
+            }
+            Render::Human(human) => {
+                let mut buffer = Buffer::no_color();
+                human
+                    .render(&record, None, &mut buffer)
+                    .map_err(|e| Error::io("Failed to render", e))?;
+                this.buffer.extend(buffer.as_slice());
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush is called whenever the source is idle. Check if the child exited
+    /// meanwhile and get woken up if it does.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buffer(cx))?;
+        let Some(stdin) = this.stdin.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        match ready!(Pin::new(stdin).poll_flush(cx)) {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => this.closed(),
+            result => result.map_err(|e| Error::io("Failed to write to command", e))?,
+        }
+        let exited = {
+            let wait = this.child.wait();
+            futures::pin_mut!(wait);
+            wait.poll(cx).is_ready()
+        };
+        if exited {
+            this.closed();
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Close stdin and wait for the child to exit
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        let this = self.get_mut();
+        this.stdin = None;
+        let status = {
+            let wait = this.child.wait();
+            futures::pin_mut!(wait);
+            ready!(wait.poll(cx)).map_err(|e| Error::io("Failed to wait for command", e))?
+        };
+        // A failed child is noted but doesn't fail rogcat. A child terminated
+        // by a signal, e.g. ctrl-c, isn't noted.
+        if status.code().is_some_and(|code| code != 0) {
+            this.stderr
+                .report(&format!("Output command exited with {status}"))
+                .map_err(|e| Error::io("Failed to write diagnostic", e))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,524 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Control socket to change the filters of a running pipeline. Each line is
+//! a command like "tag +MyTag" that is answered with "ok" or "error: ...".
+
+use crate::{
+    filter::{FilterError, FilterReload, FilterSpec},
+    record::Level,
+    Error,
+};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+/// List of filters changed by a command
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterList {
+    Tag,
+    TagIgnoreCase,
+    Message,
+    MessageIgnoreCase,
+    Pid,
+    Tid,
+    Regex,
+    Buffer,
+}
+
+impl FilterList {
+    const ALL: [FilterList; 8] = [
+        FilterList::Tag,
+        FilterList::TagIgnoreCase,
+        FilterList::Message,
+        FilterList::MessageIgnoreCase,
+        FilterList::Pid,
+        FilterList::Tid,
+        FilterList::Regex,
+        FilterList::Buffer,
+    ];
+
+    /// Name of the list in commands. The names follow the long options.
+    fn name(self) -> &'static str {
+        match self {
+            FilterList::Tag => "tag",
+            FilterList::TagIgnoreCase => "Tag",
+            FilterList::Message => "message",
+            FilterList::MessageIgnoreCase => "Message",
+            FilterList::Pid => "pid",
+            FilterList::Tid => "tid",
+            FilterList::Regex => "regex",
+            FilterList::Buffer => "buffer",
+        }
+    }
+
+    fn parse(name: &str) -> Option<FilterList> {
+        FilterList::ALL.into_iter().find(|list| list.name() == name)
+    }
+
+    fn patterns(self, spec: &mut FilterSpec) -> &mut Vec<String> {
+        match self {
+            FilterList::Tag => &mut spec.tag,
+            FilterList::TagIgnoreCase => &mut spec.tag_ignore_case,
+            FilterList::Message => &mut spec.message,
+            FilterList::MessageIgnoreCase => &mut spec.message_ignore_case,
+            FilterList::Pid => &mut spec.pid,
+            FilterList::Tid => &mut spec.tid,
+            FilterList::Regex => &mut spec.regex,
+            FilterList::Buffer => &mut spec.buffer,
+        }
+    }
+}
+
+/// Command of the control socket
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Add a pattern like "tag +MyTag" or "message !noisy". The prefix '+'
+    /// is optional and '!' inverts the pattern like on the command line.
+    Add(FilterList, String),
+    /// Remove a pattern like "tag -MyTag"
+    Remove(FilterList, String),
+    /// Remove the patterns of a list like "clear tag" or of all lists
+    Clear(Option<FilterList>),
+    /// Set the minimum level like "level warn"
+    Level(Level),
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Command, String> {
+        let line = line.trim();
+        let (name, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+        let list = |name: &str| {
+            FilterList::parse(name).ok_or_else(|| {
+                let names = FilterList::ALL.map(FilterList::name).join(", ");
+                format!("Unknown filter \"{name}\". Known filters are {names}")
+            })
+        };
+        match name {
+            "level" => argument.parse().map(Command::Level),
+            "clear" if argument.is_empty() => Ok(Command::Clear(None)),
+            "clear" => list(argument).map(|list| Command::Clear(Some(list))),
+            "" => Err("Empty command".to_owned()),
+            name if argument.is_empty() => Err(format!("Missing pattern of {name}")),
+            name => {
+                let list = list(name)?;
+                let (remove, pattern) = match argument.strip_prefix('-') {
+                    Some(pattern) => (true, pattern),
+                    None => (false, argument.strip_prefix('+').unwrap_or(argument)),
+                };
+                match (remove, pattern.to_owned()) {
+                    (_, pattern) if pattern.is_empty() => Err(format!("Missing pattern of {name}")),
+                    (true, pattern) => Ok(Command::Remove(list, pattern)),
+                    (false, pattern) => Ok(Command::Add(list, pattern)),
+                }
+            }
+        }
+    }
+}
+
+impl Command {
+    /// Change the filters of `spec`
+    fn apply(&self, spec: &mut FilterSpec) -> Result<(), String> {
+        match self {
+            Command::Add(list, pattern) => {
+                let patterns = list.patterns(spec);
+                if !patterns.contains(pattern) {
+                    patterns.push(pattern.clone());
+                }
+            }
+            Command::Remove(list, pattern) => {
+                let patterns = list.patterns(spec);
+                let Some(index) = patterns.iter().position(|p| p == pattern) else {
+                    return Err(format!("No {} filter {pattern}", list.name()));
+                };
+                patterns.remove(index);
+            }
+            Command::Clear(Some(list)) => list.patterns(spec).clear(),
+            Command::Clear(None) => {
+                for list in FilterList::ALL {
+                    list.patterns(spec).clear();
+                }
+            }
+            Command::Level(level) => spec.level = level.clone(),
+        }
+        Ok(())
+    }
+}
+
+/// Filters of a running pipeline changed by the commands of the control
+/// socket. Clones share the filters.
+#[derive(Clone, Debug)]
+pub struct Control {
+    spec: Arc<Mutex<FilterSpec>>,
+    reload: FilterReload,
+}
+
+impl Control {
+    /// Control of the pipeline started with `spec` and reloaded by `reload`
+    pub fn new(spec: FilterSpec, reload: FilterReload) -> Control {
+        Control {
+            spec: Arc::new(Mutex::new(spec)),
+            reload,
+        }
+    }
+
+    /// Run the command `line`. The filters are replaced at once before the
+    /// next record which is preceded by a diagnostic echoing the command.
+    /// Invalid commands and patterns keep the filters.
+    pub fn execute(&self, line: &str) -> Result<(), Error> {
+        let command = line.parse::<Command>().map_err(Error::Invalid)?;
+        let mut spec = self.spec.lock().expect("Poisoned lock");
+        let mut changed = spec.clone();
+        command.apply(&mut changed).map_err(Error::Invalid)?;
+        self.reload
+            .reload_with_notice(&changed, format!("Filter {}", line.trim()))?;
+        *spec = changed;
+        Ok(())
+    }
+
+    /// Replace all filters, e.g. with the ones of a reloaded profile
+    pub fn reset(&self, spec: FilterSpec) -> Result<(), FilterError> {
+        let mut current = self.spec.lock().expect("Poisoned lock");
+        self.reload.reload(&spec)?;
+        *current = spec;
+        Ok(())
+    }
+}
+
+/// Address of a control socket
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlAddress {
+    /// Port on localhost
+    Tcp(u16),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl ControlAddress {
+    /// Numbers are tcp ports on localhost. Other values are paths of unix
+    /// sockets.
+    pub fn parse(value: &str) -> Result<ControlAddress, Error> {
+        if let Ok(port) = value.parse() {
+            return Ok(ControlAddress::Tcp(port));
+        }
+        #[cfg(unix)]
+        return Ok(ControlAddress::Unix(PathBuf::from(value)));
+        #[cfg(not(unix))]
+        Err(Error::Invalid(format!(
+            "Invalid control socket {value}: expected a port"
+        )))
+    }
+}
+
+/// Answer the commands of a connection
+async fn serve<S: AsyncRead + AsyncWrite>(stream: S, control: Control) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match control.execute(&line) {
+            Ok(()) => "ok\n".to_owned(),
+            Err(e) => format!("error: {e}\n"),
+        };
+        if writer.write_all(reply.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Bound control socket. A unix socket file is removed when dropped.
+#[derive(Debug)]
+pub struct Listener {
+    address: ControlAddress,
+}
+
+impl Listener {
+    /// Bound address, e.g. with the port picked for port 0
+    pub fn address(&self) -> &ControlAddress {
+        &self.address
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let ControlAddress::Unix(path) = &self.address {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// Listen on `address` and pass the commands of the connections to
+/// `control` in the background. A unix socket that is left over by a
+/// previous run is replaced. Other files are not touched.
+pub async fn listen(address: &ControlAddress, control: Control) -> Result<Listener, Error> {
+    match address {
+        ControlAddress::Tcp(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", *port))
+                .await
+                .map_err(|e| Error::io(format!("Failed to bind control port {port}"), e))?;
+            let port = listener
+                .local_addr()
+                .map_err(|e| Error::io("Failed to bind control port", e))?
+                .port();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, control.clone()));
+                }
+            });
+            Ok(Listener {
+                address: ControlAddress::Tcp(port),
+            })
+        }
+        #[cfg(unix)]
+        ControlAddress::Unix(path) => {
+            use std::os::unix::fs::FileTypeExt;
+            use tokio::net::{UnixListener, UnixStream};
+
+            let context = || format!("Failed to bind control socket {}", path.display());
+            if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                if !metadata.file_type().is_socket() {
+                    return Err(Error::Invalid(format!(
+                        "Control socket {} exists and is not a socket",
+                        path.display()
+                    )));
+                }
+                if UnixStream::connect(path).await.is_ok() {
+                    return Err(Error::Invalid(format!(
+                        "Control socket {} is in use",
+                        path.display()
+                    )));
+                }
+                std::fs::remove_file(path).map_err(|e| Error::io(context(), e))?;
+            }
+            let listener = UnixListener::bind(path).map_err(|e| Error::io(context(), e))?;
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, control.clone()));
+                }
+            });
+            Ok(Listener {
+                address: address.clone(),
+            })
+        }
+    }
+}
+
+/// Send `command` to the control socket at `address`. Returns the answer
+/// without line break.
+pub async fn send(address: &ControlAddress, command: &str) -> Result<String, Error> {
+    async fn request<S: AsyncRead + AsyncWrite>(
+        stream: S,
+        command: &str,
+    ) -> std::io::Result<String> {
+        let (reader, mut writer) = tokio::io::split(stream);
+        writer.write_all(format!("{command}\n").as_bytes()).await?;
+        let mut reply = String::new();
+        BufReader::new(reader).read_line(&mut reply).await?;
+        Ok(reply.trim_end().to_owned())
+    }
+
+    let context = "Failed to send control command";
+    match address {
+        ControlAddress::Tcp(port) => {
+            let stream = TcpStream::connect(("127.0.0.1", *port))
+                .await
+                .map_err(|e| Error::io(context, e))?;
+            request(stream, command).await
+        }
+        #[cfg(unix)]
+        ControlAddress::Unix(path) => {
+            let stream = tokio::net::UnixStream::connect(path)
+                .await
+                .map_err(|e| Error::io(context, e))?;
+            request(stream, command).await
+        }
+    }
+    .map_err(|e| Error::io(context, e))
+}
+
+#[test]
+fn parse_commands() {
+    let parse = |line: &str| line.parse::<Command>();
+    assert_eq!(
+        parse("tag +MyTag"),
+        Ok(Command::Add(FilterList::Tag, "MyTag".to_owned()))
+    );
+    assert_eq!(
+        parse("tag MyTag"),
+        Ok(Command::Add(FilterList::Tag, "MyTag".to_owned()))
+    );
+    assert_eq!(
+        parse("  tag -MyTag  "),
+        Ok(Command::Remove(FilterList::Tag, "MyTag".to_owned()))
+    );
+    assert_eq!(
+        parse("message !noisy"),
+        Ok(Command::Add(FilterList::Message, "!noisy".to_owned()))
+    );
+    // Patterns keep their spaces and a leading '-' is added with '+'
+    assert_eq!(
+        parse("Message +-a b"),
+        Ok(Command::Add(
+            FilterList::MessageIgnoreCase,
+            "-a b".to_owned()
+        ))
+    );
+    assert_eq!(parse("level warn"), Ok(Command::Level(Level::Warn)));
+    assert_eq!(
+        parse("clear tag"),
+        Ok(Command::Clear(Some(FilterList::Tag)))
+    );
+    assert_eq!(parse("clear"), Ok(Command::Clear(None)));
+
+    for invalid in ["", "tag", "tag +", "foo bar", "level loud", "clear foo"] {
+        assert!(parse(invalid).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn apply_commands() {
+    fn apply(spec: &mut FilterSpec, line: &str) -> Result<(), String> {
+        line.parse::<Command>().unwrap().apply(spec)
+    }
+
+    let mut spec = FilterSpec::default();
+    for line in [
+        "tag a",
+        "tag b",
+        "tag a",
+        "message !noisy",
+        "tag -a",
+        "level E",
+    ] {
+        apply(&mut spec, line).unwrap();
+    }
+    assert!(apply(&mut spec, "tag -c").is_err());
+    assert_eq!(spec.tag, ["b"]);
+    assert_eq!(spec.message, ["!noisy"]);
+    assert_eq!(spec.level, Level::Error);
+
+    apply(&mut spec, "clear tag").unwrap();
+    assert!(spec.tag.is_empty());
+    assert_eq!(spec.message, ["!noisy"]);
+    apply(&mut spec, "clear").unwrap();
+    assert!(spec.message.is_empty());
+}
+
+#[tokio::test]
+async fn control_socket() {
+    use crate::{
+        pipeline::{Pipeline, Source},
+        StreamData,
+    };
+    use futures::StreamExt;
+
+    let (tx, rx) = futures::channel::mpsc::unbounded();
+    let line = |tag: &str, message: &str| {
+        let line = format!("01-10 23:02:59.028  1  2 I {tag}: {message}");
+        tx.unbounded_send(StreamData::Line(line)).unwrap();
+    };
+    let pipeline = Pipeline::new().source(Source::Stream(Box::new(rx)));
+    let control = Control::new(FilterSpec::default(), pipeline.reload_handle());
+    let mut records = pipeline.stream().await.unwrap();
+    let listener = listen(&ControlAddress::Tcp(0), control).await.unwrap();
+    let address = listener.address().clone();
+
+    line("A", "one");
+    assert_eq!(records.next().await.unwrap().message, "one");
+
+    assert_eq!(send(&address, "tag +^B$").await.unwrap(), "ok");
+    line("A", "two");
+    line("B", "three");
+    let notice = records.next().await.unwrap();
+    assert!(notice.diagnostic);
+    assert_eq!(notice.message, "Filter tag +^B$");
+    assert_eq!(records.next().await.unwrap().message, "three");
+
+    // Invalid patterns and commands keep the filters
+    let reply = send(&address, "tag +(").await.unwrap();
+    assert!(reply.starts_with("error: "), "{reply}");
+    let reply = send(&address, "tag -^C$").await.unwrap();
+    assert_eq!(reply, "error: No tag filter ^C$");
+    line("A", "four");
+    line("B", "five");
+    assert_eq!(records.next().await.unwrap().message, "five");
+
+    assert_eq!(send(&address, "message !noisy").await.unwrap(), "ok");
+    assert_eq!(send(&address, "level warn").await.unwrap(), "ok");
+    line("B", "noisy");
+    let line = |level: &str, message: &str| {
+        let line = format!("01-10 23:02:59.028  1  2 {level} B: {message}");
+        tx.unbounded_send(StreamData::Line(line)).unwrap();
+    };
+    line("W", "six");
+    // Both commands are applied before the next record and noted in order
+    assert_eq!(
+        records.next().await.unwrap().message,
+        "Filter message !noisy"
+    );
+    assert_eq!(records.next().await.unwrap().message, "Filter level warn");
+    assert_eq!(records.next().await.unwrap().message, "six");
+
+    assert_eq!(send(&address, "clear").await.unwrap(), "ok");
+    line("W", "noisy");
+    assert!(records.next().await.unwrap().diagnostic);
+    assert_eq!(records.next().await.unwrap().message, "noisy");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn control_unix_socket() {
+    let path = std::env::temp_dir().join(format!("rogcat-control-{}.sock", std::process::id()));
+    // Left over sockets are replaced
+    std::os::unix::net::UnixListener::bind(&path).unwrap();
+    let address = ControlAddress::parse(path.to_str().unwrap()).unwrap();
+    let control = Control::new(FilterSpec::default(), FilterReload::default());
+    let listener = listen(&address, control.clone()).await.unwrap();
+    // Sockets in use are not
+    assert!(listen(&address, control.clone()).await.is_err());
+
+    assert_eq!(send(&address, "tid 42").await.unwrap(), "ok");
+    assert_eq!(control.spec.lock().unwrap().tid, ["42"]);
+    // The socket is removed with the listener
+    drop(listener);
+    assert!(!path.exists());
+
+    // Other files are kept
+    let file = std::env::temp_dir().join(format!("rogcat-control-{}.txt", std::process::id()));
+    std::fs::write(&file, "notes").unwrap();
+    let address = ControlAddress::parse(file.to_str().unwrap()).unwrap();
+    let error = listen(&address, control).await.unwrap_err();
+    assert!(error.to_string().ends_with("exists and is not a socket"));
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "notes");
+    std::fs::remove_file(&file).ok();
+}
@@ -0,0 +1,200 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Sink that counts the records instead of writing them
+
+use crate::cli::CliArguments;
+use futures::{
+    sink::Sink,
+    task::{Context, Poll},
+    StreamExt,
+};
+use rogcat::{
+    pipeline::RecordStream,
+    record::{Column, Level, Record},
+    Error, LogSink,
+};
+use std::{
+    collections::BTreeMap,
+    io::{self, ErrorKind, Write},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tabled::{builder::Builder, settings::Style};
+
+/// Handle to the number of records passed to a count sink
+#[derive(Clone, Default)]
+pub struct Matches(Arc<AtomicUsize>);
+
+impl Matches {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+struct CountSink {
+    /// Column or extracted field the records are grouped by
+    by: Option<String>,
+    groups: BTreeMap<String, usize>,
+    /// Print nothing
+    quiet: bool,
+    matches: Matches,
+}
+
+/// Create a sink that prints the number of records on close. The records
+/// are grouped by `args.count_by` if passed.
+pub fn try_from(args: &CliArguments) -> (LogSink, Matches) {
+    let matches = Matches::default();
+    let sink = CountSink {
+        by: args.count_by.clone(),
+        groups: BTreeMap::new(),
+        quiet: args.quiet,
+        matches: matches.clone(),
+    };
+    (Box::new(sink), matches)
+}
+
+/// Count the records of `stream` at or above `level`
+pub fn at_level(stream: RecordStream, level: Level) -> (RecordStream, Matches) {
+    let matches = Matches::default();
+    let counter = matches.clone();
+    let stream = stream.inspect(move |record| {
+        if !record.diagnostic && record.notice.is_none() && record.level >= level {
+            counter.0.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    (Box::pin(stream), matches)
+}
+
+/// True if `key` isn't a column and needs extracted fields
+pub fn is_field(key: &str) -> bool {
+    Column::from_name(key).is_none()
+}
+
+/// Value of the column or field `key` of `record`
+fn group(record: &Record, key: &str) -> String {
+    match Column::from_name(key) {
+        Some(Column::Time) => record.time.clone().unwrap_or_default(),
+        Some(Column::Message) => record.message.clone(),
+        Some(Column::Level) => record.level.to_string(),
+        Some(Column::Tag) => record.tag.clone(),
+        Some(Column::Process) => record.process.clone(),
+        Some(Column::Thread) => record.thread.clone(),
+        Some(Column::Raw) => record.raw.clone(),
+        Some(Column::Uid) => record.uid.clone().unwrap_or_default(),
+        Some(Column::Device) => record.device.clone().unwrap_or_default(),
+        Some(Column::ThreadName) => record.thread_name.clone().unwrap_or_default(),
+        Some(Column::Buffer) => record.buffer.clone().unwrap_or_default(),
+        Some(Column::Fields) | None => record.fields.get(key).cloned().unwrap_or_default(),
+    }
+}
+
+impl CountSink {
+    /// Counts per group, most frequent first
+    fn table(&self, header: &str) -> String {
+        let mut groups = self.groups.iter().collect::<Vec<_>>();
+        groups.sort_by(|a, b| b.1.cmp(a.1));
+
+        let mut builder = Builder::default();
+        builder.push_record([header.to_uppercase(), "RECORDS".to_owned()]);
+        for (group, n) in groups {
+            builder.push_record([group.to_owned(), n.to_string()]);
+        }
+        builder.build().with(Style::modern_rounded()).to_string()
+    }
+}
+
+impl Sink<Record> for CountSink {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, record: Record) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        // Notices of logcat bypass the filters and match nothing
+        if record.notice.is_some() {
+            return Ok(());
+        }
+        this.matches.0.fetch_add(1, Ordering::Relaxed);
+        if let Some(by) = this.by.as_deref() {
+            *this.groups.entry(group(&record, by)).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.quiet {
+            return Poll::Ready(Ok(()));
+        }
+        let summary = match self.by.as_deref() {
+            Some(by) => self.table(by),
+            None => self.matches.count().to_string(),
+        };
+        // A reader like head that exits early is not a failure
+        let result = match writeln!(io::stdout().lock(), "{summary}") {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+            result => result.map_err(|e| Error::io("Failed to write", e)),
+        };
+        Poll::Ready(result)
+    }
+}
+
+#[test]
+fn count_by_group() {
+    let mut sink = CountSink {
+        by: Some("tag".to_owned()),
+        groups: BTreeMap::new(),
+        quiet: false,
+        matches: Matches::default(),
+    };
+    for tag in ["a", "b", "a", "c", "a", "b"] {
+        Pin::new(&mut sink)
+            .start_send(Record {
+                tag: tag.to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    assert_eq!(sink.matches.count(), 6);
+    assert_eq!(sink.groups["a"], 3);
+
+    let table = sink.table("tag");
+    assert!(table.contains("TAG"));
+    // Most frequent first, ties by name
+    let position = |tag: &str| table.find(&format!("│ {tag} ")).unwrap();
+    assert!(position("a") < position("b"));
+    assert!(position("b") < position("c"));
+
+    let record = Record {
+        fields: [("state".to_owned(), "on".to_owned())].into(),
+        ..Default::default()
+    };
+    assert_eq!(group(&record, "state"), "on");
+    assert_eq!(group(&record, "level"), "-");
+}
@@ -0,0 +1,469 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Detection of Java crashes, native crashes and ANRs. Each incident is the
+//! record with the signature and the following records of the same pid and
+//! tag until the trace ends.
+
+use crate::cli::CrashesOpts;
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use rogcat::{
+    filter::Timestamp,
+    pipeline::{Pipeline, Source},
+    reader::{Children, LogcatOptions},
+    record::Record,
+};
+use std::{fs, path::Path};
+use tabled::{builder::Builder, settings::Style};
+
+/// Milliseconds without a record of the incident after which its trace ends
+const TRACE_TIMEOUT: i64 = 2_000;
+
+/// Maximum number of records of an incident
+const MAX_RECORDS: usize = 1_000;
+
+/// Fields of the header of an ANR report like "Reason: ..."
+const ANR_FIELDS: [&str; 8] = [
+    "PID:", "Reason:", "Parent:", "ErrorId:", "Frozen:", "Load:", "Subject:", "Dropped:",
+];
+
+/// Native crash header of the crash buffer
+const NATIVE_HEADER: &str = "*** *** *** *** *** *** *** *** *** *** *** *** *** *** *** ***";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Java,
+    Native,
+    Anr,
+}
+
+impl Kind {
+    /// Kind of the incident started by `record`
+    fn detect(record: &Record) -> Option<Kind> {
+        let message = record.message.trim();
+        if message.starts_with("FATAL EXCEPTION") {
+            Some(Kind::Java)
+        } else if message == NATIVE_HEADER {
+            Some(Kind::Native)
+        } else if message.starts_with("ANR in ") {
+            Some(Kind::Anr)
+        } else {
+            None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Java => "java",
+            Kind::Native => "native",
+            Kind::Anr => "anr",
+        }
+    }
+}
+
+/// Crash with its records
+#[derive(Debug)]
+pub(crate) struct Incident {
+    kind: Kind,
+    records: Vec<Record>,
+    /// Name of the crashed process
+    process: Option<String>,
+    /// Exception, signal or ANR reason
+    cause: Option<String>,
+    top_frame: Option<String>,
+    /// Set once the backtrace of a native crash starts
+    backtrace: bool,
+}
+
+impl Incident {
+    fn new(kind: Kind, record: Record) -> Incident {
+        let process = match kind {
+            Kind::Anr => record
+                .message
+                .trim()
+                .strip_prefix("ANR in ")
+                .and_then(|rest| rest.split_whitespace().next())
+                .map(ToOwned::to_owned),
+            _ => None,
+        };
+        Incident {
+            kind,
+            records: vec![record],
+            process,
+            cause: None,
+            top_frame: None,
+            backtrace: false,
+        }
+    }
+
+    fn signature(&self) -> &Record {
+        &self.records[0]
+    }
+
+    fn last(&self) -> &Record {
+        &self.records[self.records.len() - 1]
+    }
+
+    /// True if `record` continues the trace
+    fn continues(&self, record: &Record) -> bool {
+        let signature = self.signature();
+        record.process == signature.process && record.tag == signature.tag
+    }
+
+    /// Add the next line of the trace. Returns false if `record` ends the
+    /// trace and is not part of it.
+    fn push(&mut self, record: Record) -> bool {
+        let message = record.message.trim();
+        if message.is_empty() || self.records.len() >= MAX_RECORDS {
+            return false;
+        }
+        match self.kind {
+            Kind::Java => {
+                if let Some(process) = message.strip_prefix("Process: ") {
+                    self.process = process.split(',').next().map(ToOwned::to_owned);
+                } else if let Some(frame) = message.strip_prefix("at ") {
+                    if self.top_frame.is_none() {
+                        self.top_frame = Some(frame.to_owned());
+                    }
+                } else if self.cause.is_none() {
+                    self.cause = Some(message.to_owned());
+                }
+            }
+            Kind::Native => {
+                let frame = message.starts_with('#');
+                if self.backtrace && !frame {
+                    return false;
+                }
+                if message.starts_with("backtrace:") {
+                    self.backtrace = true;
+                } else if frame && self.top_frame.is_none() {
+                    // "#00 pc 000000000001a2b3  /system/lib64/libc.so (abort+164)"
+                    let frame = message.split_whitespace().skip(3).collect::<Vec<_>>();
+                    self.top_frame = Some(frame.join(" "));
+                } else if message.starts_with("pid: ") {
+                    self.process = message
+                        .split_once(">>> ")
+                        .and_then(|(_, name)| name.split_once(" <<<"))
+                        .map(|(name, _)| name.to_owned());
+                } else if message.starts_with("signal ") && self.cause.is_none() {
+                    let signal = message.split(", ").next().unwrap_or(message);
+                    self.cause = Some(signal.to_owned());
+                }
+            }
+            Kind::Anr => {
+                if !anr_continues(message) {
+                    return false;
+                }
+                if let Some(reason) = message.strip_prefix("Reason: ") {
+                    self.cause = Some(format!("ANR: {reason}"));
+                }
+            }
+        }
+        self.records.push(record);
+        true
+    }
+
+    /// Name of a file holding the incident
+    fn filename(&self, index: usize) -> String {
+        let process = self.process.as_deref().unwrap_or("unknown").replace(
+            |c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '_',
+            "_",
+        );
+        format!("{index:03}-{}-{process}.log", self.kind.name())
+    }
+}
+
+/// True if `message` belongs to the header, the pressure stall information
+/// or the CPU usage of an ANR report. Other lines of the ActivityManager end
+/// the report.
+fn anr_continues(message: &str) -> bool {
+    ANR_FIELDS.iter().any(|field| message.starts_with(field))
+        || message.starts_with("-----")
+        || message.starts_with("some avg")
+        || message.starts_with("full avg")
+        || message.starts_with("CPU usage from")
+        // "45% 1000/system_server: 30% user + 15% kernel" or "+0% 4321/app"
+        || (message.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-')
+            && message.contains('%'))
+}
+
+/// Milliseconds between two records if both have a timestamp
+fn elapsed(from: &Record, to: &Record) -> Option<i64> {
+    let from = Timestamp::parse(from.time.as_deref()?)?;
+    let to = Timestamp::parse(to.time.as_deref()?)?;
+    Some(from.elapsed(to))
+}
+
+/// State machine that assembles incidents from a stream of records.
+/// Traces of different processes may interleave.
+#[derive(Default)]
+pub(crate) struct Detector {
+    open: Vec<Incident>,
+}
+
+impl Detector {
+    /// Process the next record and return the incidents it completes
+    pub(crate) fn push(&mut self, record: Record) -> Vec<Incident> {
+        let mut done = Vec::new();
+        let mut i = 0;
+        while i < self.open.len() {
+            if elapsed(self.open[i].last(), &record).is_some_and(|e| e > TRACE_TIMEOUT) {
+                done.push(self.open.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+
+        let kind = Kind::detect(&record);
+        let current = self
+            .open
+            .iter()
+            .position(|incident| incident.continues(&record));
+        match (current, kind) {
+            // A new signature of the same process ends the previous trace
+            (Some(i), Some(_)) => done.push(self.open.remove(i)),
+            (Some(i), None) => {
+                if !self.open[i].push(record) {
+                    done.push(self.open.remove(i));
+                }
+                return done;
+            }
+            (None, _) => {
+                // Other tags of the process end the trace, e.g. "Process:
+                // Sending signal" after a Java crash
+                if let Some(i) = self
+                    .open
+                    .iter()
+                    .position(|incident| incident.signature().process == record.process)
+                {
+                    done.push(self.open.remove(i));
+                }
+            }
+        }
+        if let Some(kind) = kind {
+            self.open.push(Incident::new(kind, record));
+        }
+        done
+    }
+
+    /// Incidents still open at the end of the stream
+    pub(crate) fn finish(&mut self) -> Vec<Incident> {
+        std::mem::take(&mut self.open)
+    }
+}
+
+/// Summary table with one row per incident
+fn table(incidents: &[Incident]) -> String {
+    let mut builder = Builder::default();
+    builder.push_record(["TIME", "PROCESS", "CAUSE", "TOP FRAME"]);
+    for incident in incidents {
+        let value = |value: Option<&str>| value.unwrap_or("-").to_owned();
+        builder.push_record([
+            value(incident.signature().time.as_deref()),
+            value(incident.process.as_deref()),
+            value(incident.cause.as_deref()),
+            value(incident.top_frame.as_deref()),
+        ]);
+    }
+    builder.build().with(Style::modern_rounded()).to_string()
+}
+
+/// Write the raw lines of `incident` to a file in `dir`
+fn write(dir: &Path, index: usize, incident: &Incident) -> Result<()> {
+    let file = dir.join(incident.filename(index));
+    let content = incident
+        .records
+        .iter()
+        .map(|record| format!("{}\n", record.raw))
+        .collect::<String>();
+    fs::write(&file, content).map_err(|e| anyhow!("Failed to write {}: {e}", file.display()))?;
+    println!("{}", file.display());
+    Ok(())
+}
+
+/// Scan the input files or adb logcat of `device` for crashes. A summary is
+/// printed at the end of the input or on ctrl-c unless the incidents are
+/// written to files.
+pub async fn crashes(opts: CrashesOpts, device: Option<&str>) -> Result<()> {
+    let source = if opts.input.is_empty() {
+        Source::Logcat(LogcatOptions {
+            devices: device.into_iter().map(ToOwned::to_owned).collect(),
+            buffers: ["main", "system", "crash"].map(ToOwned::to_owned).to_vec(),
+            dump: opts.dump,
+            ..Default::default()
+        })
+    } else {
+        Source::Files(opts.input)
+    };
+    if let Some(dir) = opts.output.as_deref() {
+        fs::create_dir_all(dir).map_err(|e| anyhow!("Failed to create {}: {e}", dir.display()))?;
+    }
+
+    let children = Children::default();
+    let mut records = Pipeline::new()
+        .source(source)
+        .children(children.clone())
+        .stream()
+        .await?
+        .take_until(Box::pin(tokio::signal::ctrl_c()));
+    let mut detector = Detector::default();
+    let mut incidents = Vec::new();
+    let handle = |done: Vec<Incident>, incidents: &mut Vec<Incident>| -> Result<()> {
+        for incident in done {
+            if let Some(dir) = opts.output.as_deref() {
+                write(dir, incidents.len() + 1, &incident)?;
+            }
+            incidents.push(incident);
+        }
+        Ok(())
+    };
+    while let Some(record) = records.next().await {
+        if !record.diagnostic {
+            handle(detector.push(record), &mut incidents)?;
+        }
+    }
+    children.terminate().await;
+    handle(detector.finish(), &mut incidents)?;
+
+    if opts.output.is_none() {
+        if incidents.is_empty() {
+            println!("No crashes found");
+        } else {
+            println!("{}", table(&incidents));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn detect(lines: &str) -> Vec<Incident> {
+    let mut parser = rogcat::parser::Parser::default();
+    let mut detector = Detector::default();
+    let mut incidents = lines
+        .lines()
+        .filter_map(|line| parser.parse(line))
+        .flat_map(|record| detector.push(record))
+        .collect::<Vec<_>>();
+    incidents.extend(detector.finish());
+    incidents
+}
+
+#[test]
+fn java_crash() {
+    let incidents = detect(include_str!("../tests/fixtures/crash-java.log"));
+    assert_eq!(incidents.len(), 1);
+    let incident = &incidents[0];
+    assert_eq!(incident.kind, Kind::Java);
+    assert_eq!(incident.records.len(), 7);
+    assert_eq!(incident.process.as_deref(), Some("com.example.app"));
+    assert_eq!(
+        incident.cause.as_deref(),
+        Some("java.lang.NullPointerException: Attempt to invoke virtual method on a null object reference")
+    );
+    assert_eq!(
+        incident.top_frame.as_deref(),
+        Some("com.example.app.MainActivity.onCreate(MainActivity.java:42)")
+    );
+    assert_eq!(incident.filename(1), "001-java-com.example.app.log");
+}
+
+#[test]
+fn native_crash() {
+    let incidents = detect(include_str!("../tests/fixtures/crash-native.log"));
+    assert_eq!(incidents.len(), 1);
+    let incident = &incidents[0];
+    assert_eq!(incident.kind, Kind::Native);
+    assert_eq!(incident.records.len(), 8);
+    assert_eq!(incident.process.as_deref(), Some("com.example.game"));
+    assert_eq!(incident.cause.as_deref(), Some("signal 11 (SIGSEGV)"));
+    assert_eq!(
+        incident.top_frame.as_deref(),
+        Some("/data/app/lib/arm64/libgame.so (Renderer::draw()+24)")
+    );
+}
+
+#[test]
+fn anr() {
+    let incidents = detect(include_str!("../tests/fixtures/crash-anr.log"));
+    assert_eq!(incidents.len(), 1);
+    let incident = &incidents[0];
+    assert_eq!(incident.kind, Kind::Anr);
+    // The report ends with the first line after the load
+    assert_eq!(incident.records.len(), 4);
+    assert_eq!(incident.process.as_deref(), Some("com.example.app"));
+    assert_eq!(
+        incident.cause.as_deref(),
+        Some("ANR: Input dispatching timed out (Waiting to send non-key event)")
+    );
+    assert_eq!(incident.top_frame, None);
+
+    let table = table(&incidents);
+    assert!(table.contains("01-10 23:04:00.000"), "{table}");
+    assert!(table.contains("com.example.app"), "{table}");
+}
+
+#[test]
+fn anr_ends_with_report() {
+    let report = "\
+01-10 23:04:00.000  1000  1200 E ActivityManager: ANR in com.example.app
+01-10 23:04:00.000  1000  1200 E ActivityManager: Reason: Broadcast of Intent
+01-10 23:04:00.000  1000  1200 E ActivityManager: ----- Output from /proc/pressure/memory -----
+01-10 23:04:00.000  1000  1200 E ActivityManager: some avg10=0.00 avg60=0.00 avg300=0.00 total=0
+01-10 23:04:00.000  1000  1200 E ActivityManager: ----- End output from /proc/pressure/memory -----
+01-10 23:04:00.000  1000  1200 E ActivityManager: CPU usage from 0ms to 5000ms later:
+01-10 23:04:00.000  1000  1200 E ActivityManager:   45% 1000/system_server: 30% user + 15% kernel
+01-10 23:04:00.000  1000  1200 E ActivityManager: 52% TOTAL: 35% user + 17% kernel
+";
+    let busy = (0..5_000)
+        .map(|i| format!("01-10 23:04:00.100  1000  1200 E ActivityManager: Start proc {i}\n"))
+        .collect::<String>();
+    let incidents = detect(&format!("{report}{busy}"));
+    assert_eq!(incidents.len(), 1);
+    assert_eq!(incidents[0].records.len(), 8);
+
+    // A report without an end is cut
+    let cpu = (0..5_000)
+        .map(|i| format!("01-10 23:04:00.100  1000  1200 E ActivityManager: 1% {i}/app: 1% user\n"))
+        .collect::<String>();
+    let incidents = detect(&format!("{report}{cpu}"));
+    assert_eq!(incidents[0].records.len(), MAX_RECORDS);
+}
+
+#[test]
+fn consecutive_crashes() {
+    let incidents = detect(
+        "\
+01-10 23:02:59.028  4321  4321 E AndroidRuntime: FATAL EXCEPTION: main
+01-10 23:02:59.028  4321  4321 E AndroidRuntime: java.lang.RuntimeException
+01-10 23:02:59.028  4321  4322 E AndroidRuntime: FATAL EXCEPTION: worker
+01-10 23:02:59.028  4321  4322 E AndroidRuntime: java.lang.OutOfMemoryError
+01-10 23:02:59.028  4321  4322 E AndroidRuntime:
+",
+    );
+    let causes = incidents
+        .iter()
+        .map(|incident| incident.cause.as_deref().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        causes,
+        ["java.lang.RuntimeException", "java.lang.OutOfMemoryError"]
+    );
+}
@@ -0,0 +1,197 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use futures::{
+    sink::Sink,
+    task::{Context, Poll},
+    Future,
+};
+use rogcat::{
+    record::{Format, Record},
+    sink::Queued,
+    Error, LogSink,
+};
+use std::{pin::Pin, time::Duration};
+use tokio::time::{sleep, Instant, Sleep};
+
+/// Sink that collapses consecutive records with identical tag, level and
+/// message into the first record and a summary of the repeats
+struct DedupeSink {
+    sink: Queued<Pin<LogSink>>,
+    /// Last record passed on
+    last: Option<Record>,
+    /// Last swallowed repeat of `last` and the number of repeats
+    repeats: Option<(Record, usize)>,
+    /// Maximum time a repeat count is held back
+    timeout: Duration,
+    deadline: Pin<Box<Sleep>>,
+}
+
+/// Wrap `sink` and collapse repeated records. Pending repeat counts are
+/// emitted at the latest after `timeout`.
+pub fn wrap(sink: Pin<LogSink>, timeout: Duration) -> Pin<LogSink> {
+    Box::pin(DedupeSink {
+        sink: Queued::new(sink),
+        last: None,
+        repeats: None,
+        timeout,
+        deadline: Box::pin(sleep(timeout)),
+    })
+}
+
+/// Records are repeats if tag, level and message match
+fn is_repeat(a: &Record, b: &Record) -> bool {
+    a.message == b.message && a.tag == b.tag && a.level == b.level
+}
+
+impl DedupeSink {
+    /// Queue the summary of the pending repeats. The summary carries the
+    /// timestamp of the last repeat.
+    fn summarize(&mut self) {
+        if let Some((last, n)) = self.repeats.take() {
+            let message = match n {
+                1 => "… repeated once".to_owned(),
+                n => format!("… repeated {n} times"),
+            };
+            let mut summary = Record { message, ..last };
+            summary.raw = Format::Logcat
+                .fmt_record(&summary)
+                .unwrap_or_else(|_| summary.message.clone());
+            self.sink.push(summary);
+        }
+    }
+}
+
+impl Sink<Record> for DedupeSink {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sink.poll_drain(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Record) -> Result<(), Self::Error> {
+        if self
+            .last
+            .as_ref()
+            .is_some_and(|last| is_repeat(last, &item))
+        {
+            let n = match self.repeats.take() {
+                Some((_, n)) => n + 1,
+                None => {
+                    let deadline = Instant::now() + self.timeout;
+                    self.deadline.as_mut().reset(deadline);
+                    1
+                }
+            };
+            self.repeats = Some((item, n));
+            if self.deadline.is_elapsed() {
+                self.summarize();
+            }
+        } else {
+            self.summarize();
+            self.last = Some(item.clone());
+            self.sink.push(item);
+        }
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Flush is called whenever the source is idle. Register the deadline
+        // to get polled again when a pending repeat count is due.
+        if self.repeats.is_some() && self.deadline.as_mut().poll(cx).is_ready() {
+            self.summarize();
+        }
+        self.sink.poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.summarize();
+        self.sink.poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+async fn dedupe(records: Vec<Record>) -> Vec<Record> {
+    use futures::{channel::mpsc, stream, SinkExt, StreamExt};
+
+    let (tx, rx) = mpsc::unbounded();
+    let sink = wrap(
+        Box::pin(tx.sink_map_err(|e| Error::Invalid(e.to_string()))),
+        Duration::from_secs(1),
+    );
+    stream::iter(records).map(Ok).forward(sink).await.unwrap();
+    rx.collect().await
+}
+
+#[tokio::test]
+async fn dedupe_run() {
+    let record = |time: usize| Record {
+        time: Some(format!("01-10 23:02:59.{time:03}")),
+        message: "chatty".to_owned(),
+        tag: "Tag".to_owned(),
+        ..Default::default()
+    };
+    let output = dedupe((0..1000).map(record).collect()).await;
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[0], record(0));
+    assert_eq!(output[1].time, record(999).time);
+    assert_eq!(output[1].message, "… repeated 999 times");
+    assert!(output[1].raw.ends_with("Tag: … repeated 999 times"));
+}
+
+#[tokio::test]
+async fn dedupe_alternating() {
+    let record = |message: &str| Record {
+        message: message.to_owned(),
+        ..Default::default()
+    };
+    let input = ["a", "b", "a", "b", "b", "a"].map(record).to_vec();
+    let output = dedupe(input).await;
+    let messages = output
+        .iter()
+        .map(|r| r.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(messages, ["a", "b", "a", "b", "… repeated once", "a"]);
+}
+
+#[tokio::test]
+async fn dedupe_timeout() {
+    use futures::{channel::mpsc, SinkExt, StreamExt};
+
+    let (tx, mut rx) = mpsc::unbounded();
+    let mut sink = wrap(
+        Box::pin(tx.sink_map_err(|e| Error::Invalid(e.to_string()))),
+        Duration::from_millis(10),
+    );
+    let record = Record {
+        message: "chatty".to_owned(),
+        ..Default::default()
+    };
+    sink.send(record.clone()).await.unwrap();
+    sink.send(record.clone()).await.unwrap();
+    assert_eq!(rx.next().await.unwrap(), record);
+
+    // The repeat count is emitted on flush once the timeout elapsed
+    sink.flush().await.unwrap();
+    assert!(rx.try_recv().is_err());
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    sink.flush().await.unwrap();
+    assert_eq!(rx.next().await.unwrap().message, "… repeated once");
+}
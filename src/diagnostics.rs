@@ -0,0 +1,187 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Sink that writes the diagnostics of rogcat like a failed spawn of adb to
+//! stderr instead of the output. On a terminal they're dimmed and prefixed
+//! with "[rogcat]".
+
+use crate::{cli::CliArguments, terminal};
+use futures::{
+    ready,
+    sink::Sink,
+    task::{Context, Poll},
+};
+use rogcat::{
+    record::{Level, Record},
+    Error, LogSink,
+};
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use termcolor::{ColorSpec, StandardStream, WriteColor};
+
+/// Set when a fatal diagnostic ended the source or one of its files
+#[derive(Clone, Default)]
+pub struct Failed(Arc<AtomicBool>);
+
+impl Failed {
+    pub fn failed(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Writes messages of rogcat to stderr
+pub struct Reporter(StandardStream);
+
+impl Reporter {
+    /// Colors follow --color and NO_COLOR
+    pub fn new(args: &CliArguments) -> Reporter {
+        let choice = terminal::choose_color(
+            args.color.as_deref(),
+            terminal::no_color(),
+            None,
+            atty::is(atty::Stream::Stderr),
+        );
+        Reporter(StandardStream::stderr(choice))
+    }
+
+    /// Write `message` dimmed and prefixed with "[rogcat]"
+    pub fn report(&mut self, message: &str) -> io::Result<()> {
+        let stderr = &mut self.0;
+        stderr.set_color(ColorSpec::new().set_dimmed(true))?;
+        write!(stderr, "[rogcat] {message}")?;
+        stderr.reset()?;
+        writeln!(stderr)
+    }
+}
+
+struct DiagnosticSink {
+    sink: Pin<LogSink>,
+    stderr: Reporter,
+    /// Diagnostics waiting for the records before them to be flushed
+    pending: VecDeque<Record>,
+    failed: Failed,
+}
+
+/// Wrap `sink` and write diagnostics to stderr
+pub fn wrap(sink: Pin<LogSink>, args: &CliArguments) -> (Pin<LogSink>, Failed) {
+    let failed = Failed::default();
+    let sink = Box::pin(DiagnosticSink {
+        sink,
+        stderr: Reporter::new(args),
+        pending: VecDeque::new(),
+        failed: failed.clone(),
+    });
+    (sink, failed)
+}
+
+impl DiagnosticSink {
+    /// Flush the records passed before the pending diagnostics to keep the
+    /// order on a terminal and write the diagnostics
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if self.pending.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(self.sink.as_mut().poll_flush(cx))?;
+        while let Some(record) = self.pending.pop_front() {
+            self.stderr
+                .report(&record.message)
+                .map_err(|e| Error::io("Failed to write diagnostic", e))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Sink<Record> for DiagnosticSink {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        this.sink.as_mut().poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, record: Record) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        if !record.diagnostic {
+            return this.sink.as_mut().start_send(record);
+        }
+        if record.level == Level::Fatal {
+            this.failed.0.store(true, Ordering::Relaxed);
+        }
+        this.pending.push_back(record);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        this.sink.as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        this.sink.as_mut().poll_close(cx)
+    }
+}
+
+// UnboundedReceiver::try_next is deprecated in newer releases of futures
+#[allow(deprecated)]
+#[tokio::test]
+async fn diagnostics_bypass_sink() {
+    use clap::Parser;
+    use futures::{channel::mpsc, SinkExt};
+
+    let (tx, mut rx) = mpsc::unbounded();
+    let sink: Pin<LogSink> = Box::pin(tx.sink_map_err(|e| Error::Invalid(e.to_string())));
+    let args = CliArguments::parse_from(["rogcat", "--color", "never"]);
+    let (mut sink, failed) = wrap(sink, &args);
+
+    let record = Record {
+        message: "message".to_owned(),
+        ..Default::default()
+    };
+    sink.send(record.clone()).await.unwrap();
+    sink.send(Record::from_diagnostic(
+        Level::Warn,
+        "- waiting for device -".to_owned(),
+    ))
+    .await
+    .unwrap();
+    assert!(!failed.failed());
+    sink.send(Record::from_diagnostic(
+        Level::Fatal,
+        "Failed to spawn".to_owned(),
+    ))
+    .await
+    .unwrap();
+    assert!(failed.failed());
+    sink.close().await.unwrap();
+
+    assert_eq!(rx.try_next().unwrap(), Some(record));
+    assert_eq!(rx.try_next().unwrap(), None);
+}
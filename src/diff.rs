@@ -0,0 +1,548 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Diff of the records of two files. Records are compared by a key of
+//! selected fields, so timestamps and pids don't count. Only hashes of the
+//! keys are kept in memory. The records are read a second time to print
+//! the changes.
+
+use crate::{
+    cli::DiffOpts,
+    terminal::{choose_color, no_color},
+    utils::{config_get, same_file},
+};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use futures::{Stream, StreamExt};
+use regex::Regex;
+use rogcat::{
+    pipeline::{Pipeline, Source},
+    record::Record,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{self, BufWriter},
+    ops::Range,
+    path::Path,
+};
+use termcolor::{Ansi, Color, ColorChoice, ColorSpec, NoColor, StandardStream, WriteColor};
+
+/// Maximum number of cells of the table used to diff a region without
+/// unique records. Larger regions are shown as removed and added.
+const WINDOW: usize = 1 << 22;
+
+/// Fields of the records that are compared
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(crate) enum Key {
+    Level,
+    Tag,
+    Process,
+    Thread,
+    Message,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Range of operations with the records of the old and new file they cover
+#[derive(Debug, PartialEq)]
+struct Hunk {
+    ops: Range<usize>,
+    old: Range<usize>,
+    new: Range<usize>,
+}
+
+/// Hashes the key of records
+struct Keys {
+    keys: Vec<Key>,
+    /// Numbers in messages are replaced if set
+    numbers: Option<Regex>,
+}
+
+impl Keys {
+    fn hash(&self, record: &Record) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for key in &self.keys {
+            match key {
+                Key::Level => record.level.hash(&mut hasher),
+                Key::Tag => record.tag.hash(&mut hasher),
+                Key::Process => record.process.hash(&mut hasher),
+                Key::Thread => record.thread.hash(&mut hasher),
+                Key::Message => match &self.numbers {
+                    Some(numbers) => numbers.replace_all(&record.message, "#").hash(&mut hasher),
+                    None => record.message.hash(&mut hasher),
+                },
+            }
+        }
+        hasher.finish()
+    }
+}
+
+/// Edit script turning `old` into `new`
+fn diff(old: &[u64], new: &[u64]) -> Vec<Op> {
+    let mut ops = Vec::with_capacity(old.len().max(new.len()));
+    diff_region(old, new, &mut ops);
+    ops
+}
+
+/// Diff a region. Common records at the start and end are equal. Records
+/// that are unique in both regions and in the same order split the region
+/// into smaller ones.
+fn diff_region(old: &[u64], new: &[u64], ops: &mut Vec<Op>) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+    push_n(ops, Op::Equal, prefix);
+
+    if old.is_empty() || new.is_empty() {
+        push_n(ops, Op::Delete, old.len());
+        push_n(ops, Op::Insert, new.len());
+    } else {
+        let anchors = anchors(old, new);
+        if anchors.is_empty() {
+            lcs(old, new, ops);
+        } else {
+            let (mut i, mut j) = (0, 0);
+            for (a, b) in anchors {
+                diff_region(&old[i..a], &new[j..b], ops);
+                ops.push(Op::Equal);
+                (i, j) = (a + 1, b + 1);
+            }
+            diff_region(&old[i..], &new[j..], ops);
+        }
+    }
+    push_n(ops, Op::Equal, suffix);
+}
+
+/// Positions of the records that occur once in `old` and `new` and keep
+/// their order. This is the longest increasing subsequence of the
+/// positions in `new` in the order of `old`.
+fn anchors(old: &[u64], new: &[u64]) -> Vec<(usize, usize)> {
+    // Occurrences in old and new and the position in old
+    let mut counts = HashMap::<u64, (usize, usize, usize)>::new();
+    for (i, key) in old.iter().enumerate() {
+        let entry = counts.entry(*key).or_insert((0, 0, 0));
+        entry.0 += 1;
+        entry.2 = i;
+    }
+    for key in new {
+        if let Some(entry) = counts.get_mut(key) {
+            entry.1 += 1;
+        }
+    }
+    let mut pairs = new
+        .iter()
+        .enumerate()
+        .filter_map(|(j, key)| match counts.get(key) {
+            Some((1, 1, i)) => Some((*i, j)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    pairs.sort_unstable();
+
+    // Patience sorting with back references
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; pairs.len()];
+    for (n, (_, j)) in pairs.iter().enumerate() {
+        let pile = tails.partition_point(|&t| pairs[t].1 < *j);
+        if pile > 0 {
+            previous[n] = Some(tails[pile - 1]);
+        }
+        if pile == tails.len() {
+            tails.push(n);
+        } else {
+            tails[pile] = n;
+        }
+    }
+    let mut anchors = Vec::with_capacity(tails.len());
+    let mut next = tails.last().copied();
+    while let Some(n) = next {
+        anchors.push(pairs[n]);
+        next = previous[n];
+    }
+    anchors.reverse();
+    anchors
+}
+
+/// Longest common subsequence of a region without unique records
+fn lcs(old: &[u64], new: &[u64], ops: &mut Vec<Op>) {
+    let (n, m) = (old.len(), new.len());
+    if (n + 1).saturating_mul(m + 1) > WINDOW {
+        push_n(ops, Op::Delete, n);
+        push_n(ops, Op::Insert, m);
+        return;
+    }
+    // Length of the common subsequence of old[i..] and new[j..]
+    let width = m + 1;
+    let mut table = vec![0u32; (n + 1) * width];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[i * width + j] = if old[i] == new[j] {
+                table[(i + 1) * width + j + 1] + 1
+            } else {
+                table[(i + 1) * width + j].max(table[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            ops.push(Op::Equal);
+            i += 1;
+            j += 1;
+        } else if table[(i + 1) * width + j] >= table[i * width + j + 1] {
+            ops.push(Op::Delete);
+            i += 1;
+        } else {
+            ops.push(Op::Insert);
+            j += 1;
+        }
+    }
+    push_n(ops, Op::Delete, n - i);
+    push_n(ops, Op::Insert, m - j);
+}
+
+/// Append `n` times `op` to `ops`
+fn push_n(ops: &mut Vec<Op>, op: Op, n: usize) {
+    ops.resize(ops.len() + n, op);
+}
+
+/// Group the changes of `ops` with `context` equal records around them
+fn hunks(ops: &[Op], context: usize) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut old, mut new) = (0, 0);
+    // Positions of the operations in the old and new file
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    for op in ops {
+        positions.push((old, new));
+        match op {
+            Op::Equal => (old, new) = (old + 1, new + 1),
+            Op::Delete => old += 1,
+            Op::Insert => new += 1,
+        }
+    }
+    positions.push((old, new));
+
+    for (n, op) in ops.iter().enumerate() {
+        if *op == Op::Equal {
+            continue;
+        }
+        let start = n.saturating_sub(context);
+        let end = (n + 1 + context).min(ops.len());
+        match hunks.last_mut() {
+            Some(hunk) if hunk.ops.end >= start => hunk.ops.end = end,
+            _ => hunks.push(Hunk {
+                ops: start..end,
+                old: 0..0,
+                new: 0..0,
+            }),
+        }
+    }
+    for hunk in &mut hunks {
+        let (start, end) = (positions[hunk.ops.start], positions[hunk.ops.end]);
+        hunk.old = start.0..end.0;
+        hunk.new = start.1..end.1;
+    }
+    hunks
+}
+
+/// Records of `file`. Diagnostics are skipped.
+async fn records(file: &Path) -> Result<impl Stream<Item = Record>> {
+    let records = Pipeline::new()
+        .source(Source::Files(vec![file.to_owned()]))
+        .stream()
+        .await?;
+    Ok(records.filter(|record| futures::future::ready(!record.diagnostic)))
+}
+
+/// Range of records in the unified diff notation
+fn range(range: &Range<usize>) -> String {
+    match range.len() {
+        0 => format!("{},0", range.start),
+        1 => format!("{}", range.start + 1),
+        n => format!("{},{n}", range.start + 1),
+    }
+}
+
+fn print(
+    output: &mut dyn WriteColor,
+    color: Option<Color>,
+    prefix: &str,
+    line: &str,
+) -> io::Result<()> {
+    output.set_color(ColorSpec::new().set_fg(color))?;
+    write!(output, "{prefix}{line}")?;
+    output.reset()?;
+    writeln!(output)
+}
+
+/// Write the records of `hunks` with the changes of `ops`. The files are
+/// read again in parallel.
+async fn write(
+    opts: &DiffOpts,
+    ops: &[Op],
+    hunks: &[Hunk],
+    output: &mut dyn WriteColor,
+) -> Result<()> {
+    let mut old = Box::pin(records(&opts.old).await?);
+    let mut new = Box::pin(records(&opts.new).await?);
+    let mut header = ColorSpec::new();
+    header.set_bold(true);
+    output.set_color(&header)?;
+    writeln!(output, "--- {}", opts.old.display())?;
+    writeln!(output, "+++ {}", opts.new.display())?;
+    output.reset()?;
+
+    let mut hunks = hunks.iter().peekable();
+    for (n, op) in ops.iter().enumerate() {
+        let old = match op {
+            Op::Equal | Op::Delete => old.next().await,
+            Op::Insert => None,
+        };
+        let new = match op {
+            Op::Equal | Op::Insert => new.next().await,
+            Op::Delete => None,
+        };
+        let Some(hunk) = hunks.peek() else {
+            break;
+        };
+        if n < hunk.ops.start {
+            continue;
+        }
+        if n == hunk.ops.start {
+            let line = format!("@@ -{} +{} @@", range(&hunk.old), range(&hunk.new));
+            print(output, Some(Color::Cyan), "", &line)?;
+        }
+        let changed = "The files changed while reading";
+        match op {
+            Op::Equal => print(output, None, " ", &new.ok_or_else(|| anyhow!(changed))?.raw)?,
+            Op::Delete => print(
+                output,
+                Some(Color::Red),
+                "-",
+                &old.ok_or_else(|| anyhow!(changed))?.raw,
+            )?,
+            Op::Insert => print(
+                output,
+                Some(Color::Green),
+                "+",
+                &new.ok_or_else(|| anyhow!(changed))?.raw,
+            )?,
+        }
+        if n + 1 == hunk.ops.end {
+            hunks.next();
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Print the records that were removed and added from the old to the new
+/// file. Returns false if the files differ.
+pub async fn diff_files(opts: DiffOpts) -> Result<bool> {
+    // The files are read again while the diff is written
+    if let Some(output) = opts.output.as_deref() {
+        if same_file(output, &opts.old) || same_file(output, &opts.new) {
+            return Err(anyhow!(
+                "Cannot write the diff to its input {}",
+                output.display()
+            ));
+        }
+        if output.exists() && !opts.overwrite {
+            return Err(anyhow!(
+                "{} exists. Use overwrite flag to force!",
+                output.display()
+            ));
+        }
+    }
+    let keys = Keys {
+        keys: opts.key.clone(),
+        numbers: opts
+            .ignore_numbers
+            .then(|| Regex::new(r"\b0[xX][0-9a-fA-F]+\b|\d+").expect("Invalid regex")),
+    };
+    let old = records(&opts.old)
+        .await?
+        .map(|record| keys.hash(&record))
+        .collect::<Vec<_>>()
+        .await;
+    let new = records(&opts.new)
+        .await?
+        .map(|record| keys.hash(&record))
+        .collect::<Vec<_>>()
+        .await;
+    let ops = diff(&old, &new);
+    let hunks = hunks(&ops, opts.context);
+    if hunks.is_empty() {
+        return Ok(true);
+    }
+
+    let tty = opts.output.is_none() && atty::is(atty::Stream::Stdout);
+    let choice = choose_color(
+        opts.color.as_deref(),
+        no_color(),
+        config_get("terminal_color"),
+        tty,
+    );
+    match opts.output.as_deref() {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|e| anyhow!("Failed to create {}: {e}", path.display()))?;
+            let file = BufWriter::new(file);
+            if choice == ColorChoice::Always {
+                write(&opts, &ops, &hunks, &mut Ansi::new(file)).await?;
+            } else {
+                write(&opts, &ops, &hunks, &mut NoColor::new(file)).await?;
+            }
+        }
+        None => write(&opts, &ops, &hunks, &mut StandardStream::stdout(choice)).await?,
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+fn parse(lines: &str, keys: &Keys) -> Vec<u64> {
+    let mut parser = rogcat::parser::Parser::default();
+    lines
+        .lines()
+        .filter_map(|line| parser.parse(line))
+        .map(|record| keys.hash(&record))
+        .collect()
+}
+
+/// Messages of the removed and added records of the fixtures
+#[cfg(test)]
+fn changes(keys: Vec<Key>, ignore_numbers: bool) -> (Vec<String>, Vec<String>) {
+    let old = include_str!("../tests/fixtures/diff-old.log");
+    let new = include_str!("../tests/fixtures/diff-new.log");
+    let keys = Keys {
+        keys,
+        numbers: ignore_numbers.then(|| Regex::new(r"\b0[xX][0-9a-fA-F]+\b|\d+").unwrap()),
+    };
+    let ops = diff(&parse(old, &keys), &parse(new, &keys));
+    let (mut old, mut new) = (old.lines(), new.lines());
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    for op in ops {
+        match op {
+            Op::Equal => {
+                old.next();
+                new.next();
+            }
+            Op::Delete => removed.push(old.next().unwrap().split_once(": ").unwrap().1.to_owned()),
+            Op::Insert => added.push(new.next().unwrap().split_once(": ").unwrap().1.to_owned()),
+        }
+    }
+    (removed, added)
+}
+
+#[test]
+fn diff_fixtures() {
+    let (removed, added) = changes(vec![Key::Tag, Key::Level, Key::Message], false);
+    assert_eq!(
+        removed,
+        [
+            "Start proc 4321:com.example/u0a123",
+            "Connection failed: timeout",
+        ]
+    );
+    assert_eq!(
+        added,
+        [
+            "Start proc 4567:com.example/u0a123",
+            "Connected in 35ms",
+            "Sync done",
+        ]
+    );
+
+    // Pids in messages don't count if numbers are ignored
+    let (removed, added) = changes(vec![Key::Tag, Key::Level, Key::Message], true);
+    assert_eq!(removed, ["Connection failed: timeout"]);
+    assert_eq!(added, ["Connected in 35ms", "Sync done"]);
+
+    // Only the tags
+    let (removed, added) = changes(vec![Key::Tag], false);
+    assert!(removed.is_empty());
+    assert_eq!(added, ["Sync done"]);
+}
+
+#[test]
+fn diff_sequences() {
+    let ops = |old: &[u64], new: &[u64]| {
+        diff(old, new)
+            .into_iter()
+            .map(|op| match op {
+                Op::Equal => '=',
+                Op::Delete => '-',
+                Op::Insert => '+',
+            })
+            .collect::<String>()
+    };
+    assert_eq!(ops(&[], &[]), "");
+    assert_eq!(ops(&[1, 2, 3], &[1, 2, 3]), "===");
+    assert_eq!(ops(&[1, 2, 3], &[]), "---");
+    assert_eq!(ops(&[], &[1, 2]), "++");
+    assert_eq!(ops(&[1, 2, 3], &[1, 4, 3]), "=-+=");
+    // Repeated records without anchors
+    assert_eq!(ops(&[1, 1, 2, 2], &[2, 2, 1, 1]), "--==++");
+    // Moved unique records
+    assert_eq!(ops(&[1, 2, 3, 4], &[4, 1, 2, 3]), "+===-");
+}
+
+#[test]
+fn diff_hunks() {
+    use Op::*;
+    let ops = [
+        Equal, Equal, Equal, Equal, Delete, Insert, Equal, Equal, Equal, Equal, Equal, Equal,
+        Insert, Equal,
+    ];
+    assert_eq!(
+        hunks(&ops, 1),
+        [
+            Hunk {
+                ops: 3..7,
+                old: 3..6,
+                new: 3..6,
+            },
+            Hunk {
+                ops: 11..14,
+                old: 10..12,
+                new: 10..13,
+            },
+        ]
+    );
+    // Overlapping context joins the hunks
+    assert_eq!(hunks(&ops, 3).len(), 1);
+    assert!(hunks(&[Equal, Equal], 3).is_empty());
+    assert_eq!(range(&(3..6)), "4,3");
+    assert_eq!(range(&(3..4)), "4");
+    assert_eq!(range(&(3..3)), "3,0");
+}
@@ -0,0 +1,318 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Decoding of the events buffer. The values of an event like
+//! `am_proc_start: [0,22551,10201,com.termux,activity,...]` are named
+//! after the event tag definitions and stored in the record fields.
+
+use crate::{
+    adb::adb_command,
+    record::{format_field, Record},
+    Error,
+};
+use std::collections::HashMap;
+
+/// Definitions of common events in the format of /system/etc/event-log-tags
+const BUILTIN_TAGS: &str = "\
+30011 am_proc_died (User|1|5),(PID|1|5),(Process Name|3),(OomAdj|1|5),(ProcState|1|5)
+30014 am_proc_start (User|1|5),(PID|1|5),(UID|1|5),(Process Name|3),(Type|3),(Component|3)
+30017 am_low_memory (Num Processes|1|1)
+30023 am_kill (User|1|5),(PID|1|5),(Process Name|3),(OomAdj|1|5),(Reason|3)
+3000 boot_progress_start (time|2|3)
+3010 boot_progress_system_run (time|2|3)
+3020 boot_progress_preload_start (time|2|3)
+3030 boot_progress_preload_end (time|2|3)
+3040 boot_progress_ams_ready (time|2|3)
+3050 boot_progress_enable_screen (time|2|3)
+3060 boot_progress_pms_start (time|2|3)
+3070 boot_progress_pms_system_scan_start (time|2|3)
+3080 boot_progress_pms_data_scan_start (time|2|3)
+3090 boot_progress_pms_scan_end (time|2|3)
+3100 boot_progress_pms_ready (time|2|3)
+36001 sysui_view_visibility (category|1|5),(visible|1|6)
+36002 sysui_action (category|1|5),(pkg|3)
+36004 sysui_status_bar_state (state|1),(keyguardShowing|1),(keyguardOccluded|1),(bouncerShowing|1),(secure|1),(currentlyInsecure|1)
+524290 sysui_count (name|3),(increment|1)
+524291 sysui_histogram (name|3),(bucket|1)
+524292 sysui_multi_action (content|4)
+";
+
+/// Location of the event tag definitions on the device
+const DEVICE_TAGS: &str = "/system/etc/event-log-tags";
+
+/// Type of an event value
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ValueType {
+    Int,
+    Float,
+    String,
+    /// Takes all remaining values
+    List,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Column {
+    name: String,
+    value_type: ValueType,
+}
+
+/// Event tag definitions by tag
+#[derive(Clone, Debug)]
+pub struct EventTags(HashMap<String, Vec<Column>>);
+
+impl Default for EventTags {
+    /// Builtin definitions of common events
+    fn default() -> Self {
+        let mut tags = EventTags(HashMap::new());
+        tags.extend(BUILTIN_TAGS);
+        tags
+    }
+}
+
+/// Name of a column in snake case, e.g. "Process Name" or "OomAdj" to
+/// "process_name" and "oom_adj"
+fn column_name(name: &str) -> String {
+    let mut result = String::new();
+    let mut previous: Option<char> = None;
+    for c in name.trim().chars() {
+        if c.is_whitespace() || c == '-' {
+            if !result.ends_with('_') {
+                result.push('_');
+            }
+        } else {
+            if c.is_uppercase() && previous.is_some_and(char::is_lowercase) {
+                result.push('_');
+            }
+            result.extend(c.to_lowercase());
+        }
+        previous = Some(c);
+    }
+    result
+}
+
+/// Parse a column like "(Process Name|3)" or "(PID|1|5)"
+fn column(column: &str) -> Option<Column> {
+    let column = column.trim().trim_start_matches('(').trim_end_matches(')');
+    let mut parts = column.split('|');
+    let name = column_name(parts.next()?);
+    let value_type = match parts.next().map(str::trim) {
+        Some("1") | Some("2") => ValueType::Int,
+        Some("4") => ValueType::List,
+        Some("5") => ValueType::Float,
+        _ => ValueType::String,
+    };
+    (!name.is_empty()).then_some(Column { name, value_type })
+}
+
+/// Comma separated values of an events buffer message like "[0,1234,10050]"
+pub(crate) fn values(message: &str) -> Vec<&str> {
+    let message = message.trim();
+    let message = message.strip_prefix('[').unwrap_or(message);
+    let message = message.strip_suffix(']').unwrap_or(message);
+    message.split(',').map(str::trim).collect()
+}
+
+/// Name the `values` after `columns`. Older Android versions omit trailing
+/// columns. Values without column are named after their position.
+fn name_values(columns: &[Column], values: &[&str]) -> Option<Vec<(String, String)>> {
+    let mut named = Vec::new();
+    for (i, value) in values.iter().enumerate() {
+        let Some(column) = columns.get(i) else {
+            named.push((format!("value{i}"), value.to_string()));
+            continue;
+        };
+        let valid = match column.value_type {
+            ValueType::Int => value.parse::<i64>().is_ok(),
+            ValueType::Float => value.parse::<f64>().is_ok(),
+            ValueType::String => !value.is_empty(),
+            ValueType::List => {
+                named.push((column.name.clone(), values[i..].join(",")));
+                break;
+            }
+        };
+        if !valid {
+            return None;
+        }
+        named.push((column.name.clone(), value.to_string()));
+    }
+    Some(named)
+}
+
+impl EventTags {
+    /// Add the definitions of the event-log-tags file `content`. Existing
+    /// definitions of a tag are replaced.
+    pub fn extend(&mut self, content: &str) {
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut split = line.splitn(3, char::is_whitespace);
+            let (Some(_number), Some(tag), Some(columns)) =
+                (split.next(), split.next(), split.next())
+            else {
+                continue;
+            };
+            let columns = columns
+                .trim()
+                .split("),(")
+                .map(column)
+                .collect::<Option<Vec<_>>>();
+            if let Some(columns) = columns {
+                self.0.insert(tag.to_owned(), columns);
+            }
+        }
+    }
+
+    /// Add the definitions of /system/etc/event-log-tags of `device`
+    pub async fn extend_from_device(&mut self, device: Option<&str>) -> Result<(), Error> {
+        let output = adb_command(device)?
+            .arg("shell")
+            .arg("cat")
+            .arg(DEVICE_TAGS)
+            .output()
+            .await
+            .map_err(|e| Error::io(format!("Failed to read {DEVICE_TAGS}"), e))?;
+        self.extend(&String::from_utf8_lossy(&output.stdout));
+        Ok(())
+    }
+
+    /// Named values of the event `tag` with `message`. The user column that
+    /// was added for multi user support is optional.
+    pub fn decode(&self, tag: &str, message: &str) -> Option<Vec<(String, String)>> {
+        let columns = self.0.get(tag)?;
+        let values = values(message);
+        if values.iter().all(|v| v.is_empty()) {
+            return None;
+        }
+        name_values(columns, &values).or_else(|| match columns.split_first() {
+            Some((first, columns)) if first.name == "user" => name_values(columns, &values),
+            _ => None,
+        })
+    }
+
+    /// Store the named values of an event record in the fields and rewrite
+    /// the message to key=value pairs. Returns false if `record` isn't a
+    /// known event.
+    pub fn decode_record(&self, record: &mut Record) -> bool {
+        let Some(values) = self.decode(&record.tag, &record.message) else {
+            return false;
+        };
+        record.message = values
+            .iter()
+            .map(|(key, value)| format_field(key, value))
+            .collect::<Vec<_>>()
+            .join(" ");
+        record.fields.extend(values);
+        true
+    }
+}
+
+#[test]
+fn column_names() {
+    assert_eq!(column_name("Process Name"), "process_name");
+    assert_eq!(column_name("OomAdj"), "oom_adj");
+    assert_eq!(column_name("PID"), "pid");
+    assert_eq!(column_name("keyguardShowing"), "keyguard_showing");
+}
+
+#[test]
+fn decode_events() {
+    let tags = EventTags::default();
+    let decode = |tag: &str, message: &str| {
+        let mut record = Record {
+            tag: tag.to_owned(),
+            message: message.to_owned(),
+            ..Default::default()
+        };
+        tags.decode_record(&mut record).then_some(record)
+    };
+
+    let record = decode(
+        "am_proc_start",
+        "[0,22551,10201,com.termux,pre-top-activity,{com.termux/com.termux.app.TermuxActivity}]",
+    )
+    .unwrap();
+    assert_eq!(record.fields["pid"], "22551");
+    assert_eq!(record.fields["process_name"], "com.termux");
+    assert_eq!(
+        record.message,
+        "user=0 pid=22551 uid=10201 process_name=com.termux type=pre-top-activity \
+         component={com.termux/com.termux.app.TermuxActivity}"
+    );
+    // Without user
+    let record = decode(
+        "am_proc_start",
+        "[1234,10050,com.foo,activity,com.foo/.Main]",
+    )
+    .unwrap();
+    assert_eq!(record.fields["pid"], "1234");
+    assert!(!record.fields.contains_key("user"));
+
+    let record = decode("am_proc_died", "[0,2468,com.android.chrome,900,19]").unwrap();
+    assert_eq!(record.fields["pid"], "2468");
+    assert_eq!(record.fields["oom_adj"], "900");
+    assert_eq!(record.fields["proc_state"], "19");
+
+    let record = decode("am_kill", "[0,5873,com.google.android.gm,906,empty #17]").unwrap();
+    assert_eq!(record.fields["process_name"], "com.google.android.gm");
+    assert_eq!(record.fields["reason"], "empty #17");
+    assert!(record.message.ends_with("reason=\"empty #17\""));
+
+    let record = decode("am_low_memory", "12").unwrap();
+    assert_eq!(record.message, "num_processes=12");
+
+    let record = decode("boot_progress_ams_ready", "10349").unwrap();
+    assert_eq!(record.fields["time"], "10349");
+
+    let record = decode("sysui_view_visibility", "[223,100]").unwrap();
+    assert_eq!(record.message, "category=223 visible=100");
+
+    let record = decode("sysui_multi_action", "[757,803,799,1,1,2]").unwrap();
+    assert_eq!(record.fields["content"], "757,803,799,1,1,2");
+
+    assert!(decode(
+        "am_proc_start",
+        "[0,pid,10050,com.foo,activity,com.foo/.Main]"
+    )
+    .is_none());
+    assert!(decode("am_kill", "[,,,]").is_none());
+    assert!(decode("ActivityManager", "[0,1234]").is_none());
+}
+
+#[test]
+fn device_tags() {
+    let mut tags = EventTags::default();
+    tags.extend(
+        "# comment\n\
+         42 answer (to life the universe etc|3)\n\
+         30014 am_proc_start (PID|1|5),(Process Name|3)\n\
+         1005 tag_def (tag|1),(name|3),(format|3)\n\
+         broken\n",
+    );
+    let values = tags.decode("answer", "42").unwrap();
+    assert_eq!(
+        values,
+        [("to_life_the_universe_etc".to_owned(), "42".to_owned())]
+    );
+    let values = tags.decode("am_proc_start", "[1,foo]").unwrap();
+    assert_eq!(values[1], ("process_name".to_owned(), "foo".to_owned()));
+    assert!(tags.decode("tag_def", "[1,a,b]").is_some());
+}
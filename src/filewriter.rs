@@ -18,23 +18,46 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::{cli::CliArguments, LogSink};
-use failure::{err_msg, format_err, Error};
+use crate::{capture::Windows, cli::CliArguments, props, terminal::Human, utils::config_get};
+use flate2::{write::GzEncoder, Compression};
 use futures::{
     sink::Sink,
     task::{Context, Poll},
+    Future,
 };
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 use regex::Regex;
-use rogcat::record::{Format, Record};
+use rogcat::{
+    profiles::Profile,
+    record::{Column, Format, LevelStyle, Record, SCHEMA},
+    session::{Props, Session},
+    template::Template,
+    Error, LogSink,
+};
 use std::{
-    fs::{DirBuilder, File},
-    io::Write,
+    collections::VecDeque,
+    fs::{self, DirBuilder, File},
+    io::{self, BufWriter, Write},
     path::{Path, PathBuf},
     pin::Pin,
     str::FromStr,
+    time::Duration,
 };
-use time::{macros::format_description, OffsetDateTime};
+use termcolor::Buffer;
+use time::{format_description::well_known::Rfc3339, macros::format_description, OffsetDateTime};
+use tokio::time::{sleep, Instant, Sleep};
+
+/// Default maximum time records are held in the file buffers
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// When buffered records are written to the file
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FlushMode {
+    /// After every record
+    Line,
+    /// At most every interval if the input is idle
+    Interval(Duration),
+}
 
 /// Filename format
 #[derive(Clone)]
@@ -44,66 +67,404 @@ enum FilenameFormat {
     Single(bool),
 }
 
+/// Output file. Files named *.gz are gzip compressed. The gzip trailer is
+/// written when the output is dropped without finishing it.
+enum Output {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+/// Settings of csv, json, logcat and raw files
+#[derive(Clone)]
+struct TextConfig {
+    format: Format,
+    /// Selected columns of csv and json files
+    columns: Option<Vec<Column>>,
+    level_style: LevelStyle,
+    /// Line layout replacing the format
+    template: Option<Template>,
+    /// Session metadata line written at the start of every file
+    session: Option<String>,
+    /// Device properties line written after the session metadata
+    props: Option<String>,
+}
+
 /// Textfile with format
 struct Textfile {
-    file: File,
-    format: Format,
+    file: Output,
+    config: TextConfig,
 }
 
-struct FileWriter<T> {
+/// File with a JSON array of records. The closing bracket is written when
+/// the file is flushed or dropped.
+struct JsonArray {
+    file: Output,
+    config: TextConfig,
+    empty: bool,
+    closed: bool,
+}
+
+/// Settings of human formatted files
+struct HumanConfig {
+    human: Human,
+    color: bool,
+    /// Device properties written as comments at the start of every file
+    props: Vec<String>,
+}
+
+/// Textfile with human format
+struct HumanFile {
+    file: Output,
+    human: Human,
+    color: bool,
+}
+
+struct FileWriter<T: Writer> {
     current_filename: PathBuf,
+    file_index: usize,
     file_size: usize,
     filename: PathBuf,
     filename_format: FilenameFormat,
+    /// Files written so far with their record count if old files are deleted
+    files: VecDeque<(PathBuf, usize)>,
     index: usize,
-    format: Format,
+    keep_last: Option<usize>,
+    config: T::Config,
     progress: ProgressBar,
     writer: Option<Box<T>>,
+    flush_mode: FlushMode,
+    /// Time of the last write of the buffers to the file
+    last_flush: Instant,
+    /// Wakes the sink to write records buffered while the input is idle
+    flush_timer: Option<Pin<Box<Sleep>>>,
+    /// Capture windows and the window of the current file
+    windows: Option<(Windows, usize)>,
+    /// Time of the trigger of the current capture window as part of file
+    /// names
+    trigger: Option<String>,
 }
 
 trait Writer {
-    fn with_file_format(filename: &Path, format: &Format) -> Result<Self, Error>
+    /// Settings passed to every new file
+    type Config;
+
+    fn create(filename: &Path, config: &Self::Config) -> Result<Self, Error>
     where
         Self: Sized;
     fn write(&mut self, record: &Record, index: usize) -> Result<(), Error>;
+    /// Finish the file
     fn flush(&mut self) -> Result<(), Error> {
         Ok(())
     }
+    /// File the records are written to
+    fn output(&mut self) -> &mut Output;
 }
 
 /// Crate a new log sink for given arguments
-pub fn try_from(args: CliArguments) -> Result<LogSink, Error> {
+pub fn try_from(
+    args: CliArguments,
+    profile: &Profile,
+    session: Option<Session>,
+    props: Option<Props>,
+    windows: Windows,
+) -> Result<LogSink, Error> {
     let format = args.format.as_ref().unwrap_or(&Format::Raw).to_owned();
 
+    // The metadata line must not break the format of the file
+    let line_format = matches!(
+        format,
+        Format::Csv | Format::Json | Format::Logcat | Format::Raw
+    );
+    if session.is_some() && (!line_format || args.json_array) {
+        return Err(Error::Invalid(
+            "Sessions require the csv, json, logcat or raw format".into(),
+        ));
+    }
+
+    // Pretty printed json and JSON arrays have no room for a header line
+    if props.is_some() && (format == Format::JsonPretty || args.json_array) {
+        return Err(Error::Invalid(
+            "Property stamps require the csv, html, human, json, logcat or raw format".into(),
+        ));
+    }
+    let props = props.filter(|props| !props.is_empty());
+
+    if args.json_array && !matches!(format, Format::Json | Format::JsonPretty) {
+        return Err(Error::Invalid(
+            "JSON arrays require the json or json-pretty format".into(),
+        ));
+    }
+
+    if args.html_interactive && format != Format::Html {
+        return Err(Error::Invalid(
+            "Interactive HTML requires the html format".into(),
+        ));
+    }
+
+    let template = args
+        .format_template
+        .as_deref()
+        .map(Template::parse)
+        .transpose()?;
+    let text = |format| TextConfig {
+        format,
+        columns: (!args.columns.is_empty()).then(|| args.columns.clone()),
+        level_style: args.level_style,
+        template: template.clone(),
+        session: session.as_ref().map(Session::to_line),
+        props: props.as_ref().map(Props::to_line),
+    };
+    let html = props.as_ref().map(Props::to_html);
+
     Ok(match format {
-        Format::Csv | Format::Json | Format::Raw => {
-            Box::new(FileWriter::<Textfile>::from_args(args, format)?) as LogSink
+        Format::Json | Format::JsonPretty if args.json_array => {
+            let config = text(format);
+            Box::new(FileWriter::<JsonArray>::from_args(args, config)?.split_windows(windows))
+                as LogSink
+        }
+        Format::Csv | Format::Json | Format::JsonPretty | Format::Logcat | Format::Raw => {
+            let config = text(format);
+            Box::new(FileWriter::<Textfile>::from_args(args, config)?.split_windows(windows))
+                as LogSink
+        }
+        Format::Html if args.html_interactive => {
+            Box::new(FileWriter::<html::Interactive>::from_args(args, html)?.split_windows(windows))
+                as LogSink
+        }
+        Format::Html => {
+            Box::new(FileWriter::<html::Html>::from_args(args, html)?.split_windows(windows))
+                as LogSink
+        }
+        Format::Human => {
+            // Colors are written only if explicitly requested
+            let config = HumanConfig {
+                human: Human::from(&args, profile, format)?,
+                color: args.color.as_deref() == Some("always"),
+                props: props.iter().flat_map(Props::lines).collect(),
+            };
+            Box::new(FileWriter::<HumanFile>::from_args(args, config)?.split_windows(windows))
+                as LogSink
         }
-        Format::Html => Box::new(FileWriter::<html::Html>::from_args(args, format)?) as LogSink,
-        Format::Human => panic!("Unsupported format human in output file"),
     })
 }
 
-impl Writer for Textfile {
-    fn with_file_format(filename: &Path, format: &Format) -> Result<Textfile, Error> {
+/// Metadata of a recording. The serial and model of the device are queried
+/// if the source is adb.
+pub async fn session(args: &CliArguments, profile: &Profile) -> Session {
+    let (serial, model) = if args.input.is_empty() && args.command.is_none() {
+        let device = args.device.first().map(String::as_str);
+        (
+            props::getprop(device, &["ro.serialno"]).await,
+            props::getprop(device, &["ro.product.model"]).await,
+        )
+    } else {
+        (None, None)
+    };
+    let start = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    Session {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        schema: SCHEMA,
+        serial,
+        model,
+        start: start.format(&Rfc3339).unwrap_or_default(),
+        args: std::env::args().skip(1).collect(),
+        profile: (!args.profile.is_empty()).then(|| args.profile.join(",")),
+        comment: profile.comment.clone(),
+    }
+}
+
+impl Output {
+    fn create(filename: &Path) -> Result<Output, Error> {
         let file = File::create(filename).map_err(|e| {
-            format_err!("Failed to create output file {}: {}", filename.display(), e)
+            Error::io(
+                format!("Failed to create output file {}", filename.display()),
+                e,
+            )
         })?;
+        let file = BufWriter::new(file);
+        if filename.extension().is_some_and(|e| e == "gz") {
+            Ok(Output::Gzip(GzEncoder::new(file, Compression::default())))
+        } else {
+            Ok(Output::Plain(file))
+        }
+    }
+
+    /// Flush the file and write the gzip trailer if compressed
+    fn finish(&mut self) -> Result<(), Error> {
+        match self {
+            Output::Plain(file) => file.flush(),
+            Output::Gzip(encoder) => encoder.try_finish().and_then(|_| encoder.get_mut().flush()),
+        }
+        .map_err(|e| Error::io("Failed to write", e))
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        self.finish().ok();
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(file) => file.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(file) => file.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl Writer for Textfile {
+    type Config = TextConfig;
+
+    fn create(filename: &Path, config: &TextConfig) -> Result<Textfile, Error> {
+        let mut file = Output::create(filename)?;
+        // Every file of a session can be replayed on its own
+        if let Some(session) = &config.session {
+            file.write_all(format!("{session}\n").as_bytes())
+                .map_err(|e| Error::io("Failed to write", e))?;
+        }
+        if let Some(props) = &config.props {
+            file.write_all(format!("{props}\n").as_bytes())
+                .map_err(|e| Error::io("Failed to write", e))?;
+        }
+        // Every csv file starts with a header
+        if let Some(header) = config.format.header(config.columns.as_deref()) {
+            file.write_all(format!("{header}\n").as_bytes())
+                .map_err(|e| Error::io("Failed to write", e))?;
+        }
         Ok(Textfile {
             file,
-            format: format.clone(),
+            config: config.clone(),
         })
     }
 
     fn write(&mut self, record: &Record, _index: usize) -> Result<(), Error> {
+        let line = match &self.config.template {
+            Some(template) => template.render(record),
+            None => self.config.format.fmt_styled(
+                record,
+                self.config.columns.as_deref(),
+                self.config.level_style,
+            )?,
+        };
+        self.file
+            .write_all(line.as_bytes())
+            .map_err(|e| Error::io("Failed to write", e))?;
         self.file
-            .write(self.format.fmt_record(record)?.as_bytes())
-            .map_err(|e| format_err!("Failed to write: {}", e))?;
+            .write_all(b"\n")
+            .map_err(|e| Error::io("Failed to write", e))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.file.finish()
+    }
+
+    fn output(&mut self) -> &mut Output {
+        &mut self.file
+    }
+}
+
+impl Writer for JsonArray {
+    type Config = TextConfig;
+
+    fn create(filename: &Path, config: &TextConfig) -> Result<JsonArray, Error> {
+        let mut file = Output::create(filename)?;
+        file.write_all(b"[\n")
+            .map_err(|e| Error::io("Failed to write", e))?;
+        Ok(JsonArray {
+            file,
+            config: config.clone(),
+            empty: true,
+            closed: false,
+        })
+    }
+
+    fn write(&mut self, record: &Record, _index: usize) -> Result<(), Error> {
+        if !self.empty {
+            self.file
+                .write_all(b",\n")
+                .map_err(|e| Error::io("Failed to write", e))?;
+        }
+        self.empty = false;
+        let line = self.config.format.fmt_styled(
+            record,
+            self.config.columns.as_deref(),
+            self.config.level_style,
+        )?;
         self.file
-            .write(b"\n")
-            .map_err(|e| format_err!("Failed to write: {}", e))?;
+            .write_all(line.as_bytes())
+            .map_err(|e| Error::io("Failed to write", e))
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if !self.closed {
+            self.closed = true;
+            let end: &[u8] = if self.empty { b"]\n" } else { b"\n]\n" };
+            self.file
+                .write_all(end)
+                .map_err(|e| Error::io("Failed to write", e))?;
+            self.file.finish()?;
+        }
         Ok(())
     }
+
+    fn output(&mut self) -> &mut Output {
+        &mut self.file
+    }
+}
+
+impl Drop for JsonArray {
+    fn drop(&mut self) {
+        self.flush().ok();
+    }
+}
+
+impl Writer for HumanFile {
+    type Config = HumanConfig;
+
+    fn create(filename: &Path, config: &HumanConfig) -> Result<HumanFile, Error> {
+        let mut file = Output::create(filename)?;
+        for line in &config.props {
+            file.write_all(format!("# {line}\n").as_bytes())
+                .map_err(|e| Error::io("Failed to write", e))?;
+        }
+        Ok(HumanFile {
+            file,
+            human: config.human.clone(),
+            color: config.color,
+        })
+    }
+
+    fn write(&mut self, record: &Record, _index: usize) -> Result<(), Error> {
+        let mut buffer = if self.color {
+            Buffer::ansi()
+        } else {
+            Buffer::no_color()
+        };
+        self.human
+            .render(record, None, &mut buffer)
+            .map_err(|e| Error::io("Failed to write", e))?;
+        self.file
+            .write_all(buffer.as_slice())
+            .map_err(|e| Error::io("Failed to write", e))
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.file.finish()
+    }
+
+    fn output(&mut self) -> &mut Output {
+        &mut self.file
+    }
 }
 
 #[inline]
@@ -128,15 +489,37 @@ fn parse_human_number(number: Option<String>) -> Option<usize> {
 }
 
 impl<T: Writer> FileWriter<T> {
-    pub fn from_args(args: CliArguments, format: Format) -> Result<Self, Error> {
-        let filename = args
+    pub fn from_args(args: CliArguments, config: T::Config) -> Result<Self, Error> {
+        // The progress of the input files replaces the records written
+        let input_progress = args.show_progress();
+        let mut filename = args
             .output
-            .ok_or_else(|| err_msg("Invalid output filename!"))?;
+            .ok_or_else(|| Error::Invalid("Invalid output filename!".into()))?;
+        if args.compress && !matches!(filename.extension(), Some(e) if e == "gz") {
+            let mut name = filename.into_os_string();
+            name.push(".gz");
+            filename = PathBuf::from(name);
+        }
 
+        let keep_last = parse_human_number(args.keep_last);
+        if keep_last == Some(0) {
+            return Err(Error::Invalid("Invalid keep last count 0".into()));
+        }
+        // Splitting is needed in order to delete old records. The command
+        // line requires --records-per-file with --keep-last.
         let records_per_file = parse_human_number(args.records_per_file);
         let overwrite = args.overwrite;
+        let flush_mode = if args.line_buffered {
+            FlushMode::Line
+        } else {
+            let interval = args
+                .flush_interval
+                .or_else(|| config_get("file_flush_interval"))
+                .map_or(DEFAULT_FLUSH_INTERVAL, Duration::from_millis);
+            FlushMode::Interval(interval)
+        };
 
-        let records = records_per_file.unwrap_or(std::usize::MAX);
+        let records = records_per_file.unwrap_or(usize::MAX);
         let filename_format = match args.filename_format.unwrap_or("".to_owned()).as_str() {
             "enumerate" => FilenameFormat::Enumerate(overwrite, records),
             "date" => FilenameFormat::Date(overwrite, records),
@@ -160,7 +543,7 @@ impl<T: Writer> FileWriter<T> {
                 )
             } else {
                 (
-                    ProgressBar::new(std::u64::MAX),
+                    ProgressBar::new(u64::MAX),
                     " • ",
                     "{spinner:.yellow} {msg:.dim.bold} {pos:>7.dim} {elapsed_precise:.dim}",
                 )
@@ -168,67 +551,101 @@ impl<T: Writer> FileWriter<T> {
             if let Ok(style) = ProgressStyle::default_bar().template(template) {
                 pb.set_style(style.progress_chars(chars));
             }
+            if input_progress {
+                pb.set_draw_target(ProgressDrawTarget::hidden());
+            }
             pb
         };
 
         Ok(FileWriter {
             current_filename: filename.clone(),
+            file_index: 0,
             file_size: 0,
             filename,
             filename_format,
+            files: VecDeque::new(),
             index: 0,
-            format,
+            keep_last,
+            config,
             progress,
             writer: None,
+            flush_mode,
+            last_flush: Instant::now(),
+            flush_timer: None,
+            windows: None,
+            trigger: None,
         })
     }
 
-    fn next_file(&self) -> Result<PathBuf, Error> {
+    /// Start a new file for every capture window unless writing a single file
+    fn split_windows(mut self, windows: Windows) -> Self {
+        if !matches!(self.filename_format, FilenameFormat::Single(_)) {
+            self.windows = Some((windows, 0));
+        }
+        self
+    }
+
+    fn next_file(&mut self) -> Result<PathBuf, Error> {
         match self.filename_format {
             FilenameFormat::Single(overwrite) => {
                 if self.filename.exists() && !overwrite {
-                    Err(format_err!(
+                    Err(Error::Invalid(format!(
                         "{} exists. Use overwrite flag to force!",
                         self.filename.display()
-                    ))
+                    )))
                 } else {
                     Ok(self.filename.clone())
                 }
             }
             FilenameFormat::Enumerate(_overwrite, _) => {
                 if self.filename.as_path().is_dir() {
-                    return Err(format_err!(
+                    return Err(Error::Invalid(format!(
                         "Output file {} is a directory",
                         self.filename.display()
-                    ));
+                    )));
                 }
 
                 let dir = self.filename.parent().unwrap_or_else(|| Path::new(""));
                 if !dir.is_dir() {
-                    DirBuilder::new().recursive(true).create(dir).map_err(|e| {
-                        format_err!("Failed to create outfile parent directory: {:?}", e)
-                    })?
+                    DirBuilder::new()
+                        .recursive(true)
+                        .create(dir)
+                        .map_err(|e| Error::io("Failed to create outfile parent directory", e))?
                 }
 
                 let next = |index| -> Result<PathBuf, Error> {
-                    let mut name = self.filename.clone();
-                    name = PathBuf::from(format!(
-                        "{}-{:03}",
-                        name.file_stem()
-                            .ok_or_else(|| err_msg("Invalid path"))?
-                            .to_str()
-                            .ok_or_else(|| err_msg("Invalid path"))?,
-                        index
-                    ));
-                    if let Some(extension) = self.filename.extension() {
-                        name.set_extension(extension);
-                    }
-                    Ok(dir.join(name))
+                    // Keep the enumeration in front of all extensions
+                    // e.g. testrun-000.log.gz
+                    let (name, gz) = match self.filename.extension() {
+                        Some(extension) if extension == "gz" => {
+                            (self.filename.with_extension(""), ".gz")
+                        }
+                        _ => (self.filename.clone(), ""),
+                    };
+                    let stem = name
+                        .file_stem()
+                        .ok_or_else(|| Error::Invalid("Invalid path".into()))?
+                        .to_str()
+                        .ok_or_else(|| Error::Invalid("Invalid path".into()))?;
+                    let extension = name
+                        .extension()
+                        .and_then(|e| e.to_str())
+                        .map(|e| format!(".{e}"))
+                        .unwrap_or_default();
+                    let trigger = self
+                        .trigger
+                        .as_deref()
+                        .map(|time| format!("-{time}"))
+                        .unwrap_or_default();
+                    Ok(dir.join(format!("{stem}{trigger}-{index:03}{extension}{gz}")))
                 };
 
-                for index in 0.. {
+                // Continue after the last file written. Old files might be
+                // deleted and their names must not be reused.
+                for index in self.file_index.. {
                     let n = next(index)?;
                     if !n.exists() {
+                        self.file_index = index + 1;
                         return Ok(n);
                     }
                 }
@@ -245,27 +662,39 @@ impl<T: Writer> FileWriter<T> {
                     let dir = self.filename.parent().unwrap_or_else(|| Path::new(""));
                     if !dir.is_dir() {
                         DirBuilder::new().recursive(true).create(dir).map_err(|e| {
-                            format_err!(
-                                "Failed to create outfile parent directory {}: {}",
-                                dir.display(),
-                                e
+                            Error::io(
+                                format!(
+                                    "Failed to create outfile parent directory {}",
+                                    dir.display()
+                                ),
+                                e,
                             )
                         })?;
                     }
 
-                    let now = OffsetDateTime::now_local()?;
-                    let format =
-                        format_description!("[year]-[month]-[day]-[hour]_[minute]_[second]");
-                    let time = now.format(format)?;
+                    // Files of capture windows are named after the trigger
+                    let time = match &self.trigger {
+                        Some(time) => time.clone(),
+                        None => {
+                            let now = OffsetDateTime::now_local().map_err(|e| {
+                                Error::Invalid(format!("Failed to get local time: {e}"))
+                            })?;
+                            let format = format_description!(
+                                "[year]-[month]-[day]-[hour]_[minute]_[second]"
+                            );
+                            now.format(format)
+                                .map_err(|e| Error::Format(e.to_string()))?
+                        }
+                    };
                     let enumeration = e
                         .map(|a| format!("-{a:03}"))
                         .unwrap_or_else(|| "".to_owned());
                     let filename = self
                         .filename
                         .file_name()
-                        .ok_or_else(|| err_msg("Invalid path"))?
+                        .ok_or_else(|| Error::Invalid("Invalid path".into()))?
                         .to_str()
-                        .ok_or_else(|| err_msg("Invalid path"))?;
+                        .ok_or_else(|| Error::Invalid("Invalid path".into()))?;
                     let candidate = PathBuf::from(format!("{time}{enumeration}_{filename}"));
                     let candidate = dir.join(candidate);
                     if !overwrite && candidate.exists() {
@@ -280,6 +709,16 @@ impl<T: Writer> FileWriter<T> {
     }
 
     fn write(&mut self, record: &Record) -> Result<(), Error> {
+        if let Some((windows, window)) = &mut self.windows {
+            let current = windows.count();
+            if current != *window {
+                *window = current;
+                self.trigger = windows.time().as_deref().map(file_time);
+                if self.writer.is_some() {
+                    self.flush()?;
+                }
+            }
+        }
         match self.writer {
             Some(ref mut writer) => {
                 writer.write(record, self.index)?;
@@ -287,18 +726,32 @@ impl<T: Writer> FileWriter<T> {
             }
             None => {
                 self.current_filename = self.next_file()?;
-                let mut writer = T::with_file_format(&self.current_filename, &self.format)?;
+                let mut writer = T::create(&self.current_filename, &self.config)?;
                 let message = format!("Writing {}", self.current_filename.display());
                 self.progress.set_message(message);
                 writer.write(record, self.index)?;
                 self.index += 1;
                 self.writer = Some(Box::new(writer));
+                if self.keep_last.is_some() {
+                    self.files.push_back((self.current_filename.clone(), 0));
+                }
             }
         }
 
         self.file_size += 1;
         self.progress.set_position(self.file_size as u64);
 
+        if self.flush_mode == FlushMode::Line {
+            self.flush_buffers()?;
+        }
+
+        if let Some(keep_last) = self.keep_last {
+            if let Some((_, records)) = self.files.back_mut() {
+                *records += 1;
+            }
+            self.remove_old_files(keep_last)?;
+        }
+
         match self.filename_format {
             FilenameFormat::Enumerate(_, n) | FilenameFormat::Date(_, n) => {
                 if self.file_size >= n {
@@ -311,6 +764,36 @@ impl<T: Writer> FileWriter<T> {
         }
     }
 
+    /// Delete the oldest files as long as the remaining ones contain at least
+    /// `keep_last` records
+    fn remove_old_files(&mut self, keep_last: usize) -> Result<(), Error> {
+        let mut records = self.files.iter().map(|(_, n)| n).sum::<usize>();
+        while self.files.len() > 1 {
+            let (ref file, n) = self.files[0];
+            if records - n < keep_last {
+                break;
+            }
+            fs::remove_file(file)
+                .map_err(|e| Error::io(format!("Failed to remove {}", file.display()), e))?;
+            records -= n;
+            self.files.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Write the buffered records to the current file without finishing it
+    fn flush_buffers(&mut self) -> Result<(), Error> {
+        self.last_flush = Instant::now();
+        match self.writer {
+            Some(ref mut writer) => writer
+                .output()
+                .flush()
+                .map_err(|e| Error::io("Failed to write", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Finish the current file. The next record opens a new one.
     fn flush(&mut self) -> Result<(), Error> {
         if let Some(ref mut writer) = self.writer {
             writer.flush()?;
@@ -326,7 +809,10 @@ impl<T: Writer> FileWriter<T> {
     }
 }
 
-impl<T: Writer> Sink<Record> for FileWriter<T> {
+impl<T: Writer> Sink<Record> for FileWriter<T>
+where
+    T::Config: Unpin,
+{
     type Error = Error;
 
     fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
@@ -337,132 +823,182 @@ impl<T: Writer> Sink<Record> for FileWriter<T> {
         self.write(&item)
     }
 
-    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Flush is called whenever the source is idle. Write the buffers at
+        // most every interval and register a timer for the remaining time.
+        let FlushMode::Interval(interval) = self.flush_mode else {
+            return Poll::Ready(Ok(()));
+        };
+        let due = self.last_flush + interval;
+        if Instant::now() >= due {
+            self.flush_timer = None;
+            return Poll::Ready(self.flush_buffers());
+        }
+        let timer = self
+            .flush_timer
+            .get_or_insert_with(|| Box::pin(sleep(interval)));
+        timer.as_mut().reset(due);
+        if timer.as_mut().poll(cx).is_ready() {
+            self.flush_timer = None;
+            return Poll::Ready(self.flush_buffers());
+        }
         Poll::Ready(Ok(()))
     }
 
-    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(self.flush())
     }
 }
 
 mod html {
-    use super::Writer;
-    use crc::Crc;
-    use failure::{format_err, Error};
-    use handlebars::{
-        to_json, Context, Handlebars, Helper, HelperResult, JsonRender, Output, RenderContext,
-        RenderErrorReason,
-    };
-    use rogcat::record::{Format, Record};
-    use serde::Serialize;
-    use serde_json::value::{Map, Value as Json};
-    use std::{
-        fs::File,
-        path::{Path, PathBuf},
-        str,
+    use super::{Output, Writer};
+    use rogcat::{
+        record::{Format, Record},
+        Error,
     };
+    use std::{io::Write, path::Path};
 
-    const CRC_TABLE: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
-
-    #[derive(Serialize)]
-    struct HtmlRecord {
-        index: usize,
-        record: Record,
+    /// Page header with the device properties at the start of the body
+    fn header(template: &str, props: &Option<String>) -> String {
+        match props {
+            Some(props) => template.replacen("<body>\n", &format!("<body>\n{props}"), 1),
+            None => template.to_owned(),
+        }
     }
 
-    /// Simple static html file
+    /// Simple static html file. The document header is written when the file is
+    /// created and the closing tags when the file is flushed or dropped.
     pub struct Html {
-        filename: PathBuf,
-        records: Vec<HtmlRecord>,
-    }
-
-    impl Html {
-        // TODO: ensure readability
-        fn hash_color(value: &str) -> String {
-            let mut digest = CRC_TABLE.digest();
-            digest.update(value.as_bytes());
-            let h = digest.finalize();
-            let r = h & 0xFF;
-            let g = (h & 0xFF00) >> 8;
-            let b = (h & 0xFF_0000) >> 16;
-            format!("#{r:02x}{g:02x}{b:02x}")
-        }
-        fn color_helper(
-            h: &Helper,
-            _: &Handlebars,
-            _: &Context,
-            _: &mut RenderContext,
-            out: &mut dyn Output,
-        ) -> HelperResult {
-            let param = h.param(0).ok_or_else(|| {
-                RenderErrorReason::Other("Param 0 is required for format helper.".to_string())
-            })?;
-            let value = param.value().render();
-            let rendered = if value.is_empty() || value == "0" {
-                format!("<span style=\"color:grey\">{value}</span>")
-            } else {
-                format!(
-                    "<span style=\"color:{}\">{}</span>",
-                    Self::hash_color(&value),
-                    value
-                )
-            };
-            out.write(&rendered)?;
-            Ok(())
+        file: Output,
+        closed: bool,
+    }
+
+    impl Writer for Html {
+        /// Device properties shown above the records
+        type Config = Option<String>;
+
+        fn create(filename: &Path, props: &Option<String>) -> Result<Html, Error> {
+            let mut file = Output::create(filename)?;
+            file.write_all(header(HTML_HEADER, props).as_bytes())
+                .map_err(|e| Error::io("Failed to write", e))?;
+            Ok(Html {
+                file,
+                closed: false,
+            })
+        }
+
+        fn write(&mut self, record: &Record, _index: usize) -> Result<(), Error> {
+            self.file
+                .write_all(Format::Html.fmt_record(record)?.as_bytes())
+                .map_err(|e| Error::io("Failed to write", e))?;
+            self.file
+                .write_all(b"\n")
+                .map_err(|e| Error::io("Failed to write", e))
         }
 
         fn flush(&mut self) -> Result<(), Error> {
-            let mut hb = Handlebars::new();
-            let mut data: Map<String, Json> = Map::new();
-            data.insert("records".to_owned(), to_json(&self.records));
-            let mut output_file = File::create(&self.filename)?;
-            hb.register_helper("color", Box::new(Self::color_helper));
-            hb.register_template_string("t1", HTML_TEMPLATE)?;
-            hb.render_to_write("t1", &data, &mut output_file)
-                .map_err(|e| format_err!("Rednering error: {}", e))
-                .map(|_| ())
+            if !self.closed {
+                self.closed = true;
+                self.file
+                    .write_all(HTML_FOOTER.as_bytes())
+                    .map_err(|e| Error::io("Failed to write", e))?;
+                self.file.finish()?;
+            }
+            Ok(())
+        }
+
+        fn output(&mut self) -> &mut Output {
+            &mut self.file
         }
     }
 
-    impl Writer for Html {
-        fn with_file_format(filename: &Path, _: &Format) -> Result<Html, Error> {
-            Ok(Html {
-                filename: filename.to_owned(),
-                records: Vec::new(),
+    impl Drop for Html {
+        fn drop(&mut self) {
+            self.flush().ok();
+        }
+    }
+
+    /// Self contained html file with search, level and tag filters. The
+    /// records are appended as one json array per line to a data block that
+    /// is rendered when the page is loaded. A file of an interrupted session
+    /// shows the records written so far.
+    pub struct Interactive {
+        file: Output,
+        closed: bool,
+    }
+
+    /// Values of a record in the order the page expects them
+    pub(super) fn interactive_row(record: &Record) -> Result<String, Error> {
+        let row = (
+            record.time.as_deref().unwrap_or_default(),
+            record.level.to_string(),
+            &record.process,
+            &record.thread,
+            &record.tag,
+            &record.message,
+        );
+        let json = serde_json::to_string(&row)
+            .map_err(|e| Error::Format(format!("Json serialization error: {e}")))?;
+        // A "</script>" in a message must not end the data block. A '<' only
+        // occurs in json strings.
+        Ok(json.replace('<', "\\u003c"))
+    }
+
+    impl Writer for Interactive {
+        /// Device properties shown above the controls
+        type Config = Option<String>;
+
+        fn create(filename: &Path, props: &Option<String>) -> Result<Interactive, Error> {
+            let mut file = Output::create(filename)?;
+            file.write_all(header(INTERACTIVE_HEADER, props).as_bytes())
+                .map_err(|e| Error::io("Failed to write", e))?;
+            Ok(Interactive {
+                file,
+                closed: false,
             })
         }
 
-        fn write(&mut self, record: &Record, index: usize) -> Result<(), Error> {
-            self.records.push(HtmlRecord {
-                index,
-                record: record.clone(),
-            });
+        fn write(&mut self, record: &Record, _index: usize) -> Result<(), Error> {
+            let row = interactive_row(record)?;
+            self.file
+                .write_all(row.as_bytes())
+                .and_then(|_| self.file.write_all(b"\n"))
+                .map_err(|e| Error::io("Failed to write", e))
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            if !self.closed {
+                self.closed = true;
+                self.file
+                    .write_all(INTERACTIVE_FOOTER.as_bytes())
+                    .map_err(|e| Error::io("Failed to write", e))?;
+                self.file.finish()?;
+            }
             Ok(())
         }
+
+        fn output(&mut self) -> &mut Output {
+            &mut self.file
+        }
     }
 
-    impl Drop for Html {
+    impl Drop for Interactive {
         fn drop(&mut self) {
             self.flush().ok();
         }
     }
 
-    const HTML_TEMPLATE: &str = r#"
-<!doctype HTML>
+    const HTML_HEADER: &str = r#"<!doctype HTML>
+<html>
+<head>
+<meta charset="utf-8">
 <title>Rogcat</title>
 <link href='http://fonts.googleapis.com/css?family=Source+Code+Pro' rel='stylesheet' type='text/css'>
 <style>
 body {background: black; color: #BBBBBB; font-family: 'Source Code Pro', Monaco, monospace; font-size: 12px}
-.green, .I {color: #A8FF60}
-.white {color: #EEEEEE}
-.red, .E, .A, .F {color: #FF6C60}
-.yellow, .W {color: #FFFFB6}
-.black {color: #4F4F4F}
-.blue {color: #96CBFE}
-.cyan {color: #C6C5FE}
-.magenta {color: #FF73FD}
-tr.hover { background: #260041 }
 table {
     border-spacing: 0;
     width: 100%;
@@ -474,59 +1010,670 @@ td {
     padding-right: 2ex;
     white-space: nowrap;
 }
+td:last-child {
+    white-space: pre-wrap;
+}
 tr:hover {
-    color: yellow;
+    background: #260041;
+}
+tr.level-I td:last-child {
+    color: #A8FF60;
 }
-td.level-D {
+tr.level-W td:last-child {
+    color: #FFFFB6;
+}
+tr.level-E td:last-child, tr.level-A td:last-child, tr.level-F td:last-child {
+    color: #FF6C60;
+}
+tr.level-D td.level {
     color: white;
     background: #555;
 }
-td.level-I {
+tr.level-I td.level {
     color: black;
     background: #A8FF60;
 }
-td.level-W {
+tr.level-W td.level {
     color: black;
     background: #FFFFB6;
 }
-td.level-E {
-    color: black;
-    background: #FF6C60;
-}
-td.level-A {
+tr.level-E td.level, tr.level-A td.level, tr.level-F td.level {
     color: black;
     background: #FF6C60;
 }
-td.level-F {
-    color: black;
-    background: #FF6C60;
-}
-table tr td:first-child + td + td {
+table tr td:nth-child(2), table tr td:nth-child(3), table tr td:nth-child(5) {
     text-align: right
 }
-table tr td:first-child + td + td + td {
+</style>
+</head>
+<body>
+<table>
+"#;
+
+    const HTML_FOOTER: &str = r#"</table>
+</body>
+</html>
+"#;
+
+    /// Page with the viewer. Only the rows around the visible area are in
+    /// the document to keep large captures responsive.
+    const INTERACTIVE_HEADER: &str = r##"<!doctype HTML>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Rogcat</title>
+<style>
+html, body {margin: 0; height: 100%}
+body {
+    background: black;
+    color: #BBBBBB;
+    font-family: 'Source Code Pro', Monaco, monospace;
+    font-size: 12px;
+    display: flex;
+    flex-direction: column;
 }
-table tr td:first-child + td + td + td + td {
-    text-align: right
+#controls {
+    display: flex;
+    flex-wrap: wrap;
+    gap: 2ex;
+    align-items: center;
+    padding: 4px 2ex;
+    background: #222;
 }
-table tr td:first-child + td + td + td + td + td {
+#controls label {margin-right: 1ex}
+#view {flex: 1; overflow-y: auto; position: relative}
+#spacer {position: relative}
+.row {
+    position: absolute;
+    left: 0;
+    right: 0;
+    height: 16px;
+    line-height: 16px;
+    white-space: pre;
+    overflow: hidden;
+    text-overflow: ellipsis;
 }
+.row span {display: inline-block; vertical-align: top; padding-left: 2ex; overflow: hidden}
+.row:hover {background: #260041}
+#header {position: sticky; top: 0; z-index: 1; background: #111; height: 16px; line-height: 16px; white-space: pre}
+#header span {display: inline-block; padding-left: 2ex; color: white}
+.time {width: 18ex}
+.pid, .tid {width: 6ex; text-align: right}
+.tag {width: 24ex; text-align: right}
+.level {width: 3ex; text-align: center}
+.level-I .message {color: #A8FF60}
+.level-W .message {color: #FFFFB6}
+.level-E .message, .level-A .message, .level-F .message {color: #FF6C60}
+.level-D .level {color: white; background: #555}
+.level-I .level {color: black; background: #A8FF60}
+.level-W .level {color: black; background: #FFFFB6}
+.level-E .level, .level-A .level, .level-F .level {color: black; background: #FF6C60}
 </style>
+<script>
+"use strict";
+const LEVELS = ["T", "V", "D", "I", "W", "E", "F", "A"];
+const COLUMNS = ["time", "level", "pid", "tid", "tag", "message"];
+const ROW_HEIGHT = 16;
+let records = [];
+let search = [];
+let visible = [];
 
-<table>
+function load() {
+    for (const line of document.getElementById("records").textContent.split("\n")) {
+        try {
+            if (line) records.push(JSON.parse(line));
+        } catch (e) {
+            // The last line of an interrupted session
+        }
+    }
+    search = records.map(r => r.join(" ").toLowerCase());
+
+    const levels = [...new Set(records.map(r => r[1]))];
+    levels.sort((a, b) => (LEVELS.indexOf(a) + 1 || 99) - (LEVELS.indexOf(b) + 1 || 99));
+    for (const level of levels) {
+        const box = document.createElement("input");
+        box.type = "checkbox";
+        box.checked = true;
+        box.value = level;
+        box.onchange = filter;
+        const label = document.createElement("label");
+        label.append(box, level);
+        document.getElementById("levels").append(label);
+    }
+    const tags = document.getElementById("tag");
+    for (const tag of [...new Set(records.map(r => r[4]))].sort()) {
+        tags.add(new Option(tag, tag));
+    }
+    tags.onchange = filter;
+    document.getElementById("search").oninput = filter;
+    document.getElementById("view").onscroll = render;
+    window.onresize = render;
+    filter();
+}
+
+function filter() {
+    const text = document.getElementById("search").value.toLowerCase();
+    const tag = document.getElementById("tag").value;
+    const levels = new Set([...document.querySelectorAll("#levels input:checked")].map(b => b.value));
+    visible = [];
+    records.forEach((r, i) => {
+        if (levels.has(r[1]) && (!tag || r[4] === tag) && (!text || search[i].includes(text))) {
+            visible.push(r);
+        }
+    });
+    document.getElementById("count").textContent = visible.length + " of " + records.length + " records";
+    document.getElementById("spacer").style.height = visible.length * ROW_HEIGHT + "px";
+    render();
+}
 
-{{#each records as |t| ~}}
-    <tr>
-    <td>{{t.index}}</td>
-    <td>{{t.record.time}}</td>
-    <td><a>{{color t.record.tag}}</a></td>
-    <td>{{color t.record.process}}</td>
-    <td>{{color t.record.thread}}</td>
-    <td class="level-{{t.record.level}}">{{t.record.level}}</td>
-    <td>{{t.record.message}}</td>
-    </tr>
-{{/each~}}
-
-</table>
+function render() {
+    const view = document.getElementById("view");
+    const spacer = document.getElementById("spacer");
+    const first = Math.max(0, Math.floor((view.scrollTop - spacer.offsetTop) / ROW_HEIGHT) - 20);
+    const last = Math.min(visible.length, first + Math.ceil(view.clientHeight / ROW_HEIGHT) + 40);
+    const rows = [];
+    for (let i = first; i < last; i++) {
+        const row = document.createElement("div");
+        row.className = "row level-" + visible[i][1];
+        row.style.top = i * ROW_HEIGHT + "px";
+        row.title = visible[i][5];
+        visible[i].forEach((value, column) => {
+            const cell = document.createElement("span");
+            cell.className = COLUMNS[column];
+            cell.textContent = value;
+            row.append(cell);
+        });
+        rows.push(row);
+    }
+    spacer.replaceChildren(...rows);
+}
+
+document.addEventListener("DOMContentLoaded", load);
+</script>
+</head>
+<body>
+<div id="controls">
+<input id="search" type="search" placeholder="Search">
+<span id="levels"></span>
+<select id="tag"><option value="">All tags</option></select>
+<span id="count"></span>
+</div>
+<div id="view">
+<div id="header"><span class="time">Time</span><span class="level">L</span><span class="pid">Pid</span><span class="tid">Tid</span><span class="tag">Tag</span><span class="message">Message</span></div>
+<div id="spacer"></div>
+</div>
+<script id="records" type="application/x-ndjson">
+"##;
+
+    const INTERACTIVE_FOOTER: &str = r#"</script>
+</body>
+</html>
 "#;
 }
+
+#[test]
+fn keep_last() {
+    use clap::Parser;
+    use std::io::Read;
+
+    let dir = std::env::temp_dir().join(format!("rogcat-keep-last-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    let output = dir.join("log.txt");
+    let args = CliArguments::parse_from([
+        "rogcat",
+        "-o",
+        output.to_str().unwrap(),
+        "-n",
+        "100",
+        "--keep-last",
+        "1000",
+    ]);
+    let mut writer = FileWriter::<Textfile>::from_args(
+        args,
+        TextConfig {
+            format: Format::Raw,
+            columns: None,
+            level_style: LevelStyle::Word,
+            template: None,
+            session: None,
+            props: None,
+        },
+    )
+    .unwrap();
+    for i in 0..10_000 {
+        let record = Record {
+            raw: i.to_string(),
+            ..Default::default()
+        };
+        writer.write(&record).unwrap();
+    }
+    writer.flush().unwrap();
+
+    let mut files = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(files.len(), 10);
+
+    let mut content = String::new();
+    for file in files {
+        File::open(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+    }
+    let lines = content.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1000);
+    assert_eq!(lines.first(), Some(&"9000"));
+    assert_eq!(lines.last(), Some(&"9999"));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn gzip_split() {
+    use clap::Parser;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    let dir = std::env::temp_dir().join(format!("rogcat-gzip-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    let output = dir.join("log.txt");
+    let args = CliArguments::parse_from([
+        "rogcat",
+        "-o",
+        output.to_str().unwrap(),
+        "-n",
+        "100",
+        "--compress",
+    ]);
+    let mut writer = FileWriter::<Textfile>::from_args(
+        args,
+        TextConfig {
+            format: Format::Raw,
+            columns: None,
+            level_style: LevelStyle::Word,
+            template: None,
+            session: None,
+            props: None,
+        },
+    )
+    .unwrap();
+    for i in 0..250 {
+        let record = Record {
+            raw: i.to_string(),
+            ..Default::default()
+        };
+        writer.write(&record).unwrap();
+    }
+    writer.flush().unwrap();
+
+    let mut files = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(files.len(), 3);
+
+    let mut content = String::new();
+    for file in files {
+        assert!(file.to_str().unwrap().ends_with(".txt.gz"));
+        // Each file is a complete gzip stream
+        GzDecoder::new(File::open(file).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+    }
+    let lines = content.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 250);
+    assert_eq!(lines.first(), Some(&"0"));
+    assert_eq!(lines.last(), Some(&"249"));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn gzip_dropped() {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    let dir = std::env::temp_dir().join(format!("rogcat-gzip-drop-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("log.txt.gz");
+
+    // A writer dropped without flush like on an error of another sink
+    let mut writer = Textfile::create(
+        &file,
+        &TextConfig {
+            format: Format::Raw,
+            columns: None,
+            level_style: LevelStyle::Word,
+            template: None,
+            session: None,
+            props: None,
+        },
+    )
+    .unwrap();
+    let record = Record {
+        raw: "dropped".to_owned(),
+        ..Default::default()
+    };
+    writer.write(&record, 0).unwrap();
+    drop(writer);
+
+    let mut content = String::new();
+    GzDecoder::new(File::open(&file).unwrap())
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "dropped\n");
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn json_array_split() {
+    use clap::Parser;
+
+    let dir = std::env::temp_dir().join(format!("rogcat-json-array-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    let output = dir.join("log.json");
+    for format in ["json", "json-pretty"] {
+        let args = CliArguments::parse_from([
+            "rogcat",
+            "-o",
+            output.to_str().unwrap(),
+            "-n",
+            "100",
+            "-f",
+            format,
+            "--json-array",
+            "--overwrite",
+        ]);
+        let config = TextConfig {
+            format: args.format.clone().unwrap(),
+            columns: None,
+            level_style: LevelStyle::Word,
+            template: None,
+            session: None,
+            props: None,
+        };
+        let mut writer = FileWriter::<JsonArray>::from_args(args, config).unwrap();
+        for i in 0..250 {
+            let record = Record {
+                message: i.to_string(),
+                ..Default::default()
+            };
+            writer.write(&record).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let mut files = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files.len(), 3);
+
+        let records = files
+            .iter()
+            .flat_map(|file| {
+                serde_json::from_str::<Vec<Record>>(&fs::read_to_string(file).unwrap()).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 250);
+        assert_eq!(records.last().unwrap().message, "249");
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
+
+#[test]
+fn html_interactive() {
+    use clap::Parser;
+
+    let dir = std::env::temp_dir().join(format!("rogcat-html-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("log.html");
+    let args = CliArguments::parse_from([
+        "rogcat",
+        "-o",
+        output.to_str().unwrap(),
+        "-f",
+        "html",
+        "--html-interactive",
+    ]);
+    let props = "<dl class=\"props\">\n</dl>\n".to_owned();
+    let mut writer = FileWriter::<html::Interactive>::from_args(args, Some(props)).unwrap();
+    let record = Record {
+        time: Some("01-10 23:02:59.028".to_owned()),
+        level: rogcat::record::Level::Warn,
+        tag: "Tag".to_owned(),
+        message: "</script><b>".to_owned(),
+        ..Default::default()
+    };
+    writer.write(&record).unwrap();
+    writer.write(&Record::default()).unwrap();
+    writer.flush_buffers().unwrap();
+
+    // The records flushed so far are in the data block
+    let content = fs::read_to_string(&output).unwrap();
+    assert!(content.contains("<body>\n<dl class=\"props\">\n</dl>\n<div id=\"controls\">"));
+    let data = content
+        .split("type=\"application/x-ndjson\">\n")
+        .nth(1)
+        .unwrap();
+    assert_eq!(data.matches("</script>").count(), 0);
+    let rows = data
+        .lines()
+        .map(|l| serde_json::from_str::<Vec<String>>(l).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rows[0],
+        ["01-10 23:02:59.028", "W", "", "", "Tag", "</script><b>"]
+    );
+    assert_eq!(rows.len(), 2);
+
+    writer.flush().unwrap();
+    let content = fs::read_to_string(&output).unwrap();
+    assert!(content.ends_with("</script>\n</body>\n</html>\n"));
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn csv_header_columns() {
+    use clap::Parser;
+    use rogcat::{parser, record::Level};
+
+    let dir = std::env::temp_dir().join(format!("rogcat-csv-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    let output = dir.join("log.csv");
+    let args = CliArguments::parse_from([
+        "rogcat",
+        "-o",
+        output.to_str().unwrap(),
+        "-n",
+        "100",
+        "-f",
+        "csv",
+        "--fields",
+        "level,tag,message",
+    ]);
+    let session = Session {
+        version: "0.4.7".to_owned(),
+        start: "2024-01-10T23:56:50+01:00".to_owned(),
+        ..Default::default()
+    }
+    .to_line();
+    let props = Props::from_getprop(
+        "[ro.product.model]: [Pixel 7]",
+        &["ro.product.model".to_owned()],
+    )
+    .to_line();
+    let config = TextConfig {
+        format: Format::Csv,
+        columns: Some(args.columns.clone()),
+        level_style: LevelStyle::Word,
+        template: None,
+        session: Some(session.clone()),
+        props: Some(props.clone()),
+    };
+    let mut writer = FileWriter::<Textfile>::from_args(args, config).unwrap();
+    for i in 0..250 {
+        let record = Record {
+            level: Level::Warn,
+            tag: "Tag".to_owned(),
+            message: format!("message, {i}"),
+            process: "1".to_owned(),
+            ..Default::default()
+        };
+        writer.write(&record).unwrap();
+    }
+    writer.flush().unwrap();
+
+    let mut files = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(files.len(), 3);
+
+    // Every file starts with the session, the device properties and a header
+    // that configures the parser
+    let mut parser = parser::Parser::default();
+    let mut records = Vec::new();
+    for file in files {
+        let content = fs::read_to_string(file).unwrap();
+        assert!(content.starts_with(&format!("{session}\n{props}\nlevel,tag,message\n")));
+        records.extend(content.lines().filter_map(|line| parser.parse(line)));
+    }
+    assert_eq!(records.len(), 250);
+    let last = records.last().unwrap();
+    assert_eq!(last.level, Level::Warn);
+    assert_eq!(last.tag, "Tag");
+    assert_eq!(last.message, "message, 249");
+    assert_eq!(last.process, "");
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn flush_modes() {
+    use clap::Parser;
+    use futures::{future::poll_fn, SinkExt};
+
+    let dir = std::env::temp_dir().join(format!("rogcat-flush-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    let record = Record {
+        raw: "hello".to_owned(),
+        ..Default::default()
+    };
+    let writer = |name: &str, flush: &[&str]| {
+        let output = dir.join(name);
+        let args = CliArguments::parse_from(
+            ["rogcat", "-o", output.to_str().unwrap()]
+                .iter()
+                .chain(flush),
+        );
+        let config = TextConfig {
+            format: Format::Raw,
+            columns: None,
+            level_style: LevelStyle::Word,
+            template: None,
+            session: None,
+            props: None,
+        };
+        (
+            FileWriter::<Textfile>::from_args(args, config).unwrap(),
+            output,
+        )
+    };
+
+    // Every record is written immediately
+    let (mut line, output) = writer("line.txt", &["--line-buffered"]);
+    line.feed(record.clone()).await.unwrap();
+    assert_eq!(fs::read_to_string(&output).unwrap(), "hello\n");
+
+    // Records are held until the interval passed
+    let (mut interval, output) = writer("interval.txt", &["--flush-interval", "100"]);
+    interval.feed(record.clone()).await.unwrap();
+    poll_fn(|cx| Pin::new(&mut interval).poll_flush(cx))
+        .await
+        .unwrap();
+    assert_eq!(fs::read_to_string(&output).unwrap(), "");
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    poll_fn(|cx| Pin::new(&mut interval).poll_flush(cx))
+        .await
+        .unwrap();
+    assert_eq!(fs::read_to_string(&output).unwrap(), "hello\n");
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+/// Record `time` usable in file names like "01-10-23_02_59.028"
+fn file_time(time: &str) -> String {
+    time.split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .replace([':', '/', '\\'], "_")
+}
+
+#[test]
+fn capture_window_files() {
+    use clap::Parser;
+
+    let dir = std::env::temp_dir().join(format!("rogcat-windows-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    let output = dir.join("capture.log");
+    let args =
+        CliArguments::parse_from(["rogcat", "-o", output.to_str().unwrap(), "-a", "enumerate"]);
+    let config = TextConfig {
+        format: Format::Raw,
+        columns: None,
+        level_style: LevelStyle::Word,
+        template: None,
+        session: None,
+        props: None,
+    };
+    let windows = Windows::default();
+    let mut writer = FileWriter::<Textfile>::from_args(args, config)
+        .unwrap()
+        .split_windows(windows.clone());
+    let record = |message: &str| Record {
+        raw: message.to_owned(),
+        ..Default::default()
+    };
+
+    // Every window starts a new file named after the time of the trigger
+    for (window, time) in [("a", "01-10 23:02:59.028"), ("b", "01-10 23:01:00.000")] {
+        windows.open(Some(time));
+        writer.write(&record(window)).unwrap();
+        writer.write(&record(window)).unwrap();
+    }
+    writer.flush().unwrap();
+
+    let mut files = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect::<Vec<_>>();
+    files.sort();
+    let names = files
+        .iter()
+        .map(|f| f.file_name().unwrap().to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "capture-01-10-23_01_00.000-001.log",
+            "capture-01-10-23_02_59.028-000.log"
+        ]
+    );
+    let contents = files
+        .iter()
+        .map(|f| fs::read_to_string(f).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(contents, ["b\nb\n", "a\na\n"]);
+    fs::remove_dir_all(&dir).ok();
+}
@@ -18,12 +18,74 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::{collections::HashSet, iter::FromIterator};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    future::ready,
+    iter,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use crate::{cli::CliArguments, profiles::Profile, reader::get_processes_pids};
-use failure::{format_err, Error};
+use crate::{
+    events,
+    parser::Parser,
+    pipeline::Source,
+    reader::{self, get_processes_pids},
+    record::{Level, Record},
+    Error, StreamData,
+};
+use futures::{future::BoxFuture, StreamExt};
 use regex::Regex;
-use rogcat::record::{Level, Record};
+use time::OffsetDateTime;
+use tokio::time::sleep;
+
+/// Errors of invalid filter settings
+#[derive(Debug, thiserror::Error)]
+pub enum FilterError {
+    #[error("Invalid regex string: {0}: {1}")]
+    Regex(String, regex::Error),
+    #[error("Invalid field filter {0}. Expected key=regex")]
+    Field(String),
+    #[error("Invalid time {0}. Use a timestamp like \"01-10 23:02:59.000\" or a duration like 30s, 5m, 2h or 1d")]
+    Time(String),
+    #[error("Failed to find a timestamp in the input files")]
+    NoTimestamp,
+}
+
+/// Filter settings. The filters of each list are RE2 regular expressions.
+/// The prefix `!` inverts a match and a leading `\!` matches a literal `!`.
+/// Duplicates are ignored.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FilterSpec {
+    /// Minimum level
+    pub level: Level,
+    pub tag: Vec<String>,
+    pub tag_ignore_case: Vec<String>,
+    pub message: Vec<String>,
+    pub message_ignore_case: Vec<String>,
+    pub pid: Vec<String>,
+    /// Thread ids
+    pub tid: Vec<String>,
+    /// Names of the processes whose records pass
+    pub process_name: Vec<String>,
+    /// Filters matching any of tag, pid, thread, message or device
+    pub regex: Vec<String>,
+    /// Filters on extracted fields in the form key=regex
+    pub fields: Vec<String>,
+    /// Log buffers of records read with split buffers
+    pub buffer: Vec<String>,
+    /// Start of the time window as timestamp or duration like 5m
+    pub since: Option<String>,
+    /// End of the time window as timestamp or duration like 5m
+    pub until: Option<String>,
+    /// Drop records without timestamp when filtering on time
+    pub drop_untimed: bool,
+    /// Seconds between queries of the pids of the process names. Defaults
+    /// to 3 and 0 disables the refresh.
+    pub process_refresh: Option<u64>,
+}
 
 /// Configured filters
 #[derive(Debug, Default)]
@@ -34,101 +96,916 @@ pub struct Filter {
     message: FilterGroup,
     message_ignore_case: FilterGroup,
     pid: FilterGroup,
+    tid: FilterGroup,
     process_name: FilterGroup,
     regex: FilterGroup,
+    /// Filters on extracted fields by key
+    fields: BTreeMap<String, FilterGroup>,
+    buffer: FilterGroup,
+    /// Time window of --since and --until
+    time: TimeFilter,
+    /// Pids of processes selected by name
+    tracked_pids: HashSet<String>,
+    /// Pids of processes excluded by name
+    excluded_pids: HashSet<String>,
+    /// Pids of the watched processes refreshed from the device
+    pids_update: Option<PidsUpdate>,
+    /// Last applied pids of the refresh
+    pids: ProcessPids,
+    /// Replacement filters, e.g. from a reloaded profile
+    reload: FilterReload,
+}
+
+/// Replacement filters and the optional notices of the replacements since
+/// the last record
+#[derive(Debug, Default)]
+struct PendingReload {
+    filter: Option<Filter>,
+    notices: Vec<Option<String>>,
+}
+
+/// Handle to replace the filters of a running [`Filter`]. The time window,
+/// the process refresh and the pids learned from the events buffer are kept.
+#[derive(Clone, Debug, Default)]
+pub struct FilterReload(Arc<Mutex<PendingReload>>);
+
+impl FilterReload {
+    /// Build the filters of `spec` and apply them before the next record.
+    /// The current filters stay active if `spec` is invalid.
+    pub fn reload(&self, spec: &FilterSpec) -> Result<(), FilterError> {
+        self.replace(spec, None)
+    }
+
+    /// Like [`FilterReload::reload`] but the replacement is noted with
+    /// `notice` instead of "Reloaded filters"
+    pub fn reload_with_notice(&self, spec: &FilterSpec, notice: String) -> Result<(), FilterError> {
+        self.replace(spec, Some(notice))
+    }
+
+    fn replace(&self, spec: &FilterSpec, notice: Option<String>) -> Result<(), FilterError> {
+        let filter = Filter::from_spec(spec)?;
+        let mut pending = self.0.lock().expect("Poisoned lock");
+        pending.filter = Some(filter);
+        pending.notices.push(notice);
+        Ok(())
+    }
+}
+
+/// Result of [`Filter::decide`]
+#[derive(Debug, PartialEq)]
+pub enum FilterDecision<'a> {
+    Pass,
+    Drop(Rejection<'a>),
+}
+
+/// The filter that dropped a record
+#[derive(Debug, PartialEq)]
+pub struct Rejection<'a> {
+    /// Name of the filter like "tag" or the key of a field filter
+    pub filter: &'a str,
+    /// True if `filter` is the key of a field filter
+    pub field: bool,
+    /// The negative pattern that matched or `None` if no positive pattern
+    /// matched
+    pub pattern: Option<&'a str>,
+}
+
+impl<'a> Rejection<'a> {
+    fn new(filter: &'static str, pattern: Option<&'a Regex>) -> Rejection<'a> {
+        Rejection {
+            filter,
+            field: false,
+            pattern: pattern.map(Regex::as_str),
+        }
+    }
+}
+
+impl Display for Rejection<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (self.field, self.filter) {
+            (true, key) => write!(f, "Dropped by field filter {key}")?,
+            (false, "level") => return write!(f, "Dropped by level"),
+            (false, "time") => return write!(f, "Dropped by --since/--until"),
+            (false, "excluded_process") => {
+                return write!(f, "Dropped by a negative process_name pattern")
+            }
+            (false, filter) => write!(f, "Dropped by {filter} filter")?,
+        }
+        match self.pattern {
+            Some(pattern) => write!(f, " \"!{pattern}\""),
+            None => write!(f, " (no pattern matched)"),
+        }
+    }
+}
+
+/// Pids of running processes matching or excluded by the process name filters
+#[derive(Clone, Debug, Default)]
+struct ProcessPids {
+    tracked: HashSet<String>,
+    excluded: HashSet<String>,
+}
+
+/// Shared result of the latest process list query. Taken by the filter.
+type PidsUpdate = Arc<Mutex<Option<ProcessPids>>>;
+
+/// Pattern of `filter` and whether it is inverted by the prefix `!`. The
+/// escape `\!` starts a pattern with a literal `!`.
+fn negation(filter: &str) -> (&str, bool) {
+    match filter.strip_prefix('!') {
+        Some(pattern) => (pattern, true),
+        None => match filter.strip_prefix('\\') {
+            Some(pattern) if pattern.starts_with('!') => (pattern, false),
+            _ => (filter, false),
+        },
+    }
+}
+
+/// Split process names into selected and excluded names
+fn process_names(names: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut positive = Vec::new();
+    let mut negative = Vec::new();
+    for name in names {
+        match negation(name) {
+            (name, true) => negative.push(name.to_owned()),
+            (name, false) => positive.push(name.to_owned()),
+        }
+    }
+    (positive, negative)
+}
+
+/// Looks up the pids of running processes by name
+pub trait ProcessLookup: Send + Sync + 'static {
+    /// Pids of the processes named `names` on `device`
+    fn pids(
+        &self,
+        names: Vec<String>,
+        device: Option<String>,
+    ) -> BoxFuture<'static, Result<Vec<String>, Error>>;
+}
+
+/// Lists the processes with adb shell ps
+pub struct AdbProcesses;
+
+impl ProcessLookup for AdbProcesses {
+    fn pids(
+        &self,
+        names: Vec<String>,
+        device: Option<String>,
+    ) -> BoxFuture<'static, Result<Vec<String>, Error>> {
+        Box::pin(async move { get_processes_pids(&names, device.as_deref()).await })
+    }
+}
+
+/// Query the pids of the processes on every followed device. The pids of
+/// all devices are merged because records are filtered by pid only.
+async fn get_all_pids(
+    lookup: &dyn ProcessLookup,
+    devices: &[String],
+    positive: &[String],
+    negative: &[String],
+) -> Result<ProcessPids, Error> {
+    let mut pids = ProcessPids::default();
+    let devices = if devices.is_empty() {
+        vec![None]
+    } else {
+        devices.iter().cloned().map(Some).collect()
+    };
+    for device in devices {
+        if !positive.is_empty() {
+            pids.tracked
+                .extend(lookup.pids(positive.to_vec(), device.clone()).await?);
+        }
+        if !negative.is_empty() {
+            pids.excluded
+                .extend(lookup.pids(negative.to_vec(), device).await?);
+        }
+    }
+    Ok(pids)
+}
+
+/// Query the pids of the watched processes every `interval` in the
+/// background. Failed queries keep the pids. The task ends when the filter is
+/// dropped.
+fn refresh_pids(
+    lookup: Arc<dyn ProcessLookup>,
+    devices: Vec<String>,
+    positive: Vec<String>,
+    negative: Vec<String>,
+    interval: Duration,
+) -> PidsUpdate {
+    let update = Arc::new(Mutex::new(None));
+    let weak = Arc::downgrade(&update);
+    tokio::spawn(async move {
+        loop {
+            sleep(interval).await;
+            let Ok(pids) = get_all_pids(lookup.as_ref(), &devices, &positive, &negative).await
+            else {
+                continue;
+            };
+            match weak.upgrade() {
+                Some(update) => *update.lock().expect("Poisoned lock") = Some(pids),
+                None => break,
+            }
+        }
+    });
+    update
+}
+
+/// Group the key=regex field filters by key
+fn field_filters(args: &[String]) -> Result<BTreeMap<String, FilterGroup>, FilterError> {
+    let mut regexes: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for arg in args {
+        let (key, regex) = arg
+            .split_once('=')
+            .ok_or_else(|| FilterError::Field(arg.to_owned()))?;
+        regexes
+            .entry(key.to_owned())
+            .or_default()
+            .push(regex.to_owned());
+    }
+    regexes
+        .into_iter()
+        .map(|(key, regexes)| {
+            Ok((
+                key,
+                FilterGroup::from_args(&regexes, std::iter::empty(), false)?,
+            ))
+        })
+        .collect()
+}
+
+/// Default seconds between process list queries
+const DEFAULT_PROCESS_REFRESH: u64 = 3;
+
+const SECOND: u64 = 1000;
+const MINUTE: u64 = 60 * SECOND;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+/// Leap years have the most days and every date must fit in
+const YEAR: u64 = 366 * DAY;
+/// Days before the first of a month in a leap year
+const DAYS_BEFORE_MONTH: [u64; 12] = [0, 31, 60, 91, 121, 152, 182, 213, 244, 274, 305, 335];
+
+/// Milliseconds since the start of the year. Logcat timestamps carry no
+/// year, so timestamps are ordered by the shorter distance around the turn
+/// of the year.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    fn new(month: u64, day: u64, hour: u64, minute: u64, second: u64, millis: u64) -> Option<Self> {
+        if !(1..=12).contains(&month) {
+            return None;
+        }
+        let first = DAYS_BEFORE_MONTH[month as usize - 1];
+        let next = DAYS_BEFORE_MONTH.get(month as usize).copied();
+        let days = next.unwrap_or(YEAR / DAY) - first;
+        if !(1..=days).contains(&day) || hour > 23 || minute > 59 || second > 59 || millis > 999 {
+            return None;
+        }
+        Some(Timestamp(
+            (first + day - 1) * DAY + hour * HOUR + minute * MINUTE + second * SECOND + millis,
+        ))
+    }
+
+    /// Parse "MM-DD HH:MM:SS.mmm" with an optional year prefix and an
+    /// optional fraction of any precision
+    pub fn parse(value: &str) -> Option<Self> {
+        let (date, time) = value.trim().split_once(' ')?;
+        let mut date = date.rsplitn(3, '-');
+        let day = date.next()?.parse().ok()?;
+        let month = date.next()?.parse().ok()?;
+
+        let (time, fraction) = time.trim().split_once('.').unwrap_or((time, "0"));
+        let mut time = time.splitn(3, ':');
+        let hour = time.next()?.parse().ok()?;
+        let minute = time.next()?.parse().ok()?;
+        let second = time.next()?.parse().ok()?;
+        if fraction.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let millis = format!("{:0<3}", &fraction[..fraction.len().min(3)])
+            .parse()
+            .ok()?;
+
+        Timestamp::new(month, day, hour, minute, second, millis)
+    }
+
+    fn now() -> Self {
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        Timestamp::new(
+            now.month() as u64,
+            now.day() as u64,
+            now.hour() as u64,
+            now.minute() as u64,
+            now.second() as u64,
+            now.millisecond() as u64,
+        )
+        .expect("Invalid local time")
+    }
+
+    fn sub(self, millis: u64) -> Self {
+        Timestamp((self.0 + YEAR - millis % YEAR) % YEAR)
+    }
+
+    /// True if `self` is less than half a year before `other`
+    pub fn is_before(self, other: Timestamp) -> bool {
+        let distance = (other.0 + YEAR - self.0) % YEAR;
+        distance != 0 && distance < YEAR / 2
+    }
+
+    /// Milliseconds between `self` and `other` in either direction
+    pub fn distance(self, other: Timestamp) -> u64 {
+        let distance = (other.0 + YEAR - self.0) % YEAR;
+        distance.min(YEAR - distance)
+    }
+
+    /// Milliseconds from `self` to `other`. Negative if `other` is before
+    /// `self`.
+    pub fn elapsed(self, other: Timestamp) -> i64 {
+        let distance = self.distance(other) as i64;
+        if other.is_before(self) {
+            -distance
+        } else {
+            distance
+        }
+    }
+}
+
+impl Display for Timestamp {
+    /// Format like "01-10 23:02:59.028"
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let day_of_year = self.0 / DAY;
+        let month = DAYS_BEFORE_MONTH.partition_point(|&days| days <= day_of_year);
+        let day = day_of_year - DAYS_BEFORE_MONTH[month - 1] + 1;
+        write!(
+            f,
+            "{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+            month,
+            day,
+            self.0 % DAY / HOUR,
+            self.0 % HOUR / MINUTE,
+            self.0 % MINUTE / SECOND,
+            self.0 % SECOND
+        )
+    }
+}
+
+/// Start time of `since` like "01-10 23:02:59.000" or a duration like 5m
+/// before now
+pub fn since_now(since: &str) -> Result<Timestamp, FilterError> {
+    Ok(TimeArg::parse(since)?.resolve(Timestamp::now()))
+}
+
+/// Argument of --since and --until
+#[derive(Debug, PartialEq)]
+enum TimeArg {
+    Absolute(Timestamp),
+    /// Milliseconds before the reference time
+    Relative(u64),
+}
+
+/// Milliseconds of a duration like "500ms", "1.5s", "5m", "2h" or "1d".
+/// Plain numbers are seconds.
+pub fn parse_duration(value: &str) -> Option<u64> {
+    let (number, unit) = value.split_at(
+        value
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(value.len()),
+    );
+    let unit = match unit {
+        "ms" => 1,
+        "" | "s" => SECOND,
+        "m" => MINUTE,
+        "h" => HOUR,
+        "d" => DAY,
+        _ => return None,
+    };
+    // Whole numbers are exact
+    if let Ok(number) = number.parse::<u64>() {
+        return number.checked_mul(unit);
+    }
+    let millis = number.parse::<f64>().ok()? * unit as f64;
+    (millis.is_finite() && millis < u64::MAX as f64).then_some(millis as u64)
+}
+
+impl TimeArg {
+    fn parse(value: &str) -> Result<TimeArg, FilterError> {
+        if let Some(timestamp) = Timestamp::parse(value) {
+            return Ok(TimeArg::Absolute(timestamp));
+        }
+        parse_duration(value)
+            .map(TimeArg::Relative)
+            .ok_or_else(|| FilterError::Time(value.to_owned()))
+    }
+
+    fn resolve(&self, reference: Timestamp) -> Timestamp {
+        match self {
+            TimeArg::Absolute(timestamp) => *timestamp,
+            TimeArg::Relative(millis) => reference.sub(*millis),
+        }
+    }
+}
+
+/// Filter on the record timestamps
+#[derive(Debug, Default)]
+struct TimeFilter {
+    since: Option<Timestamp>,
+    until: Option<Timestamp>,
+    /// Drop records without parseable timestamp instead of passing them
+    drop_untimed: bool,
+}
+
+impl TimeFilter {
+    async fn new(
+        spec: &FilterSpec,
+        source: &Source,
+        parser: &mut Parser,
+    ) -> Result<TimeFilter, Error> {
+        let since = spec.since.as_deref().map(TimeArg::parse).transpose()?;
+        let until = spec.until.as_deref().map(TimeArg::parse).transpose()?;
+
+        let relative = |arg: &Option<TimeArg>| matches!(arg, Some(TimeArg::Relative(_)));
+        let reference = if !relative(&since) && !relative(&until) {
+            Timestamp(0)
+        } else if let Source::Files(files) = source {
+            newest(files.clone(), parser).await?
+        } else {
+            Timestamp::now()
+        };
+
+        Ok(TimeFilter {
+            since: since.map(|t| t.resolve(reference)),
+            until: until.map(|t| t.resolve(reference)),
+            drop_untimed: spec.drop_untimed,
+        })
+    }
+
+    fn filter(&self, time: Option<&str>) -> bool {
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        match time.and_then(Timestamp::parse) {
+            Some(time) => {
+                !self.since.is_some_and(|since| time.is_before(since))
+                    && !self.until.is_some_and(|until| until.is_before(time))
+            }
+            None => !self.drop_untimed,
+        }
+    }
 }
 
-async fn get_all_pids(procs: Option<Vec<String>>, profile: &mut Profile) {
-    if let Some(processes) = procs {
-        profile.process_name.extend(processes);
+/// Find the newest timestamp in `files`
+async fn newest(files: Vec<PathBuf>, parser: &Parser) -> Result<Timestamp, Error> {
+    let newer = |newest: Option<Timestamp>, time: Option<String>| match (
+        newest,
+        time.as_deref().and_then(Timestamp::parse),
+    ) {
+        (Some(newest), Some(time)) if time.is_before(newest) => Some(newest),
+        (newest, time) => time.or(newest),
+    };
+    let mut newest = None;
+    for file in files {
+        // Each file is parsed from its first line like the records
+        let mut parser = parser.fork();
+        newest = Box::into_pin(reader::file(file).await?)
+            .fold(newest, |newest: Option<Timestamp>, data| {
+                let time = match data {
+                    StreamData::Line(line)
+                    | StreamData::ErrorLine(line)
+                    | StreamData::DeviceLine(_, line)
+                    | StreamData::BufferLine { line, .. } => {
+                        parser.parse(&line).and_then(|r| r.time)
+                    }
+                    StreamData::Record(record) => record.time,
+                    StreamData::Diagnostic(..) => None,
+                };
+                ready(newer(newest, time))
+            })
+            .await;
+        newest = iter::from_fn(|| parser.finish()).fold(newest, |newest, r| newer(newest, r.time));
+    }
+    newest.ok_or_else(|| FilterError::NoTimestamp.into())
+}
+
+impl Filter {
+    /// Create a filter for the records of `source` parsed with `parser`.
+    /// Relative times are resolved against the newest record of input files.
+    /// The pids of the processes selected by name are queried from the
+    /// device if `source` is adb logcat.
+    pub async fn new(
+        spec: FilterSpec,
+        source: &Source,
+        parser: &mut Parser,
+    ) -> Result<Filter, Error> {
+        Filter::with_processes(spec, source, parser, Arc::new(AdbProcesses)).await
+    }
+
+    /// Like [`Filter::new`] but the pids of the processes selected by name
+    /// are queried from `lookup`. Other sources than adb logcat never query
+    /// the pids.
+    pub async fn with_processes(
+        spec: FilterSpec,
+        source: &Source,
+        parser: &mut Parser,
+        lookup: Arc<dyn ProcessLookup>,
+    ) -> Result<Filter, Error> {
+        let time = TimeFilter::new(&spec, source, parser).await?;
+        let (positive, negative) = process_names(&spec.process_name);
+        let (pids, pids_update) = match source {
+            Source::Logcat(options) if !positive.is_empty() || !negative.is_empty() => {
+                let pids =
+                    get_all_pids(lookup.as_ref(), &options.devices, &positive, &negative).await?;
+                // Processes started later are only visible in the events
+                // buffer. Refresh the pids periodically.
+                let refresh = spec.process_refresh.unwrap_or(DEFAULT_PROCESS_REFRESH);
+                let pids_update = (refresh > 0).then(|| {
+                    refresh_pids(
+                        lookup,
+                        options.devices.clone(),
+                        positive,
+                        negative,
+                        Duration::from_secs(refresh),
+                    )
+                });
+                (pids, pids_update)
+            }
+            _ => (ProcessPids::default(), None),
+        };
+
+        Ok(Filter {
+            time,
+            tracked_pids: pids.tracked.clone(),
+            excluded_pids: pids.excluded.clone(),
+            pids_update,
+            pids,
+            ..Filter::from_spec(&spec)?
+        })
+    }
+
+    /// Filters of `spec` without time window and process tracking
+    fn from_spec(spec: &FilterSpec) -> Result<Filter, FilterError> {
+        let group = |filters: &[String], ignore_case| {
+            FilterGroup::from_args(filters, std::iter::empty(), ignore_case)
+        };
+
+        Ok(Filter {
+            level: spec.level.clone(),
+            tag: group(&spec.tag, false)?,
+            tag_ignore_case: group(&spec.tag_ignore_case, true)?,
+            message: group(&spec.message, false)?,
+            message_ignore_case: group(&spec.message_ignore_case, true)?,
+            pid: group(&spec.pid, false)?,
+            tid: group(&spec.tid, false)?,
+            process_name: group(&spec.process_name, false)?,
+            regex: group(&spec.regex, false)?,
+            fields: field_filters(&spec.fields)?,
+            buffer: group(&spec.buffer, false)?,
+            ..Default::default()
+        })
     }
-    if !profile.process_name.is_empty() {
-        profile
-            .pid
-            .extend(get_processes_pids(&profile.process_name).await);
+
+    /// Replace the filters with the ones passed to `reload`
+    pub fn with_reload(mut self, reload: FilterReload) -> Filter {
+        self.reload = reload;
+        self
+    }
+
+    /// Apply pending filters of the reload handle. Returns the records
+    /// noting the replacements since the last call in their order.
+    pub fn reload(&mut self) -> Vec<Record> {
+        let Some((filter, notices)) = self.reload.0.try_lock().ok().and_then(|mut pending| {
+            let notices = std::mem::take(&mut pending.notices);
+            pending.filter.take().map(|filter| (filter, notices))
+        }) else {
+            return Vec::new();
+        };
+        *self = Filter {
+            time: std::mem::take(&mut self.time),
+            tracked_pids: std::mem::take(&mut self.tracked_pids),
+            excluded_pids: std::mem::take(&mut self.excluded_pids),
+            pids_update: self.pids_update.take(),
+            pids: std::mem::take(&mut self.pids),
+            reload: self.reload.clone(),
+            ..filter
+        };
+        notices
+            .into_iter()
+            .map(|notice| match notice {
+                Some(notice) => Record::from_diagnostic(Level::Info, notice),
+                None => Record::from_diagnostic(Level::Info, "Reloaded filters".to_owned()),
+            })
+            .collect()
     }
 }
 
-pub async fn from_args_profile(args: CliArguments, profile: &mut Profile) -> Result<Filter, Error> {
-    get_all_pids(args.process_name, profile).await;
-    let pid = profile.pid.iter();
-    let process_name = profile.process_name.iter();
-    let tag = profile.tag.iter();
-    let tag_ignorecase = profile.tag_ignore_case.iter();
-    let message = profile.message.iter();
-    let message_ignorecase = profile.message_ignore_case.iter();
-    let regex = profile.regex.iter();
-    let filter = Filter {
-        level: Level::from(args.level),
-        tag: FilterGroup::from_args(&args.tag, tag, false)?,
-        tag_ignore_case: FilterGroup::from_args(&args.tag_ignore_case, tag_ignorecase, true)?,
-        message: FilterGroup::from_args(&args.message, message, false)?,
-        message_ignore_case: FilterGroup::from_args(
-            &args.message_ignore_case,
-            message_ignorecase,
-            true,
-        )?,
-        pid: FilterGroup::from_args(&args.pid, pid, false)?,
-        process_name: FilterGroup::from_args(&Vec::new(), process_name, false)?,
-        regex: FilterGroup::from_args(&args.regex_filter, regex, false)?,
+fn is_number(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Pid and process name of an am_proc_start event. The values are
+/// "user,pid,uid,name,type,component". Android versions before multi user
+/// support omit the user.
+fn am_proc_start(message: &str) -> Option<(&str, &str)> {
+    let values = events::values(message);
+    let (pid, uid, name) = match values.as_slice() {
+        [_, pid, uid, name, _, _, ..] if is_number(pid) && is_number(uid) => (pid, uid, name),
+        [pid, uid, name, _, _] => (pid, uid, name),
+        _ => return None,
     };
+    (is_number(pid) && is_number(uid) && !name.is_empty()).then_some((pid, name))
+}
 
-    Ok(filter)
+/// Pid of an am_kill or am_proc_died event. Both start with "user,pid" or
+/// just the pid on Android versions without multi user support.
+fn am_proc_died(message: &str) -> Option<&str> {
+    match events::values(message).as_slice() {
+        [_, pid, ..] if is_number(pid) => Some(pid),
+        [pid, ..] if is_number(pid) => Some(pid),
+        _ => None,
+    }
+}
+
+/// Pid of the process that died if `record` is an am_kill or am_proc_died
+/// event
+pub(crate) fn died_pid(record: &Record) -> Option<&str> {
+    match record.tag.as_str() {
+        "am_kill" | "am_proc_died" => match record.fields.get("pid") {
+            Some(pid) => Some(pid.as_str()),
+            None => am_proc_died(&record.message),
+        },
+        _ => None,
+    }
+}
+
+/// Pid and process name if `record` is an am_proc_start event
+pub(crate) fn started_process(record: &Record) -> Option<(&str, &str)> {
+    if record.tag != "am_proc_start" {
+        return None;
+    }
+    // Decoded events carry their values in the fields
+    match (record.fields.get("pid"), record.fields.get("process_name")) {
+        (Some(pid), Some(name)) => Some((pid.as_str(), name.as_str())),
+        _ => am_proc_start(&record.message),
+    }
+}
+
+/// Device and pid of a process
+type ProcessKey = (Option<String>, String);
+
+/// Name and start time of a process
+#[derive(Clone, Debug)]
+struct ProcessStart {
+    name: String,
+    /// Time of the am_proc_start event
+    time: Option<String>,
+}
+
+/// Names and start times of the processes by device and pid learned from
+/// the am_proc_start events of the records read. Shared by the pipeline and
+/// the outputs.
+#[derive(Clone, Debug, Default)]
+pub struct Processes(Arc<Mutex<HashMap<ProcessKey, ProcessStart>>>);
+
+impl Processes {
+    /// Remember the process started by `record` or forget the one that
+    /// died. Other records are ignored.
+    pub fn observe(&self, record: &Record) {
+        let device = record.device.clone();
+        if let Some((pid, name)) = started_process(record) {
+            let start = ProcessStart {
+                name: name.to_owned(),
+                time: record.time.clone(),
+            };
+            self.0
+                .lock()
+                .expect("Poisoned lock")
+                .insert((device, pid.to_owned()), start);
+        } else if let Some(pid) = died_pid(record) {
+            self.0
+                .lock()
+                .expect("Poisoned lock")
+                .remove(&(device, pid.to_owned()));
+        }
+    }
+
+    fn get(&self, device: Option<&str>, pid: &str) -> Option<ProcessStart> {
+        let key = (device.map(str::to_owned), pid.to_owned());
+        self.0.lock().expect("Poisoned lock").get(&key).cloned()
+    }
+
+    /// Name of the process `pid` of `device` if its start was seen
+    pub fn name(&self, device: Option<&str>, pid: &str) -> Option<String> {
+        self.get(device, pid).map(|start| start.name)
+    }
+
+    /// Time of the am_proc_start event of the process `pid` of `device`
+    pub fn start_time(&self, device: Option<&str>, pid: &str) -> Option<String> {
+        self.get(device, pid).and_then(|start| start.time)
+    }
 }
 
 impl Filter {
     pub fn filter(&mut self, record: &Record) -> bool {
+        if let Some(decision) = self.track(record) {
+            return decision == FilterDecision::Pass;
+        }
+
+        let buffer = record.buffer.as_deref().unwrap_or_default();
+        self.message.filter(&record.message)
+            && self.message_ignore_case.filter(&record.message)
+            && self.tag.filter(&record.tag)
+            && self.tag_ignore_case.filter(&record.tag)
+            && self.check_pid(&record.process).is_ok()
+            && self.tid.filter(&record.thread)
+            && (self.regex.filter(&record.process)
+                || self.regex.filter(&record.thread)
+                || self.regex.filter(&record.tag)
+                || self.regex.filter(&record.message)
+                || record.device.as_ref().is_some_and(|d| self.regex.filter(d)))
+            && self.check_fields(record).is_ok()
+            && self.buffer.filter(buffer)
+            && self.time.filter(record.time.as_deref())
+    }
+
+    /// Check `record` like [`Filter::filter`] and name the filter that
+    /// dropped it
+    pub fn decide(&mut self, record: &Record) -> FilterDecision<'_> {
+        if let Some(decision) = self.track(record) {
+            return decision;
+        }
+
+        let this = &*self;
+        let result = this
+            .check_text(record)
+            .and_then(|_| this.check_pid(&record.process))
+            .and_then(|_| this.tid.check_named("tid", &record.thread))
+            .and_then(|_| {
+                // Passes if any item passes. The first negative pattern
+                // that matched is named.
+                let items = [
+                    &record.process,
+                    &record.thread,
+                    &record.tag,
+                    &record.message,
+                ]
+                .into_iter()
+                .map(String::as_str)
+                .chain(record.device.as_deref());
+                let mut pattern = None;
+                for item in items {
+                    match this.regex.check(item) {
+                        Ok(()) => return Ok(()),
+                        Err(negative) => pattern = pattern.or(negative),
+                    }
+                }
+                Err(Rejection::new("regex", pattern))
+            })
+            .and_then(|_| this.check_fields(record))
+            .and_then(|_| {
+                let buffer = record.buffer.as_deref().unwrap_or_default();
+                this.buffer.check_named("buffer", buffer)
+            })
+            .and_then(|_| match this.time.filter(record.time.as_deref()) {
+                true => Ok(()),
+                false => Err(Rejection::new("time", None)),
+            });
+        match result {
+            Ok(()) => FilterDecision::Pass,
+            Err(rejection) => FilterDecision::Drop(rejection),
+        }
+    }
+
+    /// Check a notice of logcat like a buffer divider against the tag and
+    /// message filters. The other filters don't apply to notices.
+    pub fn decide_notice(&self, record: &Record) -> FilterDecision<'_> {
+        match self.check_text(record) {
+            Ok(()) => FilterDecision::Pass,
+            Err(rejection) => FilterDecision::Drop(rejection),
+        }
+    }
+
+    /// Check the message and tag filters
+    fn check_text(&self, record: &Record) -> Result<(), Rejection<'_>> {
+        self.message
+            .check_named("message", &record.message)
+            .and_then(|_| {
+                self.message_ignore_case
+                    .check_named("message_ignore_case", &record.message)
+            })
+            .and_then(|_| self.tag.check_named("tag", &record.tag))
+            .and_then(|_| {
+                self.tag_ignore_case
+                    .check_named("tag_ignore_case", &record.tag)
+            })
+    }
+
+    /// Check the level and track the processes selected by name. Returns
+    /// the decision if it doesn't depend on the other filters.
+    fn track(&mut self, record: &Record) -> Option<FilterDecision<'static>> {
         if record.level < self.level {
-            return false;
+            return Some(FilterDecision::Drop(Rejection::new("level", None)));
         }
 
+        self.apply_pids_update();
+
         match record.tag.as_ref() {
             "am_proc_start" if !self.process_name.is_empty() => {
-                let parts = record.message.splitn(5, ',').collect::<Vec<&str>>();
-                let pid = parts[1];
-                let name = parts[3];
-                if self.process_name.filter(name)
-                    && !self
-                        .pid
-                        .positive
-                        .iter()
-                        // Prevents adding duplicates
-                        .any(|x| x.is_match(pid))
-                {
-                    self.pid.positive.push(Regex::new(pid).unwrap());
-                    return true;
+                if let Some((pid, name)) = started_process(record) {
+                    if self.process_name.negative.iter().any(|r| r.is_match(name)) {
+                        // Drop records of this pid until the process dies
+                        self.excluded_pids.insert(pid.to_owned());
+                    } else if self.process_name.positive.iter().any(|r| r.is_match(name))
+                        && self.tracked_pids.insert(pid.to_owned())
+                    {
+                        return Some(FilterDecision::Pass);
+                    }
                 }
             }
-            "am_kill" | "am_proc_died" => {
-                let parts = record.message.splitn(3, ',').collect::<Vec<&str>>();
-                let pid = parts[1];
-                if self.pid.filter(pid) {
-                    if let Ok(index) = self
-                        .pid
-                        .positive
-                        .binary_search_by_key(&pid.to_string(), |x| x.to_string())
-                    {
-                        self.pid.positive.remove(index);
-                        return true;
+            "am_kill" | "am_proc_died"
+                if !self.tracked_pids.is_empty() || !self.excluded_pids.is_empty() =>
+            {
+                if let Some(pid) = died_pid(record) {
+                    self.excluded_pids.remove(pid);
+                    if self.tracked_pids.remove(pid) {
+                        return Some(FilterDecision::Pass);
                     }
                 }
             }
             _ => {}
         }
+        None
+    }
 
-        if !self.process_name.positive.is_empty() && self.pid.positive.is_empty() {
-            return false;
+    /// Records without a filtered field only pass negative filters
+    fn check_fields(&self, record: &Record) -> Result<(), Rejection<'_>> {
+        self.fields
+            .iter()
+            .try_for_each(|(key, group)| match record.fields.get(key) {
+                Some(value) => group.check(value).map_err(|pattern| Rejection {
+                    filter: key,
+                    field: true,
+                    pattern: pattern.map(Regex::as_str),
+                }),
+                None if group.positive.is_empty() => Ok(()),
+                None => Err(Rejection {
+                    filter: key,
+                    field: true,
+                    pattern: None,
+                }),
+            })
+    }
+
+    /// Add the pids of newly found processes and remove the ones of vanished
+    /// processes
+    fn apply_pids_update(&mut self) {
+        let Some(pids) = self
+            .pids_update
+            .as_ref()
+            .and_then(|update| update.try_lock().ok()?.take())
+        else {
+            return;
+        };
+        for pid in pids.tracked.difference(&self.pids.tracked) {
+            self.tracked_pids.insert(pid.clone());
+        }
+        for pid in self.pids.tracked.difference(&pids.tracked) {
+            self.tracked_pids.remove(pid);
         }
+        for pid in pids.excluded.difference(&self.pids.excluded) {
+            self.excluded_pids.insert(pid.clone());
+        }
+        for pid in self.pids.excluded.difference(&pids.excluded) {
+            self.excluded_pids.remove(pid);
+        }
+        self.pids = pids;
+    }
 
-        self.message.filter(&record.message)
-            && self.message_ignore_case.filter(&record.message)
-            && self.tag.filter(&record.tag)
-            && self.tag_ignore_case.filter(&record.tag)
-            && self.pid.filter(&record.process)
-            && (self.regex.filter(&record.process)
-                || self.regex.filter(&record.thread)
-                || self.regex.filter(&record.tag)
-                || self.regex.filter(&record.message))
+    /// Check pid against the pid filters and the pids of the processes
+    /// selected or excluded by name
+    fn check_pid(&self, pid: &str) -> Result<(), Rejection<'_>> {
+        if self.excluded_pids.contains(pid) {
+            return Err(Rejection::new("excluded_process", None));
+        }
+        if let Some(negative) = self.pid.negative.iter().find(|r| r.is_match(pid)) {
+            return Err(Rejection::new("pid", Some(negative)));
+        }
+
+        if self.pid.positive.is_empty() && self.tracked_pids.is_empty() {
+            // Nothing passes until a process with a matching name is started
+            return match self.process_name.positive.is_empty() {
+                true => Ok(()),
+                false => Err(Rejection::new("process_name", None)),
+            };
+        }
+
+        if self.tracked_pids.contains(pid) || self.pid.positive.iter().any(|r| r.is_match(pid)) {
+            Ok(())
+        } else if self.pid.positive.is_empty() {
+            Err(Rejection::new("process_name", None))
+        } else {
+            Err(Rejection::new("pid", None))
+        }
     }
 }
 
@@ -140,30 +1017,30 @@ struct FilterGroup {
 }
 
 impl FilterGroup {
+    /// Group of the filters of `args` followed by the ones of `merge`.
+    /// Duplicates are dropped and the order is kept.
     fn from_args<'a, T: Iterator<Item = &'a String>>(
         args: &'a [String],
         merge: T,
         ignore_case: bool,
-    ) -> Result<FilterGroup, Error> {
-        let mut filters: HashSet<&String> = HashSet::from_iter(args.iter());
-        filters.extend(merge);
-
+    ) -> Result<FilterGroup, FilterError> {
+        let mut seen = HashSet::new();
         let mut positive = vec![];
         let mut negative = vec![];
-        for r in filters.iter().map(|f| {
-            if ignore_case {
-                f.to_lowercase()
+        for filter in args.iter().chain(merge) {
+            let filter = if ignore_case {
+                filter.to_lowercase()
             } else {
-                (*f).to_string()
+                filter.clone()
+            };
+            if !seen.insert(filter.clone()) {
+                continue;
             }
-        }) {
-            if let Some(r) = r.strip_prefix('!') {
-                let r =
-                    Regex::new(r).map_err(|e| format_err!("Invalid regex string: {}: {}", r, e))?;
+            let (pattern, negated) = negation(&filter);
+            let r = Regex::new(pattern).map_err(|e| FilterError::Regex(pattern.to_owned(), e))?;
+            if negated {
                 negative.push(r);
             } else {
-                let r = Regex::new(&r)
-                    .map_err(|e| format_err!("Invalid regex string: {}: {}", r, e))?;
                 positive.push(r);
             }
         }
@@ -175,28 +1052,40 @@ impl FilterGroup {
         })
     }
 
+    /// Check `item` against the group. Without positive patterns an item
+    /// passes unless a negative pattern matches. With positive patterns it
+    /// must match at least one of them and no negative pattern.
     fn filter(&self, item: &str) -> bool {
-        if !self.positive.is_empty() {
-            if self.ignore_case {
-                let item = item.to_lowercase();
-                if !self.positive.iter().any(|m| m.is_match(&item)) {
-                    return false;
-                }
-            } else if !self.positive.iter().any(|m| m.is_match(item)) {
-                return false;
-            }
-        }
+        self.check(item).is_ok()
+    }
 
-        if !self.negative.is_empty() {
-            if self.ignore_case {
-                let item = item.to_lowercase();
-                return !self.negative.iter().any(|m| m.is_match(&item));
-            } else {
-                return !self.negative.iter().any(|m| m.is_match(item));
-            }
+    /// Like [`FilterGroup::filter`] but returns the negative pattern that
+    /// matched `item` or `None` if no positive pattern matched
+    fn check(&self, item: &str) -> Result<(), Option<&Regex>> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let lowercase;
+        let item = if self.ignore_case {
+            lowercase = item.to_lowercase();
+            lowercase.as_str()
+        } else {
+            item
+        };
+        if let Some(negative) = self.negative.iter().find(|m| m.is_match(item)) {
+            return Err(Some(negative));
+        }
+        if self.positive.is_empty() || self.positive.iter().any(|m| m.is_match(item)) {
+            Ok(())
+        } else {
+            Err(None)
         }
+    }
 
-        true
+    /// [`FilterGroup::check`] naming the filter `name` on rejection
+    fn check_named(&self, name: &'static str, item: &str) -> Result<(), Rejection<'_>> {
+        self.check(item)
+            .map_err(|pattern| Rejection::new(name, pattern))
     }
 
     fn is_empty(&self) -> bool {
@@ -285,7 +1174,7 @@ fn process_filter() {
     // Default filter lets anything pass
     assert!(filter.filter(&record));
     // Prevent regression with the bug that added pids for no reason
-    assert!(filter.pid.is_empty());
+    assert!(filter.tracked_pids.is_empty());
 
     // Filter the via browser
     filter.process_name.add_item("mark.via.gp", true);
@@ -299,8 +1188,7 @@ fn process_filter() {
     assert!(filter.filter(&record));
 
     // Checks if the pid was successfully added to the filter list
-    assert!(!filter.pid.is_empty());
-    assert!(filter.pid.positive.first().unwrap().is_match("22551"));
+    assert!(filter.tracked_pids.contains("22551"));
 
     // Different pid = gtfo
     record.process = "69420".to_string();
@@ -310,7 +1198,7 @@ fn process_filter() {
     record.tag = "am_proc_died".to_string();
     record.message = "[0,22551,com.termux,800,10]".to_string();
     assert!(filter.filter(&record));
-    assert!(filter.pid.is_empty());
+    assert!(filter.tracked_pids.is_empty());
 
     filter.process_name.positive.clear();
     // Just to make sure everything its passing
@@ -321,6 +1209,45 @@ fn process_filter() {
     assert!(!filter.filter(&record));
 }
 
+#[test]
+fn filtergroup_truth_table() {
+    // positive patterns, negative patterns, item, expected result
+    let table: &[(&[&str], &[&str], &str, bool)] = &[
+        (&[], &[], "Foo", true),
+        (&["foo"], &[], "foo", true),
+        (&["foo"], &[], "bar", false),
+        (&[], &["foo"], "foo", false),
+        (&[], &["foo"], "bar", true),
+        (&["foo"], &["bar"], "foo", true),
+        (&["foo"], &["bar"], "foobar", false),
+        (&["foo"], &["bar"], "bar", false),
+        (&["foo"], &["bar"], "baz", false),
+        (&["foo", "baz"], &["bar"], "baz", true),
+        (&["foo"], &["bar", "baz"], "foobaz", false),
+    ];
+
+    for ignore_case in [false, true] {
+        for (positive, negative, item, expected) in table {
+            let args = positive
+                .iter()
+                .map(|p| p.to_string())
+                .chain(negative.iter().map(|n| format!("!{n}")))
+                .collect::<Vec<_>>();
+            let group = FilterGroup::from_args(&args, [].iter(), ignore_case).unwrap();
+            assert_eq!(
+                group.filter(item),
+                *expected,
+                "{positive:?} {negative:?} {item} ignore_case={ignore_case}"
+            );
+            if ignore_case {
+                assert_eq!(group.filter(&item.to_uppercase()), *expected);
+            } else if *expected && !positive.is_empty() {
+                assert!(!group.filter(&item.to_uppercase()));
+            }
+        }
+    }
+}
+
 #[test]
 fn message_filter() {
     let mut filter = Filter::default();
@@ -345,6 +1272,11 @@ fn message_filter() {
     // Containing a positive and a negative match must return false
     filter.message.add_item("java", true);
     assert!(!filter.filter(&record));
+
+    // Also if both are in the same ignore case group
+    filter.message.positive.clear();
+    filter.message_ignore_case.add_item("java", true);
+    assert!(!filter.filter(&record));
 }
 
 #[test]
@@ -374,3 +1306,775 @@ fn filter_tag() {
     record.tag = "UrAnIuM".to_string();
     assert!(filter.filter(&record));
 }
+
+#[test]
+fn process_filter_negative() {
+    let mut filter = Filter::default();
+
+    let mut record = Record {
+        message:
+            "[0,22551,10201,com.termux,pre-top-activity,{com.termux/com.termux.app.TermuxActivity}]"
+                .to_string(),
+        tag: "am_proc_start".to_string(),
+        ..Default::default()
+    };
+
+    // Exclude termux
+    filter.process_name.add_item("com.termux", false);
+
+    // The start of other processes doesn't add anything
+    record.message = "[0,1234,10201,mark.via.gp,activity,{}]".to_string();
+    assert!(filter.filter(&record));
+    assert!(filter.tracked_pids.is_empty());
+    assert!(filter.excluded_pids.is_empty());
+
+    record.message =
+        "[0,22551,10201,com.termux,pre-top-activity,{com.termux/com.termux.app.TermuxActivity}]"
+            .to_string();
+    filter.filter(&record);
+    assert!(filter.tracked_pids.is_empty());
+    assert!(filter.excluded_pids.contains("22551"));
+
+    // Records of termux are dropped
+    record.tag = "Termux".to_string();
+    record.message = "hello".to_string();
+    record.process = "22551".to_string();
+    assert!(!filter.filter(&record));
+
+    // Pids containing the excluded one pass
+    record.process = "225510".to_string();
+    assert!(filter.filter(&record));
+
+    // Anything else passes
+    record.process = "69420".to_string();
+    assert!(filter.filter(&record));
+
+    // Remove pid when the process dies
+    record.tag = "am_proc_died".to_string();
+    record.message = "[0,22551,com.termux,800,10]".to_string();
+    assert!(filter.filter(&record));
+    assert!(filter.excluded_pids.is_empty());
+
+    // The pid is reused by a different process
+    record.tag = "Other".to_string();
+    record.message = "hello".to_string();
+    record.process = "22551".to_string();
+    assert!(filter.filter(&record));
+}
+
+#[test]
+fn pid_filter_negative() {
+    let mut filter = Filter {
+        pid: FilterGroup::from_args(&[String::from("!1234")], std::iter::empty(), false).unwrap(),
+        ..Default::default()
+    };
+
+    let mut record = Record {
+        process: "1234".to_string(),
+        ..Default::default()
+    };
+    assert!(!filter.filter(&record));
+
+    record.process = "4321".to_string();
+    assert!(filter.filter(&record));
+}
+
+#[test]
+fn tid_filter() {
+    let mut filter = Filter {
+        tid: FilterGroup::from_args(&[String::from("7842")], std::iter::empty(), false).unwrap(),
+        ..Default::default()
+    };
+
+    let mut record = Record {
+        process: "1234".to_string(),
+        thread: "7842".to_string(),
+        ..Default::default()
+    };
+    assert!(filter.filter(&record));
+
+    record.thread = "1234".to_string();
+    assert!(!filter.filter(&record));
+
+    // Both pid and tid have to match
+    filter.pid.add_item("1234", true);
+    record.thread = "7842".to_string();
+    assert!(filter.filter(&record));
+    record.process = "4321".to_string();
+    assert!(!filter.filter(&record));
+}
+
+#[test]
+fn tid_filter_negative() {
+    let mut filter = Filter {
+        tid: FilterGroup::from_args(&[String::from("!7842")], std::iter::empty(), false).unwrap(),
+        ..Default::default()
+    };
+
+    let mut record = Record {
+        thread: "7842".to_string(),
+        ..Default::default()
+    };
+    assert!(!filter.filter(&record));
+
+    record.thread = "2487".to_string();
+    assert!(filter.filter(&record));
+}
+
+#[test]
+fn field_filter() {
+    use crate::parser::extract_fields;
+
+    let mut filter = Filter {
+        fields: field_filters(&[String::from("state=^on$"), String::from("user=!root")]).unwrap(),
+        ..Default::default()
+    };
+    let mut record = Record {
+        fields: extract_fields("state=on user=nobody"),
+        ..Default::default()
+    };
+    assert!(filter.filter(&record));
+
+    record.fields = extract_fields("state=on user=root");
+    assert!(!filter.filter(&record));
+
+    record.fields = extract_fields("state=online");
+    assert!(!filter.filter(&record));
+
+    // A missing field only passes the negative filter
+    record.fields = extract_fields("state=on");
+    assert!(filter.filter(&record));
+    record.fields.clear();
+    assert!(!filter.filter(&record));
+
+    assert!(field_filters(&[String::from("state")]).is_err());
+    assert!(field_filters(&[String::from("state=(")]).is_err());
+}
+
+#[test]
+fn timestamp_parse() {
+    let time = Timestamp::parse("01-10 23:02:59.028").unwrap();
+    assert_eq!(Timestamp::parse("2024-01-10 23:02:59.028123"), Some(time));
+    assert_eq!(
+        Timestamp::parse("01-10 23:02:59"),
+        Timestamp::new(1, 10, 23, 2, 59, 0)
+    );
+    assert!(Timestamp::parse("1704927779.028").is_none());
+    assert!(Timestamp::parse("13-10 23:02:59.028").is_none());
+    assert!(Timestamp::parse("01-10 23:02:59.").is_none());
+    assert!(Timestamp::parse("01-10 23:02:75.000").is_none());
+    assert!(Timestamp::parse("01-10 23:60:00.000").is_none());
+    assert!(Timestamp::parse("01-10 24:00:00.000").is_none());
+    assert!(Timestamp::parse("04-31 23:02:59.000").is_none());
+    assert!(Timestamp::parse("02-29 23:02:59.000").is_some());
+    assert!(Timestamp::parse("12-31 23:59:59.999").is_some());
+
+    assert_eq!(TimeArg::parse("5m").unwrap(), TimeArg::Relative(5 * MINUTE));
+    assert_eq!(TimeArg::parse("2h").unwrap(), TimeArg::Relative(2 * HOUR));
+    assert_eq!(
+        TimeArg::parse("01-10 23:02:59.028").unwrap(),
+        TimeArg::Absolute(time)
+    );
+    assert!(TimeArg::parse("5y").is_err());
+    assert!(TimeArg::parse("m").is_err());
+    assert!(TimeArg::parse("99999999999999999d").is_err());
+    assert_eq!(parse_duration("500ms"), Some(500));
+    assert_eq!(parse_duration("1.5"), Some(1500));
+    assert_eq!(parse_duration("1d"), Some(DAY));
+    assert_eq!(parse_duration("1e400"), None);
+    assert_eq!(parse_duration("s"), None);
+}
+
+#[test]
+fn time_filter_rollover() {
+    let mut filter = Filter {
+        time: TimeFilter {
+            since: Timestamp::parse("12-31 23:59:59.000"),
+            until: Timestamp::parse("01-01 00:00:01.000"),
+            drop_untimed: false,
+        },
+        ..Default::default()
+    };
+    let mut pass = |time: &str| {
+        filter.filter(&Record {
+            time: Some(time.to_owned()),
+            ..Default::default()
+        })
+    };
+    assert!(!pass("12-31 23:59:58.999"));
+    assert!(pass("12-31 23:59:59.000"));
+    assert!(pass("01-01 00:00:00.500"));
+    assert!(pass("01-01 00:00:01.000"));
+    assert!(!pass("01-01 00:00:01.001"));
+    assert!(!pass("06-01 00:00:00.000"));
+
+    // Relative to the newest record across midnight
+    let newest = Timestamp::parse("01-11 00:01:00.000").unwrap();
+    let since = TimeArg::parse("5m").unwrap().resolve(newest);
+    assert_eq!(Timestamp::parse("01-10 23:56:00.000"), Some(since));
+    let since = TimeArg::parse("1h").unwrap().resolve(Timestamp(0));
+    assert_eq!(Timestamp::parse("12-31 23:00:00.000"), Some(since));
+}
+
+#[test]
+fn time_filter_out_of_order() {
+    let mut filter = Filter {
+        time: TimeFilter {
+            since: Timestamp::parse("01-10 23:00:00.000"),
+            until: None,
+            drop_untimed: false,
+        },
+        ..Default::default()
+    };
+    let record = |time: Option<&str>| Record {
+        time: time.map(ToOwned::to_owned),
+        ..Default::default()
+    };
+    // A late record slightly before the window is dropped, but does not stop
+    // the records following it
+    assert!(filter.filter(&record(Some("01-10 23:00:00.100"))));
+    assert!(!filter.filter(&record(Some("01-10 22:59:59.900"))));
+    assert!(filter.filter(&record(Some("01-10 23:00:00.050"))));
+
+    // Records without timestamp pass unless dropped
+    assert!(filter.filter(&record(None)));
+    assert!(filter.filter(&record(Some("1704927779.028"))));
+    filter.time.drop_untimed = true;
+    assert!(!filter.filter(&record(None)));
+}
+
+#[test]
+fn am_proc_events() {
+    let start = "[0,1234,10050,com.foo,activity,com.foo/.Main]";
+    assert_eq!(am_proc_start(start), Some(("1234", "com.foo")));
+    // Without user
+    let start = "[1234,10050,com.foo,activity,com.foo/.Main]";
+    assert_eq!(am_proc_start(start), Some(("1234", "com.foo")));
+    // Additional values of newer versions
+    let start = "[0,1234,10050,com.foo,activity,{com.foo/.Main},1,2]";
+    assert_eq!(am_proc_start(start), Some(("1234", "com.foo")));
+
+    for message in [
+        "",
+        "[]",
+        "[0,1234",
+        "[0,1234,10050,com.foo",
+        "[0,pid,10050,com.foo,activity,com.foo/.Main]",
+        "[0,1234,10050,,activity,com.foo/.Main]",
+        "am_proc_start is not an event",
+    ] {
+        assert_eq!(am_proc_start(message), None, "{message}");
+    }
+
+    assert_eq!(am_proc_died("[0,1234,com.foo]"), Some("1234"));
+    assert_eq!(am_proc_died("[1234,com.foo]"), Some("1234"));
+    assert_eq!(am_proc_died("[0,1234,com.foo,900,empty]"), Some("1234"));
+    assert_eq!(am_proc_died("1234"), Some("1234"));
+    assert_eq!(am_proc_died(""), None);
+    assert_eq!(am_proc_died("[,]"), None);
+    assert_eq!(am_proc_died("killed"), None);
+}
+
+#[test]
+fn processes_events() {
+    let names = Processes::default();
+    let event = |device: Option<&str>, tag: &str, message: &str| Record {
+        device: device.map(str::to_owned),
+        tag: tag.to_owned(),
+        message: message.to_owned(),
+        ..Default::default()
+    };
+    names.observe(&event(
+        None,
+        "am_proc_start",
+        "[0,1234,10050,com.foo,activity,{}]",
+    ));
+    names.observe(&event(
+        Some("a"),
+        "am_proc_start",
+        "[0,1234,10051,com.bar,activity,{}]",
+    ));
+    names.observe(&event(None, "Foo", "[0,1235,10050,com.baz,activity,{}]"));
+    assert_eq!(names.name(None, "1234").as_deref(), Some("com.foo"));
+    assert_eq!(names.name(Some("a"), "1234").as_deref(), Some("com.bar"));
+    assert_eq!(names.name(None, "1235"), None);
+
+    let mut start = event(None, "am_proc_start", "[0,1236,10052,com.qux,activity,{}]");
+    start.time = Some("01-10 23:56:50.101".to_owned());
+    names.observe(&start);
+    assert_eq!(
+        names.start_time(None, "1236").as_deref(),
+        Some("01-10 23:56:50.101")
+    );
+    assert_eq!(names.start_time(None, "1234"), None);
+
+    names.observe(&event(None, "am_proc_died", "[0,1234,com.foo]"));
+    assert_eq!(names.name(None, "1234"), None);
+    assert_eq!(names.name(Some("a"), "1234").as_deref(), Some("com.bar"));
+}
+
+#[test]
+fn process_filter_decoded_events() {
+    use crate::events::EventTags;
+
+    let tags = EventTags::default();
+    let event = |tag: &str, message: &str| {
+        let mut record = Record {
+            tag: tag.to_owned(),
+            message: message.to_owned(),
+            ..Default::default()
+        };
+        assert!(tags.decode_record(&mut record));
+        record
+    };
+    let mut filter = Filter::default();
+    filter.process_name.add_item("^com\\.termux$", true);
+
+    assert!(filter.filter(&event(
+        "am_proc_start",
+        "[0,22551,10201,com.termux,activity,{}]"
+    )));
+    assert!(!filter.filter(&event(
+        "am_proc_start",
+        "[0,22552,10202,com.other,activity,{}]"
+    )));
+    assert!(filter.tracked_pids.contains("22551"));
+    assert!(!filter.tracked_pids.contains("22552"));
+
+    assert!(filter.filter(&event("am_kill", "[0,22551,com.termux,900,empty #17]")));
+    assert!(filter.tracked_pids.is_empty());
+}
+
+#[test]
+fn process_filter_malformed_events() {
+    let mut filter = Filter {
+        process_name: FilterGroup::from_args(&[String::from("foo")], std::iter::empty(), false)
+            .unwrap(),
+        ..Default::default()
+    };
+    for (tag, message) in [
+        ("am_proc_start", ""),
+        ("am_proc_start", "[0,1234"),
+        ("am_proc_start", "foo"),
+        ("am_proc_died", ""),
+        ("am_kill", "[,,,]"),
+    ] {
+        let record = Record {
+            tag: tag.to_owned(),
+            message: message.to_owned(),
+            ..Default::default()
+        };
+        // Passes normal filtering which drops it since no pid is tracked
+        assert!(!filter.filter(&record));
+    }
+    assert!(filter.tracked_pids.is_empty());
+}
+
+#[test]
+fn process_filter_refresh() {
+    let update = PidsUpdate::default();
+    let mut filter = Filter {
+        process_name: FilterGroup::from_args(
+            &[String::from("foo"), String::from("!bar")],
+            std::iter::empty(),
+            false,
+        )
+        .unwrap(),
+        pids_update: Some(update.clone()),
+        ..Default::default()
+    };
+    let log = |pid: &str| Record {
+        process: pid.to_owned(),
+        ..Default::default()
+    };
+    let pids = |tracked: &[&str], excluded: &[&str]| ProcessPids {
+        tracked: tracked.iter().map(|p| p.to_string()).collect(),
+        excluded: excluded.iter().map(|p| p.to_string()).collect(),
+    };
+    assert!(!filter.filter(&log("1")));
+
+    // foo started after rogcat
+    *update.lock().unwrap() = Some(pids(&["1"], &["2"]));
+    assert!(filter.filter(&log("1")));
+    assert!(!filter.filter(&log("2")));
+
+    // Pids from events are kept while the refresh removes vanished ones
+    filter.tracked_pids.insert("3".to_owned());
+    *update.lock().unwrap() = Some(pids(&["4"], &[]));
+    assert!(!filter.filter(&log("1")));
+    assert!(filter.filter(&log("3")));
+    assert!(filter.filter(&log("4")));
+    assert!(filter.excluded_pids.is_empty());
+}
+
+#[test]
+fn process_filter_died_out_of_order() {
+    let mut filter = Filter::default();
+    filter.process_name.add_item("^com\\.example\\.", true);
+
+    let start = |pid: &str, name: &str| Record {
+        message: format!("[0,{pid},10201,{name},activity,{{}}]"),
+        tag: "am_proc_start".to_string(),
+        ..Default::default()
+    };
+    let died = |pid: &str, name: &str| Record {
+        message: format!("[0,{pid},{name},800,10]"),
+        tag: "am_proc_died".to_string(),
+        ..Default::default()
+    };
+    let log = |pid: &str| Record {
+        message: "hello".to_string(),
+        process: pid.to_string(),
+        ..Default::default()
+    };
+
+    assert!(filter.filter(&start("300", "com.example.c")));
+    assert!(filter.filter(&start("100", "com.example.a")));
+    assert!(filter.filter(&start("200", "com.example.b")));
+    assert!(filter.filter(&log("100")));
+    assert!(filter.filter(&log("200")));
+    assert!(filter.filter(&log("300")));
+    // Pids containing a tracked one don't pass
+    assert!(!filter.filter(&log("1000")));
+
+    assert!(filter.filter(&died("200", "com.example.b")));
+    assert!(!filter.filter(&log("200")));
+    assert!(filter.filter(&log("100")));
+    assert!(filter.filter(&log("300")));
+
+    assert!(filter.filter(&died("300", "com.example.c")));
+    assert!(!filter.filter(&log("300")));
+    assert!(filter.filter(&log("100")));
+
+    assert!(filter.filter(&died("100", "com.example.a")));
+    assert!(!filter.filter(&log("100")));
+    assert!(filter.tracked_pids.is_empty());
+
+    // The pid is reused by a process that isn't selected
+    filter.filter(&start("200", "org.other"));
+    assert!(filter.tracked_pids.is_empty());
+    assert!(!filter.filter(&log("200")));
+}
+
+#[test]
+fn filter_decisions() {
+    let spec = FilterSpec {
+        level: Level::Info,
+        tag: vec!["^Tag$".to_owned()],
+        tag_ignore_case: vec!["!noisy".to_owned()],
+        message: vec!["!secret".to_owned()],
+        message_ignore_case: vec!["!DROP".to_owned()],
+        pid: vec!["!^666$".to_owned()],
+        tid: vec!["^1".to_owned()],
+        regex: vec!["^mess".to_owned()],
+        fields: vec!["state=on".to_owned()],
+        ..Default::default()
+    };
+    let mut filter = Filter::from_spec(&spec).unwrap();
+    let record = Record {
+        level: Level::Info,
+        tag: "Tag".to_owned(),
+        process: "1".to_owned(),
+        thread: "10".to_owned(),
+        message: "message".to_owned(),
+        fields: [("state".to_owned(), "on".to_owned())].into(),
+        ..Default::default()
+    };
+    assert_eq!(filter.decide(&record), FilterDecision::Pass);
+
+    let mut reason = |record: Record| match filter.decide(&record) {
+        FilterDecision::Pass => "pass".to_owned(),
+        FilterDecision::Drop(rejection) => rejection.to_string(),
+    };
+    let cases = [
+        (
+            Record {
+                level: Level::Debug,
+                ..record.clone()
+            },
+            "Dropped by level",
+        ),
+        (
+            Record {
+                tag: "Other".to_owned(),
+                ..record.clone()
+            },
+            "Dropped by tag filter (no pattern matched)",
+        ),
+        (
+            Record {
+                message: "a secret".to_owned(),
+                ..record.clone()
+            },
+            "Dropped by message filter \"!secret\"",
+        ),
+        (
+            Record {
+                message: "please drop".to_owned(),
+                ..record.clone()
+            },
+            "Dropped by message_ignore_case filter \"!drop\"",
+        ),
+        (
+            Record {
+                process: "666".to_owned(),
+                ..record.clone()
+            },
+            "Dropped by pid filter \"!^666$\"",
+        ),
+        (
+            Record {
+                thread: "20".to_owned(),
+                ..record.clone()
+            },
+            "Dropped by tid filter (no pattern matched)",
+        ),
+        (
+            Record {
+                message: "other".to_owned(),
+                ..record.clone()
+            },
+            "Dropped by regex filter (no pattern matched)",
+        ),
+        (
+            Record {
+                fields: [("state".to_owned(), "off".to_owned())].into(),
+                ..record.clone()
+            },
+            "Dropped by field filter state (no pattern matched)",
+        ),
+        (
+            Record {
+                fields: Default::default(),
+                ..record.clone()
+            },
+            "Dropped by field filter state (no pattern matched)",
+        ),
+    ];
+    for (record, expected) in cases {
+        assert_eq!(reason(record), expected);
+    }
+
+    // The first negative regex pattern that matched is named
+    let mut filter = Filter::from_spec(&FilterSpec {
+        regex: vec!["!^1".to_owned(), "!a".to_owned()],
+        ..Default::default()
+    })
+    .unwrap();
+    let record = Record {
+        process: "1".to_owned(),
+        thread: "1".to_owned(),
+        tag: "a".to_owned(),
+        message: "a".to_owned(),
+        ..Default::default()
+    };
+    assert!(!filter.filter(&record));
+    assert_eq!(
+        filter.decide(&record),
+        FilterDecision::Drop(Rejection {
+            filter: "regex",
+            field: false,
+            pattern: Some("^1"),
+        })
+    );
+
+    // Tag filters ignoring the case
+    let mut filter = Filter::from_spec(&FilterSpec {
+        tag_ignore_case: vec!["!noisy".to_owned()],
+        ..Default::default()
+    })
+    .unwrap();
+    let noisy = Record {
+        tag: "NoisyTag".to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(
+        filter.decide(&noisy),
+        FilterDecision::Drop(Rejection {
+            filter: "tag_ignore_case",
+            field: false,
+            pattern: Some("noisy"),
+        })
+    );
+
+    // Processes selected by name
+    let mut filter = Filter::from_spec(&FilterSpec {
+        process_name: vec!["com.termux".to_owned()],
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(
+        filter.decide(&record),
+        FilterDecision::Drop(Rejection::new("process_name", None))
+    );
+    filter.excluded_pids.insert("1".to_owned());
+    assert_eq!(
+        filter.decide(&record),
+        FilterDecision::Drop(Rejection::new("excluded_process", None))
+    );
+}
+
+#[test]
+fn buffer_filter() {
+    let record = |buffer: Option<&str>| Record {
+        buffer: buffer.map(ToOwned::to_owned),
+        ..Default::default()
+    };
+    let spec = FilterSpec {
+        buffer: vec!["^crash$".to_owned(), "^main$".to_owned()],
+        ..Default::default()
+    };
+    let mut filter = Filter::from_spec(&spec).unwrap();
+    assert!(filter.filter(&record(Some("crash"))));
+    assert!(filter.filter(&record(Some("main"))));
+    assert_eq!(
+        filter.decide(&record(Some("events"))),
+        FilterDecision::Drop(Rejection::new("buffer", None))
+    );
+    // Records of unknown buffers don't match
+    assert!(!filter.filter(&record(None)));
+
+    let spec = FilterSpec {
+        buffer: vec!["!^events$".to_owned()],
+        ..Default::default()
+    };
+    let mut filter = Filter::from_spec(&spec).unwrap();
+    assert!(filter.filter(&record(Some("crash"))));
+    assert!(filter.filter(&record(None)));
+    assert!(!filter.filter(&record(Some("events"))));
+}
+
+#[test]
+fn filtergroup_negation_properties() {
+    let patterns = [
+        "", "a", "^a", "a$", "b", "ab|ba", "[ab]+", "a!", "^!a", "\\d",
+    ];
+    let items = ["", "a", "b", "ab", "ba", "!a", "a!", "A", "1", "!"];
+    let group = |args: &[String]| FilterGroup::from_args(args, iter::empty(), false).unwrap();
+
+    for pattern in patterns {
+        let matches = group(&[pattern.to_owned()]);
+        let inverted = group(&[format!("!{pattern}")]);
+        let both = group(&[pattern.to_owned(), format!("!{pattern}")]);
+        let escaped = group(&[format!("\\!{pattern}")]);
+        for item in items {
+            // The prefix inverts the match
+            assert_eq!(
+                inverted.filter(item),
+                !matches.filter(item),
+                "{pattern} {item}"
+            );
+            // A negative pattern wins over the same positive one
+            assert!(!both.filter(item), "{pattern} {item}");
+            // The escape is replaced by a literal '!' in front of the pattern
+            let expected = Regex::new(&format!("!{pattern}")).unwrap();
+            assert_eq!(
+                escaped.filter(item),
+                expected.is_match(item),
+                "{pattern} {item}"
+            );
+            // A negated escape drops items with a literal '!' only
+            let negated = group(&[format!("!\\!{pattern}")]);
+            assert_eq!(negated.filter(item), !expected.is_match(item));
+        }
+    }
+
+    assert_eq!(negation("!a"), ("a", true));
+    assert_eq!(negation("\\!a"), ("!a", false));
+    assert_eq!(negation("!\\!a"), ("\\!a", true));
+    assert_eq!(negation("\\d"), ("\\d", false));
+    assert_eq!(
+        process_names(&["a".to_owned(), "!b".to_owned(), "\\!c".to_owned()]),
+        (vec!["a".to_owned(), "!c".to_owned()], vec!["b".to_owned()])
+    );
+}
+
+#[test]
+fn filtergroup_order() {
+    let args = ["b", "!c", "a", "b", "!c"].map(ToOwned::to_owned);
+    let merge = ["a", "d", "!c", "!e"].map(ToOwned::to_owned);
+    let group = FilterGroup::from_args(&args, merge.iter(), false).unwrap();
+    let patterns = |regexes: &[Regex]| regexes.iter().map(Regex::to_string).collect::<Vec<_>>();
+    assert_eq!(patterns(&group.positive), ["b", "a", "d"]);
+    assert_eq!(patterns(&group.negative), ["c", "e"]);
+
+    // Patterns differing in case only are duplicates if the case is ignored
+    let args = ["Foo", "foo", "!BAR", "!bar"].map(ToOwned::to_owned);
+    let group = FilterGroup::from_args(&args, iter::empty(), true).unwrap();
+    assert_eq!(patterns(&group.positive), ["foo"]);
+    assert_eq!(patterns(&group.negative), ["bar"]);
+}
+
+#[cfg(test)]
+type LookupCalls = Arc<Mutex<Vec<(Vec<String>, Option<String>)>>>;
+
+/// Process list with one pid per name and device
+#[cfg(test)]
+struct MockProcesses(LookupCalls);
+
+#[cfg(test)]
+impl ProcessLookup for MockProcesses {
+    fn pids(
+        &self,
+        names: Vec<String>,
+        device: Option<String>,
+    ) -> BoxFuture<'static, Result<Vec<String>, Error>> {
+        let pids = names
+            .iter()
+            .map(|name| format!("{}:{name}", device.as_deref().unwrap_or_default()))
+            .collect();
+        self.0.lock().unwrap().push((names, device));
+        Box::pin(ready(Ok(pids)))
+    }
+}
+
+#[tokio::test]
+async fn process_lookup() {
+    let calls = LookupCalls::default();
+    let spec = FilterSpec {
+        process_name: vec!["foo".to_owned(), "!bar".to_owned(), "\\!baz".to_owned()],
+        process_refresh: Some(0),
+        ..Default::default()
+    };
+    let source = Source::Logcat(reader::LogcatOptions {
+        devices: vec!["a".to_owned(), "b".to_owned()],
+        ..Default::default()
+    });
+    let lookup = Arc::new(MockProcesses(calls.clone()));
+    let mut filter = Filter::with_processes(spec.clone(), &source, &mut Parser::default(), lookup)
+        .await
+        .unwrap();
+
+    let names = |names: &[&str], device: &str| {
+        (
+            names.iter().map(|&n| n.to_owned()).collect::<Vec<_>>(),
+            Some(device.to_owned()),
+        )
+    };
+    assert_eq!(
+        *calls.lock().unwrap(),
+        [
+            names(&["foo", "!baz"], "a"),
+            names(&["bar"], "a"),
+            names(&["foo", "!baz"], "b"),
+            names(&["bar"], "b"),
+        ]
+    );
+    let log = |pid: &str| Record {
+        process: pid.to_owned(),
+        ..Default::default()
+    };
+    assert!(filter.filter(&log("a:foo")));
+    assert!(filter.filter(&log("b:!baz")));
+    assert!(!filter.filter(&log("a:bar")));
+    assert!(!filter.filter(&log("c:foo")));
+
+    // Input files never query the processes
+    calls.lock().unwrap().clear();
+    let lookup = Arc::new(MockProcesses(calls.clone()));
+    let source = Source::Files(Vec::new());
+    Filter::with_processes(spec, &source, &mut Parser::default(), lookup)
+        .await
+        .unwrap();
+    assert!(calls.lock().unwrap().is_empty());
+}
@@ -18,6 +18,102 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+//! Log processing of rogcat as a library. A [`pipeline::Pipeline`] reads
+//! lines from a [`pipeline::Source`], parses them into [`record::Record`]s and
+//! passes the records matching a [`filter::FilterSpec`] to a stream or sink.
+
+use futures::{Sink, Stream};
+use record::{Level, Record};
+use std::{fmt::Display, io, path::PathBuf};
+
+pub mod adb;
+pub mod buffer;
+pub mod control;
+pub mod events;
+pub mod filter;
+mod lossy_lines;
+pub mod merge;
 #[allow(clippy::double_comparisons)]
 pub mod parser;
+pub mod pipeline;
+pub mod profiles;
+pub mod progress;
+pub mod reader;
 pub mod record;
+pub mod rewrite;
+pub mod session;
+pub mod sink;
+pub mod template;
+pub mod threads;
+
+/// Error type of all fallible operations
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// I/O failure with a description of the failed operation
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to find adb: {0}")]
+    Adb(which::Error),
+    #[error(transparent)]
+    Parser(#[from] parser::ParserError),
+    #[error(transparent)]
+    Filter(#[from] filter::FilterError),
+    #[error(transparent)]
+    Profile(#[from] profiles::ProfileError),
+    /// Failure to format a record
+    #[error("{0}")]
+    Format(String),
+    /// Invalid argument or input
+    #[error("{0}")]
+    Invalid(String),
+}
+
+impl Error {
+    /// Wrap `source` with a description of the failed operation
+    pub fn io(context: impl Display, source: io::Error) -> Error {
+        Error::Io {
+            context: context.to_string(),
+            source,
+        }
+    }
+}
+
+/// Logcat buffers read if not configured otherwise
+pub const DEFAULT_BUFFER: [&str; 4] = ["main", "events", "crash", "kernel"];
+
+/// Items of a source
+#[derive(Debug, Clone)]
+pub enum StreamData {
+    Record(Box<Record>),
+    Line(String),
+    /// Line of the standard error of a command. Records spanning multiple
+    /// lines are assembled apart from the lines of the standard output.
+    ErrorLine(String),
+    /// Line read from the device with the given serial
+    DeviceLine(String, String),
+    /// Line read from one log buffer, e.g. "crash", of the device with the
+    /// given serial
+    BufferLine {
+        device: Option<String>,
+        buffer: String,
+        line: String,
+    },
+    /// Message of rogcat itself like a failed spawn of adb. Fatal
+    /// diagnostics end the stream.
+    Diagnostic(Level, String),
+}
+
+pub type LogStream = Box<dyn Stream<Item = StreamData> + Send>;
+pub type LogSink = Box<dyn Sink<Record, Error = Error> + Send>;
+
+/// Detect configuration directory
+pub fn config_dir() -> PathBuf {
+    directories::BaseDirs::new()
+        .unwrap()
+        .config_dir()
+        .join("rogcat")
+}
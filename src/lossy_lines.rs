@@ -4,18 +4,17 @@
 use bytes::{Buf, BufMut, BytesMut};
 use futures::{
     task::{Context, Poll},
-    FutureExt, Stream,
+    Stream, StreamExt,
 };
-use std::{cmp, pin::Pin, usize};
-use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead};
-use tokio_util::codec::{Decoder, Encoder, LinesCodecError};
+use std::{cmp, pin::Pin};
+use tokio::io::AsyncRead;
+use tokio_util::codec::{Decoder, Encoder, FramedRead, LinesCodecError};
 
 /// Combinator created by the top-level `lossy_lines` method which is a stream over
 /// the lines of text on an I/O object.
 #[derive(Debug)]
 pub struct LossyLines<A> {
-    io: Pin<Box<A>>,
-    buffer: Vec<u8>,
+    lines: FramedRead<A, LossyLinesCodec>,
 }
 
 /// Creates a new stream from the I/O object given representing the lines of
@@ -23,53 +22,140 @@ pub struct LossyLines<A> {
 ///
 /// This method takes an asynchronous I/O object, `a`, and returns a `Stream` of
 /// lines that the object contains. The returned stream will reach its end once
-/// `a` reaches EOF.
+/// `a` reaches EOF or fails.
 pub fn lossy_lines<A>(a: A) -> LossyLines<A>
 where
-    A: AsyncRead + AsyncBufRead,
+    A: AsyncRead,
 {
     LossyLines {
-        io: Box::pin(a),
-        buffer: Vec::new(),
+        lines: FramedRead::new(a, LossyLinesCodec::new()),
     }
 }
 
 impl<A> Stream for LossyLines<A>
 where
-    A: AsyncRead + AsyncBufRead,
+    A: AsyncRead + Unpin,
 {
     type Item = String;
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let this = self.get_mut();
-        let buffer = this.buffer.as_mut();
-        let read = Box::pin(this.io.read_until(b'\n', buffer)).poll_unpin(cx);
-        let n = match read {
-            Poll::Ready(Ok(t)) => t,
-            Poll::Ready(Err(ref e)) if e.kind() == ::std::io::ErrorKind::WouldBlock => {
-                return Poll::Pending;
-            }
-            Poll::Ready(Err(_)) => return Poll::Ready(None),
-            Poll::Pending => return Poll::Pending,
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.lines
+            .poll_next_unpin(cx)
+            .map(|line| line.and_then(Result::ok))
+    }
+}
+
+/// Text encoding of the input. PowerShell and other Windows tools write
+/// UTF-16 that is detected by the byte order mark or by the zero bytes of
+/// ASCII characters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// Byte order marks that are stripped from the input
+const BOMS: [(&[u8], Encoding); 3] = [
+    (&[0xef, 0xbb, 0xbf], Encoding::Utf8),
+    (&[0xff, 0xfe], Encoding::Utf16Le),
+    (&[0xfe, 0xff], Encoding::Utf16Be),
+];
+
+/// Number of bytes inspected to detect UTF-16 without byte order mark
+pub const SAMPLE: usize = 64;
+
+/// True if an input starting with `bytes` is UTF-16. Inputs are detected
+/// like by [`LossyLinesCodec`].
+pub fn is_utf16(bytes: &[u8]) -> bool {
+    matches!(
+        Encoding::detect(bytes, true),
+        Some((Encoding::Utf16Le | Encoding::Utf16Be, _))
+    )
+}
+
+impl Encoding {
+    /// Encoding and length of the byte order mark detected from the first
+    /// bytes of the input. `None` if more bytes are needed.
+    fn detect(bytes: &[u8], eof: bool) -> Option<(Encoding, usize)> {
+        if let Some((bom, encoding)) = BOMS.iter().find(|(bom, _)| bytes.starts_with(bom)) {
+            return Some((*encoding, bom.len()));
+        }
+        let sample = &bytes[..bytes.len().min(SAMPLE)];
+        // Wait for a complete byte order mark or the first two characters
+        let partial_bom = BOMS.iter().any(|(bom, _)| bom.starts_with(sample));
+        let few = sample.len() < 2 || (sample.len() < 4 && sample.contains(&0));
+        if !eof && (partial_bom || few) {
+            return None;
+        }
+        let units = sample.len() / 2;
+        let ascii = |zero: usize| {
+            sample
+                .chunks_exact(2)
+                .filter(|unit| unit[zero] == 0 && unit[1 - zero] != 0)
+                .count()
         };
-        if n == 0 && buffer.is_empty() {
-            Poll::Ready(None)
+        let encoding = if units > 0 && ascii(1) * 2 > units {
+            Encoding::Utf16Le
+        } else if units > 0 && ascii(0) * 2 > units {
+            Encoding::Utf16Be
         } else {
-            // Strip all \r\n occurences because on Windows "adb logcat" ends lines with "\r\r\n"
-            while buffer.ends_with(&[b'\r']) || buffer.ends_with(&[b'\n']) {
-                buffer.pop();
-            }
-            let line = String::from_utf8_lossy(buffer).into();
-            buffer.clear();
-            Poll::Ready(Some(line))
+            Encoding::Utf8
+        };
+        Some((encoding, 0))
+    }
+
+    /// Bytes per code unit
+    fn width(self) -> usize {
+        match self {
+            Encoding::Utf8 => 1,
+            Encoding::Utf16Le | Encoding::Utf16Be => 2,
         }
     }
+
+    /// Offset of the first newline in `bytes` that start at a code unit
+    fn find_newline(self, bytes: &[u8]) -> Option<usize> {
+        let newline = |unit: &[u8]| match self {
+            Encoding::Utf8 => unit == b"\n",
+            Encoding::Utf16Le => unit == b"\n\0",
+            Encoding::Utf16Be => unit == b"\0\n",
+        };
+        bytes
+            .chunks_exact(self.width())
+            .position(newline)
+            .map(|unit| unit * self.width())
+    }
+
+    /// Text of a line without newline. Invalid sequences are replaced.
+    fn decode(self, line: &[u8]) -> String {
+        let mut text = match self {
+            Encoding::Utf8 => String::from_utf8_lossy(line).into_owned(),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let units = line.chunks_exact(2).map(|unit| {
+                    let unit = [unit[0], unit[1]];
+                    match self {
+                        Encoding::Utf16Le => u16::from_le_bytes(unit),
+                        _ => u16::from_be_bytes(unit),
+                    }
+                });
+                char::decode_utf16(units)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect()
+            }
+        };
+        // Strip all \r because on Windows "adb logcat" ends lines with "\r\r\n"
+        text.truncate(text.trim_end_matches('\r').len());
+        text
+    }
 }
 
+#[derive(Debug)]
 pub struct LossyLinesCodec {
     next_index: usize,
     max_length: usize,
     is_discarding: bool,
+    /// Detected from the first bytes
+    encoding: Option<Encoding>,
 }
 
 impl LossyLinesCodec {
@@ -78,15 +164,16 @@ impl LossyLinesCodec {
             next_index: 0,
             max_length: usize::MAX,
             is_discarding: false,
+            encoding: None,
         }
     }
-}
 
-fn without_carriage_return(s: &[u8]) -> &[u8] {
-    if let Some(&b'\r') = s.last() {
-        &s[..s.len() - 1]
-    } else {
-        s
+    /// Strip the byte order mark and decode the following lines with
+    /// `encoding`
+    fn start(&mut self, buf: &mut BytesMut, (encoding, bom): (Encoding, usize)) -> Encoding {
+        buf.advance(bom);
+        self.encoding = Some(encoding);
+        encoding
     }
 }
 
@@ -95,21 +182,29 @@ impl Decoder for LossyLinesCodec {
     type Error = LinesCodecError;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<String>, LinesCodecError> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => match Encoding::detect(buf, false) {
+                Some(detected) => self.start(buf, detected),
+                None => return Ok(None),
+            },
+        };
+        let width = encoding.width();
         loop {
             // Determine how far into the buffer we'll search for a newline. If
             // there's no max_length set, we'll read to the end of the buffer.
+            // The search ends at a code unit.
             let read_to = cmp::min(self.max_length.saturating_add(1), buf.len());
+            let read_to = read_to - read_to % width;
 
-            let newline_offset = buf[self.next_index..read_to]
-                .iter()
-                .position(|b| *b == b'\n');
+            let newline_offset = encoding.find_newline(&buf[self.next_index..read_to]);
 
             match (self.is_discarding, newline_offset) {
                 (true, Some(offset)) => {
                     // If we found a newline, discard up to that offset and
                     // then stop discarding. On the next iteration, we'll try
                     // to read a line normally.
-                    buf.advance(offset + self.next_index + 1);
+                    buf.advance(offset + self.next_index + width);
                     self.is_discarding = false;
                     self.next_index = 0;
                 }
@@ -119,7 +214,7 @@ impl Decoder for LossyLinesCodec {
                     // discarding up to max_len bytes unless we find a newline.
                     buf.advance(read_to);
                     self.next_index = 0;
-                    if buf.is_empty() {
+                    if buf.len() < width {
                         return Ok(None);
                     }
                 }
@@ -127,11 +222,8 @@ impl Decoder for LossyLinesCodec {
                     // Found a line!
                     let newline_index = offset + self.next_index;
                     self.next_index = 0;
-                    let line = buf.split_to(newline_index + 1);
-                    let line = &line[..line.len() - 1];
-                    let line = without_carriage_return(line);
-                    let line = String::from_utf8_lossy(line);
-                    return Ok(Some(line.to_string()));
+                    let line = buf.split_to(newline_index + width);
+                    return Ok(Some(encoding.decode(&line[..newline_index])));
                 }
                 (false, None) if buf.len() > self.max_length => {
                     // Reached the maximum length without finding a
@@ -151,19 +243,19 @@ impl Decoder for LossyLinesCodec {
     }
 
     fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<String>, LinesCodecError> {
+        if self.encoding.is_none() {
+            if let Some(detected) = Encoding::detect(buf, true) {
+                self.start(buf, detected);
+            }
+        }
         Ok(match self.decode(buf)? {
             Some(frame) => Some(frame),
             None => {
                 // No terminating newline - return remaining data, if any
-                if buf.is_empty() || buf == &b"\r"[..] {
-                    None
-                } else {
-                    let line = buf.split_to(buf.len());
-                    let line = without_carriage_return(&line);
-                    let line = String::from_utf8_lossy(line);
-                    self.next_index = 0;
-                    Some(line.to_string())
-                }
+                let encoding = self.encoding.unwrap_or(Encoding::Utf8);
+                let line = encoding.decode(&buf.split_to(buf.len()));
+                self.next_index = 0;
+                (!line.is_empty()).then_some(line)
             }
         })
     }
@@ -189,3 +281,64 @@ impl Default for LossyLinesCodec {
         Self::new()
     }
 }
+
+#[cfg(test)]
+fn decode_all(chunks: &[&[u8]]) -> Vec<String> {
+    let mut codec = LossyLinesCodec::new();
+    let mut buf = BytesMut::new();
+    let mut lines = Vec::new();
+    for chunk in chunks {
+        buf.extend_from_slice(chunk);
+        while let Some(line) = codec.decode(&mut buf).unwrap() {
+            lines.push(line);
+        }
+    }
+    while let Some(line) = codec.decode_eof(&mut buf).unwrap() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+fn utf16le(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+#[test]
+fn utf8_lines() {
+    let text = "\u{feff}a\r\r\nb\n\nc\r".as_bytes();
+    assert_eq!(decode_all(&[text]), ["a", "b", "", "c"]);
+    // Byte by byte
+    let chunks = text.chunks(1).collect::<Vec<_>>();
+    assert_eq!(decode_all(&chunks), ["a", "b", "", "c"]);
+    assert_eq!(decode_all(&[b"x"]), ["x"]);
+    assert!(decode_all(&[b""]).is_empty());
+}
+
+#[test]
+fn utf16_lines() {
+    let text = "I Tag: hello\r\nE Tag: 日志 🚀\r\nlast";
+    let expected = ["I Tag: hello", "E Tag: 日志 🚀", "last"];
+
+    // PowerShell writes a byte order mark
+    let le = [&[0xff, 0xfe][..], &utf16le(text)].concat();
+    assert_eq!(decode_all(&[&le]), expected);
+    let chunks = le.chunks(3).collect::<Vec<_>>();
+    assert_eq!(decode_all(&chunks), expected);
+
+    // Without byte order mark
+    let le = utf16le(text);
+    assert_eq!(decode_all(&[&le]), expected);
+    let chunks = le.chunks(1).collect::<Vec<_>>();
+    assert_eq!(decode_all(&chunks), expected);
+
+    let be = [0xfe, 0xff]
+        .into_iter()
+        .chain(text.encode_utf16().flat_map(u16::to_be_bytes))
+        .collect::<Vec<_>>();
+    assert_eq!(decode_all(&[&be]), expected);
+
+    // Unpaired surrogates are replaced
+    let invalid = [&utf16le("a")[..], &0xd800u16.to_le_bytes(), &utf16le("b\n")].concat();
+    assert_eq!(decode_all(&[&invalid]), ["a\u{fffd}b"]);
+}
@@ -18,113 +18,375 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use anyhow::{anyhow, Result};
 use clap::Parser;
-use failure::Error;
-use futures::{future::ready, Sink, Stream, StreamExt};
-use rogcat::{parser, record::Record};
-use std::process::exit;
-use url::Url;
+use futures::{stream, SinkExt, StreamExt};
+use regex::Regex;
+use rogcat::{
+    buffer,
+    control::{self, Control, ControlAddress},
+    events::EventTags,
+    parser::RegexParser,
+    pipeline::{Pipeline, Source},
+    profiles,
+    reader::{self, Children},
+};
+use std::{fs, process::exit, time::Duration};
+use tokio_util::sync::CancellationToken;
 
+mod capture;
 mod cli;
+mod command;
+mod count;
+mod crashes;
+mod dedupe;
+mod diagnostics;
+mod diff;
 mod filewriter;
-mod filter;
-mod lossy_lines;
-mod profiles;
-mod reader;
+mod progressbar;
+mod props;
+mod socket;
+mod stats;
 mod subcommands;
 mod terminal;
+mod trigger;
 mod utils;
 
-const DEFAULT_BUFFER: [&str; 4] = ["main", "events", "crash", "kernel"];
+/// Interval of checking the profiles file for changes
+const PROFILES_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
-#[derive(Debug, Clone)]
-pub enum StreamData {
-    Record(Record),
-    Line(String),
-}
-
-type LogStream = Box<dyn Stream<Item = StreamData> + Send>;
-type LogSink = Box<dyn Sink<Record, Error = Error> + Send>;
+async fn run(children: Children) -> Result<()> {
+    let mut args = cli::CliArguments::parse();
+    utils::config_init(&mut diagnostics::Reporter::new(&args))?;
+    // Replays run through the pipeline like other sources
+    let replay = match args.subcommands.take() {
+        Some(cli::SubCommands::Replay(opts)) => Some(opts),
+        // Conversions are file output of input files
+        Some(cli::SubCommands::Convert(opts)) => {
+            args.convert(opts)?;
+            None
+        }
+        Some(subcommand) => {
+            subcommands::parse_subcommand(subcommand, &args.device).await;
+            exit(0);
+        }
+        None => None,
+    };
 
-async fn run() -> Result<(), Error> {
-    let args = cli::CliArguments::parse();
-    utils::config_init();
-    if let Some(subcommand) = args.subcommands {
-        subcommands::parse_subcommand(subcommand).await;
-        exit(0);
-    }
+    let profile = profiles::load(args.profiles_path.as_ref(), &args.profile)?;
+    // Profile settings are applied again on reload
+    let cli_args = args.clone();
+    args.apply(&profile)?;
+    utils::config_profile(&profile.settings)?;
 
-    let source = {
-        if !args.input.is_empty() {
-            reader::files(args.input.clone()).await?
-        } else {
-            match args.command.clone() {
-                Some(command) => {
-                    if command == "-" {
-                        reader::stdin()
-                    } else if let Ok(url) = Url::parse(command.as_str()) {
-                        match url.scheme() {
-                            #[cfg(target_os = "linux")]
-                            "can" => reader::can(url.host_str().expect("Invalid can device"))?,
-                            "tcp" => reader::tcp(&url).await?,
-                            "serial" => reader::serial(),
-                            _ => reader::process(command, args.restart)?,
-                        }
-                    } else {
-                        reader::process(command, args.restart)?
-                    }
-                }
-                None => reader::logcat(&args)?,
+    let source = match replay {
+        Some(opts) => {
+            if !args.buffer_filter.is_empty() {
+                return Err(anyhow!("--buffer-filter requires adb logcat as source"));
+            }
+            if !opts.speed.is_finite() || opts.speed <= 0.0 {
+                return Err(anyhow!("Invalid replay speed {}", opts.speed));
             }
+            let speed = (!opts.no_delay).then_some(opts.speed);
+            Source::Stream(reader::replay(opts.file, speed).await?)
         }
+        None => args.source()?,
     };
+    // Queried while logcat starts
+    let props = props::spawn(&args, &source);
+    let mut events = None;
+    if args.decode_events {
+        let mut tags = EventTags::default();
+        if let Source::Logcat(options) = &source {
+            let devices = if options.devices.is_empty() {
+                vec![None]
+            } else {
+                options.devices.iter().map(|d| Some(d.as_str())).collect()
+            };
+            for device in devices {
+                // The builtin definitions cover the common events
+                if let Err(e) = tags.extend_from_device(device).await {
+                    diagnostics::Reporter::new(&args).report(&format!(
+                        "Failed to read the event definitions of the device: {e}"
+                    ))?;
+                }
+            }
+        }
+        events = Some(tags);
+    }
+
+    let filter_spec = args.filter_spec(&profile);
+    let rewrite = args.rewrite(&profile)?;
+    let mut pipeline = Pipeline::new()
+        .source(source)
+        .children(children.clone())
+        .rewrite(rewrite.clone())
+        .filter(filter_spec.clone())
+        .extract_fields(
+            args.extract_fields
+                || !args.field.is_empty()
+                || args.count_by.as_deref().is_some_and(count::is_field),
+        )
+        .merge_by_time(args.merge_by_time)
+        .last_records(args.last_records)
+        .resolve_threads(args.resolve_threads)
+        .input_format(args.input_format)
+        .strict(args.strict)
+        .fold_continuations(args.fold_continuations)
+        .parallel(args.parallel.unwrap_or(1));
+    if let Some(tags) = events {
+        pipeline = pipeline.decode_events(tags);
+    }
+    if let Some(regex) = profile.parser.as_ref() {
+        pipeline = pipeline.parser(Box::new(RegexParser::new(regex)?));
+    }
+    if args.explain {
+        let pattern = args.explain_filter.as_deref().map(Regex::new).transpose()?;
+        pipeline = pipeline.explain(pattern);
+    }
+    let parse_failures = pipeline.parse_failures();
+    let expired = pipeline.expired_lines();
+    let processes = pipeline.processes();
+    let input_progress = args
+        .show_progress()
+        .then(|| progressbar::spawn(pipeline.progress()));
+    let control = Control::new(filter_spec, pipeline.reload_handle());
+    // Removes the socket file at the end
+    let mut control_listener = None;
+    if let Some(address) = args.control_socket.as_deref() {
+        let address = ControlAddress::parse(address)?;
+        let listener = control::listen(&address, control.clone()).await?;
+        // The port picked for port 0 is needed by "rogcat ctl"
+        if let (ControlAddress::Tcp(0), ControlAddress::Tcp(port)) = (&address, listener.address())
+        {
+            diagnostics::Reporter::new(&args)
+                .report(&format!("Control socket listening on port {port}"))?;
+        }
+        control_listener = Some(listener);
+    }
+    watch_profiles(cli_args, control);
+    // Stop process after n records if argument head is passed. One record
+    // answers --quiet.
+    if let Some(head) = args.head {
+        pipeline = pipeline.head(head);
+    } else if args.quiet {
+        pipeline = pipeline.head(1);
+    }
+
+    // Records dropped by a slow output count as well
+    let mut records = pipeline.stream().await?;
+    let mut failures = None;
+    if let Some(level) = args.fail_on_level.clone() {
+        let (stream, count) = count::at_level(records, level.clone());
+        records = stream;
+        failures = Some((level, count));
+    }
+
+    // Decouple reading from a slow output
+    let (stream, dropped) = buffer::bounded(records, args.buffer_size, args.drop_policy);
 
-    let mut profile = profiles::from_args(&args)?;
-    let sink = Box::into_pin(if args.output.is_some() {
-        filewriter::try_from(args.clone())?
+    // Cancelled when the user quits via keyboard
+    let shutdown = CancellationToken::new();
+    let counting = args.count || args.count_by.is_some() || args.quiet;
+    let mut matches = None;
+    let windows = capture::Windows::default();
+    let sink = Box::into_pin(if counting {
+        let (sink, count) = count::try_from(&args);
+        matches = Some(count);
+        sink
+    } else if let Some(output) = args.output.as_deref() {
+        let sink = match socket::Address::parse(output) {
+            Some(address) => socket::try_from(address, &args)?,
+            None => {
+                let session = if args.session {
+                    Some(filewriter::session(&args, &profile).await)
+                } else {
+                    None
+                };
+                // Files start with the properties. Logcat is already running.
+                let props = match props.clone() {
+                    Some(props) => Some(props.await),
+                    None => None,
+                };
+                filewriter::try_from(args.clone(), &profile, session, props, windows.clone())?
+            }
+        };
+        if args.tee {
+            // The format option selects the format of the output only
+            let terminal_args = cli::CliArguments {
+                format: None,
+                ..args.clone()
+            };
+            let terminal = terminal::try_from(
+                &terminal_args,
+                &profile,
+                processes.clone(),
+                shutdown.clone(),
+            )?;
+            Box::new(Box::into_pin(sink).fanout(Box::into_pin(terminal)))
+        } else {
+            sink
+        }
+    } else if args.output_command.is_some() {
+        command::try_from(&args, &profile, shutdown.clone())?
     } else {
-        terminal::try_from(&args, &profile)?
+        terminal::try_from(&args, &profile, processes, shutdown.clone())?
     });
+    let sink = match args.dedupe {
+        Some(timeout) => dedupe::wrap(sink, Duration::from_secs(timeout)),
+        None => sink,
+    };
+    let sink = if args.stats { stats::wrap(sink) } else { sink };
+    let sink = match args.on_match.as_deref() {
+        Some(command) => {
+            let reporter = diagnostics::Reporter::new(&args);
+            trigger::wrap(sink, &args.watch, command, args.once, reporter)?
+        }
+        None => sink,
+    };
+    let sink = match args.trigger.as_deref() {
+        Some(trigger) => {
+            let reporter = diagnostics::Reporter::new(&args);
+            capture::wrap(sink, trigger, args.before, args.after, windows, reporter)?
+        }
+        None => sink,
+    };
+    let (sink, failed) = diagnostics::wrap(sink, &args);
+    // The terminal shows the properties once they are available
+    let terminal =
+        !counting && ((args.output.is_none() && args.output_command.is_none()) || args.tee);
+    let stream = match props {
+        Some(props) if terminal => stream::select(stream, props::banner(props)).boxed(),
+        _ => stream,
+    };
 
-    // Stop process after n records if argument head is passed
-    let mut head = args.head;
-
-    let mut filter = filter::from_args_profile(args, &mut profile).await?;
-    let mut parser = parser::Parser::default();
-
-    let future = Box::into_pin(source)
-        .map(move |a| match a {
-            StreamData::Line(line) => parser.parse(&line),
-            StreamData::Record(rec) => rec,
-        })
-        .filter(move |r| ready(filter.filter(r)))
-        .take_while(move |_| {
-            ready(match head {
-                Some(0) => false,
-                Some(n) => {
-                    head = Some(n - 1);
-                    true
-                }
-                None => true,
-            })
-        })
-        .map(Ok)
-        .forward(sink);
-
-    tokio::select! {
-        res = future => res?,
-        _ = tokio::signal::ctrl_c() => ()
+    // Terminate the source on ctrl-c, termination or quit. The sink is
+    // closed by forward and can flush its buffers and write pending output.
+    let stop = async move {
+        tokio::select! {
+            _ = terminated() => (),
+            _ = shutdown.cancelled() => (),
+        }
+    };
+    let result = stream.take_until(stop).map(Ok).forward(sink).await;
+    children.terminate().await;
+    drop(control_listener);
+    let summary = input_progress.map(progressbar::InputProgress::finish);
+    result?;
+    if let Some(summary) = summary {
+        eprintln!("{summary}");
+    }
+    if args.stats && !rewrite.is_empty() {
+        eprintln!("{}", stats::rewrites(&rewrite));
+    }
+    if dropped.count() > 0 {
+        eprintln!(
+            "{} records dropped because the output was too slow",
+            dropped.count()
+        );
+    }
+    if !expired.counts().is_empty() {
+        eprintln!("{expired}");
+    }
+    if parse_failures.count() > 0 {
+        diagnostics::Reporter::new(&args).report(&format!(
+            "{} lines dropped because they could not be parsed",
+            parse_failures.count()
+        ))?;
+    }
+    let mut failed_level = false;
+    if let Some((level, count)) = failures {
+        eprintln!("{} records ≥ {}", count.count(), level.name());
+        failed_level = count.count() > args.max_count;
+    }
+    // Like grep, no matches are a failure
+    if failed.failed()
+        || failed_level
+        || parse_failures.count() > 0
+        || matches.is_some_and(|m| m.count() == 0)
+    {
+        exit(1);
     }
     Ok(())
 }
 
+/// Poll the modification time of the profiles file and reload the filters of
+/// the selected profile on changes. Invalid profiles keep the current filters.
+/// Changes of the control socket are replaced by the reloaded filters. The
+/// file isn't polled if no profile is selected and it has no default
+/// profile.
+fn watch_profiles(args: cli::CliArguments, control: Control) {
+    let Ok(path) = profiles::file(args.profiles_path.as_ref()) else {
+        return;
+    };
+    let applies = !args.profile.is_empty()
+        || profiles::profiles_list(args.profiles_path.as_ref())
+            .is_ok_and(|profiles| profiles.contains_key(profiles::DEFAULT_PROFILE_NAME));
+    if !applies {
+        return;
+    }
+    let modified = move || fs::metadata(&path).and_then(|m| m.modified()).ok();
+    let mut reporter = diagnostics::Reporter::new(&args);
+    tokio::spawn(async move {
+        let mut last = modified();
+        loop {
+            tokio::time::sleep(PROFILES_POLL_INTERVAL).await;
+            let current = modified();
+            if current == last {
+                continue;
+            }
+            last = current;
+
+            let result = profiles::load(args.profiles_path.as_ref(), &args.profile)
+                .map_err(anyhow::Error::from)
+                .and_then(|profile| {
+                    let mut args = args.clone();
+                    args.apply(&profile)?;
+                    control.reset(args.filter_spec(&profile))?;
+                    Ok(())
+                });
+            if let Err(e) = result {
+                reporter
+                    .report(&format!("Failed to reload profiles: {e}"))
+                    .ok();
+            }
+        }
+    });
+}
+
+/// Resolves on ctrl-c and on unix on SIGTERM and SIGHUP as well. The
+/// children of the source run in process groups of their own and don't see
+/// these signals.
+async fn terminated() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let (Ok(mut term), Ok(mut hangup)) = (
+            signal(SignalKind::terminate()),
+            signal(SignalKind::hangup()),
+        ) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => (),
+                _ = term.recv() => (),
+                _ = hangup.recv() => (),
+            }
+            return;
+        }
+    }
+    tokio::signal::ctrl_c().await.ok();
+}
+
 #[tokio::main]
 async fn main() {
-    parse_result(run().await)
+    let children = Children::default();
+    let result = run(children.clone()).await;
+    // Errors may end rogcat while the source is running
+    children.terminate().await;
+    parse_result(result)
 }
 
 #[inline]
-fn parse_result(res: Result<(), Error>) {
+fn parse_result(res: Result<()>) {
     match res {
         Err(e) => {
             eprintln!("{e}");
@@ -0,0 +1,155 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Merging of the records of multiple sources in the order of their
+//! timestamps
+
+use crate::{filter::Timestamp, pipeline::RecordStream, record::Record};
+use futures::{
+    task::{Context, Poll},
+    Stream, StreamExt,
+};
+use std::pin::Pin;
+
+/// Number of records buffered per source to put records that are slightly
+/// out of order back in order
+const REORDER_WINDOW: usize = 32;
+
+/// True if `a` sorts before `b`. Records before the first timestamp of a
+/// source come first.
+fn earlier(a: Option<Timestamp>, b: Option<Timestamp>) -> bool {
+    match (a, b) {
+        (None, Some(_)) => true,
+        (Some(a), Some(b)) => a.is_before(b),
+        _ => false,
+    }
+}
+
+struct Source {
+    stream: RecordStream,
+    done: bool,
+    /// Buffered records in the order of the source with the timestamp they
+    /// are sorted by
+    window: Vec<(Option<Timestamp>, Record)>,
+    /// Timestamp of the last record with a timestamp
+    last: Option<Timestamp>,
+}
+
+impl Source {
+    /// Buffer `record`. Records without timestamp stay behind the preceding
+    /// record.
+    fn push(&mut self, record: Record) {
+        if let Some(time) = record.time.as_deref().and_then(Timestamp::parse) {
+            self.last = Some(time);
+        }
+        self.window.push((self.last, record));
+    }
+
+    /// Index of the earliest buffered record
+    fn head(&self) -> Option<usize> {
+        (0..self.window.len()).reduce(|head, i| {
+            if earlier(self.window[i].0, self.window[head].0) {
+                i
+            } else {
+                head
+            }
+        })
+    }
+}
+
+struct MergeByTime {
+    sources: Vec<Source>,
+}
+
+/// Merge `streams` into a stream of records ordered by time. Records without
+/// timestamp keep their position relative to the records of their stream.
+pub fn merge_by_time(streams: Vec<RecordStream>) -> RecordStream {
+    let sources = streams
+        .into_iter()
+        .map(|stream| Source {
+            stream,
+            done: false,
+            window: Vec::with_capacity(REORDER_WINDOW),
+            last: None,
+        })
+        .collect();
+    Box::pin(MergeByTime { sources })
+}
+
+impl Stream for MergeByTime {
+    type Item = Record;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Record>> {
+        let this = self.get_mut();
+
+        // The earliest record is known once the window of every source is
+        // full or the source ended
+        for source in &mut this.sources {
+            while !source.done && source.window.len() < REORDER_WINDOW {
+                match source.stream.poll_next_unpin(cx) {
+                    Poll::Ready(Some(record)) => source.push(record),
+                    Poll::Ready(None) => source.done = true,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+
+        let head = this
+            .sources
+            .iter()
+            .enumerate()
+            .filter_map(|(i, source)| source.head().map(|head| (i, head, source.window[head].0)))
+            .reduce(|a, b| if earlier(b.2, a.2) { b } else { a });
+        Poll::Ready(head.map(|(i, head, _)| this.sources[i].window.remove(head).1))
+    }
+}
+
+#[tokio::test]
+async fn merge_interleaved() {
+    use futures::stream::iter;
+
+    let record = |time: Option<&str>, message: &str| Record {
+        time: time.map(|t| format!("01-10 23:02:{t}")),
+        message: message.to_owned(),
+        ..Default::default()
+    };
+    let main = vec![
+        record(None, "header"),
+        record(Some("59.010"), "a"),
+        record(Some("59.030"), "c"),
+        record(None, "c continued"),
+        // Slightly out of order
+        record(Some("59.060"), "f"),
+        record(Some("59.050"), "e"),
+    ];
+    let kernel = vec![
+        record(Some("59.020"), "b"),
+        record(Some("59.040"), "d"),
+        record(Some("59.070"), "g"),
+    ];
+    let merged = merge_by_time(vec![Box::pin(iter(main)), Box::pin(iter(kernel))])
+        .map(|r| r.message)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(
+        merged,
+        ["header", "a", "b", "c", "c continued", "d", "e", "f", "g"]
+    );
+}
@@ -18,81 +18,151 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::record::{Level, Record};
-use csv::ReaderBuilder;
-use failure::Fail;
-
+use crate::{
+    record::{Column, Level, Notice, Record, COLUMNS},
+    session::Session,
+};
+use clap::ValueEnum;
+use csv::{ReaderBuilder, StringRecord};
+use regex::Regex;
+use serde::Deserialize;
 use serde_json::from_str;
 use std::{
+    borrow::{Cow, ToOwned},
+    collections::{BTreeMap, VecDeque},
     convert::Into,
+    fmt::{self, Display, Formatter},
     io::{Cursor, Read},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
+use time::{macros::format_description, OffsetDateTime};
 
-#[derive(Fail, Debug)]
-#[fail(display = "{}", _0)]
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
 pub struct ParserError(String);
 
 pub trait FormatParser: Send + Sync {
     fn try_parse_str(&self, line: &str) -> Result<Record, ParserError>;
+
+    /// Check if `line` is a header that configures the parsing of the
+    /// following lines
+    fn try_parse_header(&mut self, _line: &str) -> bool {
+        false
+    }
 }
 
 #[inline]
 fn level(level: &str) -> Result<Level, ParserError> {
-    match level {
-        "V" => Ok(Level::Verbose),
-        "D" => Ok(Level::Debug),
-        "I" => Ok(Level::Info),
-        "W" => Ok(Level::Warn),
-        "E" => Ok(Level::Error),
-        "F" => Ok(Level::Fatal),
-        "A" => Ok(Level::Assert),
-        _ => Err(ParserError(format!("Invalid level: {}", level))),
+    Level::from_letter(level).ok_or_else(|| ParserError(format!("Invalid level: {}", level)))
+}
+
+/// Check for timestamps of "logcat -v monotonic" or "logcat -v epoch"
+/// which are seconds with a fractional part.
+fn is_seconds(value: &str) -> bool {
+    match value.split_once('.') {
+        Some((seconds, fraction)) => {
+            !seconds.is_empty()
+                && !fraction.is_empty()
+                && seconds.chars().all(|c| c.is_ascii_digit())
+                && fraction.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
     }
 }
 
-// date, hour, pid, thread, level, tag
-const MIN_PARTS_COUNT: usize = 6;
-fn printable(line: &str) -> Result<Record, ParserError> {
-    if line.split_ascii_whitespace().count() < MIN_PARTS_COUNT {
-        return Err(ParserError("Invalid line size".into()));
+/// The words of `text` separated by single spaces
+fn words(text: &str) -> String {
+    text.split_ascii_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Byte offset of `word` which is a slice of `line`
+fn offset(line: &str, word: &str) -> usize {
+    word.as_ptr() as usize - line.as_ptr() as usize
+}
+
+/// Slice of `line` from the start of the word `first` to the end of `last`
+fn span<'a>(line: &'a str, first: &str, last: &str) -> &'a str {
+    &line[offset(line, first)..offset(line, last) + last.len()]
+}
+
+/// Maximum length of a tag. The tag of lines without a colon after the
+/// tag is the first word.
+const MAX_TAG_LENGTH: usize = 64;
+
+/// Split `text` following the level into tag and message. The tag ends with
+/// the first ": " or a colon at the end of the line. The message keeps its
+/// whitespace.
+fn split_tag(text: &str) -> (&str, &str) {
+    let text = text.trim_end();
+    let colon = match text.find(": ") {
+        Some(end) => Some((end, end + 2)),
+        None if text.ends_with(':') => Some((text.len() - 1, text.len())),
+        None => None,
+    };
+    match colon {
+        Some((end, message)) if end <= MAX_TAG_LENGTH => (&text[..end], &text[message..]),
+        _ => text
+            .split_once(|c: char| c.is_ascii_whitespace())
+            .unwrap_or((text, "")),
     }
+}
 
+// pid, thread, level, tag
+const MIN_PARTS_COUNT: usize = 4;
+fn printable(line: &str) -> Result<Record, ParserError> {
     let mut items = line.split_ascii_whitespace();
-    let (date, hour) = (
-        items.next().unwrap_or("01-01"),
-        items.next().unwrap_or("00:00"),
-    );
-
-    let (process, thread) = (items.next().unwrap(), items.next().unwrap());
-    if !(process.chars().all(char::is_numeric) && process.chars().all(char::is_numeric)) {
-        return Err(ParserError(
-            "Invalid Process/Thread ID: Pid {process}, Thread {thread}".into(),
-        ));
-    }
-    let level = level(items.next().unwrap())?;
-    let tag = {
-        // Basically a take_while(':') but considering the failing match too.
-        let mut list: Vec<&str> = vec![];
-        for part in items.by_ref() {
-            if let Some(fixed) = part.strip_suffix(':') {
-                list.push(fixed);
-                break;
-            } else {
-                list.push(part);
-            }
-        }
-        list.join(" ")
+    let time = match items.next() {
+        Some(seconds) if is_seconds(seconds) => seconds.to_owned(),
+        Some(date) => match items.next() {
+            Some(time) => words(span(line, date, time)),
+            None => format!("{date} 00:00"),
+        },
+        None => return Err(ParserError("Invalid line size".into())),
     };
-    let message = items.collect::<Vec<&str>>().join(" ");
 
+    let mut parts = [""; MIN_PARTS_COUNT];
+    for part in parts.iter_mut() {
+        *part = items
+            .next()
+            .ok_or_else(|| ParserError("Invalid line size".into()))?;
+    }
+
+    // The uid column of "logcat -v uid" is placed in front of the pid. The
+    // uid can be a name or a number, but in the classic layout the third
+    // column after the timestamp is always the level.
+    let (uid, process, thread, level, tag_start) = match level(parts[2]) {
+        Ok(level) => (None, parts[0], parts[1], level, Some(parts[3])),
+        Err(_) => (
+            Some(parts[0]),
+            parts[1],
+            parts[2],
+            level(parts[3])?,
+            items.next(),
+        ),
+    };
+    let is_id = |id: &str| id.bytes().all(|b| b.is_ascii_digit());
+    if !(is_id(process) && is_id(thread)) {
+        return Err(ParserError(format!(
+            "Invalid Process/Thread ID: Pid {process}, Thread {thread}"
+        )));
+    }
+    let (tag, message) = match tag_start {
+        Some(first) => split_tag(&line[offset(line, first)..]),
+        None => ("", ""),
+    };
     let rec = Record {
         raw: line.into(),
-        time: Some(format!("{date} {hour}")),
+        time: Some(time),
         message: message.trim().to_owned(),
         level,
-        tag: tag.trim().to_owned(),
+        tag: words(tag),
         process: process.trim().to_owned(),
         thread: thread.trim().to_owned(),
+        uid: uid.map(ToOwned::to_owned),
+        ..Default::default()
     };
 
     Ok(rec)
@@ -106,16 +176,229 @@ impl FormatParser for DefaultParser {
     }
 }
 
-pub struct CsvParser;
+/// Prefix of the buffer dividers of logcat like "--------- beginning of main"
+/// or "--------- switch to system"
+const DIVIDER: &str = "--------- ";
+
+/// Lines logcat prints if reading from logd fails, e.g.
+/// "logcat: Unexpected EOF!" or "read: unexpected EOF!" of older versions
+/// followed by an explanation
+const READ_FAILURE: &str = r"^(?:logcat: (?:Unexpected EOF!|Unexpected length\.|read failure)|logcat read failure|read: unexpected (?:EOF!|length\.)|This means that either the device shut down, logd crashed, or this instance of logcat was unable to read log messages as quickly as they were being produced\.|If you have enabled significant logging, look into using the -G option to increase log buffer sizes\.)$";
+
+/// Message of chatty like "uid=1000(system) Binder:1234_5 expire 13 lines"
+const CHATTY_EXPIRE: &str = r"^uid=(?P<uid>\d+(?:\([^)]*\))?) .*\bexpire (?P<lines>\d+) lines?$";
+
+/// Parser of the administrative lines of logcat and logd. The lines are
+/// passed as records flagged with a [`Notice`]. It's tried before all other
+/// parsers, because chatty notices are valid logcat lines as well.
+pub struct NoticeParser {
+    read_failure: Regex,
+    chatty: Regex,
+}
+
+impl Default for NoticeParser {
+    fn default() -> Self {
+        NoticeParser {
+            read_failure: Regex::new(READ_FAILURE).expect("Invalid read failure regex"),
+            chatty: Regex::new(CHATTY_EXPIRE).expect("Invalid chatty regex"),
+        }
+    }
+}
+
+impl NoticeParser {
+    /// Uid and number of lines of a chatty expire notice
+    fn expired<'a>(&self, record: &'a Record) -> Option<(&'a str, usize)> {
+        if record.tag != "chatty" {
+            return None;
+        }
+        let captures = self.chatty.captures(&record.message)?;
+        let uid = captures.name("uid")?.as_str();
+        let lines = captures.name("lines")?.as_str().parse().ok()?;
+        Some((uid, lines))
+    }
+}
+
+impl FormatParser for NoticeParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        let notice = |notice, buffer: Option<&str>| Record {
+            raw: line.to_owned(),
+            message: line.trim().to_owned(),
+            buffer: buffer.map(ToOwned::to_owned),
+            notice: Some(notice),
+            ..Default::default()
+        };
+        if let Some(divider) = line.strip_prefix(DIVIDER) {
+            let buffer = divider
+                .strip_prefix("beginning of ")
+                .or_else(|| divider.strip_prefix("switch to "))
+                .map(str::trim)
+                .filter(|buffer| !buffer.is_empty() && !buffer.contains(' '));
+            return Ok(match buffer {
+                Some(buffer) => notice(Notice::BufferBegin, Some(buffer)),
+                None => notice(Notice::Other, None),
+            });
+        }
+        if self.read_failure.is_match(line.trim_end()) {
+            return Ok(notice(Notice::Other, None));
+        }
+        // Cheap check before parsing the line
+        if line.contains("chatty") {
+            let record = printable(line)?;
+            if self.expired(&record).is_some() {
+                return Ok(Record {
+                    notice: Some(Notice::Expired),
+                    ..record
+                });
+            }
+        }
+        Err(ParserError("No notice".to_owned()))
+    }
+}
+
+/// Lines dropped by chatty per uid as noticed in the input
+#[derive(Clone, Debug, Default)]
+pub struct ExpiredLines(Arc<Mutex<BTreeMap<String, usize>>>);
+
+impl ExpiredLines {
+    /// Uids and their number of dropped lines, most dropped first
+    pub fn counts(&self) -> Vec<(String, usize)> {
+        let mut counts = self
+            .0
+            .lock()
+            .expect("Poisoned counts")
+            .iter()
+            .map(|(uid, n)| (uid.clone(), *n))
+            .collect::<Vec<_>>();
+        counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+        counts
+    }
+
+    fn add(&self, uid: &str, lines: usize) {
+        let mut counts = self.0.lock().expect("Poisoned counts");
+        match counts.get_mut(uid) {
+            Some(n) => *n += lines,
+            None => {
+                counts.insert(uid.to_owned(), lines);
+            }
+        }
+    }
+}
+
+impl Display for ExpiredLines {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let counts = self.counts();
+        let total = counts.iter().map(|(_, n)| n).sum::<usize>();
+        let uids = counts
+            .iter()
+            .map(|(uid, n)| format!("{n} of uid {uid}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "chatty expired {total} lines: {uids}")
+    }
+}
+
+/// Number of csv columns of a record without extracted fields
+const CSV_COLUMNS: usize = 9;
+
+/// Parser of csv rows. The columns are expected in the order of the csv
+/// output unless a header row selects a different order.
+pub struct CsvParser {
+    columns: Vec<Column>,
+}
+
+impl Default for CsvParser {
+    fn default() -> Self {
+        CsvParser {
+            columns: COLUMNS.to_vec(),
+        }
+    }
+}
+
+fn csv_row(line: &str) -> Result<StringRecord, ParserError> {
+    let reader = Cursor::new(line).chain(Cursor::new([b'\n']));
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+    let mut row = StringRecord::new();
+    match rdr.read_record(&mut row) {
+        Ok(true) => Ok(row),
+        Ok(false) => Err(ParserError("Failed to parse csv".to_string())),
+        Err(e) => Err(ParserError(format!("{e}"))),
+    }
+}
 
 impl FormatParser for CsvParser {
     fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
-        let reader = Cursor::new(line).chain(Cursor::new([b'\n']));
-        let mut rdr = ReaderBuilder::new().has_headers(false).from_reader(reader);
-        if let Some(result) = rdr.deserialize().next() {
-            result.map_err(|e| ParserError(format!("{e}")))
-        } else {
-            Err(ParserError("Failed to parse csv".to_string()))
+        let row = csv_row(line)?;
+        // Trailing uid, device, fields, thread name and buffer columns are
+        // optional
+        let required = self
+            .columns
+            .iter()
+            .rposition(|c| {
+                !matches!(
+                    c,
+                    Column::Uid
+                        | Column::Device
+                        | Column::Fields
+                        | Column::ThreadName
+                        | Column::Buffer
+                )
+            })
+            .map_or(0, |i| i + 1);
+        if row.len() < required {
+            return Err(ParserError("Missing csv columns".to_string()));
+        }
+
+        // Bring the row into the default column order
+        let cell = |column| {
+            self.columns
+                .iter()
+                .position(|c| *c == column)
+                .and_then(|i| row.get(i))
+        };
+        let mut ordered = StringRecord::new();
+        for column in &COLUMNS[..CSV_COLUMNS] {
+            ordered.push_field(match (column, cell(*column)) {
+                (_, Some(value)) => value,
+                (Column::Level, None) => "None",
+                (_, None) => "",
+            });
+        }
+        let record: Record = ordered
+            .deserialize(None)
+            .map_err(|e| ParserError(format!("{e}")))?;
+        let fields = cell(Column::Fields).map(extract_fields).unwrap_or_default();
+        let optional = |column| {
+            cell(column)
+                .filter(|value| !value.is_empty())
+                .map(ToOwned::to_owned)
+        };
+        Ok(Record {
+            fields,
+            thread_name: optional(Column::ThreadName),
+            buffer: optional(Column::Buffer),
+            ..record
+        })
+    }
+
+    fn try_parse_header(&mut self, line: &str) -> bool {
+        // Cheap check before reading the row
+        if line.split(',').next().and_then(Column::from_name).is_none() {
+            return false;
+        }
+        let columns = csv_row(line).ok().and_then(|row| {
+            row.iter()
+                .map(Column::from_name)
+                .collect::<Option<Vec<_>>>()
+        });
+        match columns {
+            Some(columns) if columns.len() > 1 => {
+                self.columns = columns;
+                true
+            }
+            _ => false,
         }
     }
 }
@@ -128,63 +411,954 @@ impl FormatParser for JsonParser {
     }
 }
 
+/// Timestamp of an Android Studio export
+#[derive(Deserialize)]
+struct StudioTimestamp {
+    seconds: i64,
+    #[serde(default)]
+    nanos: u32,
+}
+
+/// Header of an Android Studio export record. Older exports use the names
+/// of the aliases.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StudioHeader {
+    #[serde(alias = "logLevel")]
+    priority: String,
+    #[serde(alias = "pid")]
+    process_id: u64,
+    #[serde(alias = "tid")]
+    thread_id: u64,
+    #[serde(default)]
+    application_id: Option<String>,
+    #[serde(default)]
+    tag: String,
+    timestamp: StudioTimestamp,
+}
+
+#[derive(Deserialize)]
+struct StudioRecord {
+    header: StudioHeader,
+    message: String,
+}
+
+/// Parser of the JSON records exported by the logcat view of Android Studio.
+/// The header holds the priority as name like "INFO" and the time as seconds
+/// and nanos since the epoch which are formatted in UTC.
+pub struct StudioParser;
+
+impl FormatParser for StudioParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        let StudioRecord { header, message } = from_str(line)
+            .map_err(|e| ParserError(format!("Not an Android Studio record: {e}")))?;
+        let level = header.priority.parse().map_err(ParserError)?;
+        let format = format_description!(
+            "[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:3]"
+        );
+        let time = OffsetDateTime::from_unix_timestamp(header.timestamp.seconds)
+            .ok()
+            .and_then(|time| time.replace_nanosecond(header.timestamp.nanos).ok())
+            .and_then(|time| time.format(format).ok())
+            .ok_or_else(|| ParserError("Invalid Android Studio timestamp".into()))?;
+        Ok(Record {
+            time: Some(time),
+            message,
+            level,
+            tag: header.tag,
+            process: header.process_id.to_string(),
+            thread: header.thread_id.to_string(),
+            raw: line.into(),
+            application: header.application_id.filter(|id| !id.is_empty()),
+            ..Default::default()
+        })
+    }
+}
+
+/// Bytes allowed in the key of a key=value pair
+fn is_key(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'-' || b == b'.'
+}
+
+/// Bytes separating key=value pairs
+fn is_separator(b: u8) -> bool {
+    b.is_ascii_whitespace() || b == b','
+}
+
+/// Extract key=value pairs from a message. Values containing spaces can be
+/// quoted like `msg="hello world"`. Duplicate keys keep the last value.
+pub fn extract_fields(message: &str) -> BTreeMap<String, String> {
+    let mut fields = BTreeMap::new();
+    if !message.contains('=') {
+        return fields;
+    }
+
+    let bytes = message.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        while i < bytes.len() && is_key(bytes[i]) {
+            i += 1;
+        }
+        if i > start && bytes.get(i) == Some(&b'=') {
+            let key = &message[start..i];
+            i += 1;
+            let value = if bytes.get(i) == Some(&b'"') {
+                i += 1;
+                // Quotes and backslashes within quotes are escaped with a
+                // backslash
+                let mut value = String::new();
+                let mut begin = i;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if bytes[i] == b'\\' && matches!(bytes.get(i + 1), Some(b'"' | b'\\')) {
+                        value.push_str(&message[begin..i]);
+                        begin = i + 1;
+                        i += 2;
+                    } else {
+                        i += 1;
+                    }
+                }
+                value.push_str(&message[begin..i]);
+                // Skip the closing quote
+                i += 1;
+                value
+            } else {
+                let begin = i;
+                while i < bytes.len() && !is_separator(bytes[i]) {
+                    i += 1;
+                }
+                message[begin..i].to_owned()
+            };
+            fields.insert(key.to_owned(), value);
+        }
+
+        // Skip the rest of the current word and the following separators
+        while i < bytes.len() && !is_separator(bytes[i]) {
+            i += 1;
+        }
+        while i < bytes.len() && is_separator(bytes[i]) {
+            i += 1;
+        }
+    }
+    fields
+}
+
+/// Names of the capture groups a `RegexParser` maps onto `Record` fields
+const REGEX_PARSER_GROUPS: [&str; 7] = [
+    "time", "message", "level", "tag", "process", "thread", "uid",
+];
+
+/// User defined parser. Named capture groups are mapped to the record
+/// fields with the same name.
+pub struct RegexParser {
+    regex: Regex,
+}
+
+impl RegexParser {
+    pub fn new(pattern: &str) -> Result<RegexParser, ParserError> {
+        let regex = Regex::new(pattern)
+            .map_err(|e| ParserError(format!("Invalid parser regex {pattern}: {e}")))?;
+        if let Some(name) = regex
+            .capture_names()
+            .flatten()
+            .find(|name| !REGEX_PARSER_GROUPS.contains(name))
+        {
+            return Err(ParserError(format!(
+                "Invalid group name {name} in parser regex {pattern}. Valid names are {}",
+                REGEX_PARSER_GROUPS.join(", ")
+            )));
+        }
+        Ok(RegexParser { regex })
+    }
+}
+
+impl FormatParser for RegexParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        let captures = self
+            .regex
+            .captures(line)
+            .ok_or_else(|| ParserError("Line does not match parser regex".into()))?;
+        let group = |name| captures.name(name).map(|m| m.as_str().to_owned());
+        Ok(Record {
+            time: group("time"),
+            message: group("message").unwrap_or_default(),
+            level: Level::from(group("level")),
+            tag: group("tag").unwrap_or_default(),
+            process: group("process").unwrap_or_default(),
+            thread: group("thread").unwrap_or_default(),
+            raw: line.into(),
+            uid: group("uid"),
+            ..Default::default()
+        })
+    }
+}
+
+/// Syslog line like "Jan 11 01:10:02 host tag[1234]: message" with an
+/// optional "<prio>" prefix. journalctl -o short-iso timestamps are accepted
+/// too.
+const SYSLOG: &str = r"^(?:<(?P<prio>\d{1,3})>)?(?P<time>[A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2}|\d{4}-\d{2}-\d{2}T\S+) \S+ (?P<tag>[^\s\[:]+)(?:\[(?P<pid>\d+)\])?: ?(?P<message>.*)$";
+
+/// Parser of syslog and journalctl lines
+pub struct SyslogParser {
+    regex: Regex,
+}
+
+impl Default for SyslogParser {
+    fn default() -> Self {
+        SyslogParser {
+            regex: Regex::new(SYSLOG).expect("Invalid syslog regex"),
+        }
+    }
+}
+
+/// Map the severity of a syslog priority (facility * 8 + severity)
+fn syslog_level(prio: u8) -> Level {
+    match prio & 7 {
+        0 => Level::Assert,
+        1 | 2 => Level::Fatal,
+        3 => Level::Error,
+        4 => Level::Warn,
+        5 | 6 => Level::Info,
+        _ => Level::Debug,
+    }
+}
+
+impl FormatParser for SyslogParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        let captures = self
+            .regex
+            .captures(line)
+            .ok_or_else(|| ParserError("Not a syslog line".into()))?;
+        let group = |name| captures.name(name).map(|m| m.as_str());
+        let level = group("prio")
+            .and_then(|prio| prio.parse().ok())
+            .map(syslog_level)
+            .unwrap_or_default();
+        Ok(Record {
+            time: group("time").map(ToOwned::to_owned),
+            message: group("message").unwrap_or_default().to_owned(),
+            level,
+            tag: group("tag").unwrap_or_default().to_owned(),
+            process: group("pid").unwrap_or_default().to_owned(),
+            raw: line.into(),
+            ..Default::default()
+        })
+    }
+}
+
+/// env_logger line like "[2024-01-11T01:10:02Z INFO  foo::bar] message".
+/// The timestamp is optional.
+const ENV_LOGGER: &str = r"^\[(?:(?P<time>\S+) +)?(?P<level>TRACE|DEBUG|INFO|WARN|ERROR) +(?P<tag>[^\]\s]+)\] ?(?P<message>.*)$";
+
+/// tracing-subscriber fmt line like
+/// "2024-01-11T01:10:02.755Z  INFO span{a=1}: foo::bar: message"
+const TRACING_FMT: &str = r"^(?P<time>\d{4}-\d{2}-\d{2}T\S+) +(?P<level>TRACE|DEBUG|INFO|WARN|ERROR) +(?:\S+: )*?(?P<tag>[A-Za-z_][\w:]*): (?P<message>.*)$";
+
+/// Parser of the default formats of env_logger and tracing-subscriber. The
+/// module path is used as tag.
+pub struct EnvLoggerParser {
+    env_logger: Regex,
+    tracing: Regex,
+}
+
+impl Default for EnvLoggerParser {
+    fn default() -> Self {
+        EnvLoggerParser {
+            env_logger: Regex::new(ENV_LOGGER).expect("Invalid env_logger regex"),
+            tracing: Regex::new(TRACING_FMT).expect("Invalid tracing regex"),
+        }
+    }
+}
+
+impl FormatParser for EnvLoggerParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        let captures = self
+            .env_logger
+            .captures(line)
+            .or_else(|| self.tracing.captures(line))
+            .ok_or_else(|| ParserError("Not an env_logger or tracing line".into()))?;
+        let group = |name| captures.name(name).map(|m| m.as_str());
+        let level = match group("level") {
+            Some("TRACE") => Level::Trace,
+            Some("DEBUG") => Level::Debug,
+            Some("INFO") => Level::Info,
+            Some("WARN") => Level::Warn,
+            _ => Level::Error,
+        };
+        Ok(Record {
+            time: group("time").map(ToOwned::to_owned),
+            message: group("message").unwrap_or_default().to_owned(),
+            level,
+            tag: group("tag").unwrap_or_default().to_owned(),
+            raw: line.into(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Maximum number of lines joined into one csv row. The quote of a row
+/// that isn't closed by then is taken for a stray quote.
+const MAX_CSV_LINES: usize = 100;
+
+/// True if `line` opens or closes a quoted csv field. Quotes within quoted
+/// fields are doubled, so an odd number of quotes toggles.
+fn toggles_quote(line: &str) -> bool {
+    line.bytes().filter(|b| *b == b'"').count() % 2 == 1
+}
+
+/// Result of feeding a line into [`CsvRows`]
+enum CsvRow<'a> {
+    /// The line continues a quoted field
+    Pending,
+    Complete(Cow<'a, str>),
+    /// Lines of a row whose quoted field wasn't closed within
+    /// [`MAX_CSV_LINES`]. They're parsed one by one.
+    Unclosed(Vec<String>),
+}
+
+/// Joins the lines of csv rows with quoted fields that contain newlines,
+/// e.g. messages with stack traces
+#[derive(Default)]
+struct CsvRows {
+    /// Set if the input is csv. Lines of other formats aren't joined.
+    enabled: bool,
+    /// Lines of a row with an open quoted field
+    pending: Option<Vec<String>>,
+}
+
+impl CsvRows {
+    /// Feed `line` into the current row. Returns the row once all quoted
+    /// fields are closed.
+    fn push<'a>(&mut self, line: &'a str) -> CsvRow<'a> {
+        let toggles = toggles_quote(line);
+        match self.pending.take() {
+            None if !toggles => CsvRow::Complete(Cow::Borrowed(line)),
+            None => {
+                self.pending = Some(vec![line.to_owned()]);
+                CsvRow::Pending
+            }
+            Some(mut lines) => {
+                lines.push(line.to_owned());
+                if toggles {
+                    CsvRow::Complete(Cow::Owned(lines.join("\n")))
+                } else if lines.len() >= MAX_CSV_LINES {
+                    CsvRow::Unclosed(lines)
+                } else {
+                    self.pending = Some(lines);
+                    CsvRow::Pending
+                }
+            }
+        }
+    }
+
+    /// Lines of the row with an open quoted field, e.g. because the next
+    /// record starts
+    fn abandon(&mut self) -> Vec<String> {
+        self.pending.take().unwrap_or_default()
+    }
+
+    /// Row with a quoted field that is not closed at the end of the input
+    fn finish(&mut self) -> Option<String> {
+        self.pending.take().map(|lines| lines.join("\n"))
+    }
+}
+
+/// Maximum number of lines folded into the message of a record
+const MAX_FOLDED_LINES: usize = 100;
+
+/// Folds lines that can't be parsed into the message of the previous
+/// record, e.g. the continuation lines of kernel oopses. The last record is
+/// held back until the next record starts.
+#[derive(Default)]
+struct Continuations {
+    enabled: bool,
+    /// Last record and the number of lines folded into it. Records that
+    /// weren't parsed count as full.
+    pending: Option<(Record, usize)>,
+}
+
+impl Continuations {
+    /// Hold back `record` and return the previous one. Lines are folded
+    /// into records that are `parsed` only.
+    fn replace(&mut self, record: Record, parsed: bool) -> Option<Record> {
+        let folded = if parsed { 0 } else { MAX_FOLDED_LINES };
+        self.pending
+            .replace((record, folded))
+            .map(|(record, _)| record)
+    }
+
+    /// Append `line` to the message of the last record. False if there's
+    /// no record to fold into.
+    fn fold(&mut self, line: &str) -> bool {
+        match &mut self.pending {
+            Some((record, folded)) if *folded < MAX_FOLDED_LINES => {
+                for text in [&mut record.message, &mut record.raw] {
+                    text.push('\n');
+                    text.push_str(line);
+                }
+                *folded += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn finish(&mut self) -> Option<Record> {
+        self.pending.take().map(|(record, _)| record)
+    }
+}
+
+/// Header of a record of "logcat -v long" like
+/// "[ 01-11 01:10:02.755   882: 1203 I/InputReader ]"
+const LONG_HEADER: &str = r"^\[ (?P<time>.+?) +(?:(?P<uid>\S+): +)?(?P<process>\d+): *(?P<thread>\d+) (?P<level>[TVDIWEFA])/(?P<tag>.*?) *\]$";
+
+/// Record of "logcat -v long" that is continued by the following lines
+struct LongRecord {
+    record: Record,
+    lines: Vec<String>,
+    /// Blank lines after the last message line. A blank line separates
+    /// records but can be part of a message as well.
+    blanks: usize,
+}
+
+/// Reassembles records of "logcat -v long" that span multiple lines
+struct LongFormat {
+    header: Regex,
+    pending: Option<LongRecord>,
+}
+
+impl Default for LongFormat {
+    fn default() -> Self {
+        LongFormat {
+            header: Regex::new(LONG_HEADER).expect("Invalid long format regex"),
+            pending: None,
+        }
+    }
+}
+
+impl LongFormat {
+    fn try_parse_header(&self, line: &str) -> Option<Record> {
+        if !line.starts_with("[ ") {
+            return None;
+        }
+        let captures = self.header.captures(line)?;
+        let group = |name| captures.name(name).map(|m| m.as_str().to_owned());
+        Some(Record {
+            time: group("time"),
+            level: level(captures.name("level")?.as_str()).ok()?,
+            tag: group("tag").unwrap_or_default(),
+            process: group("process").unwrap_or_default(),
+            thread: group("thread").unwrap_or_default(),
+            uid: group("uid"),
+            raw: line.to_owned(),
+            ..Default::default()
+        })
+    }
+
+    /// Feed `line` into the current record. Returns the previous record if
+    /// `line` starts a new one and `None` if `line` isn't in long format.
+    fn push(&mut self, line: &str) -> Option<Option<Record>> {
+        // A header within a message is only possible after a blank line
+        let starts_record = match &self.pending {
+            Some(p) => p.lines.is_empty() || p.blanks > 0,
+            None => true,
+        };
+        if starts_record {
+            if let Some(record) = self.try_parse_header(line) {
+                let previous = self.finish();
+                self.pending = Some(LongRecord {
+                    record,
+                    lines: Vec::new(),
+                    blanks: 0,
+                });
+                return Some(previous);
+            }
+        }
+
+        let pending = self.pending.as_mut()?;
+        if line.trim().is_empty() {
+            pending.blanks += 1;
+        } else {
+            let blanks = std::mem::take(&mut pending.blanks);
+            let len = pending.lines.len() + blanks;
+            pending.lines.resize(len, String::new());
+            pending.lines.push(line.to_owned());
+        }
+        Some(None)
+    }
+
+    fn finish(&mut self) -> Option<Record> {
+        let LongRecord { record, lines, .. } = self.pending.take()?;
+        let message = lines.join("\n");
+        Some(Record {
+            raw: format!("{}\n{}", record.raw, message),
+            message,
+            ..record
+        })
+    }
+}
+
+/// Parser added with [`Parser::prepend`]. It's shared with the forks of the
+/// parser.
+struct SharedParser(Arc<Mutex<Box<dyn FormatParser>>>);
+
+impl FormatParser for SharedParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        self.0.lock().expect("Poisoned parser").try_parse_str(line)
+    }
+
+    fn try_parse_header(&mut self, line: &str) -> bool {
+        self.0
+            .lock()
+            .expect("Poisoned parser")
+            .try_parse_header(line)
+    }
+}
+
+/// Format of the input lines
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum InputFormat {
+    /// Detect the format of each line
+    #[default]
+    Auto,
+    /// Output of adb logcat including "logcat -v long"
+    Logcat,
+    Csv,
+    Json,
+}
+
+impl InputFormat {
+    /// The builtin parsers tried for lines in this format
+    fn parsers(self) -> Vec<Box<dyn FormatParser>> {
+        match self {
+            InputFormat::Auto => vec![
+                Box::new(DefaultParser),
+                Box::new(CsvParser::default()),
+                Box::new(JsonParser),
+                Box::new(StudioParser),
+                Box::new(SyslogParser::default()),
+                Box::new(EnvLoggerParser::default()),
+            ],
+            InputFormat::Logcat => vec![Box::new(DefaultParser)],
+            InputFormat::Csv => vec![Box::new(CsvParser::default())],
+            InputFormat::Json => vec![Box::new(JsonParser), Box::new(StudioParser)],
+        }
+    }
+}
+
+/// Number of lines dropped by a strict parser because they couldn't be parsed
+#[derive(Clone, Debug, Default)]
+pub struct ParseFailures(Arc<AtomicUsize>);
+
+impl ParseFailures {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
 pub struct Parser {
+    format: InputFormat,
     parsers: Vec<Box<dyn FormatParser>>,
+    /// Parsers added with prepend
+    custom: Vec<Arc<Mutex<Box<dyn FormatParser>>>>,
     last: Option<usize>,
+    /// Set until the first line of the input is read and after the header
+    /// of a session. Csv headers are read there only.
+    start: bool,
+    long: LongFormat,
+    csv: CsvRows,
+    /// Records of the lines of a csv row with a stray quote that are not
+    /// returned yet
+    unclosed: VecDeque<Record>,
+    continuations: Continuations,
+    /// Set if lines that can't be parsed are dropped instead of passed as
+    /// raw message
+    strict: Option<ParseFailures>,
+    notices: NoticeParser,
+    expired: ExpiredLines,
 }
 
 impl Default for Parser {
     fn default() -> Self {
+        Parser::with_format(InputFormat::Auto)
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Parser::with_parsers(Vec::new())
+    }
+
+    /// Parser trying `parsers` in the given order
+    pub fn with_parsers(parsers: Vec<Box<dyn FormatParser>>) -> Self {
         Parser {
-            parsers: vec![
-                Box::new(DefaultParser),
-                Box::new(CsvParser),
-                Box::new(JsonParser),
-            ],
+            format: InputFormat::Auto,
+            parsers,
+            custom: Vec::new(),
             last: None,
+            start: true,
+            long: LongFormat::default(),
+            csv: CsvRows::default(),
+            unclosed: VecDeque::new(),
+            continuations: Continuations::default(),
+            strict: None,
+            notices: NoticeParser::default(),
+            expired: ExpiredLines::default(),
+        }
+    }
+
+    /// Parser with the builtin parsers of `format`. Detection of other
+    /// formats is skipped.
+    pub fn with_format(format: InputFormat) -> Self {
+        Parser {
+            format,
+            ..Parser::with_parsers(format.parsers())
+        }
+    }
+
+    /// Replace the builtin parsers with the ones of `format`. Parsers added
+    /// with prepend are kept.
+    pub fn set_format(&mut self, format: InputFormat) {
+        self.format = format;
+        self.parsers = self
+            .custom
+            .iter()
+            .rev()
+            .map(|custom| Box::new(SharedParser(custom.clone())) as Box<dyn FormatParser>)
+            .chain(format.parsers())
+            .collect();
+        self.last = None;
+    }
+
+    /// Drop lines that can't be parsed and count them in `failures`
+    pub fn strict(&mut self, failures: ParseFailures) {
+        self.strict = Some(failures);
+    }
+
+    /// Handle to the lines dropped by chatty. It's shared with the forks of
+    /// the parser.
+    pub fn expired_lines(&self) -> ExpiredLines {
+        self.expired.clone()
+    }
+
+    /// Append lines that can't be parsed to the message of the previous
+    /// record instead of passing them as records of their own. Records are
+    /// returned once the next record starts.
+    pub fn fold_continuations(&mut self, fold: bool) {
+        self.continuations.enabled = fold;
+    }
+
+    /// Add a parser that is tried before all others
+    pub fn prepend(&mut self, parser: Box<dyn FormatParser>) {
+        let parser = Arc::new(Mutex::new(parser));
+        self.parsers
+            .insert(0, Box::new(SharedParser(parser.clone())));
+        self.custom.push(parser);
+        self.last = None;
+    }
+
+    /// Parser with the builtin parsers and the ones added with prepend but
+    /// without the state of the lines parsed so far, e.g. for parsing
+    /// multiple files concurrently
+    pub fn fork(&self) -> Parser {
+        let mut parser = Parser::with_format(self.format);
+        for custom in &self.custom {
+            parser
+                .parsers
+                .insert(0, Box::new(SharedParser(custom.clone())));
+        }
+        parser.custom = self.custom.clone();
+        parser.strict = self.strict.clone();
+        parser.expired = self.expired.clone();
+        parser.continuations.enabled = self.continuations.enabled;
+        parser
+    }
+
+    /// Read the next line like the first line of an input, e.g. a csv
+    /// header that is repeated for a batch of lines
+    pub(crate) fn start_input(&mut self) {
+        self.start = true;
+    }
+
+    /// Tracker of the records starting in the lines of this parser. Headers
+    /// of parsers added with prepend are read by the tracker as well.
+    pub fn boundaries(&self) -> Boundaries {
+        Boundaries {
+            parser: self.fork(),
+            long: None,
+            header: None,
+        }
+    }
+
+    /// Parse a line into a record. Session headers and a csv header on the
+    /// first line of the input or after a session header are consumed and
+    /// yield no record. Records of "logcat -v long" span multiple lines and are
+    /// returned once the next record starts or by [`Parser::finish`]. So
+    /// are csv rows with quoted newlines if the format is csv or a csv
+    /// header was read, and records that continuation lines are folded into.
+    pub fn parse(&mut self, line: &str) -> Option<Record> {
+        let record = self.parse_line(line);
+        // Lines of a row with a stray quote are passed one per line
+        if self.unclosed.is_empty() {
+            return record;
+        }
+        self.unclosed.extend(record);
+        self.unclosed.pop_front()
+    }
+
+    fn parse_line(&mut self, line: &str) -> Option<Record> {
+        if !self.csv.enabled && self.format != InputFormat::Csv {
+            return self.parse_row(line);
+        }
+        if self.ends_csv_row(line) {
+            let lines = self.csv.abandon();
+            self.parse_unclosed(lines);
+        }
+        match self.csv.push(line) {
+            CsvRow::Pending => None,
+            CsvRow::Complete(row) => self.parse_row(&row),
+            CsvRow::Unclosed(lines) => {
+                self.parse_unclosed(lines);
+                None
+            }
+        }
+    }
+
+    /// True if `line` is a record of its own while a csv row waits for
+    /// the end of a quoted field. The field isn't closed within its record
+    /// then and its quote is taken for a stray quote.
+    fn ends_csv_row(&self, line: &str) -> bool {
+        self.csv.pending.is_some()
+            && !toggles_quote(line)
+            && self.parsers.iter().any(|p| p.try_parse_str(line).is_ok())
+    }
+
+    /// Parse the lines of a csv row with a stray quote one by one
+    fn parse_unclosed(&mut self, lines: Vec<String>) {
+        for line in lines {
+            if let Some(record) = self.parse_row(&line) {
+                self.unclosed.push_back(record);
+            }
         }
     }
-}
 
-impl Parser {
-    pub fn new() -> Self {
-        Parser {
-            parsers: Vec::new(),
-            last: None,
+    fn parse_row(&mut self, line: &str) -> Option<Record> {
+        if !self.continuations.enabled {
+            return self
+                .try_parse_line(line)
+                .unwrap_or_else(|| self.unparsed(line));
+        }
+        match self.try_parse_line(line) {
+            Some(record) => record.and_then(|record| self.continuations.replace(record, true)),
+            None if self.continuations.fold(line) => None,
+            None => self
+                .unparsed(line)
+                .and_then(|record| self.continuations.replace(record, false)),
         }
     }
 
-    pub fn parse(&mut self, line: &str) -> Record {
+    /// Parse `line` with the first matching parser. `None` if no parser
+    /// matches.
+    fn try_parse_line(&mut self, line: &str) -> Option<Option<Record>> {
+        let start = std::mem::take(&mut self.start);
+        if matches!(self.format, InputFormat::Auto | InputFormat::Logcat) {
+            if let Some(record) = self.long.push(line) {
+                return Some(record);
+            }
+        }
+
+        if self.try_parse_header(line, start) {
+            return Some(None);
+        }
+
+        // Administrative lines of logcat. Not remembered as last parser,
+        // because they are rare.
+        if matches!(self.format, InputFormat::Auto | InputFormat::Logcat) {
+            if let Ok(record) = self.notices.try_parse_str(line) {
+                if let Some((uid, lines)) = self.notices.expired(&record) {
+                    self.expired.add(uid, lines);
+                }
+                return Some(Some(record));
+            }
+        }
+
         if let Some(last) = self.last {
             let p = &self.parsers[last];
             if let Ok(r) = p.try_parse_str(line) {
-                return r;
+                return Some(Some(r));
             }
         }
 
         for (i, p) in self.parsers.iter().map(Box::as_ref).enumerate() {
             if let Ok(r) = p.try_parse_str(line) {
                 self.last = Some(i);
-                return r;
+                return Some(Some(r));
             }
         }
+        None
+    }
+
+    /// True if `line` is the header of a recorded session or, at the `start`
+    /// of the input, the header of a csv file. Later rows made of column
+    /// names are parsed as rows.
+    fn try_parse_header(&mut self, line: &str, start: bool) -> bool {
+        if Session::is_header(line) {
+            self.start = true;
+            return true;
+        }
+        if start && self.parsers.iter_mut().any(|p| p.try_parse_header(line)) {
+            self.csv.enabled = true;
+            return true;
+        }
+        false
+    }
+
+    /// Record of a line that no parser matches. Dropped if strict.
+    fn unparsed(&self, line: &str) -> Option<Record> {
+        if let Some(failures) = &self.strict {
+            failures.0.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
 
         // Seems that we cannot parse this record
         // Treat the raw input as message
-        Record {
+        Some(Record {
             raw: String::from(line),
             message: String::from(line),
             ..Default::default()
+        })
+    }
+
+    /// Records that are complete besides the one returned by the last call
+    /// of [`Parser::parse`], e.g. the lines of a csv row with a stray quote.
+    /// Call until `None` is returned. Records that are not taken are
+    /// returned by the next calls of [`Parser::parse`].
+    pub fn ready(&mut self) -> Option<Record> {
+        self.unclosed.pop_front()
+    }
+
+    /// Records that are still assembled from multiple lines at the end of
+    /// the input. Call until `None` is returned.
+    pub fn finish(&mut self) -> Option<Record> {
+        if let Some(record) = self.unclosed.pop_front() {
+            return Some(record);
+        }
+        if let Some(row) = self.csv.finish() {
+            if let Some(record) = self.parse_row(&row) {
+                return Some(record);
+            }
+        }
+        if let Some(record) = self.long.finish() {
+            if !self.continuations.enabled {
+                return Some(record);
+            }
+            if let Some(previous) = self.continuations.replace(record, true) {
+                return Some(previous);
+            }
+        }
+        self.continuations.finish()
+    }
+}
+
+/// Tells where records start in the lines of a parser without parsing
+/// them, e.g. for cutting the input into batches that are parsed apart.
+/// Created by [`Parser::boundaries`].
+pub struct Boundaries {
+    parser: Parser,
+    /// Set once a header of "logcat -v long" is read. Whether the record
+    /// has message lines and whether the last line was blank.
+    long: Option<(bool, bool)>,
+    /// Last csv header
+    header: Option<String>,
+}
+
+impl Boundaries {
+    /// Track the state after `line`
+    pub fn push(&mut self, line: &str) {
+        let row = if self.parser.csv.enabled || self.parser.format == InputFormat::Csv {
+            if self.parser.ends_csv_row(line) {
+                self.parser.csv.abandon();
+            }
+            match self.parser.csv.push(line) {
+                CsvRow::Complete(row) => row,
+                CsvRow::Pending | CsvRow::Unclosed(_) => return,
+            }
+        } else {
+            Cow::Borrowed(line)
+        };
+
+        let start = std::mem::take(&mut self.parser.start);
+        if matches!(self.parser.format, InputFormat::Auto | InputFormat::Logcat) {
+            if self.long_header(&row) {
+                self.long = Some((false, false));
+                return;
+            }
+            if let Some((lines, blank)) = &mut self.long {
+                *blank = row.trim().is_empty();
+                *lines |= !*blank;
+                return;
+            }
+        }
+
+        if self.parser.try_parse_header(&row, start) && !Session::is_header(&row) {
+            self.header = Some(row.into_owned());
         }
     }
+
+    /// True if a record starts at `line`, so the lines before it can be
+    /// parsed apart from the lines after it
+    pub fn starts_record(&self, line: &str) -> bool {
+        if self.parser.csv.pending.is_some() {
+            return false;
+        }
+        if self.long.is_some() {
+            return self.long_header(line);
+        }
+        if !self.parser.continuations.enabled {
+            return true;
+        }
+        // Lines that can't be parsed are folded into the previous record
+        let logcat = matches!(self.parser.format, InputFormat::Auto | InputFormat::Logcat);
+        (logcat
+            && (self.parser.long.try_parse_header(line).is_some()
+                || self.parser.notices.try_parse_str(line).is_ok()))
+            || self
+                .parser
+                .parsers
+                .iter()
+                .any(|p| p.try_parse_str(line).is_ok())
+    }
+
+    /// True if no record read so far can be continued by the next lines
+    pub fn idle(&self) -> bool {
+        self.parser.csv.pending.is_none()
+            && self.long.is_none()
+            && !self.parser.continuations.enabled
+    }
+
+    /// The csv header that applies to the next lines
+    pub fn header(&self) -> Option<&str> {
+        self.header.as_deref()
+    }
+
+    /// True if `line` starts a record of "logcat -v long"
+    fn long_header(&self, line: &str) -> bool {
+        let starts_record = match self.long {
+            Some((lines, blank)) => !lines || blank,
+            None => true,
+        };
+        starts_record && self.parser.long.try_parse_header(line).is_some()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::{
-        level, printable, CsvParser, DefaultParser, FormatParser, JsonParser, Parser, ParserError,
+        extract_fields, level, printable, CsvParser, DefaultParser, EnvLoggerParser, FormatParser,
+        InputFormat, JsonParser, NoticeParser, ParseFailures, Parser, ParserError, RegexParser,
+        StudioParser, SyslogParser, MAX_CSV_LINES, MAX_TAG_LENGTH,
     };
-    use crate::record::Level;
+    use crate::record::{format_fields, Level, Notice, Record};
 
     #[test]
     fn parse_level() -> Result<(), ParserError> {
@@ -195,6 +1369,7 @@ mod test {
         assert_eq!(level("E")?, Level::Error);
         assert_eq!(level("F")?, Level::Fatal);
         assert_eq!(level("A")?, Level::Assert);
+        assert_eq!(level("T")?, Level::Trace);
         assert!(level("INEXISTENT").is_err());
         Ok(())
     }
@@ -238,6 +1413,95 @@ mod test {
         assert_eq!(record.level, Level::Warn);
         assert_eq!(record.tag, "[114416.534450,0] mdss_dsi_off-");
         assert_eq!(record.message, "");
+
+        let text = "03-26 13:17:38.345   512   530 I SurfaceFlinger: GL: vendor  Qualcomm";
+        let record = parser.try_parse_str(text)?;
+        assert_eq!(record.tag, "SurfaceFlinger");
+        assert_eq!(record.message, "GL: vendor  Qualcomm");
+
+        let text = "03-26 13:17:38.345   512   530 I AudioFlinger::Thread: standby  after 3s";
+        let record = parser.try_parse_str(text)?;
+        assert_eq!(record.tag, "AudioFlinger::Thread");
+        assert_eq!(record.message, "standby  after 3s");
+
+        // Without colon the tag is a single word
+        let text = "03-26 13:17:38.345     0     0 I healthd battery  l=80 v=4200";
+        let record = parser.try_parse_str(text)?;
+        assert_eq!(record.tag, "healthd");
+        assert_eq!(record.message, "battery  l=80 v=4200");
+
+        let text = format!(
+            "03-26 13:17:38.345     0     0 I dump {}: end",
+            "x".repeat(MAX_TAG_LENGTH)
+        );
+        let record = parser.try_parse_str(&text)?;
+        assert_eq!(record.tag, "dump");
+        assert!(record.message.ends_with(": end"));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_printable_seconds() -> Result<(), ParserError> {
+        let parser = DefaultParser {};
+        let text = "   33.567     1     1 I init: starting service 'ueventd'...";
+        let record = parser.try_parse_str(text)?;
+        assert_eq!(record.time, Some("33.567".to_string()));
+        assert_eq!(record.process, "1");
+        assert_eq!(record.thread, "1");
+        assert_eq!(record.level, Level::Info);
+        assert_eq!(record.tag, "init");
+        assert_eq!(record.message, "starting service 'ueventd'...");
+
+        let text = "1638486473.123  6496  6496 D ViewRootImpl: ViewPostIme pointer 1";
+        let record = parser.try_parse_str(text)?;
+        assert_eq!(record.time, Some("1638486473.123".to_string()));
+        assert_eq!(record.process, "6496");
+        assert_eq!(record.level, Level::Debug);
+        assert_eq!(record.tag, "ViewRootImpl");
+        assert_eq!(record.message, "ViewPostIme pointer 1");
+
+        let text = "114416.534450123     0     0 W kernel: mdss_dsi_off";
+        let record = parser.try_parse_str(text)?;
+        assert_eq!(record.time, Some("114416.534450123".to_string()));
+        assert_eq!(record.level, Level::Warn);
+        assert_eq!(record.tag, "kernel");
+
+        assert!(parser
+            .try_parse_str("1638486473. 6496 6496 D Tag: msg")
+            .is_err());
+        assert!(parser.try_parse_str(".123 6496 6496 D Tag: msg").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_printable_uid() -> Result<(), ParserError> {
+        let parser = DefaultParser {};
+        let text = "01-10 23:56:50.101 u0_a123  4242  4250 I ActivityManager: Start proc";
+        let record = parser.try_parse_str(text)?;
+        assert_eq!(record.time, Some("01-10 23:56:50.101".to_string()));
+        assert_eq!(record.uid, Some("u0_a123".to_string()));
+        assert_eq!(record.process, "4242");
+        assert_eq!(record.thread, "4250");
+        assert_eq!(record.level, Level::Info);
+        assert_eq!(record.tag, "ActivityManager");
+        assert_eq!(record.message, "Start proc");
+
+        let text = "01-10 23:56:50.101  1000   882  1203 W InputReader: D: something";
+        let record = parser.try_parse_str(text)?;
+        assert_eq!(record.uid, Some("1000".to_string()));
+        assert_eq!(record.process, "882");
+        assert_eq!(record.thread, "1203");
+        assert_eq!(record.level, Level::Warn);
+        assert_eq!(record.tag, "InputReader");
+        assert_eq!(record.message, "D: something");
+
+        let text = "01-10 23:56:50.101   882  1203 W InputReader: something";
+        let record = parser.try_parse_str(text)?;
+        assert_eq!(record.uid, None);
+        assert_eq!(record.process, "882");
+
+        let text = "01-10 23:56:50.101 u0_a123 u0_a123 4250 I Tag: not a pid";
+        assert!(parser.try_parse_str(text).is_err());
         Ok(())
     }
 
@@ -247,6 +1511,18 @@ mod test {
             am_create_service: [0,119985987,.PersistentIntentOperationService,10137,1755]";
         assert!(printable(text).is_err());
 
+        let text = "01-10 23:56:50.101  1234  NOTATHREAD I Tag: message";
+        assert_eq!(
+            printable(text).unwrap_err().to_string(),
+            "Invalid Process/Thread ID: Pid 1234, Thread NOTATHREAD"
+        );
+        // Digits of other scripts aren't ids
+        let text = "01-10 23:56:50.101  1234  \u{663}\u{663} I Tag: message";
+        assert!(printable(text).is_err());
+        // Kernel lines
+        let text = "01-10 23:56:50.101     0     0 I init: starting";
+        assert_eq!(printable(text).unwrap().process, "0");
+
         let text = "I hate the antichrist";
         assert!(printable(text).is_err());
 
@@ -255,7 +1531,7 @@ mod test {
 
     #[test]
     fn parse_csv() {
-        let parser = CsvParser {};
+        let parser = CsvParser::default();
         let text = "07-01 14:13:14.446,Sensor:batt_therm:29000 mC,Info,ThermalEngine,\
         225,295,07-01 14:13:14.446   225   295 I ThermalEngine: Sensor:batt_therm:29000 mC";
         let record = parser.try_parse_str(text).unwrap();
@@ -271,13 +1547,250 @@ mod test {
         );
     }
 
+    #[test]
+    fn extract_key_values() {
+        let fields = extract_fields(r#"state=on msg="hello world", count=3 state=off"#);
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields["msg"], "hello world");
+        assert_eq!(fields["count"], "3");
+        // Duplicate keys keep the last value
+        assert_eq!(fields["state"], "off");
+
+        assert!(extract_fields("Screen is off").is_empty());
+        assert!(extract_fields("a == b").is_empty());
+        assert_eq!(extract_fields("empty= x=\"").len(), 2);
+
+        // Quotes and backslashes are escaped within quotes
+        let fields = [
+            ("msg", r#"say "hi" \o/"#),
+            ("path", r"C:\dir"),
+            ("quote", "\""),
+            ("end", "\\"),
+        ]
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .into();
+        let formatted = format_fields(&fields);
+        assert_eq!(
+            formatted,
+            r#"end=\ msg="say \"hi\" \\o/" path=C:\dir quote="\"""#
+        );
+        assert_eq!(extract_fields(&formatted), fields);
+    }
+
+    #[test]
+    fn parse_csv_fields() {
+        use crate::record::Format;
+
+        let mut record = CsvParser::default()
+            .try_parse_str("07-01 14:13:14.446,a=1,Info,Tag,225,295,raw,,")
+            .unwrap();
+        assert!(record.fields.is_empty());
+        record.fields = extract_fields(r#"a=1 msg="hello world""#);
+        let csv = Format::Csv.fmt_record(&record).unwrap();
+        assert!(csv.ends_with(r#","a=1 msg=""hello world""""#));
+        assert_eq!(CsvParser::default().try_parse_str(&csv).unwrap(), record);
+    }
+
+    #[test]
+    fn parse_csv_header() {
+        use crate::record::{Column, Format, Record, COLUMNS};
+
+        let record = Record {
+            time: Some("07-01 14:13:14.446".to_owned()),
+            level: Level::Info,
+            tag: "Tag".to_owned(),
+            message: "a, b".to_owned(),
+            ..Default::default()
+        };
+        let columns = [Column::Message, Column::Tag, Column::Time];
+        let header = Format::Csv.header(Some(&columns)).unwrap();
+        assert_eq!(header, "message,tag,time");
+        let csv = Format::Csv.fmt_columns(&record, Some(&columns)).unwrap();
+
+        let mut parser = Parser::default();
+        assert!(parser.parse(&header).is_none());
+        let parsed = parser.parse(&csv).unwrap();
+        assert_eq!(parsed.time, record.time);
+        assert_eq!(parsed.tag, record.tag);
+        assert_eq!(parsed.message, record.message);
+        assert_eq!(parsed.level, Level::None);
+
+        // Later rows made of column names don't change the order
+        assert!(parser.parse("tag,time").is_some());
+        assert_eq!(parser.parse(&csv).unwrap().message, record.message);
+
+        // A full header of the next input restores the default order
+        let mut parser = Parser::default();
+        let header = Format::Csv.header(None).unwrap();
+        assert!(parser.parse(&header).is_none());
+        let csv = Format::Csv.fmt_record(&record).unwrap();
+        assert_eq!(parser.parse(&csv).unwrap(), record);
+
+        // So does the header after a recorded session
+        let session = crate::session::Session::default().to_line();
+        assert!(parser.parse(&session).is_none());
+        assert!(parser.parse(&header).is_none());
+        assert_eq!(parser.parse(&csv).unwrap(), record);
+
+        // Resolved thread names and log buffers are optional columns
+        let record = Record {
+            thread_name: Some("RenderThread".to_owned()),
+            buffer: Some("crash".to_owned()),
+            ..record
+        };
+        let mut columns = COLUMNS.to_vec();
+        columns.extend([Column::ThreadName, Column::Buffer]);
+        let header = Format::Csv.header(Some(&columns)).unwrap();
+        assert!(header.ends_with(",fields,thread_name,buffer"));
+        let mut parser = Parser::default();
+        assert!(parser.parse(&header).is_none());
+        let csv = Format::Csv.fmt_columns(&record, Some(&columns)).unwrap();
+        assert_eq!(parser.parse(&csv).unwrap(), record);
+
+        // Messages aren't headers
+        assert!(parser.parse("message").is_some());
+    }
+
+    #[test]
+    fn parse_csv_multiline() {
+        use crate::record::{Format, Record};
+
+        let record = Record {
+            time: Some("07-01 14:13:14.446".to_owned()),
+            level: Level::Error,
+            tag: "AndroidRuntime".to_owned(),
+            message: "FATAL EXCEPTION: main\n\tat Foo.bar(\"a, b\")\n".to_owned(),
+            raw: "\"quoted\"\nraw".to_owned(),
+            ..Default::default()
+        };
+        let csv = Format::Csv.fmt_record(&record).unwrap();
+        assert_eq!(csv.lines().count(), 4);
+        let next = "07-01 14:13:15.000,next,Info,Tag,,,raw,,";
+
+        // Rows are joined after a header or with the csv format
+        let mut parser = Parser::default();
+        assert!(parser.parse(&Format::Csv.header(None).unwrap()).is_none());
+        let mut csv_parser = Parser::with_format(InputFormat::Csv);
+        for parser in [&mut parser, &mut csv_parser] {
+            let lines = csv.lines().collect::<Vec<_>>();
+            for line in &lines[..lines.len() - 1] {
+                assert_eq!(parser.parse(line), None);
+            }
+            assert_eq!(parser.parse(lines[lines.len() - 1]), Some(record.clone()));
+            assert_eq!(parser.parse(next).unwrap().message, "next");
+        }
+
+        // A row that is not closed ends with the input and is kept as raw
+        // message
+        assert_eq!(csv_parser.parse("07-01 14:13:14.446,\"open"), None);
+        assert_eq!(csv_parser.parse("message"), None);
+        assert_eq!(
+            csv_parser.finish().unwrap().message,
+            "07-01 14:13:14.446,\"open\nmessage"
+        );
+
+        // Quotes of other formats don't join lines
+        let mut parser = Parser::default();
+        let line = "01-10 23:02:59.028  1  2 I Tag: \"unbalanced";
+        assert_eq!(parser.parse(line).unwrap().message, "\"unbalanced");
+        assert!(parser.parse(line).is_some());
+    }
+
+    #[test]
+    fn parse_csv_stray_quote() {
+        use crate::record::Format;
+
+        let header = Format::Csv.header(None).unwrap();
+        let row = |i: usize| format!("07-01 14:13:14.446,line {i},Info,Tag,,,raw,,");
+        let stray = "07-01 14:13:14.446,\"stray,Info,Tag,,,raw,,";
+
+        let parse = |parser: &mut Parser, line: &str| {
+            let mut records = parser.parse(line).into_iter().collect::<Vec<_>>();
+            records.extend(std::iter::from_fn(|| parser.ready()));
+            records
+        };
+
+        // The next record ends the row with the stray quote
+        let mut parser = Parser::default();
+        assert!(parse(&mut parser, &header).is_empty());
+        assert!(parse(&mut parser, stray).is_empty());
+        assert!(parse(&mut parser, "continued").is_empty());
+        let records = parse(&mut parser, &row(1));
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].raw, stray);
+        assert_eq!(records[1].message, "continued");
+        assert_eq!(records[2].message, "line 1");
+        assert_eq!(parse(&mut parser, &row(2))[0].message, "line 2");
+
+        // Lines that are no records are passed one by one after a few lines
+        let mut parser = Parser::default();
+        assert!(parse(&mut parser, &header).is_empty());
+        assert!(parse(&mut parser, stray).is_empty());
+        for i in 1..MAX_CSV_LINES - 1 {
+            assert!(parse(&mut parser, &format!("text {i}")).is_empty());
+        }
+        let records = parse(&mut parser, "text 99");
+        assert_eq!(records.len(), MAX_CSV_LINES);
+        assert_eq!(records[0].raw, stray);
+        assert_eq!(records[99].message, "text 99");
+        assert_eq!(parse(&mut parser, &row(1))[0].message, "line 1");
+
+        // Records that are not taken follow with the next lines
+        let mut parser = Parser::default();
+        assert!(parser.parse(&header).is_none());
+        assert!(parser.parse(stray).is_none());
+        assert_eq!(parser.parse(&row(1)).unwrap().raw, stray);
+        assert_eq!(parser.parse(&row(2)).unwrap().message, "line 1");
+        assert_eq!(parser.finish().unwrap().message, "line 2");
+    }
+
     #[test]
     fn parse_csv_unparseable() {
-        let parser = CsvParser {};
+        let parser = CsvParser::default();
         assert!(parser.try_parse_str("").is_err());
         assert!(parser.try_parse_str(",,,").is_err());
     }
 
+    #[test]
+    fn parse_forced_csv() {
+        let lines = ["message,tag", "03-01 02:19:45.207 1 2 I Ext4: mounted,fs"];
+        // Detection takes the row for a logcat line
+        let mut parser = Parser::default();
+        assert!(parser.parse(lines[0]).is_none());
+        let record = parser.parse(lines[1]).unwrap();
+        assert_eq!(record.tag, "Ext4");
+        assert_eq!(record.message, "mounted,fs");
+
+        let mut parser = Parser::with_format(InputFormat::Csv);
+        assert!(parser.parse(lines[0]).is_none());
+        let record = parser.parse(lines[1]).unwrap();
+        assert_eq!(record.tag, "fs");
+        assert_eq!(record.message, "03-01 02:19:45.207 1 2 I Ext4: mounted");
+        assert_eq!(record.level, Level::None);
+    }
+
+    #[test]
+    fn parse_strict() {
+        let json = r#"{"message":"hello","level":"Info","tag":"Tag","process":"1","thread":"2","raw":"hello"}"#;
+        let logcat = "03-01 02:19:45.207 1 2 I Tag: hello";
+
+        // Lines in other formats are passed as message
+        let mut parser = Parser::with_format(InputFormat::Json);
+        assert_eq!(parser.parse(json).unwrap().tag, "Tag");
+        let record = parser.parse(logcat).unwrap();
+        assert!(record.tag.is_empty());
+        assert_eq!(record.message, logcat);
+
+        let failures = ParseFailures::default();
+        let mut parser = Parser::with_format(InputFormat::Json);
+        parser.strict(failures.clone());
+        assert!(parser.parse(logcat).is_none());
+        assert_eq!(parser.parse(json).unwrap().tag, "Tag");
+        let mut fork = parser.fork();
+        assert!(fork.parse("garbage").is_none());
+        assert_eq!(failures.count(), 2);
+    }
+
     #[test]
     fn parse_json() {
         let parser = JsonParser {};
@@ -300,6 +1813,273 @@ mod test {
         assert_eq!(record.message, "ViewPostIme pointer 1");
     }
 
+    #[test]
+    fn parse_android_studio() {
+        let export = include_str!("../tests/fixtures/android-studio.json");
+        let lines = serde_json::from_str::<Vec<serde_json::Value>>(export)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        for mut parser in [Parser::default(), Parser::with_format(InputFormat::Json)] {
+            let records = lines
+                .iter()
+                .map(|line| parser.parse(line).unwrap())
+                .collect::<Vec<_>>();
+
+            assert_eq!(records[0].time.as_deref(), Some("2024-01-10 23:22:59.028"));
+            assert_eq!(records[0].level, Level::Info);
+            assert_eq!(records[0].tag, "ActivityManager");
+            assert_eq!(records[0].process, "6496");
+            assert_eq!(records[0].thread, "6512");
+            assert_eq!(records[0].application.as_deref(), Some("com.example.app"));
+            assert_eq!(records[0].message, "Start proc 6496:com.example.app/u0a123");
+            assert_eq!(records[0].raw, lines[0]);
+
+            // Fractions are cut to millis and empty application ids dropped
+            assert_eq!(records[1].time.as_deref(), Some("2024-01-10 23:23:00.005"));
+            assert_eq!(records[1].level, Level::Warn);
+            assert_eq!(records[1].application, None);
+            assert_eq!(
+                records[1].message,
+                "Cancelling event due to no window focus\nsecond line"
+            );
+
+            // Older exports
+            assert_eq!(records[2].time.as_deref(), Some("2024-01-10 23:23:00.999"));
+            assert_eq!(records[2].level, Level::Assert);
+            assert_eq!(records[2].process, "1234");
+            assert_eq!(records[2].thread, "1235");
+        }
+
+        // Records of rogcat's own json output keep their schema
+        let record = Record {
+            message: "header".to_owned(),
+            tag: "Tag".to_owned(),
+            ..Default::default()
+        };
+        let line = serde_json::to_string(&record).unwrap();
+        assert_eq!(Parser::default().parse(&line), Some(record));
+
+        assert!(StudioParser
+            .try_parse_str(r#"{"header":{"priority":"LOUD","processId":1,"threadId":1,"timestamp":{"seconds":0}},"message":""}"#)
+            .is_err());
+    }
+
+    #[test]
+    fn parse_regex() -> Result<(), ParserError> {
+        let parser = RegexParser::new(
+            r"^(?P<time>\w{3} +\d+ [\d:]+) (?P<tag>[\w.-]+)\[(?P<process>\d+)\]: <(?P<level>\w+)> (?P<message>.*)$",
+        )?;
+        let text = "Jan 10 23:02:59 sshd[6496]: <info> Accepted publickey for root";
+        let record = parser.try_parse_str(text)?;
+        assert_eq!(record.time, Some("Jan 10 23:02:59".to_string()));
+        assert_eq!(record.tag, "sshd");
+        assert_eq!(record.process, "6496");
+        assert_eq!(record.level, Level::Info);
+        assert_eq!(record.message, "Accepted publickey for root");
+        assert_eq!(record.raw, text);
+        // Missing groups fall back to defaults
+        assert!(record.thread.is_empty());
+        assert!(record.uid.is_none());
+
+        assert!(parser.try_parse_str("no match").is_err());
+
+        let mut parser = Parser::default();
+        parser.prepend(Box::new(RegexParser::new(
+            r"^(?P<tag>\w+)> (?P<message>.*)$",
+        )?));
+        let record = parser.parse("shell> ls -l").unwrap();
+        assert_eq!(record.tag, "shell");
+        assert_eq!(record.message, "ls -l");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_regex_invalid() {
+        assert!(RegexParser::new(r"^(?P<time>\S+) (?P<message>.*)$").is_ok());
+        assert!(RegexParser::new(r"^(?P<time>\S+) (?P<msg>.*)$").is_err());
+        assert!(RegexParser::new(r"^(?P<time>\S+").is_err());
+    }
+
+    #[test]
+    fn parse_long() {
+        let lines = "\
+[ 01-11 01:10:02.755   882: 1203 I/InputReader ]
+Reconfiguring input devices.
+
+[ 01-11 01:10:02.760  1000:  882:  882 E/AndroidRuntime ]
+FATAL EXCEPTION: main
+Process: com.example, PID: 882
+
+[ 01-11 01:10:02.761   882:  882 D/Fake ]
+    at com.example.Main.run(Main.java:42)
+
+[ 01-11 01:10:02.770   882: 1203 W/Watchdog ]
+Blocked in handler
+[ 01-11 01:10:02.771   882: 1203 W/Quoted ]
+
+01-11 01:10:02.800   882  1203 I Plain: continued";
+        let mut parser = Parser::default();
+        let mut records = lines
+            .lines()
+            .filter_map(|line| parser.parse(line))
+            .collect::<Vec<_>>();
+        records.extend(parser.finish());
+        assert_eq!(records.len(), 4);
+
+        assert_eq!(records[0].time.as_deref(), Some("01-11 01:10:02.755"));
+        assert_eq!(records[0].process, "882");
+        assert_eq!(records[0].thread, "1203");
+        assert_eq!(records[0].level, Level::Info);
+        assert_eq!(records[0].tag, "InputReader");
+        assert_eq!(records[0].message, "Reconfiguring input devices.");
+
+        assert_eq!(records[1].uid.as_deref(), Some("1000"));
+        assert_eq!(records[1].level, Level::Error);
+        // A header after a blank line starts a new record
+        assert_eq!(
+            records[1].message,
+            "FATAL EXCEPTION: main\nProcess: com.example, PID: 882"
+        );
+        assert_eq!(records[2].tag, "Fake");
+
+        // A header directly after a message line is part of the message
+        // and so is a blank line followed by a line that isn't a header
+        assert_eq!(records[3].tag, "Watchdog");
+        assert_eq!(
+            records[3].message,
+            "Blocked in handler\n[ 01-11 01:10:02.771   882: 1203 W/Quoted ]\n\n\
+             01-11 01:10:02.800   882  1203 I Plain: continued"
+        );
+        assert!(records[3].raw.starts_with("[ 01-11 01:10:02.770"));
+        assert!(parser.finish().is_none());
+    }
+
+    #[test]
+    fn parse_folded_continuations() {
+        let oops = "\
+unparsed first line
+01-10 23:02:59.028     0     0 I kernel  : usb 1-1: new high-speed USB device
+01-10 23:02:59.100     0     0 E kernel  : Unable to handle kernel NULL pointer dereference
+ pc : dwc3_gadget_ep_queue+0x24/0x80
+ lr : usb_ep_queue+0x30/0xa0
+Call trace:
+  dwc3_gadget_ep_queue+0x24/0x80
+  usb_ep_queue+0x30/0xa0
+01-10 23:02:59.200     0     0 I kernel  : after";
+        let parse = |lines: &mut dyn Iterator<Item = &str>| {
+            let mut parser = Parser::default();
+            parser.fold_continuations(true);
+            let mut records = lines
+                .filter_map(|line| parser.parse(line))
+                .collect::<Vec<_>>();
+            records.extend(std::iter::from_fn(|| parser.finish()));
+            records
+        };
+
+        let records = parse(&mut oops.lines());
+        assert_eq!(records.len(), 4);
+        // Lines without a previous record aren't folded
+        assert_eq!(records[0].message, "unparsed first line");
+        assert_eq!(records[1].message, "usb 1-1: new high-speed USB device");
+        assert_eq!(
+            records[2].message,
+            "Unable to handle kernel NULL pointer dereference\n \
+             pc : dwc3_gadget_ep_queue+0x24/0x80\n \
+             lr : usb_ep_queue+0x30/0xa0\n\
+             Call trace:\n  \
+             dwc3_gadget_ep_queue+0x24/0x80\n  \
+             usb_ep_queue+0x30/0xa0"
+        );
+        assert_eq!(records[2].level, Level::Error);
+        assert!(records[2].raw.starts_with("01-10 23:02:59.100"));
+        assert!(records[2].raw.ends_with("\n  usb_ep_queue+0x30/0xa0"));
+        assert_eq!(records[3].message, "after");
+
+        // Up to 100 lines are folded. The others are records of their own.
+        let trace = vec!["  frame"; 150];
+        let mut lines = std::iter::once("01-10 23:02:59.100     0     0 E kernel  : oops")
+            .chain(trace.iter().copied());
+        let records = parse(&mut lines);
+        assert_eq!(records.len(), 51);
+        assert_eq!(records[0].message.lines().count(), 101);
+        assert_eq!(records[50].message, "  frame");
+    }
+
+    #[test]
+    fn parse_syslog() {
+        let parser = SyslogParser::default();
+        let record = parser
+            .try_parse_str("Jan  1 10:00:00 host NetworkManager[812]: <info>  [1704103200.1] manager: startup complete")
+            .unwrap();
+        assert_eq!(record.time.as_deref(), Some("Jan  1 10:00:00"));
+        assert_eq!(record.tag, "NetworkManager");
+        assert_eq!(record.process, "812");
+        assert_eq!(record.level, Level::None);
+        assert_eq!(
+            record.message,
+            "<info>  [1704103200.1] manager: startup complete"
+        );
+
+        let record = parser
+            .try_parse_str(
+                "<4>2024-01-01T10:00:00+0100 host kernel: usb 1-1: reset high-speed USB device",
+            )
+            .unwrap();
+        assert_eq!(record.tag, "kernel");
+        assert!(record.process.is_empty());
+        assert_eq!(record.level, Level::Warn);
+        assert_eq!(record.message, "usb 1-1: reset high-speed USB device");
+
+        let level = |prio: &str| {
+            parser
+                .try_parse_str(&format!("<{prio}>Jan 11 01:10:02 host foo: bar"))
+                .unwrap()
+                .level
+        };
+        assert_eq!(level("0"), Level::Assert);
+        assert_eq!(level("10"), Level::Fatal);
+        assert_eq!(level("86"), Level::Info);
+        assert_eq!(level("191"), Level::Debug);
+
+        assert!(parser.try_parse_str("foo bar").is_err());
+    }
+
+    #[test]
+    fn parse_env_logger() {
+        let parser = EnvLoggerParser::default();
+        let record = parser
+            .try_parse_str("[2024-01-11T01:10:02Z DEBUG hyper::proto::h1::io] flushed 78 bytes")
+            .unwrap();
+        assert_eq!(record.time.as_deref(), Some("2024-01-11T01:10:02Z"));
+        assert_eq!(record.level, Level::Debug);
+        assert_eq!(record.tag, "hyper::proto::h1::io");
+        assert_eq!(record.message, "flushed 78 bytes");
+
+        let record = parser.try_parse_str("[ERROR foo] bar: baz").unwrap();
+        assert!(record.time.is_none());
+        assert_eq!(record.level, Level::Error);
+        assert_eq!(record.tag, "foo");
+        assert_eq!(record.message, "bar: baz");
+
+        let record = parser
+            .try_parse_str("2024-01-11T01:10:02.755123Z  INFO request{id=4}: server::api: served /health in 3ms")
+            .unwrap();
+        assert_eq!(record.time.as_deref(), Some("2024-01-11T01:10:02.755123Z"));
+        assert_eq!(record.level, Level::Info);
+        assert_eq!(record.tag, "server::api");
+        assert_eq!(record.message, "served /health in 3ms");
+
+        let record = parser
+            .try_parse_str("2024-01-11T01:10:02.755123Z TRACE mio::poll: registering event source")
+            .unwrap();
+        assert_eq!(record.level, Level::Trace);
+        assert_eq!(record.tag, "mio::poll");
+
+        assert!(parser.try_parse_str("[NOTICE foo] bar").is_err());
+    }
+
     // For input coming from files or STDIN
     #[test]
     fn parser_detector() {
@@ -312,13 +2092,13 @@ mod test {
             BatteryMeterDrawable,1344,1344,\"01-11 00:16:39.451  1344  1344 D \
             BatteryMeterDrawable: Screen is off, stop charging anim\"";
 
-        let record = parser.parse(csv);
+        let record = parser.parse(csv).unwrap();
         assert!(parser.last.is_some_and(|index| index == 1));
         assert_eq!(record.tag, "BatteryMeterDrawable");
 
         let raw =
             "01-11 01:05:30.308  6408  6408 D tun2socks: INFO(tun2socks): UDP: to DNS 33 bytes";
-        let record = parser.parse(raw);
+        let record = parser.parse(raw).unwrap();
         assert!(parser.last.is_some_and(|index| index == 0));
         assert_eq!(record.message, "INFO(tun2socks): UDP: to DNS 33 bytes");
 
@@ -331,14 +2111,136 @@ mod test {
             "thread":"1203",
             "raw":"01-11 01:10:02.755   882  1203 I InputReader: processEventsLocked: type=0 Count=2 code=2 value=0 deviceId=1 when=11049299731000"
         }"#;
-        let record = parser.parse(json);
+        let record = parser.parse(json).unwrap();
         assert!(parser.last.is_some_and(|index| index == 2));
         assert_eq!(record.process, "882");
 
-        let unparseable = parser.parse("i hate the antichrist");
-        assert!(parser.last.is_some_and(|index| index == 2));
+        let syslog = "<27>Jan 11 01:10:02 builder sshd[4321]: error: maximum authentication attempts exceeded";
+        let record = parser.parse(syslog).unwrap();
+        assert!(parser.last.is_some_and(|index| index == 4));
+        assert_eq!(record.tag, "sshd");
+        assert_eq!(record.level, Level::Error);
+
+        let env_logger = "[2024-01-11T01:10:02Z WARN  rogcat::profiles] Profile foo not found";
+        let record = parser.parse(env_logger).unwrap();
+        assert!(parser.last.is_some_and(|index| index == 5));
+        assert_eq!(record.tag, "rogcat::profiles");
+        assert_eq!(record.level, Level::Warn);
+
+        let unparseable = parser.parse("i hate the antichrist").unwrap();
+        assert!(parser.last.is_some_and(|index| index == 5));
         assert!(unparseable.tag.is_empty());
         assert!(unparseable.process.is_empty());
         assert!(unparseable.time.is_none());
     }
+
+    #[test]
+    fn parse_notices() {
+        let parser = NoticeParser::default();
+        let notice = |line| parser.try_parse_str(line).ok().and_then(|r| r.notice);
+        for buffer in ["main", "system", "crash", "kernel", "radio", "events"] {
+            let record = parser
+                .try_parse_str(&format!("--------- beginning of {buffer}"))
+                .unwrap();
+            assert_eq!(record.notice, Some(Notice::BufferBegin));
+            assert_eq!(record.buffer.as_deref(), Some(buffer));
+        }
+        let record = parser.try_parse_str("--------- switch to system").unwrap();
+        assert_eq!(record.buffer.as_deref(), Some("system"));
+        assert_eq!(notice("--------- unknown divider"), Some(Notice::Other));
+
+        assert_eq!(notice("logcat: Unexpected EOF!"), Some(Notice::Other));
+        assert_eq!(notice("read: unexpected EOF!"), Some(Notice::Other));
+        assert_eq!(
+            notice("This means that either the device shut down, logd crashed, or this instance of logcat was unable to read log messages as quickly as they were being produced."),
+            Some(Notice::Other)
+        );
+        // Other lines starting like read failures are not
+        assert_eq!(notice("logcat: device offline"), None);
+        assert_eq!(notice("failed to read config.xml"), None);
+        assert_eq!(notice("This means that either way works"), None);
+
+        let chatty = "01-10 23:56:50.101  1036  1036 I chatty  : uid=10007(u0_a7) com.example.app expire 13 lines";
+        let record = parser.try_parse_str(chatty).unwrap();
+        assert_eq!(record.notice, Some(Notice::Expired));
+        assert_eq!(record.tag, "chatty");
+        assert_eq!(record.level, Level::Info);
+        assert_eq!(parser.expired(&record), Some(("10007(u0_a7)", 13)));
+        let chatty = "01-10 23:56:51.101  1036  1036 I chatty  : uid=1000(system) Binder:1234_5 expire 1 line";
+        let record = parser.try_parse_str(chatty).unwrap();
+        assert_eq!(parser.expired(&record), Some(("1000(system)", 1)));
+        let chatty =
+            "01-10 23:56:52.101  1036  1036 I chatty  : uid=1000 system_server expire 3 lines";
+        let record = parser.try_parse_str(chatty).unwrap();
+        assert_eq!(parser.expired(&record), Some(("1000", 3)));
+
+        // Identical lines are folded by chatty but not dropped
+        let identical = "01-10 23:56:50.101  2345  2345 I chatty  : uid=10153(com.google.android.gms) identical 4 lines";
+        assert!(parser.try_parse_str(identical).is_err());
+        let record = "01-10 23:56:50.101  1234  1235 I Tag: chatty expire 3 lines";
+        assert!(parser.try_parse_str(record).is_err());
+        assert!(parser.try_parse_str("---------").is_err());
+    }
+
+    #[test]
+    fn count_expired_lines() {
+        let mut parser = Parser::default();
+        let expired = parser.expired_lines();
+        let line = |uid, lines| {
+            format!("01-10 23:56:50.101  1036  1036 I chatty  : uid={uid} Binder:1234_5 expire {lines} lines")
+        };
+        // Notices are no candidates for the parser tried first
+        let record = parser
+            .parse("01-10 23:56:50.101  1234  1235 I Tag: message")
+            .unwrap();
+        assert!(record.notice.is_none());
+        let record = parser.parse(&line("1000(system)", 13)).unwrap();
+        assert_eq!(record.notice, Some(Notice::Expired));
+        let record = parser.parse("--------- beginning of main").unwrap();
+        assert_eq!(record.notice, Some(Notice::BufferBegin));
+        let mut fork = parser.fork();
+        fork.parse(&line("10007(u0_a7)", 20));
+        fork.parse(&line("1000(system)", 2));
+        assert_eq!(
+            expired.counts(),
+            [
+                ("10007(u0_a7)".to_owned(), 20),
+                ("1000(system)".to_owned(), 15)
+            ]
+        );
+        assert_eq!(
+            expired.to_string(),
+            "chatty expired 35 lines: 20 of uid 10007(u0_a7), 15 of uid 1000(system)"
+        );
+
+        // Csv and json input has no notices
+        let mut parser = Parser::with_format(InputFormat::Json);
+        assert!(parser
+            .parse("--------- beginning of main")
+            .unwrap()
+            .notice
+            .is_none());
+    }
+
+    #[test]
+    fn parse_mixed_levels() {
+        let mut parser = Parser::with_format(InputFormat::Csv);
+        let mut levels = include_str!("../tests/fixtures/levels-mixed.csv")
+            .lines()
+            .filter_map(|line| parser.parse(line))
+            .map(|record| record.level)
+            .collect::<Vec<_>>();
+        levels.extend(std::iter::from_fn(|| parser.finish()).map(|record| record.level));
+        assert_eq!(
+            levels,
+            [
+                Level::Info,
+                Level::Warn,
+                Level::Error,
+                Level::Verbose,
+                Level::None,
+                Level::Fatal
+            ]
+        );
+    }
 }
@@ -0,0 +1,1204 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Builder for reading, parsing and filtering records
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), rogcat::Error> {
+//! use futures::StreamExt;
+//! use rogcat::{
+//!     filter::FilterSpec,
+//!     pipeline::{Pipeline, Source},
+//!     reader::LogcatOptions,
+//!     record::{Format, Level},
+//!     sink::FormatSink,
+//! };
+//!
+//! Pipeline::new()
+//!     .source(Source::Logcat(LogcatOptions::default()))
+//!     .filter(FilterSpec {
+//!         level: Level::Warn,
+//!         ..Default::default()
+//!     })
+//!     .run(FormatSink::new(Format::Json, std::io::stdout()))
+//!     .await
+//! # }
+//! ```
+
+use crate::{
+    events::EventTags,
+    filter::{died_pid, Filter, FilterDecision, FilterReload, FilterSpec, Processes, Rejection},
+    merge::merge_by_time,
+    parser::{
+        extract_fields, Boundaries, ExpiredLines, FormatParser, InputFormat, ParseFailures, Parser,
+    },
+    progress::Progress,
+    reader::{self, Children, LogcatOptions},
+    record::{Level, Record},
+    rewrite::Rewrite,
+    threads::{AdbResolver, ThreadId, ThreadNames},
+    Error, LogStream, StreamData,
+};
+use futures::{future::ready, stream, task::Poll, Sink, Stream, StreamExt};
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    iter,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use url::Url;
+
+/// Origin of the log lines
+pub enum Source {
+    /// Run adb logcat
+    Logcat(LogcatOptions),
+    /// Read files in the given order. Compressed files are decompressed.
+    Files(Vec<PathBuf>),
+    Stdin,
+    /// Run a command and read its stdout and stderr. Urls with the schemes
+    /// can, tcp, tcp-listen and udp are opened instead. Serial ports are a
+    /// [`Source::Serial`].
+    Command {
+        command: String,
+        restart: bool,
+    },
+    /// Run a command every `interval` and pass only the lines that weren't
+    /// printed by the former runs. The last `window` lines are remembered.
+    Poll {
+        command: String,
+        interval: Duration,
+        window: usize,
+    },
+    /// Read a serial port like `COM0@115200,8N1`, the part after `serial://`
+    /// of an url found by [`reader::serial_port`]. The port is reopened after
+    /// a disconnect if `restart` is set.
+    Serial {
+        port: String,
+        restart: bool,
+    },
+    /// Custom stream of lines or records
+    Stream(LogStream),
+}
+
+impl Source {
+    /// Open the source. Spawned processes are registered in `children`.
+    pub async fn open(self, children: &Children) -> Result<LogStream, Error> {
+        match self {
+            Source::Logcat(options) => reader::logcat(&options, children),
+            Source::Files(files) => reader::files(files).await,
+            Source::Stdin => Ok(reader::stdin()),
+            Source::Command { command, restart } => match Url::parse(&command) {
+                Ok(url) => match url.scheme() {
+                    #[cfg(target_os = "linux")]
+                    "can" => reader::can(url.host_str().expect("Invalid can device")),
+                    "tcp" => reader::tcp(&url).await,
+                    "tcp-listen" => reader::tcp_listen(&url).await,
+                    "udp" => reader::udp(&url).await,
+                    _ => reader::process(command, restart, children),
+                },
+                Err(_) => reader::process(command, restart, children),
+            },
+            Source::Poll {
+                command,
+                interval,
+                window,
+            } => Ok(reader::poll(command, interval, window, children)),
+            Source::Serial { port, restart } => reader::serial(&port, restart),
+            Source::Stream(stream) => Ok(stream),
+        }
+    }
+}
+
+/// Stream of the records passing the filter
+pub type RecordStream = Pin<Box<dyn Stream<Item = Record> + Send>>;
+
+/// Builder of a stream of filtered records
+pub struct Pipeline {
+    source: Source,
+    parser: Parser,
+    filter: FilterSpec,
+    reload: FilterReload,
+    events: Option<EventTags>,
+    extract_fields: bool,
+    merge_by_time: bool,
+    last_records: Option<usize>,
+    head: Option<usize>,
+    explain: Option<Explain>,
+    strict: bool,
+    failures: ParseFailures,
+    rewrite: Rewrite,
+    resolve_threads: bool,
+    processes: Processes,
+    parallel: usize,
+    progress: Progress,
+    children: Children,
+}
+
+/// Maximum number of dropped records explained per second without pattern
+const EXPLAIN_RATE: usize = 10;
+
+/// Explains why records are dropped with a diagnostic
+struct Explain {
+    /// Explain only the records matching this pattern
+    pattern: Option<Regex>,
+    window: Instant,
+    explained: usize,
+    /// Records not explained because of the rate limit
+    suppressed: usize,
+}
+
+impl Explain {
+    /// Diagnostic explaining why `record` is dropped
+    fn diagnostic(&mut self, record: &Record, rejection: &Rejection) -> Option<Record> {
+        match &self.pattern {
+            Some(pattern) if !pattern.is_match(&record.raw) => return None,
+            Some(_) => (),
+            None => {
+                let now = Instant::now();
+                if now.duration_since(self.window) >= Duration::from_secs(1) {
+                    self.window = now;
+                    self.explained = 0;
+                }
+                if self.explained == EXPLAIN_RATE {
+                    self.suppressed += 1;
+                    return None;
+                }
+                self.explained += 1;
+            }
+        }
+        let mut message = format!("{rejection}: {}", record.raw);
+        match std::mem::take(&mut self.suppressed) {
+            0 => (),
+            n => message.push_str(&format!(" ({n} more dropped records not explained)")),
+        }
+        Some(Record::from_diagnostic(Level::Info, message))
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            source: Source::Logcat(LogcatOptions::default()),
+            parser: Parser::default(),
+            filter: FilterSpec::default(),
+            reload: FilterReload::default(),
+            events: None,
+            extract_fields: false,
+            merge_by_time: false,
+            last_records: None,
+            head: None,
+            explain: None,
+            strict: false,
+            failures: ParseFailures::default(),
+            rewrite: Rewrite::default(),
+            resolve_threads: false,
+            processes: Processes::default(),
+            parallel: 1,
+            progress: Progress::default(),
+            children: Children::default(),
+        }
+    }
+}
+
+impl Pipeline {
+    /// Pipeline reading adb logcat without filters
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    pub fn source(mut self, source: Source) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Add a parser that is tried before the builtin ones
+    pub fn parser(mut self, parser: Box<dyn FormatParser>) -> Self {
+        self.parser.prepend(parser);
+        self
+    }
+
+    /// Parse the lines in `format` only instead of detecting the format
+    pub fn input_format(mut self, format: InputFormat) -> Self {
+        self.parser.set_format(format);
+        self
+    }
+
+    /// Parse the lines of files, stdin and streams on `workers` tasks. The
+    /// records keep the order of the lines. Records spanning multiple lines
+    /// are completed at the end of each batch of lines a worker parses.
+    pub fn parallel(mut self, workers: usize) -> Self {
+        self.parallel = workers;
+        self
+    }
+
+    /// Drop lines that can't be parsed instead of passing them as message.
+    /// They are counted in [`Pipeline::parse_failures`].
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Append lines that can't be parsed, e.g. continuation lines of kernel
+    /// oopses, to the message of the previous record
+    pub fn fold_continuations(mut self, fold: bool) -> Self {
+        self.parser.fold_continuations(fold);
+        self
+    }
+
+    /// Handle to the number of lines dropped by a strict pipeline
+    pub fn parse_failures(&self) -> ParseFailures {
+        self.failures.clone()
+    }
+
+    /// Handle to the number of lines chatty dropped per uid as noticed by
+    /// the parser before filtering
+    pub fn expired_lines(&self) -> ExpiredLines {
+        self.parser.expired_lines()
+    }
+
+    /// Handle to the bytes read of the input files and the number of
+    /// records parsed
+    pub fn progress(&self) -> Progress {
+        self.progress.clone()
+    }
+
+    /// Handle to the names and start times of the processes started while
+    /// the pipeline runs. They are learned from the events buffer.
+    pub fn processes(&self) -> Processes {
+        self.processes.clone()
+    }
+
+    /// Rewrite the message, raw line and field values of the records before
+    /// they are filtered. The counts of the rules are shared with clones of
+    /// `rewrite`.
+    pub fn rewrite(mut self, rewrite: Rewrite) -> Self {
+        self.rewrite = rewrite;
+        self
+    }
+
+    pub fn filter(mut self, filter: FilterSpec) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Handle to replace the filters while the pipeline runs. A record
+    /// noting the reload is inserted into the stream.
+    pub fn reload_handle(&self) -> FilterReload {
+        self.reload.clone()
+    }
+
+    /// Name the values of known events of the events buffer and store them
+    /// in the record fields
+    pub fn decode_events(mut self, tags: EventTags) -> Self {
+        self.events = Some(tags);
+        self
+    }
+
+    /// Extract key=value pairs from the messages into the record fields
+    pub fn extract_fields(mut self, extract_fields: bool) -> Self {
+        self.extract_fields = extract_fields;
+        self
+    }
+
+    /// Merge the records of multiple files in the order of their timestamps
+    /// instead of reading the files one after another
+    pub fn merge_by_time(mut self, merge_by_time: bool) -> Self {
+        self.merge_by_time = merge_by_time;
+        self
+    }
+
+    /// Read only the last `count` lines of each input file. The files are
+    /// searched from the end and must not be compressed.
+    pub fn last_records(mut self, count: Option<usize>) -> Self {
+        self.last_records = count;
+        self
+    }
+
+    /// Resolve the thread names of records read from adb in the background.
+    /// Records of threads that aren't resolved yet carry no name.
+    pub fn resolve_threads(mut self, resolve_threads: bool) -> Self {
+        self.resolve_threads = resolve_threads;
+        self
+    }
+
+    /// Register the processes spawned by the source in `children`. The
+    /// caller terminates them with [`Children::terminate`], e.g. before
+    /// exiting.
+    pub fn children(mut self, children: Children) -> Self {
+        self.children = children;
+        self
+    }
+
+    /// Stop after `head` records
+    pub fn head(mut self, head: usize) -> Self {
+        self.head = Some(head);
+        self
+    }
+
+    /// Insert a diagnostic naming the filter for dropped records. Without
+    /// `pattern` up to 10 records per second are explained, otherwise all
+    /// records matching `pattern`.
+    pub fn explain(mut self, pattern: Option<Regex>) -> Self {
+        self.explain = Some(Explain {
+            pattern,
+            window: Instant::now(),
+            explained: 0,
+            suppressed: 0,
+        });
+        self
+    }
+
+    /// Open the source and stream the records passing the filter
+    pub async fn stream(self) -> Result<RecordStream, Error> {
+        let mut parser = self.parser;
+        let mut filter = Filter::new(self.filter, &self.source, &mut parser)
+            .await?
+            .with_reload(self.reload);
+        // Lines read to find the newest timestamp are parsed again
+        if self.strict {
+            parser.strict(self.failures);
+        }
+        let extract = self.extract_fields;
+        let events = self.events;
+        let rewrite = self.rewrite;
+        let mut explain = self.explain;
+        let processes = self.processes;
+        let progress = self.progress.clone();
+        let threads = match &self.source {
+            Source::Logcat(options) if self.resolve_threads => {
+                let device = match options.devices.as_slice() {
+                    [device] => Some(device.clone()),
+                    _ => None,
+                };
+                Some(ThreadNames::new(AdbResolver::new(device)))
+            }
+            _ => None,
+        };
+        let records = match self.source {
+            // Each file is parsed on its own from its first line, e.g. its
+            // csv header. The records are merged by time or chained.
+            Source::Files(files) => {
+                let merge = self.merge_by_time && files.len() > 1;
+                let mut streams = Vec::with_capacity(files.len());
+                for file in files {
+                    let lines = Box::into_pin(match self.last_records {
+                        Some(count) => reader::last_lines(file, count).await?,
+                        None => reader::file_with_progress(file, &self.progress).await?,
+                    });
+                    streams.push(if self.parallel > 1 && !merge {
+                        parse_parallel(lines, parser.fork(), self.parallel)
+                    } else {
+                        parse(lines, parser.fork())
+                    });
+                }
+                if merge {
+                    merge_by_time(streams)
+                } else {
+                    Box::pin(stream::iter(streams).flatten())
+                }
+            }
+            source @ (Source::Stdin | Source::Stream(_)) if self.parallel > 1 => {
+                let lines = open(source, &self.progress, &self.children).await?;
+                parse_parallel(Box::into_pin(lines), parser, self.parallel)
+            }
+            source => parse(
+                Box::into_pin(open(source, &self.progress, &self.children).await?),
+                parser,
+            ),
+        };
+
+        // Reload notices are returned before the record that noticed the
+        // reload
+        let mut records = records;
+        let mut pending = VecDeque::new();
+        let records: RecordStream = Box::pin(stream::poll_fn(move |cx| {
+            if let Some(record) = pending.pop_front() {
+                return Poll::Ready(Some(record));
+            }
+            loop {
+                let Some(mut record) = futures::ready!(records.poll_next_unpin(cx)) else {
+                    return Poll::Ready(None);
+                };
+                if !record.diagnostic {
+                    progress.record();
+                    if let Some(events) = events.as_ref() {
+                        events.decode_record(&mut record);
+                    }
+                    rewrite.apply(&mut record);
+                    if extract && record.fields.is_empty() {
+                        record.fields = extract_fields(&record.message);
+                    }
+                    processes.observe(&record);
+                    if let Some(threads) = threads.as_ref() {
+                        if let Some(pid) = died_pid(&record) {
+                            threads.evict(record.device.as_deref(), pid);
+                        }
+                    }
+                }
+                let notices = filter.reload();
+                // Diagnostics bypass the filters and notices all but the tag
+                // and message filters
+                let output = if record.diagnostic {
+                    Some(record)
+                } else if let Some(explain) = explain.as_mut() {
+                    let decision = match record.notice {
+                        Some(_) => filter.decide_notice(&record),
+                        None => filter.decide(&record),
+                    };
+                    match decision {
+                        FilterDecision::Pass => Some(record),
+                        FilterDecision::Drop(rejection) => explain.diagnostic(&record, &rejection),
+                    }
+                } else if record.notice.is_some() {
+                    (filter.decide_notice(&record) == FilterDecision::Pass).then_some(record)
+                } else {
+                    filter.filter(&record).then_some(record)
+                };
+                let output = match (output, threads.as_ref()) {
+                    (Some(mut record), Some(threads)) if !record.diagnostic => {
+                        let device = record.device.as_deref();
+                        if let Some(thread) = ThreadId::new(device, &record.process, &record.thread)
+                        {
+                            record.thread_name = threads.name(&thread);
+                        }
+                        Some(record)
+                    }
+                    (output, _) => output,
+                };
+                if !notices.is_empty() {
+                    pending.extend(notices);
+                    pending.extend(output);
+                    return Poll::Ready(pending.pop_front());
+                }
+                if let Some(output) = output {
+                    return Poll::Ready(Some(output));
+                }
+            }
+        }));
+        Ok(match self.head {
+            Some(n) => head(records, n),
+            None => records,
+        })
+    }
+
+    /// Pass the records to `sink` until the source ends. The sink is closed
+    /// at the end.
+    pub async fn run<S: Sink<Record, Error = Error>>(self, sink: S) -> Result<(), Error> {
+        self.stream().await?.map(Ok).forward(sink).await
+    }
+}
+
+/// Open `source`. The bytes read of files are tracked by `progress` and
+/// spawned processes are registered in `children`.
+async fn open(
+    source: Source,
+    progress: &Progress,
+    children: &Children,
+) -> Result<LogStream, Error> {
+    match source {
+        Source::Files(files) => reader::files_with_progress(files, progress).await,
+        source => source.open(children).await,
+    }
+}
+
+/// Stop after `n` records. Diagnostics and notices aren't counted.
+fn head(records: RecordStream, n: usize) -> RecordStream {
+    if n == 0 {
+        return Box::pin(stream::empty());
+    }
+    let mut remaining = n;
+    Box::pin(
+        records
+            .flat_map(move |record| {
+                if !record.diagnostic && record.notice.is_none() {
+                    remaining -= 1;
+                }
+                // End right after the last record instead of waiting for the
+                // next one
+                let end = (remaining == 0).then_some(None);
+                stream::iter(iter::once(Some(record)).chain(end))
+            })
+            .take_while(|record| ready(record.is_some()))
+            .filter_map(ready),
+    )
+}
+
+/// Parser of the lines of each source: device, buffer and the standard
+/// error of a command. Records spanning multiple lines are assembled from
+/// the lines of their source only.
+struct Parsers {
+    parser: Parser,
+    tagged: BTreeMap<ParserKey, Parser>,
+}
+
+impl Parsers {
+    fn new(parser: Parser) -> Parsers {
+        Parsers {
+            parser,
+            tagged: BTreeMap::new(),
+        }
+    }
+
+    /// Records that are complete after `data`
+    fn push(&mut self, data: StreamData) -> Vec<Record> {
+        if let Some((key, line)) = tagged_line(&data) {
+            return self.parse(key, line);
+        }
+        match data {
+            StreamData::Record(record) => vec![*record],
+            StreamData::Diagnostic(level, message) => vec![Record::from_diagnostic(level, message)],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Read the csv header `data` like at the start of the input
+    fn header(&mut self, data: StreamData) {
+        if let Some((key, _)) = tagged_line(&data) {
+            self.parser(key).start_input();
+        }
+        self.push(data);
+    }
+
+    /// Parser of the lines of `key`
+    fn parser(&mut self, key: ParserKey) -> &mut Parser {
+        if key == ParserKey::default() {
+            return &mut self.parser;
+        }
+        let parser = &self.parser;
+        self.tagged.entry(key).or_insert_with(|| parser.fork())
+    }
+
+    fn parse(&mut self, key: ParserKey, line: &str) -> Vec<Record> {
+        let ParserKey { device, buffer, .. } = key.clone();
+        let parser = self.parser(key);
+        let mut records = parser.parse(line).into_iter().collect::<Vec<_>>();
+        records.extend(iter::from_fn(|| parser.ready()));
+        if device.is_some() || buffer.is_some() {
+            for record in &mut records {
+                record.device.clone_from(&device);
+                record.buffer.clone_from(&buffer);
+            }
+        }
+        records
+    }
+
+    /// Records that are complete at the end of the input
+    fn finish(&mut self) -> Vec<Record> {
+        let mut records = iter::from_fn(|| self.parser.finish()).collect::<Vec<_>>();
+        for (key, parser) in &mut self.tagged {
+            records.extend(iter::from_fn(|| parser.finish()).map(|record| Record {
+                device: key.device.clone(),
+                buffer: key.buffer.clone(),
+                ..record
+            }));
+        }
+        records
+    }
+}
+
+/// Parse the lines of `source`
+fn parse(source: Pin<LogStream>, parser: Parser) -> RecordStream {
+    let mut parsers = Parsers::new(parser);
+    Box::pin(
+        source
+            .map(Some)
+            // Records spanning multiple lines are complete at the end
+            .chain(stream::iter([None]))
+            .flat_map(move |data| {
+                stream::iter(match data {
+                    Some(data) => parsers.push(data),
+                    None => parsers.finish(),
+                })
+            }),
+    )
+}
+
+/// Number of lines after which a batch of a parallel pipeline ends at the
+/// next record
+const PARALLEL_BATCH: usize = 1024;
+
+/// Source of the lines parsed by one parser
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct ParserKey {
+    device: Option<String>,
+    buffer: Option<String>,
+    /// Set for the standard error of a command
+    stderr: bool,
+}
+
+impl ParserKey {
+    /// `line` read from this source
+    fn data(&self, line: String) -> StreamData {
+        match (self.device.clone(), self.buffer.clone()) {
+            (None, None) if self.stderr => StreamData::ErrorLine(line),
+            (None, None) => StreamData::Line(line),
+            (Some(device), None) => StreamData::DeviceLine(device, line),
+            (device, Some(buffer)) => StreamData::BufferLine {
+                device,
+                buffer,
+                line,
+            },
+        }
+    }
+}
+
+/// Line of `data` and the source it's read from
+fn tagged_line(data: &StreamData) -> Option<(ParserKey, &str)> {
+    let key = |device: Option<&String>, buffer: Option<&String>| ParserKey {
+        device: device.cloned(),
+        buffer: buffer.cloned(),
+        stderr: false,
+    };
+    match data {
+        StreamData::Line(line) => Some((key(None, None), line)),
+        StreamData::ErrorLine(line) => Some((
+            ParserKey {
+                stderr: true,
+                ..ParserKey::default()
+            },
+            line,
+        )),
+        StreamData::DeviceLine(device, line) => Some((key(Some(device), None), line)),
+        StreamData::BufferLine {
+            device,
+            buffer,
+            line,
+        } => Some((key(device.as_ref(), Some(buffer)), line)),
+        StreamData::Record(_) | StreamData::Diagnostic(..) => None,
+    }
+}
+
+/// Lines parsed apart from the lines before them
+#[derive(Default)]
+struct Batch {
+    /// Csv headers read before the lines
+    headers: Vec<StreamData>,
+    lines: Vec<StreamData>,
+}
+
+/// Cuts the lines of a parallel pipeline into batches that are parsed
+/// apart. Batches end where a record starts and carry the csv headers read
+/// before them, so every worker parses the columns of the header.
+struct Batches {
+    parser: Parser,
+    boundaries: BTreeMap<ParserKey, Boundaries>,
+    batch: Batch,
+}
+
+impl Batches {
+    fn new(parser: Parser) -> Batches {
+        Batches {
+            parser,
+            boundaries: BTreeMap::new(),
+            batch: Batch::default(),
+        }
+    }
+
+    /// Add the lines that are ready. Returns the batches that are complete.
+    /// The last batch is passed early if no record can be continued by the
+    /// next lines.
+    fn push(&mut self, ready: Vec<StreamData>) -> Vec<Batch> {
+        let mut batches = Vec::new();
+        for data in ready {
+            if self.batch.lines.len() >= PARALLEL_BATCH && self.starts_record(&data) {
+                batches.push(std::mem::take(&mut self.batch));
+            }
+            if self.batch.lines.is_empty() {
+                self.batch.headers = self.headers();
+            }
+            if let Some((key, line)) = tagged_line(&data) {
+                let parser = &self.parser;
+                self.boundaries
+                    .entry(key)
+                    .or_insert_with(|| parser.boundaries())
+                    .push(line);
+            }
+            self.batch.lines.push(data);
+        }
+        if !self.batch.lines.is_empty() && self.boundaries.values().all(Boundaries::idle) {
+            batches.push(std::mem::take(&mut self.batch));
+        }
+        batches
+    }
+
+    /// The last batch at the end of the input
+    fn finish(&mut self) -> Vec<Batch> {
+        (!self.batch.lines.is_empty())
+            .then(|| std::mem::take(&mut self.batch))
+            .into_iter()
+            .collect()
+    }
+
+    /// True if the batch can end before `data`
+    fn starts_record(&self, data: &StreamData) -> bool {
+        let line = tagged_line(data);
+        self.boundaries.iter().all(|(key, boundaries)| match &line {
+            Some((tagged, line)) if tagged == key => boundaries.starts_record(line),
+            _ => boundaries.idle(),
+        })
+    }
+
+    /// Lines with the csv headers read so far
+    fn headers(&self) -> Vec<StreamData> {
+        self.boundaries
+            .iter()
+            .filter_map(|(key, boundaries)| Some(key.data(boundaries.header()?.to_owned())))
+            .collect()
+    }
+}
+
+/// Parse the lines of `source` on `workers` blocking tasks. The lines that
+/// are ready are cut into batches that are parsed by the next idle worker.
+/// The records of the batches are passed in the order of the batches. At
+/// most `workers` batches are parsed or wait for the batches before them.
+/// Each worker keeps its parser, so the parser that matched last is tried
+/// first.
+fn parse_parallel(source: Pin<LogStream>, parser: Parser, workers: usize) -> RecordStream {
+    let idle = (0..workers)
+        .map(|_| Parsers::new(parser.fork()))
+        .collect::<Vec<_>>();
+    let idle = Arc::new(Mutex::new(idle));
+    let mut batches = Batches::new(parser);
+    Box::pin(
+        source
+            .ready_chunks(PARALLEL_BATCH)
+            .map(Some)
+            .chain(stream::iter([None]))
+            .flat_map(move |ready| {
+                stream::iter(match ready {
+                    Some(ready) => batches.push(ready),
+                    None => batches.finish(),
+                })
+            })
+            .map(move |batch| {
+                let idle = idle.clone();
+                tokio::task::spawn_blocking(move || {
+                    // A worker is idle if less than `workers` batches are
+                    // in flight
+                    let mut parsers = idle
+                        .lock()
+                        .expect("Poisoned parsers")
+                        .pop()
+                        .expect("No idle parser");
+                    for header in batch.headers {
+                        parsers.header(header);
+                    }
+                    let mut records = batch
+                        .lines
+                        .into_iter()
+                        .flat_map(|data| parsers.push(data))
+                        .collect::<Vec<_>>();
+                    records.extend(parsers.finish());
+                    idle.lock().expect("Poisoned parsers").push(parsers);
+                    records
+                })
+            })
+            .buffered(workers)
+            .flat_map(|records| stream::iter(records.expect("Failed to parse lines"))),
+    )
+}
+
+#[tokio::test]
+async fn pipeline_stream() {
+    use futures::stream::iter;
+
+    let lines = [
+        "01-10 23:02:59.028  1  2 D Tag: state=on",
+        "01-10 23:02:59.029  1  2 E Tag: state=off",
+        "01-10 23:02:59.030  1  2 I Other: state=on",
+        "01-10 23:02:59.031  1  2 W Tag: state=on",
+        "01-10 23:02:59.032  1  2 E Tag: state=on",
+    ];
+    let source = Source::Stream(Box::new(iter(
+        lines.map(|l| StreamData::Line(l.to_owned())),
+    )));
+    let records = Pipeline::new()
+        .source(source)
+        .filter(FilterSpec {
+            level: Level::Info,
+            tag: vec!["^Tag$".to_owned()],
+            fields: vec!["state=on".to_owned()],
+            ..Default::default()
+        })
+        .extract_fields(true)
+        .head(1)
+        .stream()
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].raw, lines[3]);
+    assert_eq!(records[0].fields["state"], "on");
+}
+
+#[tokio::test]
+async fn pipeline_reload() {
+    let lines = [
+        "01-10 23:02:59.028  1  2 D Tag: one",
+        "01-10 23:02:59.029  1  2 D Other: two",
+        "01-10 23:02:59.030  1  2 D Other: three",
+    ];
+    let (tx, rx) = futures::channel::mpsc::unbounded();
+    let pipeline = Pipeline::new()
+        .source(Source::Stream(Box::new(rx)))
+        .filter(FilterSpec {
+            tag: vec!["^Tag$".to_owned()],
+            ..Default::default()
+        });
+    let reload = pipeline.reload_handle();
+    let mut records = pipeline.stream().await.unwrap();
+
+    tx.unbounded_send(StreamData::Line(lines[0].to_owned()))
+        .unwrap();
+    assert_eq!(records.next().await.unwrap().message, "one");
+
+    // Invalid filters keep the current ones
+    assert!(reload
+        .reload(&FilterSpec {
+            tag: vec!["(".to_owned()],
+            ..Default::default()
+        })
+        .is_err());
+    reload
+        .reload(&FilterSpec {
+            tag: vec!["^Other$".to_owned()],
+            ..Default::default()
+        })
+        .unwrap();
+    tx.unbounded_send(StreamData::Line(lines[1].to_owned()))
+        .unwrap();
+    let notice = records.next().await.unwrap();
+    assert_eq!(notice.message, "Reloaded filters");
+    assert!(notice.diagnostic);
+    assert_eq!(records.next().await.unwrap().message, "two");
+    tx.unbounded_send(StreamData::Line(lines[2].to_owned()))
+        .unwrap();
+    assert_eq!(records.next().await.unwrap().message, "three");
+}
+
+#[tokio::test]
+async fn pipeline_explain() {
+    use futures::stream::iter;
+
+    let lines = [
+        "01-10 23:02:59.028  1  2 D Tag: one",
+        "01-10 23:02:59.029  1  2 D Other: two",
+        "01-10 23:02:59.030  1  2 D Noise: three",
+    ];
+    let source = Source::Stream(Box::new(iter(
+        lines.map(|l| StreamData::Line(l.to_owned())),
+    )));
+    let records = Pipeline::new()
+        .source(source)
+        .filter(FilterSpec {
+            tag: vec!["^Tag$".to_owned(), "!^Noise$".to_owned()],
+            ..Default::default()
+        })
+        .explain(Some(Regex::new("two|three").unwrap()))
+        .stream()
+        .await
+        .unwrap()
+        .map(|r| (r.diagnostic, r.message))
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(
+        records,
+        [
+            (false, "one".to_owned()),
+            (
+                true,
+                format!("Dropped by tag filter (no pattern matched): {}", lines[1])
+            ),
+            (
+                true,
+                format!("Dropped by tag filter \"!^Noise$\": {}", lines[2])
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn pipeline_csv_roundtrip() {
+    use crate::record::Format;
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("rogcat-csv-roundtrip-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("log.csv");
+    let records = [
+        Record {
+            time: Some("07-01 14:13:14.446".to_owned()),
+            level: Level::Error,
+            tag: "AndroidRuntime".to_owned(),
+            process: "1234".to_owned(),
+            thread: "1234".to_owned(),
+            message: "java.lang.IllegalStateException: \"a, b\"\n\tat Foo.bar(Foo.java:1)"
+                .to_owned(),
+            raw: "E AndroidRuntime: \"a, b\"\n\tat Foo.bar(Foo.java:1)".to_owned(),
+            ..Default::default()
+        },
+        Record {
+            time: Some("07-01 14:13:14.447".to_owned()),
+            level: Level::Info,
+            tag: "Tag".to_owned(),
+            message: "single line".to_owned(),
+            raw: "single line".to_owned(),
+            ..Default::default()
+        },
+    ];
+    let mut csv = Format::Csv.header(None).unwrap() + "\n";
+    for record in &records {
+        csv += &(Format::Csv.fmt_record(record).unwrap() + "\n");
+    }
+    fs::write(&file, csv).unwrap();
+
+    let read = Pipeline::new()
+        .source(Source::Files(vec![file]))
+        .stream()
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    fs::remove_dir_all(&dir).ok();
+    assert_eq!(read, records);
+}
+
+#[tokio::test]
+async fn pipeline_fold_devices() {
+    use futures::stream::iter;
+
+    // Continuation lines of interleaved devices are folded into the record
+    // of their device
+    let line =
+        |device: &str, line: &str| StreamData::DeviceLine(device.to_owned(), line.to_owned());
+    let lines = [
+        line("a", "01-10 23:02:59.028  0  0 E kernel: oops a"),
+        line("b", "01-10 23:02:59.029  0  0 E kernel: oops b"),
+        line("a", " pc : a"),
+        line("b", " pc : b"),
+        line("a", "01-10 23:02:59.030  0  0 I kernel: next a"),
+    ];
+    let records = Pipeline::new()
+        .source(Source::Stream(Box::new(iter(lines))))
+        .fold_continuations(true)
+        .stream()
+        .await
+        .unwrap()
+        .map(|record| (record.device.unwrap(), record.message))
+        .collect::<Vec<_>>()
+        .await;
+    let record = |device: &str, message: &str| (device.to_owned(), message.to_owned());
+    assert_eq!(
+        records,
+        [
+            record("a", "oops a\n pc : a"),
+            record("a", "next a"),
+            record("b", "oops b\n pc : b"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn pipeline_parallel() {
+    use futures::stream::iter;
+
+    // Lines of different formats in batches of multiple workers
+    let lines = (0..5 * PARALLEL_BATCH)
+        .map(|i| match i % 3 {
+            0 => format!("01-10 23:02:59.028  1  2 D Tag: line {i}"),
+            1 => format!(r#"{{"time":null,"message":"line {i}","level":"Info","tag":"Json","process":"","thread":"","raw":""}}"#),
+            _ => format!("unparsed line {i}"),
+        })
+        .collect::<Vec<_>>();
+    let records = |workers| {
+        let source = Source::Stream(Box::new(iter(
+            lines
+                .iter()
+                .map(|l| StreamData::Line(l.clone()))
+                .collect::<Vec<_>>(),
+        )));
+        Pipeline::new()
+            .source(source)
+            .filter(FilterSpec {
+                message: vec!["!line 1$".to_owned()],
+                ..Default::default()
+            })
+            .parallel(workers)
+            .stream()
+    };
+    let sequential = records(1).await.unwrap().collect::<Vec<_>>().await;
+    let parallel = records(4).await.unwrap().collect::<Vec<_>>().await;
+    assert_eq!(sequential.len(), lines.len() - 1);
+    assert_eq!(parallel, sequential);
+    assert_eq!(parallel[1].message, "unparsed line 2");
+    assert_eq!(parallel[3].tag, "Json");
+}
+
+#[tokio::test]
+async fn pipeline_parallel_multiline() {
+    // Csv with other columns than the default and quoted newlines
+    let csv = iter::once("message,tag,time".to_owned())
+        .chain((0..3 * PARALLEL_BATCH).map(|i| match i % 4 {
+            0 => format!("\"line {i}\nat Foo.bar\",Tag,01-10 23:02:59.028"),
+            _ => format!("line {i},Tag,01-10 23:02:59.028"),
+        }))
+        .collect::<Vec<_>>();
+    // Records of "logcat -v long"
+    let long = (0..3 * PARALLEL_BATCH)
+        .map(|i| format!("[ 01-10 23:02:59.028  1: 2 I/Tag ]\nline {i}\nat Foo.bar\n"))
+        .collect::<Vec<_>>();
+
+    for rows in [csv, long] {
+        let lines = rows
+            .iter()
+            .flat_map(|row| row.split('\n'))
+            .map(|line| StreamData::Line(line.to_owned()))
+            .collect::<Vec<_>>();
+        let records = |workers| {
+            let lines = lines.clone();
+            async move {
+                Pipeline::new()
+                    .source(Source::Stream(Box::new(stream::iter(lines))))
+                    .parallel(workers)
+                    .stream()
+                    .await
+                    .unwrap()
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        };
+        let sequential = records(1).await;
+        let parallel = records(4).await;
+        assert_eq!(sequential.len(), 3 * PARALLEL_BATCH);
+        assert_eq!(sequential[0].message, "line 0\nat Foo.bar");
+        assert_eq!(sequential[0].tag, "Tag");
+        assert_eq!(parallel, sequential);
+    }
+}
+
+#[tokio::test]
+async fn pipeline_csv_header_rows() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("rogcat-csv-header-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // Rows made of column names are no headers after the first line
+    let first = dir.join("first.csv");
+    let rows = iter::once("message,tag,time".to_owned())
+        .chain((0..3 * PARALLEL_BATCH).map(|i| match i {
+            1000 => "tag,message".to_owned(),
+            _ => format!("line {i},Tag,01-10 23:02:59.028"),
+        }))
+        .collect::<Vec<_>>();
+    fs::write(&first, rows.join("\n")).unwrap();
+    // The header of the next file applies to its rows
+    let second = dir.join("second.csv");
+    fs::write(&second, "tag,message\nOther,last").unwrap();
+
+    for workers in [1, 4] {
+        let records = Pipeline::new()
+            .source(Source::Files(vec![first.clone(), second.clone()]))
+            .parallel(workers)
+            .stream()
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(records.len(), 3 * PARALLEL_BATCH + 1);
+        assert_eq!(records[1000].raw, "tag,message");
+        assert_eq!(records[1001].message, "line 1001");
+        assert_eq!(records[2000].tag, "Tag");
+        let last = &records[3 * PARALLEL_BATCH];
+        assert_eq!(
+            (last.tag.as_str(), last.message.as_str()),
+            ("Other", "last")
+        );
+    }
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn pipeline_csv_stray_quote() {
+    use crate::record::Format;
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("rogcat-csv-stray-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("log.csv");
+    let rows = iter::once(Format::Csv.header(None).unwrap())
+        .chain((0..5).map(|i| match i {
+            1 => "07-01 14:13:14.446,\"stray,Info,Tag,,,raw,,".to_owned(),
+            _ => format!("07-01 14:13:14.446,line {i},Info,Tag,,,raw,,"),
+        }))
+        .collect::<Vec<_>>();
+    fs::write(&file, rows.join("\n")).unwrap();
+
+    let messages = Pipeline::new()
+        .source(Source::Files(vec![file]))
+        .stream()
+        .await
+        .unwrap()
+        .map(|record| record.message)
+        .collect::<Vec<_>>()
+        .await;
+    fs::remove_dir_all(&dir).ok();
+    assert_eq!(
+        messages,
+        [
+            "line 0",
+            "07-01 14:13:14.446,\"stray,Info,Tag,,,raw,,",
+            "line 2",
+            "line 3",
+            "line 4"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn pipeline_long_sources() {
+    use futures::stream::iter;
+
+    // Lines of the standard error don't continue records of the standard
+    // output
+    let lines = [
+        StreamData::Line("[ 01-10 23:02:59.028  1: 2 I/Out ]".to_owned()),
+        StreamData::ErrorLine("[ 01-10 23:02:59.029  3: 4 E/Err ]".to_owned()),
+        StreamData::Line("out message".to_owned()),
+        StreamData::ErrorLine("err message".to_owned()),
+        StreamData::Line(String::new()),
+    ];
+    let records = Pipeline::new()
+        .source(Source::Stream(Box::new(iter(lines))))
+        .stream()
+        .await
+        .unwrap()
+        .map(|record| (record.tag, record.message))
+        .collect::<Vec<_>>()
+        .await;
+    let record = |tag: &str, message: &str| (tag.to_owned(), message.to_owned());
+    assert_eq!(
+        records,
+        [record("Out", "out message"), record("Err", "err message")]
+    );
+}
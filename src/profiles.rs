@@ -18,78 +18,218 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::{cli::CliArguments, utils};
-use failure::{format_err, Error};
+use crate::config_dir;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap, convert::Into, env::var, fs::File, io::Read, ops::AddAssign,
+    collections::HashMap,
+    env::var,
+    fs::File,
+    io::{self, Read},
+    ops::AddAssign,
     path::PathBuf,
 };
 use toml::from_str;
 
-const DEFAULT_PROFILE_NAME: &str = "default";
+/// Profile that applies if none is selected
+pub const DEFAULT_PROFILE_NAME: &str = "default";
+
+/// Errors of loading and resolving profiles
+#[derive(Debug, thiserror::Error)]
+pub enum ProfileError {
+    #[error("Failed to open {}: {1}", .0.display())]
+    Open(PathBuf, io::Error),
+    #[error("Failed to parse {}: {1}", .0.display())]
+    Parse(PathBuf, toml::de::Error),
+    #[error("{0}")]
+    Syntax(toml::de::Error),
+    #[error("Unknown profile {0}{}", did_you_mean(.1))]
+    Unknown(String, Vec<String>),
+    #[error("Profile {0} is ambiguous. Candidates are {}", quoted(.1))]
+    Ambiguous(String, Vec<String>),
+    #[error("Unknown extend profile name {0} used in {1}")]
+    UnknownExtends(String, String),
+    #[error("Reached recursion limit while resolving profile {0} extends")]
+    RecursionLimit(String),
+    #[error("Cannot find {}. Use --profiles-path to specify the path manually!", .0.display())]
+    NotFound(PathBuf),
+    #[error("Cannot find {} set in ROGCAT_PROFILES!", .0.display())]
+    EnvNotFound(PathBuf),
+}
 
 /// Profile definition with filters and misc
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct Profile {
+    pub buffer: Option<Vec<String>>,
     pub comment: Option<String>,
     pub extends: Vec<String>,
+    pub format: Option<String>,
+    pub head: Option<usize>,
     pub highlight: Vec<String>,
+    pub level: Option<String>,
+    /// Colors of levels by level name
+    pub level_colors: HashMap<String, String>,
     pub message: Vec<String>,
     pub message_ignore_case: Vec<String>,
+    pub parser: Option<String>,
     pub pid: Vec<String>,
     pub process_name: Vec<String>,
     pub regex: Vec<String>,
+    /// Rewrite rules applied in order
+    pub rewrite: Vec<String>,
+    /// Settings overriding the configuration file
+    pub settings: HashMap<String, toml::Value>,
+    pub tail: Option<usize>,
     pub tag: Vec<String>,
+    /// Colors of tags by tag name
+    pub tag_colors: HashMap<String, String>,
     pub tag_ignore_case: Vec<String>,
+    pub tid: Vec<String>,
 }
 
-pub fn profiles_list(profiles_path: Option<&PathBuf>) -> Result<HashMap<String, Profile>, Error> {
+pub fn profiles_list(
+    profiles_path: Option<&PathBuf>,
+) -> Result<HashMap<String, Profile>, ProfileError> {
     let file = file(profiles_path)?;
     if !file.exists() {
         Ok(HashMap::new())
     } else {
         let mut config = String::new();
-        File::open(file.clone())
-            .map_err(|e| format_err!("Failed to open {}: {}", file.display(), e))?
-            .read_to_string(&mut config)?;
-
-        let mut config_file: ConfigurationFile = from_str(&config)
-            .map_err(|e| format_err!("Failed to parse {}: {}", file.display(), e))?;
-
-        let profiles: HashMap<String, Profile> = config_file
-            .profile
-            .drain()
-            .map(|(k, v)| (k, v.into()))
-            .collect();
-        Ok(profiles)
+        File::open(&file)
+            .and_then(|mut f| f.read_to_string(&mut config))
+            .map_err(|e| ProfileError::Open(file.clone(), e))?;
+
+        from_toml(&config).map_err(|e| ProfileError::Parse(file, e))
     }
 }
-/// Create a new Profiles instance from a give configuration file
-/// and default if file is not present or readable
-pub fn from_args(args: &CliArguments) -> Result<Profile, Error> {
-    let profiles = profiles_list(args.profiles_path.as_ref())?;
-    if profiles.is_empty() {
-        Ok(Profile::default())
-    } else {
-        let mut profile = Profile::default();
-        if let Some(selected) = args.profile.as_ref() {
-            profile = profiles
-                .get(selected.as_str())
-                .ok_or_else(|| format_err!("Unknown profile {}", selected))?
-                .clone();
-            expand(selected.as_str(), &mut profile, &profiles)?;
-        } else if let Some(default_profile) = profiles.get(DEFAULT_PROFILE_NAME) {
+
+/// Parse the profiles from the content of a profiles file
+pub fn parse(config: &str) -> Result<HashMap<String, Profile>, ProfileError> {
+    from_toml(config).map_err(ProfileError::Syntax)
+}
+
+fn from_toml(config: &str) -> Result<HashMap<String, Profile>, toml::de::Error> {
+    let mut config_file: ConfigurationFile = from_str(config)?;
+    Ok(config_file
+        .profile
+        .drain()
+        .map(|(k, v)| (k, v.into()))
+        .collect())
+}
+
+/// Maximum number of profiles suggested for an unknown profile. Only
+/// profiles within an edit distance of a third of the name are suggested.
+const SUGGESTIONS: usize = 3;
+
+/// Load the `selected` profiles or the default profile from the profiles
+/// file. The file is located at `profiles_path`, the path in the environment
+/// variable ROGCAT_PROFILES or in the configuration directory.
+pub fn load<S: AsRef<str>>(
+    profiles_path: Option<&PathBuf>,
+    selected: &[S],
+) -> Result<Profile, ProfileError> {
+    select(&profiles_list(profiles_path)?, selected)
+}
+
+/// Select the `selected` profiles or the default profile from `profiles`
+/// and resolve the profiles they extend. Multiple profiles are merged like
+/// a profile extending them. Profiles are selected by their name or a
+/// prefix that matches a single profile.
+pub fn select<S: AsRef<str>>(
+    profiles: &HashMap<String, Profile>,
+    selected: &[S],
+) -> Result<Profile, ProfileError> {
+    let mut profile = Profile::default();
+    if selected.is_empty() {
+        if let Some(default_profile) = profiles.get(DEFAULT_PROFILE_NAME) {
             profile = default_profile.clone();
-            expand(DEFAULT_PROFILE_NAME, &mut profile, &profiles)?;
+            expand(DEFAULT_PROFILE_NAME, &mut profile, profiles)?;
+        }
+        return Ok(profile);
+    }
+    for selected in selected {
+        let (name, selected) = resolve(profiles, selected.as_ref())?;
+        let mut selected = selected.clone();
+        expand(name, &mut selected, profiles)?;
+        profile += selected;
+    }
+    Ok(profile)
+}
+
+/// Profile named `name` or the only profile starting with `name`
+fn resolve<'a>(
+    profiles: &'a HashMap<String, Profile>,
+    name: &str,
+) -> Result<(&'a str, &'a Profile), ProfileError> {
+    if let Some((name, profile)) = profiles.get_key_value(name) {
+        return Ok((name, profile));
+    }
+    let by_distance = |names: Vec<&String>, max: usize| {
+        let mut names = names
+            .into_iter()
+            .map(|candidate| (edit_distance(name, candidate), candidate.clone()))
+            .filter(|(distance, _)| *distance <= max)
+            .collect::<Vec<_>>();
+        names.sort();
+        names.into_iter().map(|(_, name)| name).collect::<Vec<_>>()
+    };
+    let mut matches = profiles.iter().filter(|(n, _)| n.starts_with(name));
+    match (matches.next(), matches.next()) {
+        (Some((name, profile)), None) => Ok((name, profile)),
+        (Some(_), Some(_)) => {
+            let candidates = profiles.keys().filter(|n| n.starts_with(name)).collect();
+            Err(ProfileError::Ambiguous(
+                name.to_owned(),
+                by_distance(candidates, usize::MAX),
+            ))
+        }
+        _ => {
+            let max = (name.chars().count() / 3).max(2);
+            let mut suggestions = by_distance(profiles.keys().collect(), max);
+            suggestions.truncate(SUGGESTIONS);
+            Err(ProfileError::Unknown(name.to_owned(), suggestions))
+        }
+    }
+}
+
+/// Number of single char insertions, deletions and substitutions to turn
+/// `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
         }
+    }
+    row[b.len()]
+}
+
+/// Names like "`a`, `b`"
+fn quoted(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("`{name}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-        Ok(profile)
+/// Suggestion like ". Did you mean `a` or `b`?"
+fn did_you_mean(names: &[String]) -> String {
+    match names {
+        [] => String::new(),
+        [names @ .., last] if !names.is_empty() => {
+            format!(". Did you mean {} or `{last}`?", quoted(names))
+        }
+        names => format!(". Did you mean {}?", quoted(names)),
     }
 }
 
 /// Expand a profile with file content
-fn expand(n: &str, p: &mut Profile, a: &HashMap<String, Profile>) -> Result<(), Error> {
+fn expand(n: &str, p: &mut Profile, a: &HashMap<String, Profile>) -> Result<(), ProfileError> {
     let mut recursion_limit = 100;
     while !p.extends.is_empty() {
         let extends = p.extends.clone();
@@ -97,31 +237,25 @@ fn expand(n: &str, p: &mut Profile, a: &HashMap<String, Profile>) -> Result<(),
         for e in &extends {
             let f = a
                 .get(e)
-                .ok_or_else(|| format_err!("Unknown extend profile name {} used in {}", e, n))?;
+                .ok_or_else(|| ProfileError::UnknownExtends(e.clone(), n.to_owned()))?;
             *p += f.clone();
         }
 
         recursion_limit -= 1;
         if recursion_limit == 0 {
-            return Err(format_err!(
-                "Reached recursion limit while resolving profile {} extends",
-                n
-            ));
+            return Err(ProfileError::RecursionLimit(n.to_owned()));
         }
     }
     Ok(())
 }
 
 /// Return path to profile file by checking cli argument, env and default to configdir
-fn file(profile_path: Option<&PathBuf>) -> Result<PathBuf, Error> {
+pub fn file(profile_path: Option<&PathBuf>) -> Result<PathBuf, ProfileError> {
     if let Some(path) = profile_path {
         if path.exists() {
             return Ok(path.to_owned());
         } else {
-            return Err(format_err!(
-                "Cannot find {}. Use --profiles-path to specify the path manually!",
-                path.display()
-            ));
+            return Err(ProfileError::NotFound(path.to_owned()));
         }
     }
 
@@ -129,13 +263,10 @@ fn file(profile_path: Option<&PathBuf>) -> Result<PathBuf, Error> {
         if f.exists() {
             Ok(f)
         } else {
-            Err(format_err!(
-                "Cannot find {} set in ROGCAT_PROFILES!",
-                f.display()
-            ))
+            Err(ProfileError::EnvNotFound(f))
         }
     } else {
-        Ok(utils::config_dir().join("profiles.toml"))
+        Ok(config_dir().join("profiles.toml"))
     }
 }
 
@@ -148,31 +279,74 @@ struct ConfigurationFile {
 /// Struct with exact layout as used in config file
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 struct ProfileFile {
+    buffer: Option<Vec<String>>,
     comment: Option<String>,
     extends: Option<Vec<String>>,
+    format: Option<String>,
+    head: Option<usize>,
     highlight: Option<Vec<String>>,
+    level: Option<String>,
+    level_colors: Option<HashMap<String, ColorValue>>,
     message: Option<Vec<String>>,
     message_ignore_case: Option<Vec<String>>,
+    parser: Option<String>,
     pid: Option<Vec<String>>,
     process_name: Option<Vec<String>>,
     regex: Option<Vec<String>>,
+    rewrite: Option<Vec<String>>,
+    settings: Option<HashMap<String, toml::Value>>,
+    tail: Option<usize>,
     tag: Option<Vec<String>>,
+    tag_colors: Option<HashMap<String, ColorValue>>,
     tag_ignore_case: Option<Vec<String>>,
+    tid: Option<Vec<String>>,
+}
+
+/// Color in the profiles file. Named colors are strings and Ansi256 colors
+/// numbers.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum ColorValue {
+    Ansi256(u8),
+    Name(String),
+}
+
+/// Colors by name as strings like "red" or "202"
+fn colors(colors: Option<HashMap<String, ColorValue>>) -> HashMap<String, String> {
+    colors
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(k, v)| match v {
+            ColorValue::Ansi256(n) => (k, n.to_string()),
+            ColorValue::Name(name) => (k, name),
+        })
+        .collect()
 }
 
 impl From<ProfileFile> for Profile {
     fn from(f: ProfileFile) -> Profile {
         Profile {
+            buffer: f.buffer,
             comment: f.comment,
             extends: f.extends.unwrap_or_default(),
+            format: f.format,
+            head: f.head,
             highlight: f.highlight.unwrap_or_default(),
+            level: f.level,
+            level_colors: colors(f.level_colors),
             message: f.message.unwrap_or_default(),
             message_ignore_case: f.message_ignore_case.unwrap_or_default(),
+            parser: f.parser,
             pid: f.pid.unwrap_or_default(),
             process_name: f.process_name.unwrap_or_default(),
             regex: f.regex.unwrap_or_default(),
+            rewrite: f.rewrite.unwrap_or_default(),
+            settings: f.settings.unwrap_or_default(),
+            tail: f.tail,
             tag: f.tag.unwrap_or_default(),
+            tag_colors: colors(f.tag_colors),
             tag_ignore_case: f.tag_ignore_case.unwrap_or_default(),
+            tid: f.tid.unwrap_or_default(),
         }
     }
 }
@@ -191,5 +365,109 @@ impl AddAssign for Profile {
         vec_extend!(self.highlight, other.highlight);
         vec_extend!(self.message, other.message);
         vec_extend!(self.tag, other.tag);
+
+        // Rules of the extended profile run first. The order matters.
+        let mut rewrite = other.rewrite;
+        for rule in std::mem::take(&mut self.rewrite) {
+            if !rewrite.contains(&rule) {
+                rewrite.push(rule);
+            }
+        }
+        self.rewrite = rewrite;
+
+        // Settings of the extending profile take precedence
+        macro_rules! option_or {
+            ($x:expr, $y:expr) => {
+                if $x.is_none() {
+                    $x = $y;
+                }
+            };
+        }
+
+        option_or!(self.buffer, other.buffer);
+        option_or!(self.format, other.format);
+        option_or!(self.head, other.head);
+        option_or!(self.level, other.level);
+        option_or!(self.parser, other.parser);
+        option_or!(self.tail, other.tail);
+
+        for (k, v) in other.level_colors {
+            self.level_colors.entry(k).or_insert(v);
+        }
+        for (k, v) in other.tag_colors {
+            self.tag_colors.entry(k).or_insert(v);
+        }
+        for (k, v) in other.settings {
+            self.settings.entry(k).or_insert(v);
+        }
     }
 }
+
+#[test]
+fn select_by_prefix() {
+    let profiles = from_toml(
+        r#"
+        [profile.myapp-network-debug]
+        tag = ["OkHttp"]
+        [profile.myapp-ui]
+        tag = ["ViewRoot"]
+        [profile.system]
+        level = "warn"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(select(&profiles, &["myapp-n"]).unwrap().tag, ["OkHttp"]);
+    assert_eq!(
+        select(&profiles, &["sys"]).unwrap().level.as_deref(),
+        Some("warn")
+    );
+
+    let error = select(&profiles, &["myapp"]).unwrap_err();
+    assert!(matches!(error, ProfileError::Ambiguous(..)));
+    assert_eq!(
+        error.to_string(),
+        "Profile myapp is ambiguous. Candidates are `myapp-ui`, `myapp-network-debug`"
+    );
+
+    let error = select(&profiles, &["myap-network-debug"]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Unknown profile myap-network-debug. Did you mean `myapp-network-debug`?"
+    );
+    let error = select(&HashMap::new(), &["foo"]).unwrap_err();
+    assert_eq!(error.to_string(), "Unknown profile foo");
+}
+
+#[test]
+fn select_multiple() {
+    let profiles = from_toml(
+        r#"
+        [profile.default]
+        tag = ["default"]
+        [profile.network]
+        tag = ["OkHttp"]
+        level = "debug"
+        [profile.quiet]
+        tag = ["ActivityManager"]
+        level = "warn"
+        head = 10
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(select::<&str>(&profiles, &[]).unwrap().tag, ["default"]);
+
+    let profile = select(&profiles, &["net", "quiet"]).unwrap();
+    assert_eq!(profile.tag, ["ActivityManager", "OkHttp"]);
+    assert_eq!(profile.level.as_deref(), Some("debug"));
+    assert_eq!(profile.head, Some(10));
+}
+
+#[test]
+fn edit_distances() {
+    assert_eq!(edit_distance("", ""), 0);
+    assert_eq!(edit_distance("abc", ""), 3);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(edit_distance("myap", "myapp"), 1);
+}
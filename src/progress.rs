@@ -0,0 +1,213 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Progress of reading input files. Readers count the bytes read from the
+//! files and a [`Progress`] handle reports them with the file sizes.
+
+use futures::task::{Context, Poll};
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Interval of publishing the bytes read by a [`CountingReader`]
+pub const UPDATE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Bytes read of an input file
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileProgress {
+    pub path: PathBuf,
+    pub read: u64,
+    /// Size of the file. Compressed files are counted before decompression.
+    pub total: u64,
+}
+
+impl FileProgress {
+    /// The whole file is read
+    pub fn done(&self) -> bool {
+        self.read >= self.total
+    }
+}
+
+struct TrackedFile {
+    path: PathBuf,
+    total: u64,
+    read: Arc<AtomicU64>,
+}
+
+/// Handle to the progress of the input files and the number of records
+/// parsed from them. Files are tracked in the order they are opened.
+#[derive(Clone, Default)]
+pub struct Progress {
+    files: Arc<Mutex<Vec<TrackedFile>>>,
+    records: Arc<AtomicUsize>,
+}
+
+impl Progress {
+    /// Track the file `path` of `total` bytes. The returned counter is
+    /// updated by the reader of the file.
+    pub(crate) fn track(&self, path: &Path, total: u64) -> Arc<AtomicU64> {
+        let read = Arc::new(AtomicU64::new(0));
+        self.files.lock().expect("Poisoned lock").push(TrackedFile {
+            path: path.to_owned(),
+            total,
+            read: read.clone(),
+        });
+        read
+    }
+
+    /// Count a parsed record
+    pub(crate) fn record(&self) {
+        self.records.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of records parsed
+    pub fn records(&self) -> usize {
+        self.records.load(Ordering::Relaxed)
+    }
+
+    /// Bytes read of each tracked file
+    pub fn files(&self) -> Vec<FileProgress> {
+        self.files
+            .lock()
+            .expect("Poisoned lock")
+            .iter()
+            .map(|file| FileProgress {
+                path: file.path.clone(),
+                read: file.read.load(Ordering::Relaxed),
+                total: file.total,
+            })
+            .collect()
+    }
+}
+
+/// Rate limit of updates
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Throttle {
+        Throttle {
+            interval,
+            last: None,
+        }
+    }
+
+    /// True if the last update is at least the interval before `now`. The
+    /// first update is always ready.
+    pub fn ready(&mut self, now: Instant) -> bool {
+        match self.last {
+            Some(last) if now.saturating_duration_since(last) < self.interval => false,
+            _ => {
+                self.last = Some(now);
+                true
+            }
+        }
+    }
+}
+
+/// Reader that publishes the number of bytes read from `inner` at most
+/// every [`UPDATE_INTERVAL`] and at the end
+pub struct CountingReader<R> {
+    inner: R,
+    read: u64,
+    published: Arc<AtomicU64>,
+    throttle: Throttle,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R, published: Arc<AtomicU64>) -> CountingReader<R> {
+        CountingReader {
+            inner,
+            read: 0,
+            published,
+            throttle: Throttle::new(UPDATE_INTERVAL),
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, buf));
+        let n = (buf.filled().len() - filled) as u64;
+        self.read += n;
+        // Nothing read is the end of the file
+        if n == 0 || self.throttle.ready(Instant::now()) {
+            self.published.store(self.read, Ordering::Relaxed);
+        }
+        Poll::Ready(result)
+    }
+}
+
+#[test]
+fn throttle() {
+    let start = Instant::now();
+    let mut throttle = Throttle::new(Duration::from_millis(50));
+    assert!(throttle.ready(start));
+    assert!(!throttle.ready(start + Duration::from_millis(10)));
+    assert!(!throttle.ready(start + Duration::from_millis(49)));
+    assert!(throttle.ready(start + Duration::from_millis(50)));
+    assert!(!throttle.ready(start + Duration::from_millis(60)));
+    assert!(throttle.ready(start + Duration::from_millis(200)));
+    // Clocks going backwards don't update
+    assert!(!throttle.ready(start));
+}
+
+#[tokio::test]
+async fn counting_reader() {
+    use tokio::io::AsyncReadExt;
+
+    let progress = Progress::default();
+    let content = vec![b'a'; 100_000];
+    let read = progress.track(Path::new("input.log"), content.len() as u64);
+    let mut reader = CountingReader::new(content.as_slice(), read);
+
+    // The first read is published right away
+    let mut buffer = [0; 1000];
+    reader.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(progress.files()[0].read, 1000);
+    assert!(!progress.files()[0].done());
+
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(
+        progress.files(),
+        [FileProgress {
+            path: PathBuf::from("input.log"),
+            read: 100_000,
+            total: 100_000,
+        }]
+    );
+    assert!(progress.files()[0].done());
+}
@@ -0,0 +1,124 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Progress bar of the input files on stderr
+
+use indicatif::{ProgressBar, ProgressStyle};
+use rogcat::progress::{FileProgress, Progress, UPDATE_INTERVAL};
+use std::time::Instant;
+use tokio::task::JoinHandle;
+
+/// Bar showing the bytes read of all input files and the current file
+pub struct InputProgress {
+    bar: ProgressBar,
+    progress: Progress,
+    start: Instant,
+    task: JoinHandle<()>,
+}
+
+/// Draw the progress of the files tracked by `progress` until finished
+pub fn spawn(progress: Progress) -> InputProgress {
+    let bar = ProgressBar::new(0);
+    let template = "{spinner:.yellow} {msg:.dim.bold} [{bar:30.yellow/green}] {binary_bytes:.dim}/{binary_total_bytes:.dim} {binary_bytes_per_sec:.dim} ({eta:.dim})";
+    if let Ok(style) = ProgressStyle::default_bar().template(template) {
+        bar.set_style(style.progress_chars("•• "));
+    }
+    let task = tokio::spawn({
+        let bar = bar.clone();
+        let progress = progress.clone();
+        async move {
+            let mut interval = tokio::time::interval(UPDATE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let files = progress.files();
+                bar.set_length(files.iter().map(|f| f.total).sum());
+                bar.set_position(files.iter().map(|f| f.read).sum());
+                bar.set_message(message(&files));
+            }
+        }
+    });
+    InputProgress {
+        bar,
+        progress,
+        start: Instant::now(),
+        task,
+    }
+}
+
+impl InputProgress {
+    /// Remove the bar and return a summary of the parsed records
+    pub fn finish(self) -> String {
+        self.task.abort();
+        self.bar.finish_and_clear();
+        format!(
+            "Parsed {} records in {:.2?}",
+            self.progress.records(),
+            self.start.elapsed()
+        )
+    }
+}
+
+/// Name and progress of the file read now. The first file that isn't read
+/// completely is read now.
+fn message(files: &[FileProgress]) -> String {
+    let Some(index) = files
+        .iter()
+        .position(|file| !file.done())
+        .or_else(|| files.len().checked_sub(1))
+    else {
+        return String::new();
+    };
+    let file = &files[index];
+    let name = file.path.file_name().map_or_else(
+        || file.path.display().to_string(),
+        |name| name.to_string_lossy().to_string(),
+    );
+    if files.len() == 1 {
+        return name;
+    }
+    let percent = match file.total {
+        0 => 100,
+        total => file.read.min(total) * 100 / total,
+    };
+    format!("{}/{} {name} {percent}%", index + 1, files.len())
+}
+
+#[test]
+fn messages() {
+    let file = |path: &str, read, total| FileProgress {
+        path: path.into(),
+        read,
+        total,
+    };
+    assert_eq!(message(&[]), "");
+    assert_eq!(message(&[file("/tmp/a.log", 10, 100)]), "a.log");
+    assert_eq!(
+        message(&[file("a.log", 100, 100), file("b.log.gz", 25, 100)]),
+        "2/2 b.log.gz 25%"
+    );
+    assert_eq!(
+        message(&[file("a.log", 10, 100), file("b.log", 0, 100)]),
+        "1/2 a.log 10%"
+    );
+    assert_eq!(
+        message(&[file("a.log", 100, 100), file("b.log", 100, 100)]),
+        "2/2 b.log 100%"
+    );
+}
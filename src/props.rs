@@ -0,0 +1,105 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Device properties of `--stamp-props`. The properties are queried once in
+//! the background while logcat starts.
+
+use crate::cli::CliArguments;
+use futures::{
+    future::{BoxFuture, Shared},
+    stream, FutureExt, StreamExt,
+};
+use rogcat::{
+    adb::adb_command,
+    pipeline::{RecordStream, Source},
+    record::{Level, Record},
+    session::Props,
+};
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Properties stamped if none are selected
+const DEFAULT_PROPS: [&str; 3] = [
+    "ro.product.model",
+    "ro.build.fingerprint",
+    "ro.build.version.release",
+];
+
+/// Maximum duration of querying the properties
+const GETPROP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Properties that are available once the query finished
+pub type PendingProps = Shared<BoxFuture<'static, Props>>;
+
+/// Start querying the selected properties of the device of `source`. None
+/// if no properties are selected or the source is not adb.
+pub fn spawn(args: &CliArguments, source: &Source) -> Option<PendingProps> {
+    let names = args.stamp_props.clone()?;
+    let Source::Logcat(options) = source else {
+        return None;
+    };
+    let names = if names.is_empty() {
+        DEFAULT_PROPS.map(str::to_owned).to_vec()
+    } else {
+        names
+    };
+    let device = options.devices.first().cloned();
+    let query =
+        tokio::spawn(async move { query(device.as_deref(), &names).await.unwrap_or_default() });
+    Some(query.map(Result::unwrap_or_default).boxed().shared())
+}
+
+/// Read the properties `names` of `device`. Failures are not reported.
+async fn query(device: Option<&str>, names: &[String]) -> Option<Props> {
+    let stdout = getprop(device, &[]).await?;
+    Some(Props::from_getprop(&stdout, names))
+}
+
+/// Trimmed output of `getprop` with `args` on `device`, e.g. the value of a
+/// property or all properties without `args`. None if adb fails, times out
+/// or prints nothing.
+pub async fn getprop(device: Option<&str>, args: &[&str]) -> Option<String> {
+    let mut command = adb_command(device).ok()?;
+    command
+        .args(["shell", "getprop"])
+        .args(args)
+        .kill_on_drop(true);
+    let output = timeout(GETPROP_TIMEOUT, command.output())
+        .await
+        .ok()?
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    (output.status.success() && !stdout.is_empty()).then_some(stdout)
+}
+
+/// The properties as diagnostics that are shown dimmed on the terminal as
+/// soon as they are available
+pub fn banner(props: PendingProps) -> RecordStream {
+    props
+        .map(|props| {
+            let records = props
+                .lines()
+                .map(|line| Record::from_diagnostic(Level::Info, line))
+                .collect::<Vec<_>>();
+            stream::iter(records)
+        })
+        .flatten_stream()
+        .boxed()
+}
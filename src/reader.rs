@@ -18,58 +18,480 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+#[cfg(target_os = "linux")]
+use crate::record::Record;
 use crate::{
-    cli::CliArguments,
-    lossy_lines::{lossy_lines, LossyLinesCodec},
-    utils::{adb, config_get},
-    LogStream, StreamData, DEFAULT_BUFFER,
+    adb::{adb, adb_command},
+    filter::{since_now, Timestamp},
+    lossy_lines::{self, lossy_lines, LossyLinesCodec},
+    parser::Parser,
+    progress::{CountingReader, Progress},
+    record::Level,
+    session::Session,
+    Error, LogStream, StreamData, DEFAULT_BUFFER,
 };
-use failure::{err_msg, format_err, Error};
+use async_compression::tokio::bufread::ZstdDecoder;
+use bytes::Bytes;
+use flate2::bufread::MultiGzDecoder;
 use futures::{
-    stream::{iter, select},
+    channel::mpsc,
+    executor::block_on,
+    future::ready,
+    ready,
+    stream::{iter, select, select_all, unfold},
     task::{Context, Poll},
-    Stream, StreamExt, TryStreamExt,
+    Future, SinkExt, Stream, StreamExt, TryStreamExt,
+};
+use serde::de::{Deserializer, SeqAccess, Visitor};
+use std::{
+    borrow::ToOwned,
+    collections::{hash_map::DefaultHasher, BTreeSet, VecDeque},
+    convert::Into,
+    fmt,
+    hash::{Hash, Hasher},
+    io::{ErrorKind, Read, SeekFrom},
+    path::{Path, PathBuf},
+    pin::Pin,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
-#[cfg(target_os = "linux")]
-use rogcat::record::Record;
-use std::{borrow::ToOwned, convert::Into, path::PathBuf, pin::Pin, process::Stdio};
 use time::{macros::format_description, OffsetDateTime};
 use tokio::{
     fs::File,
-    io::{AsyncBufReadExt, BufReader},
-    net::TcpStream,
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, BufReader},
+    net::{TcpListener, TcpStream, UdpSocket},
     process::{Child, Command},
+    time::{sleep, timeout, Sleep},
+};
+use tokio_serial::{DataBits, Parity, SerialPortBuilderExt, SerialStream, StopBits};
+use tokio_stream::wrappers::{LinesStream, TcpListenerStream};
+use tokio_util::{
+    codec::{Decoder, FramedRead},
+    io::{StreamReader, SyncIoBridge},
 };
-use tokio_stream::wrappers::LinesStream;
-use tokio_util::codec::{Decoder, FramedRead};
 use url::Url;
 
+/// Initial delay before respawning a terminated process
+const RESPAWN_DELAY: Duration = Duration::from_millis(500);
+/// Maximum delay before respawning a terminated process
+const RESPAWN_DELAY_MAX: Duration = Duration::from_secs(10);
+
+/// Time a terminated child and its descendants get to exit before they are
+/// killed
+const TERMINATE_GRACE: Duration = Duration::from_secs(2);
+
+/// Size of the receive buffer for udp datagrams. Larger datagrams are truncated
+const UDP_BUFFER_SIZE: usize = 64 * 1024;
+/// Delay before receiving again after a failed receive, multiplied by the
+/// number of failures in a row
+const UDP_ERROR_DELAY: Duration = Duration::from_millis(100);
+/// Number of failed receives in a row that end a udp stream
+const UDP_MAX_ERRORS: u32 = 10;
+
+/// Delay between replayed records without timestamp
+const REPLAY_DELAY: Duration = Duration::from_millis(10);
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Size of the blocks read from the end of a file for --last-records
+const TAIL_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Default number of lines remembered by a polled command
+pub const POLL_WINDOW: usize = 10_000;
+
 /// A spawned child process that implements LogStream
 struct Process {
     cmd: Vec<String>,
+    /// Serial of the device if multiple devices are followed
+    device: Option<String>,
+    /// Log buffer if the buffers are read by separate processes
+    buffer: Option<String>,
     /// Respawn cmd upon termination
     respawn: bool,
-    child: Option<Child>,
+    /// Adb and device selector if the process is adb
+    adb: Option<Vec<String>>,
+    /// Run adb wait-for-device before spawning
+    wait_for_device: bool,
+    child: Option<SpawnedChild>,
     stream: Option<Pin<LogStream>>,
+    /// Respawn delay and wait for device before the next spawn
+    pending: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    /// Delay of the next respawn
+    delay: Duration,
+    /// Time of the last spawn
+    spawned: Instant,
+    /// Last adb error reported to the user
+    adb_error: Arc<Mutex<Option<String>>>,
+    /// Timestamp of the newest line of logcat. A respawned logcat starts at
+    /// this timestamp instead of replaying the whole buffer.
+    checkpoint: Option<Timestamp>,
+    /// Lines at the checkpoint. A respawned logcat prints them again.
+    at_checkpoint: Vec<String>,
+    /// Checkpoint passed to the current logcat until it passed the lines at
+    /// the checkpoint
+    resumed: Option<Timestamp>,
+    /// Item passed before polling the stream again
+    queued: Option<StreamData>,
+    /// Registry of the spawned children of the pipeline
+    children: Children,
 }
 
-/// Open a file and provide a stream of lines
+/// Open files and provide a stream of their lines in the given order. Gzip
+/// and zstd compressed files are decompressed on the fly.
 pub async fn files(files: Vec<PathBuf>) -> Result<LogStream, Error> {
-    let f = iter::<_>(files)
-        .map(|f| async move {
-            let file = File::open(f.clone())
-                .await
-                .map_err(move |e| format_err!("Failed to open {}: {}", f.display(), e))
-                .unwrap();
-            Decoder::framed(LossyLinesCodec::new(), file)
-                .map_ok(StreamData::Line)
-                .map_err(move |e| format_err!("Failed to read file: {}", e))
-                .filter_map(|x| async move { x.ok() })
-        })
-        .filter_map(|x| async move { Some(x.await) })
-        .flatten();
+    files_with_progress(files, &Progress::default()).await
+}
+
+/// Same as [`files`] but the bytes read of each file are tracked by
+/// `progress`
+pub async fn files_with_progress(
+    files: Vec<PathBuf>,
+    progress: &Progress,
+) -> Result<LogStream, Error> {
+    let mut streams = Vec::with_capacity(files.len());
+    for f in files {
+        streams.push(Box::into_pin(file_with_progress(f, progress).await?));
+    }
+
+    Ok(Box::new(iter(streams).flatten()))
+}
+
+/// True if `reader` starts with a JSON array of objects like the exports of
+/// Android Studio. Lines starting with a bracket like "[INFO]" are not.
+async fn is_json_array<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<bool> {
+    let start = skip_whitespace(reader.fill_buf().await?);
+    Ok(match start.strip_prefix(b"[") {
+        Some(rest) => matches!(skip_whitespace(rest).first(), Some(b'{' | b']')),
+        None => false,
+    })
+}
+
+/// `bytes` without leading ASCII whitespace
+fn skip_whitespace(bytes: &[u8]) -> &[u8] {
+    let n = bytes.iter().take_while(|b| b.is_ascii_whitespace()).count();
+    &bytes[n..]
+}
+
+/// Number of elements of a JSON array read ahead
+const JSON_ARRAY_BUFFER: usize = 64;
+
+/// Visitor passing the elements of a JSON array as lines to a channel. The
+/// elements are read until the channel is closed.
+struct Elements<'a>(&'a mut mpsc::Sender<StreamData>);
+
+impl<'de> Visitor<'de> for Elements<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(element) = seq.next_element::<serde_json::Value>()? {
+            let line = StreamData::Line(element.to_string());
+            if block_on(self.0.send(line)).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read a JSON array on a blocking task and provide its elements as lines
+/// while the array is read. Malformed arrays are passed as fatal diagnostic
+/// and end the stream.
+fn json_array<R: AsyncRead + Send + Unpin + 'static>(reader: R, f: PathBuf) -> LogStream {
+    let (mut tx, rx) = mpsc::channel(JSON_ARRAY_BUFFER);
+    let reader = SyncIoBridge::new(reader);
+    tokio::task::spawn_blocking(move || {
+        let mut deserializer =
+            serde_json::Deserializer::from_reader(std::io::BufReader::new(reader));
+        let result = deserializer
+            .deserialize_seq(Elements(&mut tx))
+            .and_then(|_| deserializer.end());
+        if let Err(e) = result {
+            let message = format!("Failed to read {}: {e}", f.display());
+            block_on(tx.send(StreamData::Diagnostic(Level::Fatal, message))).ok();
+        }
+    });
+    Box::new(rx)
+}
+
+/// Open a file and provide a stream of its lines. Files with a JSON array
+/// provide a line per element. Read errors are passed as fatal diagnostic
+/// and end the stream.
+pub async fn file(f: PathBuf) -> Result<LogStream, Error> {
+    file_with_progress(f, &Progress::default()).await
+}
+
+/// Same as [`file`] but the bytes read are tracked by `progress`
+pub async fn file_with_progress(f: PathBuf, progress: &Progress) -> Result<LogStream, Error> {
+    let mut reader = BufReader::new(open(&f, progress).await?);
+    let is_array = is_json_array(&mut reader)
+        .await
+        .map_err(|e| Error::io(format!("Failed to read {}", f.display()), e))?;
+    if is_array {
+        return Ok(json_array(reader, f));
+    }
+    Ok(lines(reader, f))
+}
+
+/// Lines of `reader` that reads the file `f`. Read errors like a corrupt
+/// archive are passed as fatal diagnostic and end the stream.
+fn lines<R: AsyncRead + Send + Unpin + 'static>(reader: R, f: PathBuf) -> LogStream {
+    let stream =
+        FramedRead::new(reader, LossyLinesCodec::new()).scan(false, move |failed, line| {
+            if *failed {
+                return ready(None);
+            }
+            ready(Some(match line {
+                Ok(line) => StreamData::Line(line),
+                Err(e) => {
+                    *failed = true;
+                    let message = format!("Failed to read {}: {}", f.display(), e);
+                    StreamData::Diagnostic(Level::Fatal, message)
+                }
+            }))
+        });
+    Box::new(stream)
+}
+
+/// Offset of the first of the last `count` lines of `file`. The file is read
+/// backwards in blocks. A line break at the end of the file doesn't start
+/// another line. Line breaks are single bytes in UTF-8 and the offset is
+/// always at the start of a char.
+async fn last_lines_offset(file: &mut File, count: usize) -> std::io::Result<u64> {
+    let len = file.metadata().await?.len();
+    if count == 0 {
+        return Ok(len);
+    }
+    let mut block = vec![0; TAIL_BLOCK_SIZE];
+    let mut breaks = 0;
+    let mut end = len;
+    while end > 0 {
+        let start = end.saturating_sub(TAIL_BLOCK_SIZE as u64);
+        let block = &mut block[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start)).await?;
+        file.read_exact(block).await?;
+        for (i, byte) in block.iter().enumerate().rev() {
+            let offset = start + i as u64;
+            if *byte != b'\n' || offset + 1 == len {
+                continue;
+            }
+            breaks += 1;
+            if breaks == count {
+                return Ok(offset + 1);
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+/// Provide a stream of the last `count` lines of a file without reading the
+/// lines before. Compressed files cannot be searched from the end and fail.
+/// UTF-16 files are read completely because their encoding is detected at
+/// the start.
+pub async fn last_lines(f: PathBuf, count: usize) -> Result<LogStream, Error> {
+    let mut file = File::open(&f)
+        .await
+        .map_err(|e| Error::io(format!("Failed to open {}", f.display()), e))?;
+    let context = || format!("Failed to read {}", f.display());
+    let mut start = [0; lossy_lines::SAMPLE];
+    let mut read = 0;
+    while read < start.len() {
+        match file.read(&mut start[read..]).await {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) => return Err(Error::io(context(), e)),
+        }
+    }
+    let start = &start[..read];
+    let extension = f.extension().and_then(|e| e.to_str());
+    if matches!(extension, Some("gz" | "zst"))
+        || start.starts_with(GZIP_MAGIC)
+        || start.starts_with(ZSTD_MAGIC)
+    {
+        return Err(Error::Invalid(format!(
+            "--last-records doesn't support the compressed file {}",
+            f.display()
+        )));
+    }
+    if lossy_lines::is_utf16(start) {
+        file.seek(SeekFrom::Start(0))
+            .await
+            .map_err(|e| Error::io(context(), e))?;
+        let mut lines = Box::into_pin(lines(BufReader::new(file), f));
+        let mut last = VecDeque::with_capacity(count.min(TAIL_BLOCK_SIZE));
+        while let Some(data) = lines.next().await {
+            // Read failures end the stream and are kept
+            if last.len() == count && matches!(data, StreamData::Line(_)) {
+                last.pop_front();
+            }
+            if count > 0 || !matches!(data, StreamData::Line(_)) {
+                last.push_back(data);
+            }
+        }
+        return Ok(Box::new(iter(last)));
+    }
+    let offset = last_lines_offset(&mut file, count)
+        .await
+        .map_err(|e| Error::io(context(), e))?;
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .map_err(|e| Error::io(context(), e))?;
+    Ok(lines(BufReader::new(file), f))
+}
 
-    Ok(Box::new(f))
+/// Provide a stream of the last `count` lines of each file in the given
+/// order
+pub async fn files_last_lines(files: Vec<PathBuf>, count: usize) -> Result<LogStream, Error> {
+    let mut streams = Vec::with_capacity(files.len());
+    for f in files {
+        streams.push(Box::into_pin(last_lines(f, count).await?));
+    }
+    Ok(Box::new(iter(streams).flatten()))
+}
+
+/// Time to wait before replaying a record at `time` that follows a record
+/// at `previous`. The original delay is divided by `speed`. Records without
+/// timestamp follow after a fixed delay.
+pub fn replay_delay(previous: Option<Timestamp>, time: Option<Timestamp>, speed: f64) -> Duration {
+    let delay = match (previous, time) {
+        (Some(previous), Some(time)) => Duration::from_millis(previous.elapsed(time).max(0) as u64),
+        _ => REPLAY_DELAY,
+    };
+    delay.div_f64(speed)
+}
+
+/// Replay a recorded file at `speed` times the pace of the record
+/// timestamps or as fast as possible without speed. The metadata of a
+/// recorded session is passed as diagnostic.
+pub async fn replay(f: PathBuf, speed: Option<f64>) -> Result<LogStream, Error> {
+    let lines = Box::into_pin(file(f).await?);
+    // The lines are parsed for the timestamps only
+    let mut parser = Parser::default();
+    let mut previous = None;
+    let stream = lines.then(move |data| {
+        let mut delay = Duration::ZERO;
+        let data = match data {
+            StreamData::Line(line) => match Session::parse(&line) {
+                Some(session) => StreamData::Diagnostic(Level::Info, session.to_string()),
+                None => {
+                    if let (Some(speed), Some(record)) = (speed, parser.parse(&line)) {
+                        let time = record.time.as_deref().and_then(Timestamp::parse);
+                        delay = replay_delay(previous, time, speed);
+                        previous = time.or(previous);
+                    }
+                    StreamData::Line(line)
+                }
+            },
+            data => data,
+        };
+        async move {
+            if !delay.is_zero() {
+                sleep(delay).await;
+            }
+            data
+        }
+    });
+    Ok(Box::new(stream))
+}
+
+/// True for Windows named pipes like \\.\pipe\logs
+#[cfg_attr(not(windows), allow(dead_code))]
+fn is_named_pipe(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    path.get(..9)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(r"\\.\pipe\"))
+}
+
+/// Connect to a named pipe. Busy pipes are retried until an instance is
+/// available.
+#[cfg(windows)]
+async fn open_pipe(path: &Path) -> Result<Box<dyn AsyncRead + Send + Unpin>, Error> {
+    use tokio::net::windows::named_pipe::ClientOptions;
+    /// All instances of the pipe are connected to other clients
+    const ERROR_PIPE_BUSY: i32 = 231;
+
+    loop {
+        match ClientOptions::new().open(path) {
+            Ok(pipe) => return Ok(Box::new(pipe)),
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                sleep(Duration::from_millis(50)).await
+            }
+            Err(e) => return Err(Error::io(format!("Failed to open {}", path.display()), e)),
+        }
+    }
+}
+
+/// Open a file and wrap it in a decompressor if the extension or the first
+/// bytes indicate a gzip or zstd archive. The compressed bytes are counted
+/// for `progress`. Named pipes are read as they are on Windows.
+async fn open(
+    path: &Path,
+    progress: &Progress,
+) -> Result<Box<dyn AsyncRead + Send + Unpin>, Error> {
+    #[cfg(windows)]
+    if is_named_pipe(path) {
+        return open_pipe(path).await;
+    }
+
+    let file = File::open(path)
+        .await
+        .map_err(|e| Error::io(format!("Failed to open {}", path.display()), e))?;
+    // Pipes and devices have no size
+    let total = file.metadata().await.map(|m| m.len()).unwrap_or(0);
+    let read = progress.track(path, total);
+    let mut reader = BufReader::new(CountingReader::new(file, read));
+    let magic = reader
+        .fill_buf()
+        .await
+        .map_err(|e| Error::io(format!("Failed to read {}", path.display()), e))?;
+    let extension = path.extension().and_then(|e| e.to_str());
+
+    Ok(
+        if extension == Some("gz") || magic.starts_with(GZIP_MAGIC) {
+            Box::new(gunzip(reader))
+        } else if extension == Some("zst") || magic.starts_with(ZSTD_MAGIC) {
+            Box::new(ZstdDecoder::new(reader))
+        } else {
+            Box::new(reader)
+        },
+    )
+}
+
+/// Size of the chunks of decompressed gzip input
+const GZIP_CHUNK_SIZE: usize = 64 * 1024;
+/// Number of decompressed chunks read ahead
+const GZIP_BUFFER: usize = 4;
+
+/// Decompress all gzip members of `reader` on a blocking task
+fn gunzip<R: AsyncRead + Send + Unpin + 'static>(reader: R) -> impl AsyncRead + Send + Unpin {
+    let (mut tx, rx) = mpsc::channel(GZIP_BUFFER);
+    let reader = SyncIoBridge::new(reader);
+    tokio::task::spawn_blocking(move || {
+        let mut decoder = MultiGzDecoder::new(std::io::BufReader::new(reader));
+        let mut buffer = vec![0u8; GZIP_CHUNK_SIZE];
+        loop {
+            let chunk = match decoder.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => Ok(Bytes::copy_from_slice(&buffer[..n])),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = chunk.is_err();
+            if block_on(tx.send(chunk)).is_err() || failed {
+                break;
+            }
+        }
+    });
+    StreamReader::new(rx)
 }
 
 /// Open stdin and provide a stream of lines
@@ -80,17 +502,157 @@ pub fn stdin() -> LogStream {
     Box::new(s)
 }
 
-/// Open a serial port and provide a stream of lines
-pub fn serial() -> LogStream {
-    unimplemented!()
+/// Serial port settings parsed from a url like `serial://COM0@115200,8N1`
+#[derive(Debug, PartialEq)]
+struct SerialSettings {
+    path: String,
+    baud_rate: u32,
+    data_bits: DataBits,
+    parity: Parity,
+    stop_bits: StopBits,
+}
+
+const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+/// Serial port of an input like `serial://COM0@115200,8N1`. On Windows
+/// `COM3` works without the prefix.
+pub fn serial_port(input: &str) -> Option<&str> {
+    match input.get(..9) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("serial://") => Some(&input[9..]),
+        _ if cfg!(windows) && is_com_port(input) => Some(input),
+        _ => None,
+    }
+}
+
+/// True for Windows port names like COM3 with optional settings
+fn is_com_port(input: &str) -> bool {
+    let port = input.split_once('@').map_or(input, |(port, _)| port);
+    port.get(..3).is_some_and(|p| p.eq_ignore_ascii_case("com"))
+        && port.len() > 3
+        && port[3..].bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Parse the part after `serial://`. The baud rate and framing are optional
+/// and default to 115200 and 8N1.
+fn parse_serial(spec: &str) -> Result<SerialSettings, Error> {
+    let (path, settings) = match spec.rsplit_once('@') {
+        Some((path, settings)) => (path, Some(settings)),
+        None => (spec, None),
+    };
+    if path.is_empty() {
+        return Err(Error::Invalid(format!("Missing serial port in {spec}")));
+    }
+
+    let (baud_rate, framing) = match settings.map(|s| s.split_once(',').unwrap_or((s, "8N1"))) {
+        Some(("", framing)) => (DEFAULT_BAUD_RATE, framing),
+        Some((baud_rate, framing)) => {
+            let baud_rate = baud_rate
+                .parse::<u32>()
+                .ok()
+                .filter(|&b| b > 0)
+                .ok_or_else(|| Error::Invalid(format!("Invalid baud rate {baud_rate}")))?;
+            (baud_rate, framing)
+        }
+        None => (DEFAULT_BAUD_RATE, "8N1"),
+    };
+
+    let invalid_framing =
+        || Error::Invalid(format!("Invalid serial framing {framing}. Use e.g 8N1"));
+    let mut chars = framing.chars();
+    let data_bits = match chars.next() {
+        Some('5') => DataBits::Five,
+        Some('6') => DataBits::Six,
+        Some('7') => DataBits::Seven,
+        Some('8') => DataBits::Eight,
+        _ => return Err(invalid_framing()),
+    };
+    let parity = match chars.next().map(|c| c.to_ascii_uppercase()) {
+        Some('N') => Parity::None,
+        Some('E') => Parity::Even,
+        Some('O') => Parity::Odd,
+        _ => return Err(invalid_framing()),
+    };
+    let stop_bits = match chars.next() {
+        Some('1') => StopBits::One,
+        Some('2') => StopBits::Two,
+        _ => return Err(invalid_framing()),
+    };
+    if chars.next().is_some() {
+        return Err(invalid_framing());
+    }
+
+    Ok(SerialSettings {
+        path: path.to_owned(),
+        baud_rate,
+        data_bits,
+        parity,
+        stop_bits,
+    })
+}
+
+/// Open the serial port of `settings`
+fn open_serial(settings: &SerialSettings) -> Result<SerialStream, Error> {
+    tokio_serial::new(settings.path.as_str(), settings.baud_rate)
+        .data_bits(settings.data_bits)
+        .parity(settings.parity)
+        .stop_bits(settings.stop_bits)
+        .open_native_async()
+        .map_err(|e| Error::io(format!("Failed to open {}", settings.path), e.into()))
+}
+
+/// Open a serial port like `COM0@115200,8N1` and provide a stream of lines.
+/// A disconnect is passed as diagnostic. It's fatal unless the port is
+/// reopened.
+pub fn serial(port: &str, reopen: bool) -> Result<LogStream, Error> {
+    let settings = parse_serial(port)?;
+    let lines = Decoder::framed(LossyLinesCodec::new(), open_serial(&settings)?);
+    let state = (settings, Some(lines), RESPAWN_DELAY);
+    let stream = unfold(state, move |(settings, mut lines, mut delay)| async move {
+        loop {
+            match lines.as_mut() {
+                Some(port) => {
+                    let error = match port.next().await {
+                        Some(Ok(line)) => {
+                            return Some((StreamData::Line(line), (settings, lines, delay)))
+                        }
+                        Some(Err(e)) => e.to_string(),
+                        None => "closed".to_owned(),
+                    };
+                    let (level, action) = if reopen {
+                        (Level::Warn, ", reopening")
+                    } else {
+                        (Level::Fatal, "")
+                    };
+                    let message = format!("Serial port {} {error}{action}", settings.path);
+                    let message = StreamData::Diagnostic(level, message);
+                    return Some((message, (settings, None, delay)));
+                }
+                None if reopen => {
+                    sleep(delay).await;
+                    delay = (delay * 2).min(RESPAWN_DELAY_MAX);
+                    if let Ok(port) = open_serial(&settings) {
+                        let message = format!("Reopened serial port {}", settings.path);
+                        let message = StreamData::Diagnostic(Level::Info, message);
+                        let lines = Some(Decoder::framed(LossyLinesCodec::new(), port));
+                        return Some((message, (settings, lines, RESPAWN_DELAY)));
+                    }
+                }
+                None => return None,
+            }
+        }
+    });
+
+    Ok(Box::new(stream))
 }
 
 #[cfg(target_os = "linux")]
 pub fn can(dev: &str) -> Result<LogStream, Error> {
     let process = dev.to_string();
-    let now = OffsetDateTime::now_local()?;
+    let now = OffsetDateTime::now_local()
+        .map_err(|e| Error::Invalid(format!("Failed to get local time: {e}")))?;
     let format = format_description!("[unix_timestamp].[subsecond]");
-    let stream = tokio_socketcan::CANSocket::open(dev)?
+    let stream = tokio_socketcan::CANSocket::open(dev)
+        .map_err(|e| Error::Invalid(format!("Failed to open {dev}: {e}")))?
         .map_ok(move |s| {
             let data = s
                 .data()
@@ -100,7 +662,7 @@ pub fn can(dev: &str) -> Result<LogStream, Error> {
             let extended = if s.is_extended() { "E" } else { " " };
             let time = now.format(format).ok();
 
-            StreamData::Record(Record {
+            StreamData::Record(Box::new(Record {
                 time: time.to_owned(),
                 message: format!("{} {} ", extended, data.join(" ")),
                 tag: format!("0x{:x}", s.id()),
@@ -117,7 +679,7 @@ pub fn can(dev: &str) -> Result<LogStream, Error> {
                 ),
                 process: process.clone(),
                 ..Default::default()
-            })
+            }))
         })
         .filter_map(|r| async move { r.ok() });
     Ok(Box::new(stream))
@@ -125,14 +687,15 @@ pub fn can(dev: &str) -> Result<LogStream, Error> {
 
 /// Connect to tcp socket and profile a stream of lines
 pub async fn tcp(url: &Url) -> Result<LogStream, Error> {
-    let addrs = url.socket_addrs(|| None)?;
+    let addrs = url
+        .socket_addrs(|| None)
+        .map_err(|e| Error::io(format!("Failed to resolve {url}"), e))?;
     let addr = addrs
         .first()
-        .ok_or_else(|| err_msg("Failed to parse addr"))?;
+        .ok_or_else(|| Error::Invalid("Failed to parse addr".into()))?;
     let tcp = TcpStream::connect(&addr)
         .await
-        .map_err(|e| format_err!("Failed to connect: {}", e))
-        .unwrap();
+        .map_err(|e| Error::io(format!("Failed to connect to {addr}"), e))?;
 
     let stream = Decoder::framed(LossyLinesCodec::new(), tcp)
         .map_ok(StreamData::Line)
@@ -141,17 +704,107 @@ pub async fn tcp(url: &Url) -> Result<LogStream, Error> {
     Ok(Box::new(stream))
 }
 
-pub async fn get_processes_pids(processes: &[String]) -> Vec<String> {
-    let command = Command::new(adb().expect("Failed to find adb"))
+/// Listen on a tcp socket and provide a stream of lines of all connected
+/// clients. Lines are tagged with the peer address.
+pub async fn tcp_listen(url: &Url) -> Result<LogStream, Error> {
+    let addrs = url
+        .socket_addrs(|| None)
+        .map_err(|e| Error::io(format!("Failed to resolve {url}"), e))?;
+    let addr = addrs
+        .first()
+        .ok_or_else(|| Error::Invalid("Failed to parse addr".into()))?;
+    let listener = TcpListener::bind(&addr)
+        .await
+        .map_err(|e| Error::io(format!("Failed to listen on {addr}"), e))?;
+
+    let stream = TcpListenerStream::new(listener)
+        .filter_map(|connection| async move { connection.ok() })
+        .map(|connection| {
+            let peer = connection
+                .peer_addr()
+                .map(|a| a.to_string())
+                .unwrap_or_default();
+            Decoder::framed(LossyLinesCodec::new(), connection)
+                .map_ok(move |line| StreamData::DeviceLine(peer.clone(), line))
+                .filter_map(|x| async move { x.ok() })
+                .boxed()
+        })
+        .flatten_unordered(None);
+
+    Ok(Box::new(stream))
+}
+
+/// Bind a udp socket and provide a stream of lines of the received datagrams
+pub async fn udp(url: &Url) -> Result<LogStream, Error> {
+    let addrs = url
+        .socket_addrs(|| None)
+        .map_err(|e| Error::io(format!("Failed to resolve {url}"), e))?;
+    let addr = addrs
+        .first()
+        .ok_or_else(|| Error::Invalid("Failed to parse addr".into()))?;
+    let socket = UdpSocket::bind(&addr)
+        .await
+        .map_err(|e| Error::io(format!("Failed to bind {addr}"), e))?;
+    Ok(udp_socket(socket))
+}
+
+/// Provide a stream of lines received on `socket`. Each datagram may carry
+/// multiple lines. A failed receive is a warning and delays the next one.
+/// Failures in a row end the stream with a fatal diagnostic.
+fn udp_socket(socket: UdpSocket) -> LogStream {
+    let datagrams = unfold(
+        Some((socket, vec![0u8; UDP_BUFFER_SIZE], 0)),
+        |state| async move {
+            let (socket, mut buffer, errors) = state?;
+            if errors > 0 {
+                sleep(UDP_ERROR_DELAY * errors).await;
+            }
+            match socket.recv(&mut buffer).await {
+                Ok(n) => {
+                    let lines = String::from_utf8_lossy(&buffer[..n])
+                        .lines()
+                        .map(|l| l.trim_end_matches('\r'))
+                        .filter(|l| !l.is_empty())
+                        .map(|l| StreamData::Line(l.to_owned()))
+                        .collect::<Vec<_>>();
+                    Some((lines, Some((socket, buffer, 0))))
+                }
+                Err(e) => {
+                    let message = format!("Failed to receive from udp socket: {e}");
+                    let errors = errors + 1;
+                    if errors < UDP_MAX_ERRORS {
+                        let warning = StreamData::Diagnostic(Level::Warn, message);
+                        Some((vec![warning], Some((socket, buffer, errors))))
+                    } else {
+                        Some((vec![StreamData::Diagnostic(Level::Fatal, message)], None))
+                    }
+                }
+            }
+        },
+    );
+    Box::new(datagrams.map(iter).flatten())
+}
+
+/// Pids of the running processes named `processes` on `device`
+pub async fn get_processes_pids(
+    processes: &[String],
+    device: Option<&str>,
+) -> Result<Vec<String>, Error> {
+    let mut command = adb_command(device)?
         .arg("shell")
         .arg("ps")
         .arg("-Ao")
         .arg("pid,args")
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
+        .kill_on_drop(true)
         .spawn()
-        .expect("Failed to launch adb");
-    let stdout = BufReader::new(command.stdout.unwrap());
+        .map_err(|e| Error::io("Failed to launch adb", e))?;
+    let stdout = command
+        .stdout
+        .take()
+        .ok_or_else(|| Error::Invalid("Missing stdout of adb".into()))?;
+    let stdout = BufReader::new(stdout);
     let future = LinesStream::new(stdout.lines())
         .skip(1)
         .filter_map(|x| async move {
@@ -173,91 +826,759 @@ pub async fn get_processes_pids(processes: &[String]) -> Vec<String> {
             }
         });
 
-    future.collect::<Vec<String>>().await
+    Ok(future.collect::<Vec<String>>().await)
+}
+
+/// Options of adb logcat
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogcatOptions {
+    /// Serials of the devices to follow. Records of multiple devices carry
+    /// the serial.
+    pub devices: Vec<String>,
+    pub buffers: Vec<String>,
+    /// Dump only the most recent lines
+    pub tail: Option<usize>,
+    /// Dump the log and exit
+    pub dump: bool,
+    /// Dump the log of the last boot
+    pub last: bool,
+    /// Restart adb when it exits
+    pub restart: bool,
+    /// Run adb wait-for-device before starting adb logcat
+    pub wait_for_device: bool,
+    /// Start with the records since this time, passed as -T
+    pub since: Option<String>,
+    /// Run one logcat per buffer to tag the records with their buffer.
+    /// Ignored if the additional arguments select the buffers.
+    pub split_buffers: bool,
+    /// Additional arguments of logcat. Flags given here take precedence over
+    /// the ones set by rogcat.
+    pub args: Vec<String>,
 }
 
-/// Start a process and stream it stdout
-pub fn logcat(args: &CliArguments) -> Result<LogStream, Error> {
-    let mut cmd = vec![adb()?.display().to_string()];
+impl Default for LogcatOptions {
+    fn default() -> Self {
+        LogcatOptions {
+            devices: Vec::new(),
+            buffers: DEFAULT_BUFFER.iter().map(|&b| b.to_owned()).collect(),
+            tail: None,
+            dump: false,
+            last: false,
+            restart: true,
+            wait_for_device: true,
+            since: None,
+            split_buffers: false,
+            args: Vec::new(),
+        }
+    }
+}
 
-    if let Some(device) = args.device.as_ref() {
-        cmd.push("-s".into());
-        cmd.push(device.to_owned());
+impl LogcatOptions {
+    /// True if the additional arguments select the buffers
+    pub fn args_select_buffers(&self) -> bool {
+        has_flag(&self.args, &["-b", "--buffer"])
     }
+}
+
+/// Options of logcat that take a value. The value is the next argument
+/// unless it's attached like "-t100" or "--buffer=main".
+const LOGCAT_VALUE_OPTIONS: [&str; 21] = [
+    "-b",
+    "--buffer",
+    "-t",
+    "-T",
+    "-e",
+    "--regex",
+    "-f",
+    "--file",
+    "-m",
+    "--max-count",
+    "-v",
+    "--format",
+    "-n",
+    "--rotate-count",
+    "-r",
+    "--rotate-kbytes",
+    "-G",
+    "--buffer-size",
+    "--pid",
+    "--uid",
+    "--id",
+];
 
-    cmd.push("logcat".into());
-    let mut respawn = args.restart | config_get::<bool>("restart").unwrap_or(true);
+/// Log buffers of logcat
+const LOGCAT_BUFFERS: [&str; 10] = [
+    "main", "system", "radio", "events", "crash", "default", "all", "kernel", "security", "stats",
+];
 
-    if let Some(count) = args.tail {
+/// True if `value` attached to the short option `flag` like "main" of
+/// "-bmain" is a value of the option and not the rest of another argument
+fn is_short_value(flag: &str, value: &str) -> bool {
+    match flag {
+        "-b" => value
+            .split(',')
+            .all(|buffer| LOGCAT_BUFFERS.contains(&buffer)),
+        "-t" | "-T" => value.starts_with(|c: char| c.is_ascii_digit() || c == '\''),
+        _ => false,
+    }
+}
+
+/// Check if `args` contain one of `flags` like "-t", "-t100", "--buffer" or
+/// "--buffer=main". Arguments like "-dump" or "-bin" don't match "-d" or
+/// "-b", and neither do values of other options like the "-d" of "-e -d".
+fn has_flag(args: &[String], flags: &[&str]) -> bool {
+    let mut args = args.iter().map(String::as_str);
+    while let Some(arg) = args.next() {
+        let matches = flags.iter().any(|flag| {
+            arg == *flag
+                || match arg.strip_prefix(flag) {
+                    Some(value) if flag.starts_with("--") => value.starts_with('='),
+                    Some(value) => is_short_value(flag, value),
+                    None => false,
+                }
+        });
+        if matches {
+            return true;
+        }
+        if LOGCAT_VALUE_OPTIONS.contains(&arg) {
+            args.next();
+        }
+    }
+    false
+}
+
+/// Arguments of logcat and whether to respawn it when it exits
+fn logcat_args(options: &LogcatOptions) -> Result<(Vec<String>, bool), Error> {
+    let has = |flags: &[&str]| has_flag(&options.args, flags);
+    let mut cmd = vec!["logcat".to_owned()];
+    let mut respawn = options.restart && !has(&["-d", "-t", "-L", "--last"]);
+
+    if let Some(count) = options.tail.filter(|_| !has(&["-t", "-T"])) {
         cmd.push("-t".into());
         cmd.push(count.to_string());
         respawn = false;
     };
 
-    if args.dump {
+    if let Some(since) = options
+        .since
+        .as_deref()
+        .filter(|_| options.tail.is_none() && !has(&["-t", "-T"]))
+    {
+        let since = since_now(since)?;
+        cmd.push("-T".into());
+        cmd.push(since.to_string());
+    }
+
+    if options.dump && !has(&["-d"]) {
         cmd.push("-d".into());
         respawn = false;
     }
 
-    if args.last {
+    if options.last && !has(&["-L", "--last"]) {
         cmd.push("--last".into());
         respawn = false;
     }
 
-    for buffer in args
-        .buffer
-        .as_ref()
-        .map(|v| v.to_owned())
-        .or_else(|| config_get("buffer"))
-        .unwrap_or_else(|| DEFAULT_BUFFER.iter().map(|&s| s.to_string()).collect())
+    if !has(&["-b", "--buffer"]) {
+        for buffer in &options.buffers {
+            cmd.push("-b".into());
+            cmd.push(buffer.to_owned());
+        }
+    }
+
+    cmd.extend(options.args.iter().cloned());
+    Ok((cmd, respawn))
+}
+
+/// Start adb logcat and stream its stdout. The processes are registered in
+/// `children`.
+pub fn logcat(options: &LogcatOptions, children: &Children) -> Result<LogStream, Error> {
+    let adb = adb()?.display().to_string();
+
+    // One logcat per buffer unless the arguments select the buffers
+    let split =
+        options.split_buffers && !options.buffers.is_empty() && !options.args_select_buffers();
+    let mut commands = Vec::new();
+    if split {
+        for buffer in &options.buffers {
+            let options = LogcatOptions {
+                buffers: vec![buffer.clone()],
+                ..options.clone()
+            };
+            commands.push((Some(buffer.clone()), logcat_args(&options)?));
+        }
+    } else {
+        commands.push((None, logcat_args(options)?));
+    }
+
+    let adb_process = |device: Option<&String>, (cmd, respawn): &(Vec<String>, bool)| {
+        let adb = match device {
+            Some(device) => vec![adb.clone(), "-s".into(), device.clone()],
+            None => vec![adb.clone()],
+        };
+        Process {
+            adb: Some(adb.clone()),
+            wait_for_device: options.wait_for_device,
+            children: children.clone(),
+            ..Process::with_cmd([adb, cmd.clone()].concat(), *respawn)
+        }
+    };
+
+    // One adb per device and buffer. Lines are tagged with the device serial
+    // if there are multiple devices and with the buffer if split.
+    let devices = match options.devices.as_slice() {
+        [] => vec![None],
+        devices => devices.iter().map(Some).collect(),
+    };
+    let mut processes = Vec::new();
+    for device in devices {
+        for (buffer, args) in &commands {
+            processes.push(Process {
+                device: device.filter(|_| options.devices.len() > 1).cloned(),
+                buffer: buffer.clone(),
+                ..adb_process(device, args)
+            });
+        }
+    }
+    Ok(match processes.len() {
+        1 => Box::new(processes.remove(0)),
+        _ => Box::new(select_all(processes)),
+    })
+}
+
+/// Check if a line of adb on stderr is about the connection to the device
+fn is_adb_error(line: &str) -> bool {
+    const ADB_ERRORS: [&str; 5] = [
+        "error: ",
+        "adb: error: ",
+        "- waiting for device -",
+        "* daemon ",
+        "* cannot start server",
+    ];
+    ADB_ERRORS.iter().any(|e| line.starts_with(e))
+}
+
+/// Delay before the next respawn of a process that ran for `uptime`. The
+/// delay doubles with every respawn and is reset if the process ran longer
+/// than the maximum delay.
+fn respawn_delay(delay: Duration, uptime: Duration) -> (Duration, Duration) {
+    let delay = if uptime > RESPAWN_DELAY_MAX {
+        RESPAWN_DELAY
+    } else {
+        delay
+    };
+    (delay, (delay * 2).min(RESPAWN_DELAY_MAX))
+}
+
+/// Send `signal` to the process group led by `pid`
+#[cfg(unix)]
+fn signal_group(pid: u32, signal: libc::c_int) {
+    // Negative pids address the group
+    // SAFETY: kill takes no pointers and has no preconditions. A group that
+    // exited already is reported by its return value which is ignored.
+    unsafe { libc::kill(-(pid as libc::pid_t), signal) };
+}
+
+/// True if a process of the group led by `pid` is running
+#[cfg(unix)]
+fn group_alive(pid: u32) -> bool {
+    // SAFETY: kill takes no pointers and signal 0 only checks whether the
+    // group exists without signalling it
+    unsafe { libc::kill(-(pid as libc::pid_t), 0) == 0 }
+}
+
+/// Registry of the children spawned by the process sources of a pipeline.
+/// On unix each child leads a process group of its own. Clones share the
+/// registry.
+#[derive(Clone, Debug, Default)]
+pub struct Children(Arc<ChildrenState>);
+
+#[derive(Debug, Default)]
+struct ChildrenState {
+    /// Ids of the spawned children that are not reaped yet
+    pids: Mutex<BTreeSet<u32>>,
+    /// Set by [`Children::terminate`]. Children are not respawned afterwards.
+    shutdown: AtomicBool,
+}
+
+impl Children {
+    fn insert(&self, pid: u32) {
+        self.0.pids.lock().expect("Poisoned lock").insert(pid);
+    }
+
+    fn remove(&self, pid: u32) {
+        self.0.pids.lock().expect("Poisoned lock").remove(&pid);
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.0.shutdown.load(Ordering::Relaxed)
+    }
+
+    /// Terminate the children and wait until they are reaped. Children that
+    /// don't exit within the grace period are killed. The sources don't
+    /// respawn their process afterwards. Call before exiting because the
+    /// children don't see the signals of the terminal.
+    pub async fn terminate(&self) {
+        self.0.shutdown.store(true, Ordering::Relaxed);
+        let children = self.0.pids.lock().expect("Poisoned lock").clone();
+        if children.is_empty() {
+            return;
+        }
+        #[cfg(unix)]
+        for pid in &children {
+            signal_group(*pid, libc::SIGTERM);
+        }
+        // The sources reap their children once the output ends
+        let deadline = Instant::now() + TERMINATE_GRACE * 2;
+        while !self.0.pids.lock().expect("Poisoned lock").is_empty() && Instant::now() < deadline {
+            sleep(Duration::from_millis(20)).await;
+        }
+        #[cfg(unix)]
+        for pid in self.0.pids.lock().expect("Poisoned lock").iter() {
+            signal_group(*pid, libc::SIGKILL);
+        }
+    }
+}
+
+/// Terminate `child` and wait for it to exit. On unix the process group of
+/// the child gets SIGTERM and SIGKILL if a process of the group outlives the
+/// grace period. Elsewhere the child is killed.
+async fn reap(mut child: Child, children: Children) {
+    let Some(pid) = child.id() else {
+        return;
+    };
+    #[cfg(unix)]
     {
-        cmd.push("-b".into());
-        cmd.push(buffer.to_owned());
+        let deadline = Instant::now() + TERMINATE_GRACE;
+        signal_group(pid, libc::SIGTERM);
+        let exited = timeout(TERMINATE_GRACE, child.wait()).await.is_ok();
+        // Descendants like the helpers of adb may outlive the child
+        while exited && group_alive(pid) && Instant::now() < deadline {
+            sleep(Duration::from_millis(20)).await;
+        }
+        if group_alive(pid) {
+            signal_group(pid, libc::SIGKILL);
+        }
     }
+    child.kill().await.ok();
+    children.remove(pid);
+}
+
+/// Child of a process source registered in `Children`. Dropped children are
+/// reaped in the background or killed if there is no runtime.
+struct SpawnedChild(Option<Child>, Children);
 
-    Ok(Box::new(Process::with_cmd(cmd, respawn)))
+impl Drop for SpawnedChild {
+    fn drop(&mut self) {
+        let Some(child) = self.0.take() else {
+            return;
+        };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(reap(child, self.1.clone()));
+        }
+    }
 }
 
-/// Start a process and stream it stdout
-pub fn process(cmd: String, respawn: bool) -> Result<LogStream, Error> {
+/// Start a process and stream it stdout. The process is registered in
+/// `children`.
+pub fn process(cmd: String, respawn: bool, children: &Children) -> Result<LogStream, Error> {
     let cmd = cmd.split_whitespace().map(ToOwned::to_owned).collect();
-    Ok(Box::new(Process::with_cmd(cmd, respawn)))
+    Ok(Box::new(Process {
+        children: children.clone(),
+        ..Process::with_cmd(cmd, respawn)
+    }))
 }
 
 impl Process {
     fn with_cmd(cmd: Vec<String>, respawn: bool) -> Process {
         Process {
             cmd,
+            device: None,
+            buffer: None,
             respawn,
+            adb: None,
+            wait_for_device: false,
             child: None,
             stream: None,
+            pending: None,
+            delay: RESPAWN_DELAY,
+            spawned: Instant::now(),
+            adb_error: Arc::new(Mutex::new(None)),
+            checkpoint: None,
+            at_checkpoint: Vec::new(),
+            resumed: None,
+            queued: None,
+            children: Children::default(),
         }
     }
 
-    fn spawn(&mut self, ctx: &mut Context<'_>) -> Poll<Option<StreamData>> {
-        let mut child = Command::new(self.cmd[0].clone())
-            .args(&self.cmd[1..])
+    /// Spawn the process. A failed spawn is passed as diagnostic. It's fatal
+    /// unless the process is respawned.
+    fn spawn(&mut self) {
+        self.spawned = Instant::now();
+        // Respawned logcats continue at the last seen timestamp
+        let cmd = match (&self.adb, self.checkpoint) {
+            (Some(adb), Some(checkpoint)) => {
+                self.resumed = Some(checkpoint);
+                resume_args(&self.cmd, adb.len() + 1, checkpoint)
+            }
+            _ => self.cmd.clone(),
+        };
+        let mut command = std::process::Command::new(&cmd[0]);
+        command
+            .args(&cmd[1..])
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .kill_on_drop(true)
-            .spawn()
-            .map_err(|e| {
-                eprintln!("Failed to spawn process ({:?}): {e}", self.cmd);
-                std::process::exit(1);
-            })
-            .unwrap();
+            .stderr(Stdio::piped());
+        // A process group of its own lets rogcat terminate the descendants
+        // of the child as well
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let spawned = Command::from(command).kill_on_drop(true).spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                let level = if self.respawn {
+                    Level::Error
+                } else {
+                    Level::Fatal
+                };
+                let message = format!("Failed to spawn {}: {e}", cmd.join(" "));
+                self.stream = Some(Box::pin(iter([StreamData::Diagnostic(level, message)])));
+                return;
+            }
+        };
 
         let stdout = BufReader::new(child.stdout.take().unwrap());
         let stderr = BufReader::new(child.stderr.take().unwrap());
-        self.child = Some(child);
+        if let Some(pid) = child.id() {
+            self.children.insert(pid);
+        }
+        self.child = Some(SpawnedChild(Some(child), self.children.clone()));
+
+        let device = self.device.clone();
+        let buffer = self.buffer.clone();
+        let line = move |line| match (&device, &buffer) {
+            (device, Some(buffer)) => StreamData::BufferLine {
+                device: device.clone(),
+                buffer: buffer.clone(),
+                line,
+            },
+            (Some(device), None) => StreamData::DeviceLine(device.clone(), line),
+            (None, None) => StreamData::Line(line),
+        };
+        let stdout = lossy_lines(stdout).map(line.clone());
+        let error_line = move |l| match line(l) {
+            StreamData::Line(l) => StreamData::ErrorLine(l),
+            data => data,
+        };
+        // Connection errors of adb are passed once as diagnostic instead of
+        // records
+        let adb = self.adb.is_some();
+        let adb_error = self.adb_error.clone();
+        let stderr = lossy_lines(stderr).filter_map(move |l| {
+            if adb && is_adb_error(&l) {
+                let mut last = adb_error.lock().expect("Poisoned lock");
+                if last.as_ref() != Some(&l) {
+                    *last = Some(l.clone());
+                    ready(Some(StreamData::Diagnostic(Level::Warn, l)))
+                } else {
+                    ready(None)
+                }
+            } else {
+                ready(Some(error_line(l)))
+            }
+        });
+
+        self.stream = Some(Box::pin(select(stdout, stderr)));
+    }
+
+    /// Wait before the next spawn. The previous child is reaped first.
+    /// Respawns are delayed and adb processes wait for the device to become
+    /// available.
+    fn wait(&mut self) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let child = self.child.take().and_then(|mut child| child.0.take());
+        let delay = if self.stream.is_some() {
+            let (delay, next) = respawn_delay(self.delay, self.spawned.elapsed());
+            self.delay = next;
+            delay
+        } else {
+            Duration::ZERO
+        };
+        let wait_for_device = self.adb.clone().filter(|_| self.wait_for_device);
+        let children = self.children.clone();
+        Box::pin(async move {
+            if let Some(child) = child {
+                reap(child, children).await;
+            }
+            sleep(delay).await;
+            if let Some(adb) = wait_for_device {
+                Command::new(&adb[0])
+                    .args(&adb[1..])
+                    .arg("wait-for-device")
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .kill_on_drop(true)
+                    .status()
+                    .await
+                    .ok();
+            }
+        })
+    }
+}
+
+impl Process {
+    /// Track the timestamp of the lines of adb logcat. Returns false for
+    /// lines at the checkpoint that a respawned logcat prints again. If the
+    /// first line after a respawn is older than the checkpoint the device
+    /// clock changed e.g. across a reboot. The lines are passed anyway and
+    /// the checkpoint restarts at the new clock.
+    fn track(&mut self, data: &StreamData) -> bool {
+        if self.adb.is_none() {
+            return true;
+        }
+        let line = match data {
+            StreamData::Line(line)
+            | StreamData::DeviceLine(_, line)
+            | StreamData::BufferLine { line, .. } => line,
+            _ => return true,
+        };
+        let Some(timestamp) = line_timestamp(line) else {
+            return true;
+        };
+        match self.resumed {
+            // Passed by the former logcat
+            Some(resumed) if timestamp == resumed && self.at_checkpoint.contains(line) => {
+                return false;
+            }
+            Some(resumed) if timestamp != resumed => {
+                self.resumed = None;
+                if timestamp.is_before(resumed) {
+                    let message = format!(
+                        "Device clock changed: first record after restart at {timestamp} is older than {resumed}. Records may be duplicated"
+                    );
+                    self.queued = Some(StreamData::Diagnostic(Level::Warn, message));
+                    self.checkpoint = None;
+                }
+            }
+            _ => (),
+        }
+        match self.checkpoint {
+            Some(checkpoint) if timestamp.is_before(checkpoint) => (),
+            Some(checkpoint) if timestamp == checkpoint => self.at_checkpoint.push(line.clone()),
+            _ => {
+                self.checkpoint = Some(timestamp);
+                self.at_checkpoint = vec![line.clone()];
+            }
+        }
+        true
+    }
+}
+
+/// Timestamp at the start of a line of logcat with or without year
+fn line_timestamp(line: &str) -> Option<Timestamp> {
+    let line = line.trim_start();
+    let date = line.find(' ')?;
+    let time = line[date..].trim_start();
+    let end = line.len() - time.len() + time.find(char::is_whitespace).unwrap_or(time.len());
+    Timestamp::parse(&line[..end])
+}
+
+/// Arguments of a respawned logcat that starts at `checkpoint`. Arguments
+/// of `-T` after the first `skip` arguments (adb, device and "logcat") are
+/// replaced.
+fn resume_args(cmd: &[String], skip: usize, checkpoint: Timestamp) -> Vec<String> {
+    let skip = skip.min(cmd.len());
+    let mut args = cmd[..skip].to_vec();
+    let mut rest = cmd[skip..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.strip_prefix("-T") {
+            Some("") => {
+                rest.next();
+            }
+            Some(_) => (),
+            None => args.push(arg.clone()),
+        }
+    }
+    args.push("-T".into());
+    args.push(checkpoint.to_string());
+    args
+}
+
+/// Run `cmd` every `interval` and pass only the lines that weren't printed
+/// by the former runs, e.g. of `adb shell dmesg` printing the whole kernel
+/// buffer. The last `window` lines of a run are remembered. The runs are
+/// registered in `children`.
+pub fn poll(cmd: String, interval: Duration, window: usize, children: &Children) -> LogStream {
+    let cmd = cmd.split_whitespace().map(ToOwned::to_owned).collect();
+    Box::new(Polled::new(cmd, interval, window, children.clone()))
+}
+
+fn line_hash(line: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Length of the longest proper prefix of `pattern[..=i]` that is a suffix
+/// of it for each `i`
+fn prefix_function(pattern: &[u64]) -> Vec<usize> {
+    let mut failure = vec![0; pattern.len()];
+    let mut k = 0;
+    for i in 1..pattern.len() {
+        while k > 0 && pattern[i] != pattern[k] {
+            k = failure[k - 1];
+        }
+        if pattern[i] == pattern[k] {
+            k += 1;
+        }
+        failure[i] = k;
+    }
+    failure
+}
+
+/// Index of the first line of `run` after the lines of the former run that
+/// ended with `last`. Either `run` contains `last` or older lines were
+/// dropped meanwhile and `run` starts with the end of `last`. `None` if they
+/// don't overlap.
+fn overlap_end(last: &[u64], run: &[u64]) -> Option<usize> {
+    if last.is_empty() {
+        return Some(0);
+    }
+    // First occurrence of `last` in `run`
+    let failure = prefix_function(last);
+    let mut matched = 0;
+    for (i, hash) in run.iter().enumerate() {
+        while matched > 0 && last[matched] != *hash {
+            matched = failure[matched - 1];
+        }
+        if last[matched] == *hash {
+            matched += 1;
+        }
+        if matched == last.len() {
+            return Some(i + 1);
+        }
+    }
+    // Longest suffix of `last` that `run` starts with
+    let failure = prefix_function(run);
+    let mut matched = 0;
+    for hash in last {
+        while matched > 0 && (matched == run.len() || run[matched] != *hash) {
+            matched = failure[matched - 1];
+        }
+        if matched < run.len() && run[matched] == *hash {
+            matched += 1;
+        }
+    }
+    (matched > 0).then_some(matched)
+}
+
+/// Command that is run again after it terminated. The output of a run is
+/// expected to overlap with the end of the former run. The lines after the
+/// overlap are passed once the run terminated, including lines repeating
+/// older ones. If a run doesn't overlap, the output changed completely, e.g.
+/// after a reboot, and all lines are passed with a diagnostic.
+struct Polled {
+    cmd: Vec<String>,
+    interval: Duration,
+    /// Number of lines remembered of a run
+    window: usize,
+    process: Option<Process>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    /// Hashes of the last lines of the former run
+    last: Vec<u64>,
+    /// Lines of the current run
+    run: Vec<String>,
+    queued: VecDeque<StreamData>,
+    children: Children,
+}
 
-        let stdout = lossy_lines(stdout).map(StreamData::Line);
-        let stderr = lossy_lines(stderr).map(StreamData::Line);
+impl Polled {
+    fn new(cmd: Vec<String>, interval: Duration, window: usize, children: Children) -> Polled {
+        let mut polled = Polled {
+            process: None,
+            cmd,
+            interval,
+            window: window.max(1),
+            sleep: None,
+            last: Vec::new(),
+            run: Vec::new(),
+            queued: VecDeque::new(),
+            children,
+        };
+        polled.process = Some(polled.process());
+        polled
+    }
 
-        let mut stream = select(stdout, stderr);
-        let poll = stream.poll_next_unpin(ctx);
-        self.stream = Some(Box::pin(stream));
-        poll
+    /// Process of the next run
+    fn process(&self) -> Process {
+        Process {
+            children: self.children.clone(),
+            ..Process::with_cmd(self.cmd.clone(), false)
+        }
+    }
+
+    /// The current run terminated. Queue the lines after the overlap with
+    /// the former run. Runs without output are ignored.
+    fn finish(&mut self) {
+        let run = std::mem::take(&mut self.run);
+        if run.is_empty() {
+            return;
+        }
+        let hashes = run.iter().map(|line| line_hash(line)).collect::<Vec<_>>();
+        let start = match overlap_end(&self.last, &hashes) {
+            Some(start) => start,
+            None => {
+                let message = format!(
+                    "Output of {} changed completely. Passing all lines again",
+                    self.cmd.join(" ")
+                );
+                self.queued
+                    .push_back(StreamData::Diagnostic(Level::Warn, message));
+                0
+            }
+        };
+        self.last = hashes[hashes.len().saturating_sub(self.window)..].to_vec();
+        self.queued
+            .extend(run.into_iter().skip(start).map(StreamData::Line));
+    }
+}
+
+impl Stream for Polled {
+    type Item = StreamData;
+
+    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Option<StreamData>> {
+        loop {
+            if let Some(queued) = self.queued.pop_front() {
+                return Poll::Ready(Some(queued));
+            }
+
+            if let Some(sleep) = self.sleep.as_mut() {
+                ready!(sleep.as_mut().poll(ctx));
+                self.sleep = None;
+                if self.children.is_shutdown() {
+                    return Poll::Ready(None);
+                }
+                self.process = Some(self.process());
+            }
+
+            let Some(process) = self.process.as_mut() else {
+                return Poll::Ready(None);
+            };
+            match ready!(process.poll_next_unpin(ctx)) {
+                Some(StreamData::Line(line) | StreamData::ErrorLine(line)) => self.run.push(line),
+                // Commands that fail to spawn aren't run again
+                Some(data @ StreamData::Diagnostic(Level::Fatal, _)) => {
+                    self.process = None;
+                    return Poll::Ready(Some(data));
+                }
+                Some(data) => return Poll::Ready(Some(data)),
+                None => {
+                    self.process = None;
+                    self.finish();
+                    self.sleep = Some(Box::pin(sleep(self.interval)));
+                }
+            }
+        }
     }
 }
 
@@ -265,13 +1586,794 @@ impl Stream for Process {
     type Item = StreamData;
 
     fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Option<StreamData>> {
-        if let Some(ref mut inner) = self.stream {
-            match inner.poll_next_unpin(ctx) {
-                Poll::Ready(None) if self.respawn => self.spawn(ctx),
-                poll => poll,
+        loop {
+            if let Some(queued) = self.queued.take() {
+                return Poll::Ready(Some(queued));
             }
-        } else {
-            self.spawn(ctx)
+
+            if let Some(pending) = self.pending.as_mut() {
+                ready!(pending.as_mut().poll(ctx));
+                self.pending = None;
+                if self.children.is_shutdown() {
+                    return Poll::Ready(None);
+                }
+                self.spawn();
+            }
+
+            if let Some(inner) = self.stream.as_mut() {
+                match inner.poll_next_unpin(ctx) {
+                    Poll::Ready(None) if self.respawn && !self.children.is_shutdown() => (),
+                    Poll::Ready(None) => {
+                        self.child = None;
+                        return Poll::Ready(None);
+                    }
+                    Poll::Ready(Some(data)) => {
+                        if !self.track(&data) {
+                            continue;
+                        }
+                        match self.queued.take() {
+                            Some(notice) => {
+                                self.queued = Some(data);
+                                return Poll::Ready(Some(notice));
+                            }
+                            None => return Poll::Ready(Some(data)),
+                        }
+                    }
+                    poll => return poll,
+                }
+            }
+            self.pending = Some(self.wait());
         }
     }
 }
+
+#[test]
+fn adb_errors() {
+    assert!(is_adb_error("error: no devices/emulators found"));
+    assert!(is_adb_error("adb: error: device 'abc' not found"));
+    assert!(is_adb_error("- waiting for device -"));
+    assert!(!is_adb_error(
+        "01-10 23:02:59.028  6496  6497 E Tag: error: x"
+    ));
+}
+
+#[test]
+fn respawn_backoff() {
+    let mut delay = RESPAWN_DELAY;
+    let mut delays = vec![];
+    for _ in 0..7 {
+        let (current, next) = respawn_delay(delay, Duration::ZERO);
+        delays.push(current.as_millis());
+        delay = next;
+    }
+    assert_eq!(delays, [500, 1000, 2000, 4000, 8000, 10000, 10000]);
+    // Reset after a long running process
+    let (current, next) = respawn_delay(delay, Duration::from_secs(60));
+    assert_eq!((current, next), (RESPAWN_DELAY, RESPAWN_DELAY * 2));
+}
+
+#[test]
+fn logcat_argv() {
+    let args = |options: LogcatOptions| logcat_args(&options).unwrap();
+    let extra = |args: &[&str]| args.iter().map(|&a| a.to_owned()).collect::<Vec<_>>();
+
+    let (cmd, respawn) = args(LogcatOptions {
+        buffers: vec!["main".into()],
+        tail: Some(10),
+        ..Default::default()
+    });
+    assert_eq!(cmd, ["logcat", "-t", "10", "-b", "main"]);
+    assert!(!respawn);
+
+    let (cmd, respawn) = args(LogcatOptions {
+        buffers: vec!["main".into()],
+        since: Some("01-10 23:02:59.028".into()),
+        ..Default::default()
+    });
+    assert_eq!(cmd, ["logcat", "-T", "01-10 23:02:59.028", "-b", "main"]);
+    assert!(respawn);
+
+    // Extra arguments take precedence
+    let (cmd, respawn) = args(LogcatOptions {
+        buffers: vec!["main".into()],
+        tail: Some(10),
+        since: Some("5m".into()),
+        last: true,
+        args: extra(&["-T100", "--buffer=crash", "-L", "-v", "uid"]),
+        ..Default::default()
+    });
+    assert_eq!(
+        cmd,
+        ["logcat", "-T100", "--buffer=crash", "-L", "-v", "uid"]
+    );
+    assert!(!respawn);
+
+    let (_, respawn) = args(LogcatOptions {
+        args: extra(&["-d"]),
+        ..Default::default()
+    });
+    assert!(!respawn);
+
+    assert!(logcat_args(&LogcatOptions {
+        since: Some("yesterday".into()),
+        ..Default::default()
+    })
+    .is_err());
+}
+
+#[test]
+fn logcat_arg_flags() {
+    let has = |args: &[&str], flags: &[&str]| {
+        has_flag(
+            &args.iter().map(|&a| a.to_owned()).collect::<Vec<_>>(),
+            flags,
+        )
+    };
+    assert!(has(&["-d"], &["-d"]));
+    assert!(has(&["-b", "main"], &["-b", "--buffer"]));
+    assert!(has(&["-bmain,crash"], &["-b", "--buffer"]));
+    assert!(has(&["--buffer=crash"], &["-b", "--buffer"]));
+    assert!(has(&["-t100"], &["-t", "-T"]));
+    assert!(has(&["-v", "uid", "-L"], &["-L", "--last"]));
+
+    // Other arguments starting like a flag
+    assert!(!has(&["-dump"], &["-d"]));
+    assert!(!has(&["-bin"], &["-b", "--buffer"]));
+    assert!(!has(&["--buffers"], &["-b", "--buffer"]));
+    assert!(!has(&["--lastx"], &["-L", "--last"]));
+    assert!(!has(&["-tag"], &["-t", "-T"]));
+    // Values of other options
+    assert!(!has(&["-e", "-d"], &["-d"]));
+    assert!(!has(&["--regex", "-b"], &["-b", "--buffer"]));
+}
+
+#[test]
+fn logcat_resume_argv() {
+    let cmd = |args: &[&str]| args.iter().map(|&a| a.to_owned()).collect::<Vec<_>>();
+    let checkpoint = Timestamp::parse("01-10 23:02:59.028").unwrap();
+
+    let args = resume_args(&cmd(&["adb", "logcat", "-b", "main"]), 2, checkpoint);
+    assert_eq!(
+        args,
+        ["adb", "logcat", "-b", "main", "-T", "01-10 23:02:59.028"]
+    );
+
+    // A timestamp of --since is replaced. Device selectors are kept.
+    let since = cmd(&[
+        "adb",
+        "-s",
+        "-T1",
+        "logcat",
+        "-T",
+        "01-10 20:00:00.000",
+        "-v",
+    ]);
+    let args = resume_args(&since, 4, checkpoint);
+    assert_eq!(
+        args,
+        [
+            "adb",
+            "-s",
+            "-T1",
+            "logcat",
+            "-v",
+            "-T",
+            "01-10 23:02:59.028"
+        ]
+    );
+    let args = resume_args(&cmd(&["adb", "logcat", "-T100"]), 2, checkpoint);
+    assert_eq!(args, ["adb", "logcat", "-T", "01-10 23:02:59.028"]);
+
+    assert_eq!(
+        line_timestamp("01-10 23:02:59.028  6496  6497 I Tag: x"),
+        Some(checkpoint)
+    );
+    assert_eq!(
+        line_timestamp("2024-01-10 23:02:59.028123  6496  6497 I Tag: x"),
+        Some(checkpoint)
+    );
+    assert_eq!(line_timestamp("--------- beginning of main"), None);
+    assert_eq!(line_timestamp("1704924179.028  6496  6497 I Tag: x"), None);
+}
+
+#[test]
+fn serial_settings() {
+    let settings = parse_serial("/dev/ttyUSB0@9600,7E2").unwrap();
+    assert_eq!(settings.path, "/dev/ttyUSB0");
+    assert_eq!(settings.baud_rate, 9600);
+    assert_eq!(settings.data_bits, DataBits::Seven);
+    assert_eq!(settings.parity, Parity::Even);
+    assert_eq!(settings.stop_bits, StopBits::Two);
+
+    let settings = parse_serial("COM0").unwrap();
+    assert_eq!(settings.path, "COM0");
+    assert_eq!(settings.baud_rate, DEFAULT_BAUD_RATE);
+    assert_eq!(settings.data_bits, DataBits::Eight);
+    assert_eq!(settings.parity, Parity::None);
+    assert_eq!(settings.stop_bits, StopBits::One);
+
+    assert_eq!(parse_serial("COM0@57600").unwrap().baud_rate, 57600);
+    assert!(parse_serial("COM0@fast,8N1").is_err());
+    assert!(parse_serial("COM0@115200,9N1").is_err());
+    assert!(parse_serial("COM0@115200,8X1").is_err());
+    assert!(parse_serial("COM0@115200,8N1X").is_err());
+    assert!(parse_serial("@115200,8N1").is_err());
+
+    // Lowercase framing and missing baud rates
+    let settings = parse_serial("/dev/ttyACM0@,7o2").unwrap();
+    assert_eq!(settings.baud_rate, DEFAULT_BAUD_RATE);
+    assert_eq!(settings.data_bits, DataBits::Seven);
+    assert_eq!(settings.parity, Parity::Odd);
+    assert_eq!(settings.stop_bits, StopBits::Two);
+    assert_eq!(parse_serial("COM0@").unwrap().baud_rate, DEFAULT_BAUD_RATE);
+    assert_eq!(
+        parse_serial("COM0@9600,5n1").unwrap().data_bits,
+        DataBits::Five
+    );
+    assert!(parse_serial("COM0@0,8N1").is_err());
+    assert!(parse_serial("COM0@-9600").is_err());
+    assert!(parse_serial("COM0@115200,").is_err());
+    assert!(parse_serial("COM0@115200,8N").is_err());
+    assert!(parse_serial("COM0@115200,8P1").is_err());
+    assert!(parse_serial("COM0@115200,8N3").is_err());
+    assert!(parse_serial("COM0@115200,4N1").is_err());
+}
+
+#[test]
+fn serial_ports() {
+    assert_eq!(
+        serial_port("serial://COM0@115200,8N1"),
+        Some("COM0@115200,8N1")
+    );
+    assert_eq!(serial_port("SERIAL:///dev/ttyUSB0"), Some("/dev/ttyUSB0"));
+    assert_eq!(serial_port("serial:/dev/ttyUSB0"), None);
+    assert_eq!(serial_port("log.txt"), None);
+    assert_eq!(serial_port("serial"), None);
+
+    assert!(is_com_port("COM3"));
+    assert!(is_com_port("com12@9600,7E1"));
+    assert!(!is_com_port("COM"));
+    assert!(!is_com_port("COMx"));
+    assert!(!is_com_port("command.log"));
+    assert_eq!(serial_port("COM3").is_some(), cfg!(windows));
+}
+
+#[tokio::test]
+async fn udp_datagrams() {
+    use crate::parser::Parser;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let stream = udp_socket(socket);
+
+    let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let line = "01-01 12:00:00.000  100  101 I tag: message";
+    sender
+        .send_to(format!("{}\n{}\n", line, line).as_bytes(), addr)
+        .await
+        .unwrap();
+    sender
+        .send_to(&[0xff, 0xfe, 0x00, 0x80], addr)
+        .await
+        .unwrap();
+    sender
+        .send_to(format!("{}\r\n{}\n{}", line, line, line).as_bytes(), addr)
+        .await
+        .unwrap();
+
+    let mut parser = Parser::default();
+    let records = Box::into_pin(stream)
+        .take(6)
+        .filter_map(|data| {
+            ready(match data {
+                StreamData::Line(line) => parser.parse(&line),
+                _ => None,
+            })
+        })
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(records.len(), 6);
+    assert_eq!(records.iter().filter(|r| r.tag == "tag").count(), 5);
+}
+
+#[test]
+fn replay_delays() {
+    let time = |t| Timestamp::parse(t);
+    let a = time("01-10 23:59:59.000");
+    let b = time("01-11 00:00:01.500");
+    assert_eq!(replay_delay(a, b, 1.0), Duration::from_millis(2500));
+    assert_eq!(replay_delay(a, b, 2.0), Duration::from_millis(1250));
+    assert_eq!(replay_delay(a, b, 0.5), Duration::from_millis(5000));
+    // Records out of order follow immediately
+    assert_eq!(replay_delay(b, a, 1.0), Duration::ZERO);
+    // Records without timestamp
+    assert_eq!(replay_delay(None, b, 1.0), REPLAY_DELAY);
+    assert_eq!(replay_delay(a, None, 2.0), REPLAY_DELAY / 2);
+}
+
+#[tokio::test]
+async fn replay_session() {
+    let dir = std::env::temp_dir().join(format!("rogcat-replay-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let capture = dir.join("capture.log");
+    let session = Session {
+        version: "0.4.7".to_owned(),
+        start: "2024-01-10T23:56:50+01:00".to_owned(),
+        ..Default::default()
+    };
+    let lines = [
+        "01-10 23:56:50.100  1234  1235 I Tag: first",
+        "01-10 23:56:50.300  1234  1235 I Tag: second",
+    ];
+    std::fs::write(
+        &capture,
+        format!("{}\n{}\n{}\n", session.to_line(), lines[0], lines[1]),
+    )
+    .unwrap();
+
+    let start = Instant::now();
+    let data = Box::into_pin(replay(capture, Some(2.0)).await.unwrap())
+        .collect::<Vec<_>>()
+        .await;
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(
+        matches!(&data[0], StreamData::Diagnostic(Level::Info, m) if *m == session.to_string())
+    );
+    assert!(matches!(&data[1], StreamData::Line(l) if l == lines[0]));
+    assert!(matches!(&data[2], StreamData::Line(l) if l == lines[1]));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn utf16_file() {
+    let dir = std::env::temp_dir().join(format!("rogcat-utf16-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("powershell.log");
+    let text = "01-10 23:56:50.100  1234  1235 I Tag: first\r\n\
+                01-10 23:56:50.300  1234  1235 I Tag: 日志\r\n";
+    let bytes = [0xff, 0xfe]
+        .into_iter()
+        .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+        .collect::<Vec<_>>();
+    std::fs::write(&path, bytes).unwrap();
+
+    let data = Box::into_pin(file(path).await.unwrap())
+        .collect::<Vec<_>>()
+        .await;
+    let lines = text.lines().collect::<Vec<_>>();
+    assert_eq!(data.len(), 2);
+    assert!(matches!(&data[0], StreamData::Line(l) if l == lines[0]));
+    assert!(matches!(&data[1], StreamData::Line(l) if l == lines[1]));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn named_pipes() {
+    assert!(is_named_pipe(Path::new(r"\\.\pipe\mylogs")));
+    assert!(is_named_pipe(Path::new(r"\\.\PIPE\mylogs")));
+    assert!(!is_named_pipe(Path::new(r"C:\pipe\mylogs")));
+    assert!(!is_named_pipe(Path::new("pipe.log")));
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn named_pipe_input() {
+    use tokio::{io::AsyncWriteExt, net::windows::named_pipe::ServerOptions};
+
+    let name = format!(r"\\.\pipe\rogcat-test-{}", std::process::id());
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&name)
+        .unwrap();
+    let client = tokio::spawn(async move {
+        Box::into_pin(file(PathBuf::from(name)).await.unwrap())
+            .collect::<Vec<_>>()
+            .await
+    });
+    server.connect().await.unwrap();
+    server.write_all(b"one\r\ntwo\r\n").await.unwrap();
+    // Closing keeps the data that isn't read yet
+    drop(server);
+
+    let data = client.await.unwrap();
+    assert_eq!(data.len(), 2);
+    assert!(matches!(&data[0], StreamData::Line(l) if l == "one"));
+    assert!(matches!(&data[1], StreamData::Line(l) if l == "two"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn split_buffer_processes() {
+    let process = |buffer: &str, lines: &str| Process {
+        buffer: Some(buffer.to_owned()),
+        ..Process::with_cmd(vec!["printf".to_owned(), lines.to_owned()], false)
+    };
+    let data = select_all([
+        process("main", "m1\nm2\nm3\n"),
+        process("crash", "c1\nc2\n"),
+    ])
+    .collect::<Vec<_>>()
+    .await;
+
+    // The lines of each buffer keep their order
+    let lines = |wanted: &str| {
+        data.iter()
+            .filter_map(|data| match data {
+                StreamData::BufferLine {
+                    device: None,
+                    buffer,
+                    line,
+                } if buffer == wanted => Some(line.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(lines("main"), ["m1", "m2", "m3"]);
+    assert_eq!(lines("crash"), ["c1", "c2"]);
+    assert_eq!(data.len(), 5);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn respawn_checkpoint() {
+    // Fake logcat that prints its -T argument. Resumed logcats print records
+    // of a device with a reset clock.
+    let script = r#"if [ -z "$1" ]; then
+            printf '01-10 23:02:59.028 I Tag: a\n01-10 23:03:00.000 I Tag: b\n'
+        else
+            echo "01-01 00:00:01.000 I Tag: $0 $1"
+        fi"#;
+    let process = Process {
+        adb: Some(vec!["sh".to_owned()]),
+        ..Process::with_cmd(vec!["sh".into(), "-c".into(), script.into()], true)
+    };
+    let data = process.take(5).collect::<Vec<_>>().await;
+    let line = |data: &StreamData| match data {
+        StreamData::Line(line) => Some(line.clone()),
+        _ => None,
+    };
+    assert_eq!(line(&data[0]).unwrap(), "01-10 23:02:59.028 I Tag: a");
+    assert_eq!(line(&data[1]).unwrap(), "01-10 23:03:00.000 I Tag: b");
+    // The device clock changed: warn and pass the duplicates
+    assert!(matches!(
+        &data[2],
+        StreamData::Diagnostic(Level::Warn, message) if message.contains("clock changed")
+    ));
+    assert_eq!(
+        line(&data[3]).unwrap(),
+        "01-01 00:00:01.000 I Tag: -T 01-10 23:03:00.000"
+    );
+    // The checkpoint follows the new clock
+    assert_eq!(
+        line(&data[4]).unwrap(),
+        "01-01 00:00:01.000 I Tag: -T 01-01 00:00:01.000"
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn respawn_after_checkpoint() {
+    // Fake logcat that prints the records at the -T argument again
+    let script = r#"if [ -z "$1" ]; then
+            printf '01-10 23:02:59.028 I Tag: a\n'
+        fi
+        printf '01-10 23:03:00.000 I Tag: b\n01-10 23:03:00.000 I Tag: c\n'
+        if [ -n "$1" ]; then
+            printf '01-10 23:03:00.000 I Tag: d\n01-10 23:03:01.000 I Tag: e\n'
+        fi"#;
+    let process = Process {
+        adb: Some(vec!["sh".to_owned()]),
+        ..Process::with_cmd(vec!["sh".into(), "-c".into(), script.into()], true)
+    };
+    let lines = process
+        .take(5)
+        .map(|data| match data {
+            StreamData::Line(line) => line[26..].to_owned(),
+            data => panic!("Unexpected {data:?}"),
+        })
+        .collect::<Vec<_>>()
+        .await;
+    // The lines at the checkpoint are passed once. New lines at the
+    // checkpoint are not lost.
+    assert_eq!(lines, ["a", "b", "c", "d", "e"]);
+}
+
+#[tokio::test]
+async fn json_array_files() {
+    let fixture =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/android-studio.json");
+    let lines = |stream: LogStream| async move {
+        Box::into_pin(stream)
+            .map(|data| match data {
+                StreamData::Line(line) => line,
+                data => panic!("Unexpected {data:?}"),
+            })
+            .collect::<Vec<_>>()
+            .await
+    };
+
+    // Elements of arrays are passed as lines like the ones of a JSONL file
+    let array = lines(file(fixture.clone()).await.unwrap()).await;
+    assert_eq!(array.len(), 3);
+    assert!(array[0].starts_with('{') && !array[0].contains('\n'));
+
+    let jsonl = std::env::temp_dir().join(format!("rogcat-studio-{}.jsonl", std::process::id()));
+    std::fs::write(&jsonl, array.join("\n")).unwrap();
+    assert_eq!(
+        lines(files(vec![jsonl.clone()]).await.unwrap()).await,
+        array
+    );
+    std::fs::remove_file(&jsonl).ok();
+
+    // Elements before a malformed part are passed
+    let broken = std::env::temp_dir().join(format!("rogcat-studio-{}.json", std::process::id()));
+    std::fs::write(&broken, r#"[{"a": 1}, {"b": "#).unwrap();
+    let data = Box::into_pin(file(broken.clone()).await.unwrap())
+        .collect::<Vec<_>>()
+        .await;
+    std::fs::remove_file(&broken).ok();
+    assert_eq!(data.len(), 2);
+    assert!(matches!(&data[0], StreamData::Line(line) if line == r#"{"a":1}"#));
+    assert!(matches!(
+        &data[1],
+        StreamData::Diagnostic(Level::Fatal, message) if message.starts_with("Failed to read")
+    ));
+
+    let mut parser = Parser::default();
+    let tags = array
+        .iter()
+        .map(|line| parser.parse(line).unwrap().tag)
+        .collect::<Vec<_>>();
+    assert_eq!(tags, ["ActivityManager", "ViewRootImpl", "libc"]);
+
+    // Lines starting with a bracket are no array
+    let mut reader: &[u8] = b"[2024-01-11T01:10:02Z INFO  foo] message\n";
+    assert!(!is_json_array(&mut reader).await.unwrap());
+    let mut reader: &[u8] = b" \n[\n  {}]";
+    assert!(is_json_array(&mut reader).await.unwrap());
+}
+
+#[tokio::test]
+async fn last_lines_of_file() {
+    let dir = std::env::temp_dir().join(format!("rogcat-last-lines-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let collect = |stream: LogStream| async move {
+        Box::into_pin(stream)
+            .map(|data| match data {
+                StreamData::Line(line) => line,
+                data => panic!("Unexpected {data:?}"),
+            })
+            .collect::<Vec<_>>()
+            .await
+    };
+
+    // Multi-byte chars and lines longer than a block cross block boundaries
+    let line = |i: usize| match i % 1000 {
+        0 => format!(
+            "01-10 23:56:50.101  1234  1235 I Tag: long {i} {}",
+            "ä".repeat(50_000)
+        ),
+        _ => format!("01-10 23:56:50.101  1234  1235 I Tag: message {i} äöü€"),
+    };
+    let content = (0..100_000).map(|i| line(i) + "\n").collect::<String>();
+    let f = dir.join("large.log");
+    std::fs::write(&f, &content).unwrap();
+
+    for count in [1, 999, 1000, 1001, 5000] {
+        let lines = collect(last_lines(f.clone(), count).await.unwrap()).await;
+        assert_eq!(lines.len(), count);
+        assert_eq!(lines[0], line(100_000 - count));
+        assert_eq!(lines[count - 1], line(99_999));
+    }
+    assert!(collect(last_lines(f.clone(), 0).await.unwrap())
+        .await
+        .is_empty());
+
+    // Files without final line break and with fewer lines than requested
+    let short = dir.join("short.log");
+    std::fs::write(&short, "a\nb\n\nc").unwrap();
+    let lines = collect(last_lines(short.clone(), 2).await.unwrap()).await;
+    assert_eq!(lines, ["", "c"]);
+    let lines = collect(last_lines(short.clone(), 10).await.unwrap()).await;
+    assert_eq!(lines, ["a", "b", "", "c"]);
+
+    // The count applies per file
+    let lines = collect(
+        files_last_lines(vec![short.clone(), f.clone()], 2)
+            .await
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(lines, ["", "c", &line(99_998), &line(99_999)]);
+
+    // UTF-16 files are detected by their byte order mark at the start
+    for (bom, encode) in [
+        ([0xff, 0xfe], u16::to_le_bytes as fn(u16) -> [u8; 2]),
+        ([0xfe, 0xff], u16::to_be_bytes),
+    ] {
+        let utf16 = dir.join("utf16.log");
+        let bytes = bom
+            .into_iter()
+            .chain("a\r\nb\r\n日志\r\n".encode_utf16().flat_map(encode))
+            .collect::<Vec<_>>();
+        std::fs::write(&utf16, bytes).unwrap();
+        let lines = collect(last_lines(utf16.clone(), 2).await.unwrap()).await;
+        assert_eq!(lines, ["b", "日志"]);
+        assert!(collect(last_lines(utf16, 0).await.unwrap())
+            .await
+            .is_empty());
+    }
+
+    let gz = dir.join("large.log.gz");
+    std::fs::write(&gz, [0x1f, 0x8b, 0, 0]).unwrap();
+    let error = last_lines(gz, 10).await.err().unwrap().to_string();
+    assert!(error.contains("compressed"), "{error}");
+    let zst = dir.join("large.bin");
+    std::fs::write(&zst, [0x28, 0xb5, 0x2f, 0xfd]).unwrap();
+    assert!(last_lines(zst, 10).await.is_err());
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn gzip_members() {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    // Concatenated archives like of `cat a.gz b.gz` are read completely
+    let mut bytes = Vec::new();
+    for line in ["first", "second"] {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        writeln!(encoder, "{line}").unwrap();
+        bytes.extend(encoder.finish().unwrap());
+    }
+    let gz = std::env::temp_dir().join(format!("rogcat-members-{}.gz", std::process::id()));
+    std::fs::write(&gz, bytes).unwrap();
+
+    let lines = Box::into_pin(file(gz.clone()).await.unwrap())
+        .collect::<Vec<_>>()
+        .await;
+    std::fs::remove_file(&gz).ok();
+    assert!(matches!(
+        lines.as_slice(),
+        [StreamData::Line(a), StreamData::Line(b)] if a == "first" && b == "second"
+    ));
+}
+
+#[test]
+fn poll_overlap() {
+    let hashes = |lines: &str| {
+        lines
+            .chars()
+            .map(|c| line_hash(&c.to_string()))
+            .collect::<Vec<_>>()
+    };
+    let end = |last, run| overlap_end(&hashes(last), &hashes(run));
+    // Former lines followed by new ones
+    assert_eq!(end("ab", "abc"), Some(2));
+    assert_eq!(end("bc", "abcd"), Some(3));
+    // Older lines were dropped
+    assert_eq!(end("abc", "bcd"), Some(2));
+    assert_eq!(end("abc", "c"), Some(1));
+    // New lines repeating older ones are passed
+    assert_eq!(end("abab", "ababab"), Some(4));
+    assert_eq!(end("aab", "aba"), Some(2));
+    assert_eq!(end("ab", "ab"), Some(2));
+    assert_eq!(end("", "ab"), Some(0));
+    assert_eq!(end("ab", "xy"), None);
+    assert_eq!(end("ab", "ba"), Some(1));
+    assert_eq!(end("ab", "xa"), None);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn polled_command() {
+    let dir = std::env::temp_dir().join(format!("rogcat-poll-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("dmesg");
+
+    // Fake dmesg that appends a line to its buffer on every run
+    let script = format!(
+        "n=$(cat {log} 2>/dev/null | wc -l); echo \"line $n\" >> {log}; cat {log}",
+        log = log.display()
+    );
+    let cmd = vec!["sh".to_owned(), "-c".to_owned(), script];
+    let polled = Polled::new(cmd, Duration::from_millis(10), 100, Children::default());
+    let lines = polled
+        .take(4)
+        .map(|data| match data {
+            StreamData::Line(line) => line,
+            data => panic!("Unexpected {data:?}"),
+        })
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(lines, ["line 0", "line 1", "line 2", "line 3"]);
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn polled_command_repeats() {
+    let dir = std::env::temp_dir().join(format!("rogcat-poll-repeats-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("dmesg");
+
+    // The same line is appended on every run
+    let script = format!("echo same >> {log}; cat {log}", log = log.display());
+    let cmd = vec!["sh".to_owned(), "-c".to_owned(), script];
+    let polled = Polled::new(cmd, Duration::from_millis(10), 100, Children::default());
+    let data = polled.take(3).collect::<Vec<_>>().await;
+    assert!(data
+        .iter()
+        .all(|data| matches!(data, StreamData::Line(line) if line == "same")));
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn polled_command_changed() {
+    let dir = std::env::temp_dir().join(format!("rogcat-poll-changed-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let runs = dir.join("runs");
+
+    // The buffer rotates in the third run and is cleared in the fourth
+    let script = format!(
+        "n=$(cat {runs} 2>/dev/null || echo 0); echo $((n + 1)) > {runs}
+        case $n in
+            0) printf 'a\\nb\\n' ;;
+            1) printf 'a\\nb\\nc\\n' ;;
+            2) printf 'b\\nc\\nd\\n' ;;
+            *) printf 'x\\ny\\n' ;;
+        esac",
+        runs = runs.display()
+    );
+    let cmd = vec!["sh".to_owned(), "-c".to_owned(), script];
+    let polled = Polled::new(cmd, Duration::from_millis(10), 100, Children::default());
+    let data = polled.take(7).collect::<Vec<_>>().await;
+    let line = |n: usize| match &data[n] {
+        StreamData::Line(line) => line.as_str(),
+        data => panic!("Unexpected {data:?}"),
+    };
+    assert_eq!([line(0), line(1), line(2), line(3)], ["a", "b", "c", "d"]);
+    assert!(matches!(
+        &data[4],
+        StreamData::Diagnostic(Level::Warn, message) if message.contains("changed completely")
+    ));
+    assert_eq!([line(5), line(6)], ["x", "y"]);
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn command_stderr_lines() {
+    let cmd = ["sh", "-c", "echo out; echo err >&2"].map(ToOwned::to_owned);
+    let data = Process::with_cmd(cmd.to_vec(), false)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(data.len(), 2);
+    assert!(data
+        .iter()
+        .any(|data| matches!(data, StreamData::Line(l) if l == "out")));
+    assert!(data
+        .iter()
+        .any(|data| matches!(data, StreamData::ErrorLine(l) if l == "err")));
+}
+
+#[tokio::test]
+async fn respawn_after_terminated_registry() {
+    let process = |children: &Children| Process {
+        children: children.clone(),
+        ..Process::with_cmd(vec!["echo".into(), "line".into()], true)
+    };
+    // Processes of terminated registries are not spawned again
+    let terminated = Children::default();
+    terminated.terminate().await;
+    assert_eq!(process(&terminated).count().await, 0);
+    // The processes of the next pipeline are respawned
+    let children = Children::default();
+    assert_eq!(process(&children).take(2).count().await, 2);
+    children.terminate().await;
+}
@@ -18,14 +18,21 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::Error;
 use clap::ValueEnum;
+use crc::Crc;
 use csv::WriterBuilder;
-use failure::{format_err, Error};
-use serde::{Deserialize, Serialize};
-use std::{fmt::Display, str::FromStr};
+use serde::{
+    de,
+    ser::{SerializeMap, SerializeTuple},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
 
 type StdResult<T, E> = std::result::Result<T, E>;
 
+const CRC_TABLE: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
 /// Supported output formats enum.
 #[derive(Clone, Debug, PartialEq, ValueEnum)]
 pub enum Format {
@@ -33,26 +40,302 @@ pub enum Format {
     Html,
     Human,
     Json,
+    JsonPretty,
+    Logcat,
     Raw,
 }
 
 impl Format {
     pub fn fmt_record(&self, record: &Record) -> Result<String, Error> {
+        self.fmt_columns(record, None)
+    }
+
+    /// Format a record with the given columns in the given order. Only csv
+    /// and json support a column selection. The human format fails.
+    pub fn fmt_columns(
+        &self,
+        record: &Record,
+        columns: Option<&[Column]>,
+    ) -> Result<String, Error> {
+        self.fmt_styled(record, columns, LevelStyle::Word)
+    }
+
+    /// Format a record like [`Format::fmt_columns`] with the levels of csv
+    /// and json in `style`
+    pub fn fmt_styled(
+        &self,
+        record: &Record,
+        columns: Option<&[Column]>,
+        style: LevelStyle,
+    ) -> Result<String, Error> {
+        let json_error =
+            |e: serde_json::Error| Error::Format(format!("Json serialization error: {e}"));
+        match (self, columns) {
+            (Format::Csv, columns) => {
+                let csv_error =
+                    |e: csv::Error| Error::Format(format!("Csv serialization error: {e}"));
+                // The default buffer of 8k is allocated for every record
+                let mut wtr = WriterBuilder::new()
+                    .has_headers(false)
+                    .buffer_capacity(256)
+                    .from_writer(Vec::with_capacity(256));
+                wtr.serialize(Row(record, columns.unwrap_or(&COLUMNS), style))
+                    .map_err(csv_error)?;
+                // The writer writes to memory and fields are strings
+                let csv = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+                Ok(csv.trim_end_matches('\n').to_owned())
+            }
+            (Format::Html, _) => Ok(html::row(record)),
+            // Human readable output depends on the terminal
+            (Format::Human, _) => Err(Error::Format(
+                "The human format is only written to a terminal".to_owned(),
+            )),
+            (Format::Json, None) => {
+                serde_json::to_string(&Styled(record, style)).map_err(json_error)
+            }
+            (Format::Json, Some(columns)) => {
+                serde_json::to_string(&Object(record, columns, style)).map_err(json_error)
+            }
+            (Format::JsonPretty, None) => {
+                serde_json::to_string_pretty(&Styled(record, style)).map_err(json_error)
+            }
+            (Format::JsonPretty, Some(columns)) => {
+                serde_json::to_string_pretty(&Object(record, columns, style)).map_err(json_error)
+            }
+            (Format::Logcat, _) => Ok(logcat(record)),
+            (Format::Raw, _) => Ok(record.raw.clone()),
+        }
+    }
+
+    /// Header line of files in this format
+    pub fn header(&self, columns: Option<&[Column]>) -> Option<String> {
+        match self {
+            Format::Csv => Some(
+                columns
+                    .unwrap_or(&COLUMNS)
+                    .iter()
+                    .map(Column::name)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Columns of the csv and json output
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Column {
+    Time,
+    Message,
+    Level,
+    Tag,
+    Process,
+    Thread,
+    Raw,
+    Uid,
+    Device,
+    Fields,
+    /// Name of the thread if resolved
+    #[clap(name = "thread_name")]
+    ThreadName,
+    /// Log buffer if the buffers are read separately
+    Buffer,
+}
+
+/// All columns in the default order
+pub const COLUMNS: [Column; 10] = [
+    Column::Time,
+    Column::Message,
+    Column::Level,
+    Column::Tag,
+    Column::Process,
+    Column::Thread,
+    Column::Raw,
+    Column::Uid,
+    Column::Device,
+    Column::Fields,
+];
+
+impl Column {
+    pub fn name(&self) -> &'static str {
         match self {
-            Format::Csv => {
-                let mut wtr = WriterBuilder::new().has_headers(false).from_writer(vec![]);
-                wtr.serialize(record)?;
-                wtr.flush()?;
-                Ok(String::from_utf8(wtr.into_inner().unwrap())?
-                    .trim_end_matches('\n')
-                    .to_owned())
+            Column::Time => "time",
+            Column::Message => "message",
+            Column::Level => "level",
+            Column::Tag => "tag",
+            Column::Process => "process",
+            Column::Thread => "thread",
+            Column::Raw => "raw",
+            Column::Uid => "uid",
+            Column::Device => "device",
+            Column::Fields => "fields",
+            Column::ThreadName => "thread_name",
+            Column::Buffer => "buffer",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Column> {
+        COLUMNS
+            .iter()
+            .chain(&[Column::ThreadName, Column::Buffer])
+            .find(|c| c.name() == name)
+            .copied()
+    }
+}
+
+/// Csv row of the selected columns. Csv cannot hold maps. The fields are
+/// written as one column in the syntax they are extracted from.
+struct Row<'a>(&'a Record, &'a [Column], LevelStyle);
+
+impl Serialize for Row<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        let Row(record, columns, style) = self;
+        let mut row = serializer.serialize_tuple(columns.len())?;
+        for column in columns.iter() {
+            match column {
+                Column::Time => row.serialize_element(&record.time)?,
+                Column::Message => row.serialize_element(&record.message)?,
+                Column::Level => row.serialize_element(&Styled(&record.level, *style))?,
+                Column::Tag => row.serialize_element(&record.tag)?,
+                Column::Process => row.serialize_element(&record.process)?,
+                Column::Thread => row.serialize_element(&record.thread)?,
+                Column::Raw => row.serialize_element(&record.raw)?,
+                Column::Uid => row.serialize_element(&record.uid)?,
+                Column::Device => row.serialize_element(&record.device)?,
+                Column::Fields => row.serialize_element(&format_fields(&record.fields))?,
+                Column::ThreadName => row.serialize_element(&record.thread_name)?,
+                Column::Buffer => row.serialize_element(&record.buffer)?,
+            }
+        }
+        row.end()
+    }
+}
+
+/// Json object of the selected columns
+struct Object<'a>(&'a Record, &'a [Column], LevelStyle);
+
+impl Serialize for Object<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        let Object(record, columns, style) = self;
+        let mut object = serializer.serialize_map(Some(columns.len()))?;
+        for column in columns.iter() {
+            let key = column.name();
+            match column {
+                Column::Time => object.serialize_entry(key, &record.time)?,
+                Column::Message => object.serialize_entry(key, &record.message)?,
+                Column::Level => object.serialize_entry(key, &Styled(&record.level, *style))?,
+                Column::Tag => object.serialize_entry(key, &record.tag)?,
+                Column::Process => object.serialize_entry(key, &record.process)?,
+                Column::Thread => object.serialize_entry(key, &record.thread)?,
+                Column::Raw => object.serialize_entry(key, &record.raw)?,
+                Column::Uid => object.serialize_entry(key, &record.uid)?,
+                Column::Device => object.serialize_entry(key, &record.device)?,
+                Column::Fields => object.serialize_entry(key, &record.fields)?,
+                Column::ThreadName => object.serialize_entry(key, &record.thread_name)?,
+                Column::Buffer => object.serialize_entry(key, &record.buffer)?,
             }
-            Format::Html => unimplemented!(),
-            Format::Human => unimplemented!(),
-            Format::Json => serde_json::to_string(record)
-                .map_err(|e| format_err!("Json serialization error: {}", e)),
-            Format::Raw => Ok(record.raw.clone()),
         }
+        object.end()
+    }
+}
+
+/// Format fields as space separated key=value pairs. Values that contain
+/// separators or quotes are quoted.
+pub fn format_fields(fields: &BTreeMap<String, String>) -> String {
+    fields
+        .iter()
+        .map(|(key, value)| format_field(key, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Format a key=value pair and quote the value if it contains separators
+/// or quotes. Quotes and backslashes within quotes are escaped with a
+/// backslash.
+pub(crate) fn format_field(key: &str, value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == ',' || c == '"') {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        format!("{key}=\"{value}\"")
+    } else {
+        format!("{key}={value}")
+    }
+}
+
+/// Format a record like "adb logcat -v threadtime"
+fn logcat(record: &Record) -> String {
+    let or_zero = |value: &str| {
+        if value.is_empty() {
+            "0".to_owned()
+        } else {
+            value.to_owned()
+        }
+    };
+    let level = match record.level {
+        Level::None | Level::Trace => Level::Verbose,
+        ref level => level.clone(),
+    };
+    format!(
+        "{} {:>5} {:>5} {} {}: {}",
+        record.time.as_deref().unwrap_or("01-01 00:00:00.000"),
+        or_zero(&record.process),
+        or_zero(&record.thread),
+        level,
+        record.tag,
+        record.message
+    )
+}
+
+pub(crate) mod html {
+    use super::{Record, CRC_TABLE};
+
+    /// Escape text for use in html element content and attribute values
+    pub fn escape(value: &str) -> String {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&#39;"),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    // TODO: ensure readability
+    fn hash_color(value: &str) -> String {
+        let mut digest = CRC_TABLE.digest();
+        digest.update(value.as_bytes());
+        let h = digest.finalize();
+        let r = h & 0xFF;
+        let g = (h & 0xFF00) >> 8;
+        let b = (h & 0xFF_0000) >> 16;
+        format!("#{r:02x}{g:02x}{b:02x}")
+    }
+
+    fn colored(value: &str) -> String {
+        let color = if value.is_empty() || value == "0" {
+            "grey".to_owned()
+        } else {
+            hash_color(value)
+        };
+        format!("<span style=\"color:{}\">{}</span>", color, escape(value))
+    }
+
+    /// Render a record as html table row
+    pub fn row(record: &Record) -> String {
+        format!(
+            "<tr class=\"level-{level}\"><td>{}</td><td>{}</td><td>{}</td><td class=\"level\">{level}</td><td>{}</td><td>{}</td></tr>",
+            escape(record.time.as_deref().unwrap_or_default()),
+            colored(&record.process),
+            colored(&record.thread),
+            colored(&record.tag),
+            escape(&record.message),
+            level = record.level,
+        )
     }
 }
 
@@ -64,6 +347,8 @@ impl FromStr for Format {
             "html" => Ok(Format::Html),
             "human" => Ok(Format::Human),
             "json" => Ok(Format::Json),
+            "json-pretty" => Ok(Format::JsonPretty),
+            "logcat" => Ok(Format::Logcat),
             "raw" => Ok(Format::Raw),
             _ => Err("Format parsing error"),
         }
@@ -80,6 +365,8 @@ impl Display for Format {
                 Format::Html => "html",
                 Format::Human => "human",
                 Format::Json => "json",
+                Format::JsonPretty => "json-pretty",
+                Format::Logcat => "logcat",
                 Format::Raw => "raw",
             }
         )
@@ -91,7 +378,38 @@ const LEVEL_VALUES: [&str; 16] = [
     "W", "E", "F", "A",
 ];
 
-#[derive(Clone, Debug, Deserialize, PartialOrd, PartialEq, Serialize, Default)]
+/// Letters, names and words of the levels. The letters are shown on the
+/// terminal and the words are serialized by default. All of them are parsed.
+const LEVELS: [(Level, &str, &str, &str); 8] = [
+    (Level::Trace, "T", "trace", "Trace"),
+    (Level::Verbose, "V", "verbose", "Verbose"),
+    (Level::Debug, "D", "debug", "Debug"),
+    (Level::Info, "I", "info", "Info"),
+    (Level::Warn, "W", "warn", "Warn"),
+    (Level::Error, "E", "error", "Error"),
+    (Level::Fatal, "F", "fatal", "Fatal"),
+    (Level::Assert, "A", "assert", "Assert"),
+];
+
+/// Style of the levels in csv and json output
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum LevelStyle {
+    /// Single letters like "I" as printed by logcat
+    Letter,
+    /// Words like "Info"
+    #[default]
+    Word,
+}
+
+/// Value serialized with levels in a style
+struct Styled<'a, T>(&'a T, LevelStyle);
+
+/// Severity of a record. Levels are ordered by declaration: `None` is the
+/// level of records without one and lower than all others. `Trace` of
+/// env_logger and tracing is the finest level and lower than `Verbose`, the
+/// lowest level of Android. A minimum level of trace passes verbose records,
+/// a minimum level of verbose drops trace records.
+#[derive(Clone, Debug, Eq, Hash, PartialOrd, PartialEq, Default)]
 pub enum Level {
     #[default]
     None,
@@ -107,41 +425,65 @@ pub enum Level {
 
 impl Display for Level {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match *self {
-                Level::None => "-",
-                Level::Trace => "T",
-                Level::Verbose => "V",
-                Level::Debug => "D",
-                Level::Info => "I",
-                Level::Warn => "W",
-                Level::Error => "E",
-                Level::Fatal => "F",
-                Level::Assert => "A",
-            }
-        )
+        write!(f, "{}", self.letter())
     }
 }
 
+impl Serialize for Level {
+    fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        serializer.serialize_str(self.word())
+    }
+}
+
+impl Serialize for Styled<'_, Level> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        match self.1 {
+            LevelStyle::Letter => serializer.serialize_str(self.0.letter()),
+            LevelStyle::Word => serializer.serialize_str(self.0.word()),
+        }
+    }
+}
+
+/// Levels are read as letters like "I", words like "Info" and names like
+/// "info". Records without level are "None" or "-".
+impl<'de> Deserialize<'de> for Level {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> StdResult<Self, D::Error> {
+        let level = std::borrow::Cow::<str>::deserialize(deserializer)?;
+        match level.as_ref() {
+            "None" | "-" => Ok(Level::None),
+            level => level.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    /// Parse a letter like "W" or a name like "warn" or "WARN"
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        Level::from_letter(s)
+            .or_else(|| {
+                LEVELS
+                    .iter()
+                    .find(|(_, _, name, _)| name.eq_ignore_ascii_case(s))
+                    .map(|(level, ..)| level.clone())
+            })
+            .ok_or_else(|| {
+                format!(
+                    "Invalid level \"{s}\". Valid levels are {}",
+                    LEVEL_VALUES.join(", ")
+                )
+            })
+    }
+}
+
+/// Lenient conversion of level columns of csv or json input. Unknown levels
+/// are `Level::None`.
 impl From<Option<String>> for Level {
     fn from(level: Option<String>) -> Self {
-        if let Some(lvl) = level {
-            match lvl.as_str() {
-                "T" | "trace" => Level::Trace,
-                "V" | "verbose" => Level::Verbose,
-                "D" | "debug" => Level::Debug,
-                "I" | "info" => Level::Info,
-                "W" | "warn" => Level::Warn,
-                "E" | "error" => Level::Error,
-                "F" | "fatal" => Level::Fatal,
-                "A" | "assert" => Level::Assert,
-                _ => Level::None,
-            }
-        } else {
-            Level::None
-        }
+        level
+            .and_then(|level| level.parse().ok())
+            .unwrap_or_default()
     }
 }
 
@@ -149,9 +491,64 @@ impl Level {
     pub fn values() -> [&'static str; 16] {
         LEVEL_VALUES
     }
+
+    /// Level of a single letter like "W"
+    pub fn from_letter(letter: &str) -> Option<Level> {
+        LEVELS
+            .iter()
+            .find(|(_, l, ..)| *l == letter)
+            .map(|(level, ..)| level.clone())
+    }
+
+    /// Single letter like "W"
+    pub fn letter(&self) -> &'static str {
+        LEVELS
+            .iter()
+            .find(|(level, ..)| level == self)
+            .map_or("-", |(_, letter, ..)| letter)
+    }
+
+    /// Lower case name like "warn"
+    pub fn name(&self) -> &'static str {
+        LEVELS
+            .iter()
+            .find(|(level, ..)| level == self)
+            .map_or("none", |(_, _, name, _)| name)
+    }
+
+    /// Capitalized word like "Warn"
+    pub fn word(&self) -> &'static str {
+        LEVELS
+            .iter()
+            .find(|(level, ..)| level == self)
+            .map_or("None", |(.., word)| word)
+    }
+}
+
+/// Version of the serialized record layout. Increase it if records can't be
+/// read by older versions anymore and migrate the older layouts on reading.
+/// Added fields with defaults don't need a new version. Version 0 are
+/// records written before the version was recorded.
+pub const SCHEMA: u32 = 1;
+
+/// Kind of the administrative lines of logcat and logd that are no log
+/// messages
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Notice {
+    /// Start of a buffer like "--------- beginning of main"
+    BufferBegin,
+    /// Lines of a uid dropped by chatty like "uid=10007(u0_a7) com.example
+    /// expire 13 lines"
+    Expired,
+    /// Other lines like read failures of logcat
+    Other,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+/// Log record. The core fields up to `raw` are always serialized. All other
+/// fields have defaults and are skipped if unset, so records of older and
+/// newer versions can be read. Unknown fields are ignored.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct Record {
     pub time: Option<String>,
     pub message: String,
@@ -160,4 +557,356 @@ pub struct Record {
     pub process: String,
     pub thread: String,
     pub raw: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Key value pairs extracted from the message
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    /// Name of the thread resolved from the device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_name: Option<String>,
+    /// Log buffer of the record if the buffers are read separately
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer: Option<String>,
+    /// Application id of records imported from Android Studio
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub application: Option<String>,
+    /// Set if the record is an administrative line of logcat or logd.
+    /// Notices bypass the filters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notice: Option<Notice>,
+    /// Message of rogcat itself instead of the source. Diagnostics bypass
+    /// the filters.
+    #[serde(skip)]
+    pub diagnostic: bool,
+}
+
+/// Borrowed [`Record`] with the level in a style. Keep the fields and their
+/// attributes in sync with the record.
+#[derive(Serialize)]
+struct RecordRef<'a> {
+    time: &'a Option<String>,
+    message: &'a str,
+    level: Styled<'a, Level>,
+    tag: &'a str,
+    process: &'a str,
+    thread: &'a str,
+    raw: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uid: &'a Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: &'a Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    fields: &'a BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_name: &'a Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buffer: &'a Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    application: &'a Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notice: &'a Option<Notice>,
+}
+
+impl Serialize for Styled<'_, Record> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        let Styled(record, style) = self;
+        RecordRef {
+            time: &record.time,
+            message: &record.message,
+            level: Styled(&record.level, *style),
+            tag: &record.tag,
+            process: &record.process,
+            thread: &record.thread,
+            raw: &record.raw,
+            uid: &record.uid,
+            device: &record.device,
+            fields: &record.fields,
+            thread_name: &record.thread_name,
+            buffer: &record.buffer,
+            application: &record.application,
+            notice: &record.notice,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl Record {
+    /// Record of a diagnostic message of rogcat
+    pub fn from_diagnostic(level: Level, message: String) -> Record {
+        Record {
+            level,
+            tag: "rogcat".to_owned(),
+            raw: message.clone(),
+            message,
+            diagnostic: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[test]
+fn level_order_and_parsing() {
+    let ordered = [
+        Level::None,
+        Level::Trace,
+        Level::Verbose,
+        Level::Debug,
+        Level::Info,
+        Level::Warn,
+        Level::Error,
+        Level::Fatal,
+        Level::Assert,
+    ];
+    assert!(ordered.windows(2).all(|w| w[0] < w[1]));
+
+    for value in Level::values() {
+        let level = value.parse::<Level>().unwrap();
+        assert_eq!(level.to_string().parse::<Level>(), Ok(level));
+    }
+    assert_eq!("T".parse(), Ok(Level::Trace));
+    assert_eq!("WARN".parse(), Ok(Level::Warn));
+    assert_eq!("Verbose".parse(), Ok(Level::Verbose));
+    assert!("wran".parse::<Level>().unwrap_err().contains("wran"));
+    assert!("w".parse::<Level>().is_err());
+    assert_eq!(Level::from(Some("wran".to_owned())), Level::None);
+    assert_eq!(Level::None.to_string(), "-");
+}
+
+#[test]
+fn html_row() {
+    let record = Record {
+        time: Some("01-10 23:02:59.028".to_owned()),
+        message: "<script>alert(\"&\")</script>".to_owned(),
+        level: Level::Error,
+        tag: "Tag".to_owned(),
+        process: "6496".to_owned(),
+        thread: "6497".to_owned(),
+        ..Default::default()
+    };
+    let row = Format::Html.fmt_record(&record).unwrap();
+    assert!(row.starts_with("<tr class=\"level-E\">"));
+    assert!(row.ends_with("</tr>"));
+    assert!(row.contains("<td>01-10 23:02:59.028</td>"));
+    assert!(row.contains("&lt;script&gt;alert(&quot;&amp;&quot;)&lt;/script&gt;"));
+    assert!(!row.contains("<script>"));
+}
+
+#[test]
+fn human_format_fails() {
+    assert!(matches!(
+        Format::Human.fmt_record(&Record::default()),
+        Err(Error::Format(_))
+    ));
+}
+
+#[test]
+fn logcat_format() {
+    use crate::parser::{DefaultParser, FormatParser};
+
+    let roundtrip = |record: Record| {
+        let line = Format::Logcat.fmt_record(&record).unwrap();
+        let parsed = DefaultParser.try_parse_str(&line).unwrap();
+        assert_eq!(
+            Record {
+                raw: line,
+                ..record
+            },
+            parsed
+        );
+    };
+
+    let record = Record {
+        time: Some("01-10 23:02:59.028".to_owned()),
+        message: "ViewPostIme pointer 1".to_owned(),
+        level: Level::Debug,
+        tag: "ViewRootImpl".to_owned(),
+        process: "6496".to_owned(),
+        thread: "12".to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(
+        Format::Logcat.fmt_record(&record).unwrap(),
+        "01-10 23:02:59.028  6496    12 D ViewRootImpl: ViewPostIme pointer 1"
+    );
+    roundtrip(record.clone());
+
+    // Empty tag
+    roundtrip(Record {
+        tag: String::new(),
+        ..record.clone()
+    });
+
+    // Empty message
+    roundtrip(Record {
+        message: String::new(),
+        ..record.clone()
+    });
+
+    // Missing time, pid and thread
+    let record = Record {
+        message: "frame".to_owned(),
+        tag: "0x12".to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(
+        Format::Logcat.fmt_record(&record).unwrap(),
+        "01-01 00:00:00.000     0     0 V 0x12: frame"
+    );
+}
+
+#[test]
+fn json_fields() {
+    let record = Record {
+        message: "Screen is off".to_owned(),
+        ..Default::default()
+    };
+    let json = Format::Json.fmt_record(&record).unwrap();
+    assert!(!json.contains("fields"));
+
+    let record = Record {
+        fields: [("msg", "hello world"), ("state", "off")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        ..record
+    };
+    let json = Format::Json.fmt_record(&record).unwrap();
+    assert!(json.ends_with(r#""fields":{"msg":"hello world","state":"off"}}"#));
+    assert_eq!(serde_json::from_str::<Record>(&json).unwrap(), record);
+}
+
+#[test]
+fn json_compatibility() {
+    let parse = |fixture: &str| {
+        fixture
+            .lines()
+            .map(|line| serde_json::from_str::<Record>(line).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    // Records written before the optional fields were skipped
+    let v0 = parse(include_str!("../tests/fixtures/records-v0.json"));
+    assert_eq!(v0.len(), 3);
+    assert_eq!(v0[0].uid, None);
+    assert_eq!(v0[1].device.as_deref(), Some("emulator-5554"));
+    assert_eq!(v0[2].time, None);
+    assert!(v0.iter().all(|r| r.fields.is_empty() && r.buffer.is_none()));
+
+    // Current records and records of newer versions with unknown fields
+    let v1 = parse(include_str!("../tests/fixtures/records-v1.json"));
+    assert_eq!(v1.len(), 3);
+    assert_eq!(v1[1].fields["user"], "alice");
+    assert_eq!(v1[1].thread_name.as_deref(), Some("OkHttp"));
+    assert_eq!(v1[1].application.as_deref(), Some("com.example"));
+    assert_eq!(v1[2].message, "from the future");
+
+    for record in v0.iter().chain(&v1) {
+        let json = Format::Json.fmt_record(record).unwrap();
+        assert_eq!(&serde_json::from_str::<Record>(&json).unwrap(), record);
+    }
+
+    // Unset optional fields are not written. The first record of both
+    // versions is the same.
+    let json = Format::Json.fmt_record(&v0[0]).unwrap();
+    assert_eq!(
+        json,
+        include_str!("../tests/fixtures/records-v1.json")
+            .lines()
+            .next()
+            .unwrap()
+    );
+    assert_eq!(v0[0], v1[0]);
+
+    // The core fields are required
+    assert!(serde_json::from_str::<Record>(r#"{"message":"m"}"#).is_err());
+}
+
+#[test]
+fn styled_record_layout() {
+    let record = Record {
+        time: Some("01-10 23:02:59.028".to_owned()),
+        message: "message".to_owned(),
+        level: Level::Info,
+        tag: "Tag".to_owned(),
+        process: "6496".to_owned(),
+        thread: "6497".to_owned(),
+        raw: "raw".to_owned(),
+        uid: Some("1000".to_owned()),
+        device: Some("emulator-5554".to_owned()),
+        fields: [("key".to_owned(), "value".to_owned())].into(),
+        thread_name: Some("main".to_owned()),
+        buffer: Some("main".to_owned()),
+        application: Some("com.example".to_owned()),
+        notice: Some(Notice::Other),
+        diagnostic: false,
+    };
+    // Styled records have the layout of records
+    assert_eq!(
+        Format::Json.fmt_record(&record).unwrap(),
+        serde_json::to_string(&record).unwrap()
+    );
+}
+
+#[test]
+fn level_styles() {
+    let levels = [
+        (Level::None, "-", "None"),
+        (Level::Trace, "T", "Trace"),
+        (Level::Verbose, "V", "Verbose"),
+        (Level::Debug, "D", "Debug"),
+        (Level::Info, "I", "Info"),
+        (Level::Warn, "W", "Warn"),
+        (Level::Error, "E", "Error"),
+        (Level::Fatal, "F", "Fatal"),
+        (Level::Assert, "A", "Assert"),
+    ];
+    let columns = [Column::Level, Column::Tag];
+    for (level, letter, word) in levels {
+        assert_eq!(level.to_string(), letter);
+        assert_eq!(level.word(), word);
+        let record = Record {
+            level: level.clone(),
+            tag: "Tag".to_owned(),
+            ..Default::default()
+        };
+        for (style, value) in [(LevelStyle::Letter, letter), (LevelStyle::Word, word)] {
+            let csv = Format::Csv
+                .fmt_styled(&record, Some(&columns), style)
+                .unwrap();
+            assert_eq!(csv, format!("{value},Tag"));
+            let json = Format::Json
+                .fmt_styled(&record, Some(&columns), style)
+                .unwrap();
+            assert_eq!(json, format!(r#"{{"level":"{value}","tag":"Tag"}}"#));
+            let json = Format::Json.fmt_styled(&record, None, style).unwrap();
+            assert!(json.contains(&format!(r#""level":"{value}""#)), "{json}");
+            // Both styles are read
+            assert_eq!(serde_json::from_str::<Record>(&json).unwrap(), record);
+            assert_eq!(
+                serde_json::from_str::<Level>(&format!("\"{value}\"")).unwrap(),
+                level
+            );
+        }
+        // Records are serialized with words unless styled
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.contains(&format!(r#""level":"{word}""#)), "{json}");
+    }
+    assert_eq!(
+        serde_json::from_str::<Level>("\"info\"").unwrap(),
+        Level::Info
+    );
+    assert!(serde_json::from_str::<Level>("\"Loud\"")
+        .unwrap_err()
+        .to_string()
+        .contains("Invalid level \"Loud\""));
+    // The default is the word
+    assert_eq!(
+        Format::Csv
+            .fmt_columns(&Record::default(), Some(&columns))
+            .unwrap(),
+        "None,"
+    );
 }
@@ -0,0 +1,278 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Sed like rules rewriting the message, raw line and field values of
+//! records, e.g. to
+//! redact tokens with `s/Bearer [A-Za-z0-9._-]+/Bearer ***/`. Rules are
+//! written as `s/pattern/replacement/flags` with any delimiter following the
+//! `s`. The replacement refers to capture groups with `$1` or `${name}`.
+//! The flag `g` replaces all matches instead of the first and `i` ignores
+//! the case.
+
+use crate::{record::Record, Error};
+use regex::{Regex, RegexBuilder};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Single rewrite rule
+#[derive(Clone, Debug)]
+struct Rule {
+    /// Rule as given by the user
+    rule: String,
+    pattern: Regex,
+    replacement: String,
+    global: bool,
+    /// Records changed by this rule
+    touched: Arc<AtomicUsize>,
+}
+
+/// Rewrite rules applied in order. Clones share the counters of the
+/// rules.
+#[derive(Clone, Debug, Default)]
+pub struct Rewrite {
+    rules: Vec<Rule>,
+}
+
+impl Rewrite {
+    /// Parse `rules`. Invalid rules and patterns are errors.
+    pub fn parse<S: AsRef<str>>(rules: &[S]) -> Result<Rewrite, Error> {
+        let rules = rules
+            .iter()
+            .map(|rule| Rule::parse(rule.as_ref()))
+            .collect::<Result<_, _>>()?;
+        Ok(Rewrite { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Apply the rules to the message, raw line and field values of
+    /// `record`. Later rules see the output of the former ones.
+    pub fn apply(&self, record: &mut Record) {
+        for rule in &self.rules {
+            let mut touched = false;
+            let texts = [&mut record.message, &mut record.raw]
+                .into_iter()
+                .chain(record.fields.values_mut());
+            for text in texts {
+                if let Some(replaced) = rule.replace(text) {
+                    *text = replaced;
+                    touched = true;
+                }
+            }
+            if touched {
+                rule.touched.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Rules with the number of records they changed
+    pub fn counts(&self) -> Vec<(&str, usize)> {
+        self.rules
+            .iter()
+            .map(|rule| (rule.rule.as_str(), rule.touched.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+impl Rule {
+    fn parse(rule: &str) -> Result<Rule, Error> {
+        let invalid =
+            |reason: &str| Error::Invalid(format!("Invalid rewrite rule {rule}: {reason}"));
+        let mut chars = rule.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid("expected s/pattern/replacement/"));
+        }
+        let Some(delimiter) = chars.next().filter(|c| !c.is_alphanumeric() && *c != '\\') else {
+            return Err(invalid("expected a delimiter after 's'"));
+        };
+
+        // Split at unescaped delimiters. Escaped delimiters lose their
+        // backslash, other escapes are kept for the regex.
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(c) if c == delimiter => parts.last_mut().unwrap().push(c),
+                    Some(c) => {
+                        let part = parts.last_mut().unwrap();
+                        part.push('\\');
+                        part.push(c);
+                    }
+                    None => return Err(invalid("trailing backslash")),
+                },
+                c if c == delimiter => parts.push(String::new()),
+                c => parts.last_mut().unwrap().push(c),
+            }
+        }
+        let [pattern, replacement, flags] = <[String; 3]>::try_from(parts)
+            .map_err(|_| invalid("expected s/pattern/replacement/"))?;
+        if pattern.is_empty() {
+            return Err(invalid("empty pattern"));
+        }
+        let mut global = false;
+        let mut ignore_case = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => ignore_case = true,
+                c => return Err(invalid(&format!("unknown flag '{c}'"))),
+            }
+        }
+        let pattern = RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|e| invalid(&e.to_string()))?;
+        Ok(Rule {
+            rule: rule.to_owned(),
+            pattern,
+            replacement,
+            global,
+            touched: Arc::default(),
+        })
+    }
+
+    /// Replaced `text` if the pattern matches
+    fn replace(&self, text: &str) -> Option<String> {
+        if !self.pattern.is_match(text) {
+            return None;
+        }
+        let limit = if self.global { 0 } else { 1 };
+        Some(
+            self.pattern
+                .replacen(text, limit, self.replacement.as_str())
+                .into_owned(),
+        )
+    }
+}
+
+#[cfg(test)]
+fn apply(rules: &[&str], message: &str) -> (Record, Rewrite) {
+    let rewrite = Rewrite::parse(rules).unwrap();
+    let mut record = Record {
+        message: message.to_owned(),
+        raw: format!("I Auth: {message}"),
+        ..Default::default()
+    };
+    rewrite.apply(&mut record);
+    (record, rewrite)
+}
+
+#[test]
+fn rewrite_capture_groups() {
+    let (record, _) = apply(
+        &[r"s/(Bearer) [A-Za-z0-9._-]+/$1 ***/"],
+        "Authorization: Bearer abc.def-1",
+    );
+    assert_eq!(record.message, "Authorization: Bearer ***");
+    assert_eq!(record.raw, "I Auth: Authorization: Bearer ***");
+
+    let (record, _) = apply(
+        &[r"s|serial=(?P<prefix>\w{2})\w+|serial=${prefix}xxxx|"],
+        "serial=R58M123456",
+    );
+    assert_eq!(record.message, "serial=R5xxxx");
+}
+
+#[test]
+fn rewrite_flags() {
+    let (record, _) = apply(&["s/a/b/"], "aaa");
+    assert_eq!(record.message, "baa");
+    let (record, _) = apply(&["s/a/b/g"], "aaa");
+    assert_eq!(record.message, "bbb");
+    let (record, _) = apply(&["s/TOKEN/***/gi"], "token Token");
+    assert_eq!(record.message, "*** ***");
+    // Escaped delimiters are part of the pattern
+    let (record, _) = apply(&[r"s/\/data\/user/\/redacted/"], "/data/user/0");
+    assert_eq!(record.message, "/redacted/0");
+}
+
+#[test]
+fn rewrite_no_match() {
+    let (record, rewrite) = apply(&["s/secret/***/", "s/token/***/"], "token 42");
+    assert_eq!(record.message, "*** 42");
+    assert_eq!(
+        rewrite.counts(),
+        [("s/secret/***/", 0), ("s/token/***/", 1)]
+    );
+}
+
+#[test]
+fn rewrite_fields() {
+    // Fields of structured input like json carry the values of the message
+    let rewrite = Rewrite::parse(&["s/Bearer .+/Bearer ***/"]).unwrap();
+    let mut record = Record {
+        fields: [
+            ("auth".to_owned(), "Bearer abc".to_owned()),
+            ("user".to_owned(), "alice".to_owned()),
+        ]
+        .into(),
+        ..Default::default()
+    };
+    rewrite.apply(&mut record);
+    assert_eq!(record.fields["auth"], "Bearer ***");
+    assert_eq!(record.fields["user"], "alice");
+    assert_eq!(rewrite.counts()[0].1, 1);
+}
+
+#[test]
+fn rewrite_order() {
+    // Later rules see the output of the former ones
+    let (record, rewrite) = apply(&["s/key=\\w+/key=***/", "s/\\*+/[redacted]/"], "key=42");
+    assert_eq!(record.message, "key=[redacted]");
+    assert_eq!(rewrite.counts()[1].1, 1);
+
+    let (record, _) = apply(&["s/\\*+/[redacted]/", "s/key=\\w+/key=***/"], "key=42");
+    assert_eq!(record.message, "key=***");
+}
+
+#[test]
+fn rewrite_counts_shared() {
+    let rewrite = Rewrite::parse(&["s/a/b/"]).unwrap();
+    let handle = rewrite.clone();
+    for message in ["a", "b", "a"] {
+        let mut record = Record {
+            message: message.to_owned(),
+            ..Default::default()
+        };
+        rewrite.apply(&mut record);
+    }
+    assert_eq!(handle.counts(), [("s/a/b/", 2)]);
+}
+
+#[test]
+fn rewrite_errors() {
+    for invalid in [
+        "",
+        "s",
+        "a/b/c/",
+        "s/a/b",
+        "s/a/b/c/d",
+        "s//b/",
+        "s/(/b/",
+        "s/a/b/x",
+        "sab",
+    ] {
+        assert!(Rewrite::parse(&[invalid]).is_err(), "{invalid}");
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Metadata of a recorded session. The metadata is the first line of output
+//! files written with `--session` and skipped by the parser. The device
+//! properties of `--stamp-props` follow on a line of their own.
+
+use crate::record::html;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+};
+
+/// Prefix of the metadata line
+const PREFIX: &str = "# rogcat session ";
+
+/// Prefix of the device properties line
+const PROPS_PREFIX: &str = "# rogcat props ";
+
+/// Metadata of a recording
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// Version of rogcat that recorded the session
+    pub version: String,
+    /// Layout version of the records. Sessions written before the version
+    /// was recorded read as 0.
+    #[serde(default)]
+    pub schema: u32,
+    /// Serial of the device if the source is adb
+    pub serial: Option<String>,
+    /// Model of the device if the source is adb
+    pub model: Option<String>,
+    /// Start of the recording in RFC 3339 format
+    pub start: String,
+    /// Command line arguments of the recording
+    pub args: Vec<String>,
+    pub profile: Option<String>,
+    /// Comment of the profile
+    pub comment: Option<String>,
+}
+
+impl Session {
+    /// Format the metadata as a single line without line break
+    pub fn to_line(&self) -> String {
+        let json = serde_json::to_string(self).expect("Failed to serialize session");
+        format!("{PREFIX}{json}")
+    }
+
+    /// Parse a line written by [`Session::to_line`]
+    pub fn parse(line: &str) -> Option<Session> {
+        serde_json::from_str(line.strip_prefix(PREFIX)?).ok()
+    }
+
+    /// True if `line` is a metadata or device properties line
+    pub fn is_header(line: &str) -> bool {
+        line.starts_with(PREFIX) || line.starts_with(PROPS_PREFIX)
+    }
+}
+
+/// System properties of the device a log was recorded from
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Props(pub BTreeMap<String, String>);
+
+impl Props {
+    /// Pick `names` from the output of `getprop`. Lines look like
+    /// "[ro.product.model]: [Pixel 7]".
+    pub fn from_getprop(output: &str, names: &[String]) -> Props {
+        let props = output
+            .lines()
+            .filter_map(|line| {
+                let (name, value) = line
+                    .trim_end()
+                    .strip_prefix('[')?
+                    .strip_suffix(']')?
+                    .split_once("]: [")?;
+                names
+                    .iter()
+                    .any(|n| n == name)
+                    .then(|| (name.to_owned(), value.to_owned()))
+            })
+            .collect();
+        Props(props)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Format the properties as a single line without line break
+    pub fn to_line(&self) -> String {
+        let json = serde_json::to_string(self).expect("Failed to serialize properties");
+        format!("{PROPS_PREFIX}{json}")
+    }
+
+    /// Parse a line written by [`Props::to_line`]
+    pub fn parse(line: &str) -> Option<Props> {
+        serde_json::from_str(line.strip_prefix(PROPS_PREFIX)?).ok()
+    }
+
+    /// One "name: value" line per property
+    pub fn lines(&self) -> impl Iterator<Item = String> + '_ {
+        self.0
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}"))
+    }
+
+    /// Definition list for the header of html pages
+    pub fn to_html(&self) -> String {
+        let mut dl = "<dl class=\"props\">\n".to_owned();
+        for (name, value) in &self.0 {
+            dl.push_str(&format!(
+                "<dt>{}</dt><dd>{}</dd>\n",
+                html::escape(name),
+                html::escape(value)
+            ));
+        }
+        dl.push_str("</dl>\n");
+        dl
+    }
+}
+
+impl Display for Session {
+    /// Describe the recording like "Session recorded by rogcat 0.4.7 on
+    /// Pixel 7 (1A2B3C) at 2024-01-10T23:56:50+01:00: -t ActivityManager"
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Session recorded by rogcat {}", self.version)?;
+        match (&self.model, &self.serial) {
+            (Some(model), Some(serial)) => write!(f, " on {model} ({serial})")?,
+            (Some(device), None) | (None, Some(device)) => write!(f, " on {device}")?,
+            (None, None) => (),
+        }
+        write!(f, " at {}", self.start)?;
+        if let Some(profile) = &self.profile {
+            write!(f, " with profile {profile}")?;
+        }
+        if !self.args.is_empty() {
+            write!(f, ": {}", self.args.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn session_round_trip() {
+    let session = Session {
+        version: "0.4.7".to_owned(),
+        schema: crate::record::SCHEMA,
+        serial: Some("1A2B3C".to_owned()),
+        model: Some("Pixel 7".to_owned()),
+        start: "2024-01-10T23:56:50+01:00".to_owned(),
+        args: vec!["-t".to_owned(), "ActivityManager".to_owned()],
+        profile: None,
+        comment: None,
+    };
+    let line = session.to_line();
+    assert!(!line.contains('\n'));
+    assert!(Session::is_header(&line));
+    assert_eq!(Session::parse(&line), Some(session.clone()));
+    assert_eq!(
+        session.to_string(),
+        "Session recorded by rogcat 0.4.7 on Pixel 7 (1A2B3C) at 2024-01-10T23:56:50+01:00: -t \
+         ActivityManager"
+    );
+
+    // Sessions of versions without schema
+    let old = Session::parse(
+        r#"# rogcat session {"version":"0.4.6","serial":null,"model":null,"start":"2024-01-10T23:56:50+01:00","args":[],"profile":null,"comment":null}"#,
+    )
+    .unwrap();
+    assert_eq!(old.schema, 0);
+    assert_eq!(old.version, "0.4.6");
+
+    assert!(Session::parse("01-10 23:56:50.101  1234  1235 I Tag: message").is_none());
+    assert!(Session::parse("# rogcat session {").is_none());
+}
+
+#[test]
+fn props_from_getprop() {
+    let output = "[dalvik.vm.heapsize]: [512m]\n\
+                  [ro.build.fingerprint]: [google/panther/panther:14/UQ1A.240105.004/11206848:user/release-keys]\n\
+                  [ro.build.version.release]: [14]\n\
+                  [ro.product.model]: [Pixel 7]\r\n\
+                  [ro.serialno]: []\n\
+                  garbage\n\
+                  [ro.product.brand]: [google";
+    let names = [
+        "ro.product.model",
+        "ro.build.version.release",
+        "ro.serialno",
+        "ro.product.brand",
+    ]
+    .map(str::to_owned);
+    let props = Props::from_getprop(output, &names);
+    assert_eq!(
+        props.lines().collect::<Vec<_>>(),
+        [
+            "ro.build.version.release: 14",
+            "ro.product.model: Pixel 7",
+            "ro.serialno: "
+        ]
+    );
+
+    let line = props.to_line();
+    assert!(Session::is_header(&line));
+    assert_eq!(Props::parse(&line), Some(props));
+    assert!(Props::from_getprop("", &names).is_empty());
+
+    let props = Props::from_getprop("[ro.product.model]: [<Pixel> & co]", &names);
+    assert_eq!(
+        props.to_html(),
+        "<dl class=\"props\">\n<dt>ro.product.model</dt><dd>&lt;Pixel&gt; &amp; co</dd>\n</dl>\n"
+    );
+}
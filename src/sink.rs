@@ -0,0 +1,230 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    record::{Column, Format, LevelStyle, Record},
+    template::Template,
+    Error,
+};
+use futures::{
+    ready,
+    sink::Sink,
+    task::{Context, Poll},
+};
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display, Formatter},
+    io::{BufWriter, Write},
+    pin::Pin,
+};
+
+/// Number of records matched by each highlight pattern
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HighlightSummary {
+    /// Patterns in the order they were given with their count
+    pub counts: Vec<(String, usize)>,
+}
+
+impl Display for HighlightSummary {
+    /// Summary like "highlights: ANR×3, OutOfMemory×1"
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "highlights: ")?;
+        for (n, (pattern, count)) in self.counts.iter().enumerate() {
+            if n > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{pattern}×{count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Sinks that highlight records matching patterns
+pub trait Highlights {
+    /// Number of records matched by each pattern so far
+    fn highlights(&self) -> HighlightSummary;
+}
+
+/// Sink that writes records in a format to a writer, one record per line
+pub struct FormatSink<T: Write> {
+    format: Format,
+    columns: Option<Vec<Column>>,
+    level_style: LevelStyle,
+    template: Option<Template>,
+    sink: BufWriter<T>,
+}
+
+impl<T: Write> FormatSink<T> {
+    /// Create a sink writing to `sink`. Records in the human format fail to
+    /// be written.
+    pub fn new(format: Format, sink: T) -> FormatSink<T> {
+        FormatSink {
+            format,
+            columns: None,
+            level_style: LevelStyle::default(),
+            template: None,
+            sink: BufWriter::new(sink),
+        }
+    }
+
+    /// Select and order the columns of csv and json output. All columns
+    /// are written if `columns` is empty.
+    pub fn columns(mut self, columns: Vec<Column>) -> Self {
+        self.columns = (!columns.is_empty()).then_some(columns);
+        self
+    }
+
+    /// Write the levels of csv and json output in `style`
+    pub fn level_style(mut self, style: LevelStyle) -> Self {
+        self.level_style = style;
+        self
+    }
+
+    /// Write the records with `template` instead of the format
+    pub fn template(mut self, template: Template) -> Self {
+        self.template = Some(template);
+        self
+    }
+}
+
+impl<T: Write + std::marker::Unpin> Sink<Record> for FormatSink<T> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Record) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let result = match (&this.template, &this.format) {
+            (Some(template), _) => {
+                let mut line = template.render(&item);
+                line.push('\n');
+                this.sink.write_all(line.as_bytes())
+            }
+            // Raw lines are written without copying them
+            (None, Format::Raw) => this
+                .sink
+                .write_all(item.raw.as_bytes())
+                .and_then(|_| this.sink.write_all(b"\n")),
+            (None, _) => {
+                let mut line =
+                    this.format
+                        .fmt_styled(&item, this.columns.as_deref(), this.level_style)?;
+                line.push('\n');
+                this.sink.write_all(line.as_bytes())
+            }
+        };
+        result.map_err(|e| Error::io("Failed to write", e))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(
+            self.get_mut()
+                .sink
+                .flush()
+                .map_err(|e| Error::io("Failed to write", e)),
+        )
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// Sink with records waiting for it to become ready. Wrapping sinks that
+/// pass on more than one record per record queue them here.
+pub struct Queued<S> {
+    sink: S,
+    queue: VecDeque<Record>,
+}
+
+impl<S: Sink<Record, Error = Error> + Unpin> Queued<S> {
+    pub fn new(sink: S) -> Queued<S> {
+        Queued {
+            sink,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Queue `record` until the sink is ready
+    pub fn push(&mut self, record: Record) {
+        self.queue.push_back(record);
+    }
+
+    /// Queue `records` in order until the sink is ready
+    pub fn extend(&mut self, records: impl IntoIterator<Item = Record>) {
+        self.queue.extend(records);
+    }
+
+    /// Pass queued records to the sink
+    pub fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        while !self.queue.is_empty() {
+            ready!(Pin::new(&mut self.sink).poll_ready(cx))?;
+            let record = self.queue.pop_front().expect("Empty queue");
+            Pin::new(&mut self.sink).start_send(record)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Pass the queued records to the sink and flush it
+    pub fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.sink).poll_flush(cx)
+    }
+
+    /// Pass the queued records to the sink and close it
+    pub fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.sink).poll_close(cx)
+    }
+}
+
+#[test]
+fn highlight_summary() {
+    let summary = HighlightSummary {
+        counts: vec![
+            ("ANR".to_owned(), 3),
+            ("OutOfMemory".to_owned(), 1),
+            ("strict-mode".to_owned(), 12),
+        ],
+    };
+    assert_eq!(
+        summary.to_string(),
+        "highlights: ANR×3, OutOfMemory×1, strict-mode×12"
+    );
+}
+
+#[tokio::test]
+async fn queued_records() {
+    use futures::{channel::mpsc, future::poll_fn, SinkExt, StreamExt};
+
+    let (tx, rx) = mpsc::unbounded();
+    let mut sink = Queued::new(tx.sink_map_err(|e| Error::Invalid(e.to_string())));
+    let record = |message: &str| Record {
+        message: message.to_owned(),
+        ..Default::default()
+    };
+    sink.push(record("a"));
+    sink.extend([record("b"), record("c")]);
+    poll_fn(|cx| sink.poll_close(cx)).await.unwrap();
+    let messages = rx.map(|r| r.message).collect::<Vec<_>>().await;
+    assert_eq!(messages, ["a", "b", "c"]);
+}
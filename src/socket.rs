@@ -0,0 +1,428 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Sink that writes the formatted records to a tcp or unix socket. Broken
+//! connections are reestablished and records are queued meanwhile.
+
+use crate::{cli::CliArguments, diagnostics::Reporter};
+use anyhow::{anyhow, Result};
+use futures::{
+    future::BoxFuture,
+    ready,
+    sink::Sink,
+    task::{Context, Poll},
+    Future, FutureExt,
+};
+use rogcat::{
+    record::{Column, Format, LevelStyle, Record},
+    Error, LogSink,
+};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display, Formatter},
+    io,
+    path::Path,
+    pin::Pin,
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWrite,
+    net::TcpStream,
+    time::{sleep, timeout, Sleep},
+};
+
+/// Maximum number of records queued while disconnected or while the
+/// connection is too slow. The oldest records are dropped first.
+const QUEUE_SIZE: usize = 10_000;
+
+/// Time between connection attempts
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Maximum duration of a connection attempt
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Address of a socket output
+#[derive(Clone, Debug, PartialEq)]
+pub enum Address {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Address {
+    /// Address of an output like "tcp://host:port" or "unix:///path".
+    /// `None` if `output` is a file.
+    pub fn parse(output: &Path) -> Option<Address> {
+        let output = output.to_str()?;
+        if let Some(address) = output.strip_prefix("tcp://") {
+            return Some(Address::Tcp(address.to_owned()));
+        }
+        #[cfg(unix)]
+        if let Some(path) = output.strip_prefix("unix://") {
+            return Some(Address::Unix(PathBuf::from(path)));
+        }
+        None
+    }
+
+    async fn connect(self) -> io::Result<Connection> {
+        let connect = async {
+            Ok(match self {
+                Address::Tcp(address) => Box::pin(TcpStream::connect(address).await?) as Connection,
+                #[cfg(unix)]
+                Address::Unix(path) => Box::pin(tokio::net::UnixStream::connect(path).await?),
+            })
+        };
+        timeout(CONNECT_TIMEOUT, connect)
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => write!(f, "tcp://{address}"),
+            #[cfg(unix)]
+            Address::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+type Connection = Pin<Box<dyn AsyncWrite + Send>>;
+
+enum State {
+    /// Waiting for the next connection attempt
+    Waiting(Pin<Box<Sleep>>),
+    Connecting(BoxFuture<'static, io::Result<Connection>>),
+    Connected(Connection),
+}
+
+struct SocketSink {
+    address: Address,
+    format: Format,
+    columns: Option<Vec<Column>>,
+    level_style: LevelStyle,
+    state: State,
+    /// Formatted records not yet written
+    queue: VecDeque<Vec<u8>>,
+    /// Maximum length of the queue
+    capacity: usize,
+    /// Bytes of the first queued record that are written
+    written: usize,
+    /// Records dropped from the full queue since the last report
+    dropped: usize,
+    /// Set when the connection broke or failed until it's reestablished
+    disconnected: bool,
+    reconnect_delay: Duration,
+    /// Set by close. A single connection attempt is made to write the
+    /// remaining records.
+    closing: bool,
+    close_attempted: bool,
+    reporter: Reporter,
+}
+
+/// Create a sink writing the records to `address` in the format of
+/// `args.format`. The output defaults to json.
+pub fn try_from(address: Address, args: &CliArguments) -> Result<LogSink> {
+    Ok(Box::new(SocketSink::new(address, args)?))
+}
+
+impl SocketSink {
+    fn new(address: Address, args: &CliArguments) -> Result<SocketSink> {
+        let format = args.format.clone().unwrap_or(Format::Json);
+        if matches!(format, Format::Human | Format::Html) {
+            return Err(anyhow!(
+                "The {format} format is not supported for {address}"
+            ));
+        }
+        Ok(SocketSink {
+            state: State::Connecting(address.clone().connect().boxed()),
+            address,
+            format,
+            columns: (!args.columns.is_empty()).then(|| args.columns.clone()),
+            level_style: args.level_style,
+            queue: VecDeque::new(),
+            capacity: QUEUE_SIZE,
+            written: 0,
+            dropped: 0,
+            disconnected: false,
+            reconnect_delay: RECONNECT_DELAY,
+            closing: false,
+            close_attempted: false,
+            reporter: Reporter::new(args),
+        })
+    }
+
+    /// Connect if needed and write the queue. Ready once the queue is
+    /// written or no connection could be made while closing.
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            match &mut self.state {
+                State::Waiting(delay) => {
+                    if !self.closing {
+                        ready!(delay.as_mut().poll(cx));
+                    } else if self.close_attempted {
+                        return Poll::Ready(());
+                    } else {
+                        self.close_attempted = true;
+                    }
+                    self.state = State::Connecting(self.address.clone().connect().boxed());
+                }
+                State::Connecting(connect) => match ready!(connect.as_mut().poll(cx)) {
+                    Ok(connection) => {
+                        if self.disconnected {
+                            let dropped = std::mem::take(&mut self.dropped);
+                            self.report(&format!(
+                                "Reconnected to {}. {dropped} records dropped meanwhile.",
+                                self.address
+                            ));
+                        }
+                        self.disconnected = false;
+                        self.written = 0;
+                        self.state = State::Connected(connection);
+                    }
+                    Err(e) => {
+                        if !self.disconnected {
+                            self.report(&format!(
+                                "Failed to connect to {}: {e}. Retrying.",
+                                self.address
+                            ));
+                        }
+                        self.disconnected = true;
+                        self.state = State::Waiting(Box::pin(sleep(self.reconnect_delay)));
+                    }
+                },
+                State::Connected(connection) => {
+                    let result = ready!(poll_write_queue(
+                        connection,
+                        &mut self.queue,
+                        &mut self.written,
+                        cx
+                    ));
+                    let Err(e) = result else {
+                        // Records dropped while connected are reported
+                        // once the connection caught up
+                        let dropped = std::mem::take(&mut self.dropped);
+                        if dropped > 0 {
+                            self.report(&format!(
+                                "{dropped} records dropped because {} was too slow",
+                                self.address
+                            ));
+                        }
+                        return Poll::Ready(());
+                    };
+                    self.report(&format!("Connection to {} lost: {e}", self.address));
+                    self.disconnected = true;
+                    self.state = State::Connecting(self.address.clone().connect().boxed());
+                }
+            }
+        }
+    }
+
+    /// Connection problems are reported but don't end the output
+    fn report(&mut self, message: &str) {
+        self.reporter.report(message).ok();
+    }
+}
+
+/// Write the records of `queue` to `connection`. `written` bytes of the first
+/// record are already written.
+fn poll_write_queue(
+    connection: &mut Connection,
+    queue: &mut VecDeque<Vec<u8>>,
+    written: &mut usize,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<()>> {
+    while let Some(line) = queue.front() {
+        let n = ready!(connection.as_mut().poll_write(cx, &line[*written..]))?;
+        if n == 0 {
+            return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+        }
+        *written += n;
+        if *written == line.len() {
+            queue.pop_front();
+            *written = 0;
+        }
+    }
+    connection.as_mut().poll_flush(cx)
+}
+
+impl Sink<Record> for SocketSink {
+    type Error = Error;
+
+    /// The queue is bounded by dropping the oldest records, also while
+    /// connected. Writing continues while records are passed.
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let _ = self.get_mut().poll_send(cx);
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, record: Record) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let mut line = this
+            .format
+            .fmt_styled(&record, this.columns.as_deref(), this.level_style)?
+            .into_bytes();
+        line.push(b'\n');
+        this.queue.push_back(line);
+        if this.queue.len() > this.capacity {
+            // A partially written record is completed
+            this.queue.remove(usize::from(this.written > 0));
+            this.dropped += 1;
+        }
+        Ok(())
+    }
+
+    /// Pending until the queue is written. Records queued while disconnected
+    /// don't block the flush of the pipeline.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.poll_send(cx).is_pending() && !this.disconnected {
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Write the queue and shut down the connection. One connection attempt
+    /// is made if disconnected.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        this.closing = true;
+        ready!(this.poll_send(cx));
+        if let State::Connected(connection) = &mut this.state {
+            ready!(connection.as_mut().poll_shutdown(cx)).ok();
+        }
+        let lost = this.queue.len() + std::mem::take(&mut this.dropped);
+        if lost > 0 {
+            this.report(&format!("{lost} records not sent to {}", this.address));
+        }
+        this.queue.clear();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn socket_addresses() {
+    assert_eq!(
+        Address::parse(Path::new("tcp://localhost:1234")),
+        Some(Address::Tcp("localhost:1234".to_owned()))
+    );
+    #[cfg(unix)]
+    assert_eq!(
+        Address::parse(Path::new("unix:///tmp/rogcat.sock")),
+        Some(Address::Unix(PathBuf::from("/tmp/rogcat.sock")))
+    );
+    assert_eq!(Address::parse(Path::new("/tmp/tcp://log")), None);
+}
+
+#[tokio::test]
+async fn socket_reconnect() {
+    use clap::Parser;
+    use futures::SinkExt;
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        net::TcpListener,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = Address::Tcp(listener.local_addr().unwrap().to_string());
+    let args = CliArguments::parse_from(["rogcat", "-f", "raw", "--color", "never"]);
+    let mut sink = SocketSink::new(address, &args).unwrap();
+    sink.reconnect_delay = Duration::from_millis(10);
+    let record = |i: usize| Record {
+        raw: i.to_string(),
+        ..Default::default()
+    };
+
+    for i in 0..10 {
+        sink.feed(record(i)).await.unwrap();
+    }
+    sink.flush().await.unwrap();
+    let (connection, _) = listener.accept().await.unwrap();
+    let mut lines = BufReader::new(connection).lines();
+    for i in 0..10 {
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), i.to_string());
+    }
+    drop(lines);
+
+    // Records written until the broken connection is noticed are lost
+    let mut i = 10;
+    let connection = loop {
+        sink.send(record(i)).await.unwrap();
+        i += 1;
+        let accept = timeout(Duration::from_millis(10), listener.accept()).await;
+        if let Ok(Ok((connection, _))) = accept {
+            break connection;
+        }
+    };
+    sink.send(record(1000)).await.unwrap();
+    sink.close().await.unwrap();
+
+    let mut lines = BufReader::new(connection).lines();
+    let mut received = Vec::new();
+    while let Some(line) = lines.next_line().await.unwrap() {
+        received.push(line.parse::<usize>().unwrap());
+    }
+    assert_eq!(received.last(), Some(&1000));
+    assert!(received.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[tokio::test]
+async fn socket_queue_bound() {
+    use clap::Parser;
+    use futures::SinkExt;
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        net::TcpListener,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = Address::Tcp(listener.local_addr().unwrap().to_string());
+    let args = CliArguments::parse_from(["rogcat", "-f", "raw", "--color", "never"]);
+    let mut sink = SocketSink::new(address, &args).unwrap();
+    sink.capacity = 3;
+    sink.flush().await.unwrap();
+    let (connection, _) = listener.accept().await.unwrap();
+    assert!(matches!(sink.state, State::Connected(_)));
+
+    // Records passed faster than they are written drop the oldest ones
+    for i in 0..10 {
+        let record = Record {
+            raw: i.to_string(),
+            ..Default::default()
+        };
+        Pin::new(&mut sink).start_send(record).unwrap();
+    }
+    assert_eq!(sink.queue.len(), 3);
+    assert_eq!(sink.dropped, 7);
+
+    // The drops are reported once the queue is written
+    sink.flush().await.unwrap();
+    assert_eq!(sink.dropped, 0);
+    sink.close().await.unwrap();
+    let mut lines = BufReader::new(connection).lines();
+    let mut received = Vec::new();
+    while let Some(line) = lines.next_line().await.unwrap() {
+        received.push(line);
+    }
+    assert_eq!(received, ["7", "8", "9"]);
+}
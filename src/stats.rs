@@ -0,0 +1,212 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use futures::{
+    ready,
+    sink::Sink,
+    task::{Context, Poll},
+};
+use rogcat::{
+    record::{Level, Record},
+    rewrite::Rewrite,
+    Error, LogSink,
+};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    pin::Pin,
+    time::Instant,
+};
+use tabled::{builder::Builder, settings::Style};
+
+/// Number of tags and processes shown in the summary
+const TOP: usize = 10;
+
+/// Record counters collected while forwarding records
+struct Stats {
+    start: Instant,
+    records: usize,
+    levels: HashMap<Level, usize>,
+    tags: HashMap<String, usize>,
+    processes: HashMap<String, usize>,
+}
+
+/// Sink that counts records and prints a summary to stderr when closed
+struct StatsSink {
+    sink: Pin<LogSink>,
+    stats: Stats,
+}
+
+/// Wrap `sink` and print statistics of all records passed on close
+pub fn wrap(sink: Pin<LogSink>) -> Pin<LogSink> {
+    Box::pin(StatsSink {
+        sink,
+        stats: Stats::new(),
+    })
+}
+
+/// Increment the counter of `key` without allocating if already present
+fn count(map: &mut HashMap<String, usize>, key: &str) {
+    match map.get_mut(key) {
+        Some(n) => *n += 1,
+        None => {
+            map.insert(key.to_owned(), 1);
+        }
+    }
+}
+
+/// Build a table with the `TOP` most frequent entries of `map`
+fn top(header: &str, map: &HashMap<String, usize>) -> String {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+    let mut builder = Builder::default();
+    builder.push_record([header, "RECORDS"]);
+    for (key, n) in entries.into_iter().take(TOP) {
+        builder.push_record([key.to_owned(), n.to_string()]);
+    }
+    builder.build().with(Style::modern_rounded()).to_string()
+}
+
+/// Table with the number of records changed by each rewrite rule
+pub fn rewrites(rewrite: &Rewrite) -> String {
+    let mut builder = Builder::default();
+    builder.push_record(["REWRITE", "RECORDS"]);
+    for (rule, n) in rewrite.counts() {
+        builder.push_record([rule.to_owned(), n.to_string()]);
+    }
+    builder.build().with(Style::modern_rounded()).to_string()
+}
+
+impl Stats {
+    fn new() -> Stats {
+        Stats {
+            start: Instant::now(),
+            records: 0,
+            levels: HashMap::new(),
+            tags: HashMap::new(),
+            processes: HashMap::new(),
+        }
+    }
+
+    fn record(&mut self, record: &Record) {
+        self.records += 1;
+        *self.levels.entry(record.level.clone()).or_default() += 1;
+        count(&mut self.tags, &record.tag);
+        count(&mut self.processes, &record.process);
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.records as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        let mut levels = self.levels.iter().collect::<Vec<_>>();
+        levels.sort_by(|a, b| a.0.partial_cmp(b.0).unwrap());
+        let mut builder = Builder::default();
+        builder.push_record(["LEVEL", "RECORDS"]);
+        for (level, n) in levels {
+            builder.push_record([level.to_string(), n.to_string()]);
+        }
+        let levels = builder.build().with(Style::modern_rounded()).to_string();
+
+        writeln!(
+            f,
+            "{} records in {:.1}s ({:.1} records/s)",
+            self.records, elapsed, rate
+        )?;
+        writeln!(f, "{levels}")?;
+        writeln!(f, "{}", top("TAG", &self.tags))?;
+        write!(f, "{}", top("PROCESS", &self.processes))
+    }
+}
+
+impl Sink<Record> for StatsSink {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sink.as_mut().poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Record) -> Result<(), Self::Error> {
+        // Notices like buffer dividers are no records of the source
+        if item.notice.is_none() {
+            self.stats.record(&item);
+        }
+        self.sink.as_mut().start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sink.as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.sink.as_mut().poll_close(cx))?;
+        eprintln!("{}", self.stats);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn stats_summary() {
+    let mut stats = Stats::new();
+    for (tag, level) in [
+        ("a", Level::Info),
+        ("b", Level::Error),
+        ("a", Level::Info),
+        ("c", Level::Debug),
+        ("a", Level::Error),
+    ] {
+        stats.record(&Record {
+            tag: tag.to_owned(),
+            level,
+            process: "1".to_owned(),
+            ..Default::default()
+        });
+    }
+    assert_eq!(stats.records, 5);
+    assert_eq!(stats.tags["a"], 3);
+    assert_eq!(stats.levels[&Level::Error], 2);
+    assert_eq!(stats.processes["1"], 5);
+
+    let summary = stats.to_string();
+    assert!(summary.starts_with("5 records in "));
+    // Levels are ordered by severity, tags by count
+    assert!(summary.find("│ D ").unwrap() < summary.find("│ E ").unwrap());
+    assert!(summary.find("│ a ").unwrap() < summary.find("│ b ").unwrap());
+}
+
+#[test]
+fn rewrite_summary() {
+    let rewrite = Rewrite::parse(&["s/a/b/", "s/x/y/"]).unwrap();
+    let mut record = Record {
+        message: "a".to_owned(),
+        ..Default::default()
+    };
+    rewrite.apply(&mut record);
+    let summary = rewrites(&rewrite);
+    assert!(summary.contains("│ s/a/b/  │ 1 "), "{summary}");
+    assert!(summary.contains("│ s/x/y/  │ 0 "), "{summary}");
+}
@@ -19,24 +19,41 @@
 // SOFTWARE.
 
 use crate::{
-    cli::{ClearOpts, CliArguments, LogOpts, SubCommands},
-    profiles::profiles_list,
-    reader::stdin,
-    utils::{self, adb},
-    StreamData, DEFAULT_BUFFER,
+    cli::{
+        BugReportOpts, ClearOpts, CliArguments, CompletionList, CompletionsOpts, ConfigAction,
+        ConfigOpts, CtlOpts, DevicesOpts, ListFormat, LogOpts, ProfilesOpts, SubCommands,
+    },
+    crashes, diff, utils,
 };
+use anyhow::{anyhow, Error, Result};
 use clap::{crate_name, CommandFactory};
-use clap_complete::{generate, Generator};
-use failure::Error;
+use clap_complete::{generate, Shell};
 use futures::{
+    channel::mpsc,
+    executor::block_on_stream,
     future::ready,
-    sink::Sink,
+    ready,
+    sink::{Sink, SinkExt},
     stream::StreamExt,
     task::{Context, Poll},
+    Future,
+};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use rogcat::{
+    adb::adb_command,
+    config_dir,
+    control::{self, ControlAddress},
+    profiles::{profiles_list, Profile},
+    reader::stdin,
+    record::Level,
+    StreamData, DEFAULT_BUFFER,
 };
-use rogcat::record::Level;
+use serde::Serialize;
 use std::{
     borrow::ToOwned,
+    collections::HashMap,
+    fs::{self, File},
+    io::{Seek, Write},
     path::PathBuf,
     pin::Pin,
     process::{exit, Stdio},
@@ -46,64 +63,440 @@ use tabled::{
     settings::{object::Rows, Alignment, Style, Width},
 };
 
+use time::{macros::format_description, OffsetDateTime};
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    process::Command,
+    io::{AsyncReadExt, AsyncWrite},
+    process::{Child, ChildStdin},
 };
-use tokio_stream::wrappers::LinesStream;
+use toml_edit::{Array, Document, InlineTable, Item, Value};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 
-pub async fn parse_subcommand(command: SubCommands) {
+/// Run `command` on the device selected with -s
+pub async fn parse_subcommand(command: SubCommands, serials: &[String]) {
+    let device = match serials {
+        [] => None,
+        [device] => Some(device.as_str()),
+        _ => {
+            eprintln!("Subcommands support only one device");
+            exit(1)
+        }
+    };
     match command {
-        SubCommands::Clear(opts) => clear(opts).await,
-        SubCommands::Completions(opts) => completions(opts.shell).await,
-        SubCommands::Devices => devices().await,
-        SubCommands::Log(opts) => log(opts).await.unwrap(),
-        SubCommands::Profiles(opts) => profiles(opts.profiles_path).unwrap(),
+        SubCommands::BugReport(opts) => bugreport(opts, device).await.unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1)
+        }),
+        SubCommands::Clear(opts) => clear(opts, device).await,
+        SubCommands::Crashes(opts) => crashes::crashes(opts, device).await.unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1)
+        }),
+        SubCommands::Completions(opts) => completions(opts, device).await,
+        SubCommands::Config(opts) => config(opts).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1)
+        }),
+        SubCommands::Ctl(opts) => ctl(opts).await.unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1)
+        }),
+        SubCommands::Devices(opts) => devices(opts, device).await.unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1)
+        }),
+        SubCommands::Diff(opts) => match diff::diff_files(opts).await {
+            Ok(true) => (),
+            Ok(false) => exit(1),
+            Err(e) => {
+                eprintln!("{e}");
+                exit(2)
+            }
+        },
+        SubCommands::Log(opts) => log(opts, device).await.unwrap(),
+        SubCommands::Profiles(opts) => profiles(opts).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1)
+        }),
+        // Run through the pipeline by the caller
+        SubCommands::Convert(_) | SubCommands::Replay(_) => {
+            eprintln!("Conversions and replays are not a subcommand of a device");
+            exit(1)
+        }
     }
 }
 
-pub async fn completions<T: Generator>(shell: T) {
-    let mut cmd = CliArguments::command();
-    generate(shell, &mut cmd, crate_name!(), &mut std::io::stdout());
-    exit(0);
+/// Number of chunks of a bug report waiting to be written
+const REPORT_BUFFER: usize = 16;
+
+/// File of a bug report that is optionally zipped
+enum Report<W: Write + Seek> {
+    File(W),
+    Zip(ZipWriter<W>),
 }
 
-pub async fn devices() {
-    let child = Command::new(adb().expect("Failed to find adb"))
-        .arg("devices")
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("Failed to run adb devices");
-
-    let lines = BufReader::new(child.stdout.unwrap()).lines();
-    let result = LinesStream::new(lines)
-        .skip(1)
-        .filter_map(|x| async move {
-            match x {
-                Ok(line) if line.is_empty() => None,
-                Ok(line) if line.starts_with("* daemon") => None,
-                Ok(line) => Some(line),
-                _ => None,
+impl<W: Write + Seek> Report<W> {
+    /// Start a report in `file`. A zipped report contains a single file
+    /// named `zip`.
+    fn new(file: W, zip: Option<&str>) -> Result<Report<W>> {
+        Ok(match zip {
+            Some(name) => {
+                let mut zip = ZipWriter::new(file);
+                let options =
+                    FileOptions::default().compression_method(CompressionMethod::Deflated);
+                zip.start_file(name, options)?;
+                Report::Zip(zip)
             }
+            None => Report::File(file),
         })
-        .for_each(|line| {
-            let mut split = line.split_whitespace();
-            let id = split.next().unwrap_or("unknown");
-            let name = split.next().unwrap_or("unknown");
-            println!("{id} {name}");
-            ready(())
-        });
-
-    result.await;
+    }
+
+    /// Flush the file or write the end of the zip archive
+    fn finish(self) -> Result<W> {
+        let mut file = match self {
+            Report::File(file) => file,
+            Report::Zip(mut zip) => zip.finish()?,
+        };
+        file.flush()?;
+        Ok(file)
+    }
+}
+
+impl<W: Write + Seek> Write for Report<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Report::File(file) => file.write(buf),
+            Report::Zip(zip) => zip.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Report::File(file) => file.flush(),
+            Report::Zip(zip) => zip.flush(),
+        }
+    }
+}
+
+/// Call adb bugreport and write the report to a file
+pub async fn bugreport(args: BugReportOpts, device: Option<&str>) -> Result<()> {
+    let filename = match args.file {
+        Some(file) => file,
+        None => {
+            let now = OffsetDateTime::now_local()?;
+            let format = format_description!("[month]-[day]_[hour]-[minute]-[second]");
+            PathBuf::from(format!("{}-bugreport.txt", now.format(format)?))
+        }
+    };
+
+    let mut path = filename.clone();
+    if args.zip {
+        path.set_extension("zip");
+    }
+
+    if tokio::fs::metadata(&path).await.is_ok() && !args.overwrite {
+        return Err(anyhow!(
+            "{} exists. Use overwrite flag to force!",
+            path.display()
+        ));
+    }
+
+    let mut child = adb_command(device)?
+        .arg("bugreport")
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| anyhow!("Failed to run adb bugreport: {}", e))?;
+
+    let zip = if args.zip {
+        let name = filename
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| anyhow!("Invalid filename {}", filename.display()))?;
+        Some(name.to_owned())
+    } else {
+        None
+    };
+    // The report is written on a blocking task while adb is read
+    let (mut tx, rx) = mpsc::channel::<Vec<u8>>(REPORT_BUFFER);
+    let report_path = path.clone();
+    let writer = tokio::task::spawn_blocking(move || {
+        let file = File::create(&report_path)
+            .map_err(|e| anyhow!("Failed to create {}: {}", report_path.display(), e))?;
+        let mut report = Report::new(file, zip.as_deref())?;
+        for chunk in block_on_stream(rx) {
+            report.write_all(&chunk)?;
+        }
+        report
+            .finish()
+            .map_err(|e| anyhow!("Failed to write {}: {e}", report_path.display()))?;
+        Ok::<_, Error>(())
+    });
+
+    let progress = ProgressBar::new_spinner();
+    if let Ok(style) = ProgressStyle::default_spinner()
+        .template("{spinner:.yellow} {msg:.dim.bold} {elapsed:.dim}")
+    {
+        progress.set_style(style);
+    }
+
+    let mut stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("Failed to read adb bugreport output"))?;
+    let mut buffer = [0u8; 8 * 1024];
+    let mut size = 0;
+    loop {
+        let n = stdout.read(&mut buffer).await?;
+        // A failed writer closes the channel and returns the error below
+        if n == 0 || tx.send(buffer[..n].to_vec()).await.is_err() {
+            break;
+        }
+        size += n as u64;
+        progress.set_message(format!("Writing {} ({})", path.display(), HumanBytes(size)));
+        progress.tick();
+    }
+    drop(tx);
+    writer.await??;
+
+    let status = child.wait().await?;
+    if !status.success() {
+        progress.abandon_with_message(format!("adb bugreport failed: {status}"));
+        return Err(anyhow!("adb bugreport failed: {}", status));
+    }
+
+    progress.finish_with_message(format!("Wrote {} to {}", HumanBytes(size), path.display()));
+    Ok(())
+}
+
+/// Completion of profile names and device serials for bash. Wraps the
+/// function generated by clap_complete.
+const BASH_DYNAMIC: &str = r#"
+_rogcat_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    case "${COMP_WORDS[COMP_CWORD-1]}" in
+        -p|--profile)
+            COMPREPLY=($(compgen -W "$(rogcat completions --list profiles 2>/dev/null)" -- "$cur"))
+            ;;
+        -s|--serial)
+            COMPREPLY=($(compgen -W "$(rogcat completions --list devices 2>/dev/null)" -- "$cur"))
+            ;;
+        *)
+            _rogcat "$@"
+            ;;
+    esac
+}
+
+complete -F _rogcat_dynamic -o bashdefault -o default rogcat
+"#;
+
+/// Completion of profile names and device serials for zsh. Wraps the
+/// function generated by clap_complete.
+const ZSH_DYNAMIC: &str = r#"
+_rogcat_dynamic() {
+    case "${words[CURRENT-1]}" in
+        -p|--profile)
+            compadd -- ${(f)"$(rogcat completions --list profiles 2>/dev/null)"}
+            ;;
+        -s|--serial)
+            compadd -- ${(f)"$(rogcat completions --list devices 2>/dev/null)"}
+            ;;
+        *)
+            _rogcat "$@"
+            ;;
+    esac
+}
+
+compdef _rogcat_dynamic rogcat
+"#;
+
+/// Completion of profile names and device serials for fish
+const FISH_DYNAMIC: &str = r#"
+complete -c rogcat -s p -l profile -x -a '(rogcat completions --list profiles 2>/dev/null)'
+complete -c rogcat -s s -l serial -x -a '(rogcat completions --list devices 2>/dev/null)'
+"#;
+
+/// Generate the completion script for a shell or print the candidates of
+/// dynamic completions
+pub async fn completions(args: CompletionsOpts, device: Option<&str>) {
+    let shell = match (args.list, args.shell) {
+        (Some(CompletionList::Profiles), _) => {
+            // Errors of the profiles file are no candidates
+            let mut names = profiles_list(None)
+                .map(|profiles| profiles.into_keys().collect::<Vec<_>>())
+                .unwrap_or_default();
+            names.sort();
+            names.iter().for_each(|name| println!("{name}"));
+            exit(0);
+        }
+        (Some(CompletionList::Devices), _) => {
+            let devices = list_devices(device).await.unwrap_or_default();
+            devices
+                .iter()
+                .for_each(|device| println!("{}", device.serial));
+            exit(0);
+        }
+        (None, Some(shell)) => shell,
+        (None, None) => unreachable!("Shell is required without list"),
+    };
+
+    let dynamic = match shell {
+        Shell::Bash => BASH_DYNAMIC,
+        Shell::Zsh => ZSH_DYNAMIC,
+        Shell::Fish => FISH_DYNAMIC,
+        _ if args.dynamic => {
+            eprintln!("Dynamic completions are not supported for {shell}");
+            exit(1);
+        }
+        _ => "",
+    };
+
+    let mut cmd = CliArguments::command();
+    generate(shell, &mut cmd, crate_name!(), &mut std::io::stdout());
+    if args.dynamic {
+        print!("{dynamic}");
+    }
     exit(0);
 }
 
+/// Device attached to adb as listed by `adb devices -l`
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+struct Device {
+    serial: String,
+    /// State like "device", "unauthorized" or "offline"
+    state: String,
+    product: Option<String>,
+    model: Option<String>,
+    transport_id: Option<String>,
+}
+
+impl Device {
+    /// The device accepts commands
+    fn ready(&self) -> bool {
+        self.state == "device"
+    }
+
+    /// State with a hint for devices that do not accept commands
+    fn state_text(&self) -> String {
+        match self.state.as_str() {
+            "unauthorized" => "UNAUTHORIZED (allow USB debugging on the device)".to_owned(),
+            "offline" => "OFFLINE (reconnect the device or restart adb)".to_owned(),
+            "no permissions" => "NO PERMISSIONS (check the udev rules)".to_owned(),
+            state => state.to_owned(),
+        }
+    }
+}
+
+/// Parse a line of adb devices -l like "emulator-5554 device product:sdk
+/// model:Pixel transport_id:1"
+fn device_line(line: &str) -> Option<Device> {
+    if line.trim().is_empty() || line.starts_with("* daemon") || line.starts_with("List of devices")
+    {
+        return None;
+    }
+    let mut split = line.split_whitespace();
+    let mut device = Device {
+        serial: split.next()?.to_owned(),
+        state: split.next().unwrap_or("unknown").to_owned(),
+        ..Default::default()
+    };
+    // The only state of several words. Its explanation ends with a link.
+    if device.state == "no" {
+        device.state = "no permissions".to_owned();
+    }
+    for word in split {
+        match word.split_once(':') {
+            Some(("product", value)) => device.product = Some(value.to_owned()),
+            Some(("model", value)) => device.model = Some(value.to_owned()),
+            Some(("transport_id", value)) => device.transport_id = Some(value.to_owned()),
+            // Attributes like usb:1-1 or device:generic_x86
+            _ => (),
+        }
+    }
+    Some(device)
+}
+
+/// Devices attached to adb
+async fn list_devices(device: Option<&str>) -> Result<Vec<Device>> {
+    let output = adb_command(device)?
+        .args(["devices", "-l"])
+        .stderr(Stdio::null())
+        .output()
+        .await?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(device_line)
+        .collect())
+}
+
+/// Table of `devices`
+fn devices_table(devices: &[Device]) -> String {
+    let none = || "-".to_owned();
+    let mut items = vec![["SERIAL", "STATE", "PRODUCT", "MODEL", "TRANSPORT ID"]
+        .map(ToOwned::to_owned)
+        .to_vec()];
+    items.extend(devices.iter().map(|device| {
+        vec![
+            device.serial.clone(),
+            device.state_text(),
+            device.product.clone().unwrap_or_else(none),
+            device.model.clone().unwrap_or_else(none),
+            device.transport_id.clone().unwrap_or_else(none),
+        ]
+    }));
+    let mut table = Builder::from(items).build();
+    table.with(Style::modern_rounded());
+    table.to_string()
+}
+
+pub async fn devices(opts: DevicesOpts, device: Option<&str>) -> Result<()> {
+    let devices = list_devices(device)
+        .await
+        .map_err(|e| anyhow!("Failed to run adb devices: {e}"))?;
+    match opts.format {
+        ListFormat::Json => println!("{}", serde_json::to_string_pretty(&devices)?),
+        ListFormat::Table if devices.is_empty() => eprintln!("No devices found"),
+        ListFormat::Table => println!("{}", devices_table(&devices)),
+    }
+    if opts.require_device && !devices.iter().any(Device::ready) {
+        return Err(anyhow!("No device is ready"));
+    }
+    Ok(())
+}
+
+/// Number of bytes buffered before waiting for the shell to read them
+const LOG_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Writes `log` commands to a single `adb shell`. The shell runs them in
+/// order.
 struct Logger {
     tag: String,
     level: Level,
+    /// Read level and tag from lines like "W/Tag: message"
+    prefixed: bool,
+    shell: Child,
+    stdin: Option<ChildStdin>,
+    buffer: Vec<u8>,
 }
 
 impl Logger {
+    fn spawn(tag: String, level: Level, prefixed: bool, device: Option<&str>) -> Result<Logger> {
+        let mut shell = adb_command(device)?
+            .arg("shell")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| anyhow!("Failed to spawn adb shell: {e}"))?;
+        let stdin = shell.stdin.take();
+        Ok(Logger {
+            tag,
+            level,
+            prefixed,
+            shell,
+            stdin,
+            buffer: Vec::new(),
+        })
+    }
+
     fn level(level: &Level) -> &str {
         match *level {
             Level::Trace | Level::Verbose => "v",
@@ -114,51 +507,118 @@ impl Logger {
             Level::Error | Level::Assert => "e",
         }
     }
+
+    /// Shell command logging `message`. A prefix like "W/Tag: " overrides
+    /// the level and tag if enabled.
+    fn command(&self, line: &str) -> String {
+        let (level, tag, message) = match prefix(line).filter(|_| self.prefixed) {
+            Some((level, tag, message)) => (level, tag, message),
+            None => (self.level.clone(), self.tag.as_str(), line),
+        };
+        log_command(&level, tag, message)
+    }
+
+    fn poll_write_buffer(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        while !self.buffer.is_empty() {
+            let stdin = self
+                .stdin
+                .as_mut()
+                .ok_or_else(|| anyhow!("adb shell is closed"))?;
+            let n = ready!(Pin::new(stdin).poll_write(cx, &self.buffer))
+                .map_err(|e| anyhow!("Failed to write to adb shell: {e}"))?;
+            self.buffer.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Quote `text` for the device shell
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Shell command that logs `message`
+fn log_command(level: &Level, tag: &str, message: &str) -> String {
+    format!(
+        "log -p {} -t {} {}\n",
+        Logger::level(level),
+        shell_quote(tag),
+        shell_quote(message)
+    )
+}
+
+/// Split a line like "W/Tag: message" into level, tag and message
+fn prefix(line: &str) -> Option<(Level, &str, &str)> {
+    let (level, rest) = line.split_once('/')?;
+    let level = Level::from_letter(level)?;
+    let (tag, message) = rest.split_once(':')?;
+    let tag = tag.trim();
+    (!tag.is_empty()).then(|| (level, tag, message.strip_prefix(' ').unwrap_or(message)))
 }
 
 impl Sink<String> for Logger {
     type Error = Error;
 
-    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.buffer.len() >= LOG_BUFFER_SIZE {
+            ready!(this.poll_write_buffer(cx))?;
+        }
         Poll::Ready(Ok(()))
     }
 
-    fn start_send(self: Pin<&mut Self>, message: String) -> Result<(), Self::Error> {
-        let child = Command::new(adb()?)
-            .arg("shell")
-            .arg("log")
-            .arg("-p")
-            .arg(Self::level(&self.level))
-            .arg("-t")
-            .arg(format!("\"{}\"", &self.tag))
-            .arg(format!("\"{message}\""))
-            .stdout(Stdio::piped())
-            .output();
-        tokio::spawn(child);
+    fn start_send(self: Pin<&mut Self>, line: String) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let command = this.command(&line);
+        this.buffer.extend(command.as_bytes());
         Ok(())
     }
 
-    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buffer(cx))?;
+        match this.stdin.as_mut() {
+            Some(stdin) => Pin::new(stdin)
+                .poll_flush(cx)
+                .map_err(|e| anyhow!("Failed to write to adb shell: {e}")),
+            None => Poll::Ready(Ok(())),
+        }
     }
 
-    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    /// Close stdin and wait for the shell to run the pending commands
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        let this = self.get_mut();
+        this.stdin = None;
+        let status = {
+            let wait = this.shell.wait();
+            futures::pin_mut!(wait);
+            ready!(wait.poll(cx)).map_err(|e| anyhow!("Failed to wait for adb shell: {e}"))?
+        };
+        if status.success() {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Ready(Err(anyhow!("adb shell exited with {status}")))
+        }
     }
 }
 
 /// Call something like adb shell log <message>
-pub async fn log(args: LogOpts) -> Result<(), Error> {
+pub async fn log(args: LogOpts, device: Option<&str>) -> Result<()> {
     let message = args.message.as_str();
     let tag = args.tag.unwrap_or("Rogcat".to_string());
-    let level = Level::from(args.level);
+    let level = args.level.unwrap_or_default();
     match message {
         "-" => {
-            let sink = Logger { tag, level };
+            let sink = Logger::spawn(tag, level, args.prefixed, device)?;
             let stdin = Box::into_pin(stdin());
             stdin
-                .map(|d| match d {
-                    StreamData::Line(l) => l,
+                .filter_map(|d| match d {
+                    StreamData::Line(l) | StreamData::ErrorLine(l) => ready(Some(l)),
+                    StreamData::Diagnostic(_, message) => {
+                        eprintln!("{message}");
+                        ready(None)
+                    }
                     _ => panic!("Received non line item during log"),
                 })
                 .map(Ok)
@@ -166,14 +626,9 @@ pub async fn log(args: LogOpts) -> Result<(), Error> {
                 .await?;
         }
         _ => {
-            Command::new(adb().expect("Failed to find adb"))
+            adb_command(device)?
                 .arg("shell")
-                .arg("log")
-                .arg("-p")
-                .arg(Logger::level(&level))
-                .arg("-t")
-                .arg(format!("\"{tag}\""))
-                .arg(format!("\"{message}\""))
+                .arg(log_command(&level, &tag, message).trim_end())
                 .stdout(Stdio::piped())
                 .output()
                 .await?;
@@ -184,14 +639,15 @@ pub async fn log(args: LogOpts) -> Result<(), Error> {
 }
 
 /// Call adb logcat -c -b BUFFERS
-pub async fn clear(args: ClearOpts) {
+pub async fn clear(args: ClearOpts, device: Option<&str>) {
     let buffer = args
         .buffer
         .or_else(|| utils::config_get("buffer"))
         .unwrap_or_else(|| DEFAULT_BUFFER.iter().map(|&s| s.to_owned()).collect())
         .join(" -b ");
 
-    let mut child = Command::new(adb().expect("Failed to find adb"))
+    let mut child = adb_command(device)
+        .expect("Failed to find adb")
         .arg("logcat")
         .arg("-c")
         .arg("-b")
@@ -209,36 +665,345 @@ pub async fn clear(args: ClearOpts) {
     );
 }
 
-pub fn profiles(path: Option<PathBuf>) -> Result<(), Error> {
-    let list = profiles_list(path.as_ref())?;
-    if list.is_empty() {
-        let profiles_path = utils::config_dir().join("profiles.toml");
-        eprintln!("No profiles found! Check your profiles file ({profiles_path:?}) or set a ROGCAT_PROFILES environment variable.");
-        exit(0)
+/// Send a command to the control socket of a running rogcat and print the
+/// acknowledgement. Rejected commands fail with the reason.
+pub async fn ctl(opts: CtlOpts) -> Result<()> {
+    let address = ControlAddress::parse(&opts.control_socket)?;
+    let reply = control::send(&address, &opts.command.join(" ")).await?;
+    match reply.strip_prefix("error: ") {
+        Some(reason) => Err(anyhow!("{reason}")),
+        None => {
+            println!("{reply}");
+            Ok(())
+        }
     }
+}
 
-    // Table header
-    let mut items = vec![vec![String::from("PROFILE NAME"), String::from("COMMENT")]];
-    let mut values = list
-        .iter()
-        .map(|(name, profile)| {
-            let comment = match profile.comment.as_ref() {
-                Some(s) => s.as_str(),
-                None => "No comment",
-            };
-            vec![name.to_string(), comment.to_string()]
+/// Read or write the settings of the configuration file. Writes keep
+/// comments and unknown keys and replace the file atomically.
+pub fn config(opts: ConfigOpts) -> Result<()> {
+    let file = utils::config_file();
+    let content = match fs::read_to_string(&file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(anyhow!("Failed to read {}: {e}", file.display())),
+    };
+    let mut document = content
+        .parse::<Document>()
+        .map_err(|e| anyhow!("Failed to parse {}: {e}", file.display()))?;
+
+    match opts.action {
+        ConfigAction::Get { key } => {
+            match document.get(&key) {
+                Some(item) => println!("{}", setting_text(item)),
+                None => exit(1),
+            }
+            return Ok(());
+        }
+        ConfigAction::List => {
+            for (key, item) in document.iter() {
+                println!("{key} = {}", setting_text(item));
+            }
+            return Ok(());
+        }
+        ConfigAction::Set { key, value } => set_setting(&mut document, &key, &value)?,
+        ConfigAction::Unset { key } => {
+            if document.remove(&key).is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir).map_err(|e| anyhow!("Failed to create {}: {e}", dir.display()))?;
+    }
+    let temporary = file.with_extension("toml.tmp");
+    fs::write(&temporary, document.to_string())
+        .and_then(|_| fs::rename(&temporary, &file))
+        .map_err(|e| anyhow!("Failed to write {}: {e}", file.display()))
+}
+
+/// Validate `value` and write it to `key`
+fn set_setting(document: &mut Document, key: &str, value: &str) -> Result<()> {
+    let mut value = edit_value(&utils::parse_setting(key, value)?);
+    // Keep a comment after the old value
+    if let Some(old) = document.get(key).and_then(Item::as_value) {
+        *value.decor_mut() = old.decor().clone();
+    }
+    document[key] = Item::Value(value);
+    Ok(())
+}
+
+/// Value of a setting without quotes of strings
+fn setting_text(item: &Item) -> String {
+    match item.as_str() {
+        Some(text) => text.to_owned(),
+        None => item.to_string().trim().to_owned(),
+    }
+}
+
+/// Value for the configuration file
+fn edit_value(value: &toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => s.as_str().into(),
+        toml::Value::Integer(n) => (*n).into(),
+        toml::Value::Float(f) => (*f).into(),
+        toml::Value::Boolean(b) => (*b).into(),
+        toml::Value::Datetime(d) => d.to_string().into(),
+        toml::Value::Array(items) => Value::Array(items.iter().map(edit_value).collect::<Array>()),
+        toml::Value::Table(table) => Value::InlineTable(
+            table
+                .iter()
+                .map(|(k, v)| (k.as_str(), edit_value(v)))
+                .collect::<InlineTable>(),
+        ),
+    }
+}
+
+/// Profile as listed by the profiles subcommand
+#[derive(Debug, PartialEq, Serialize)]
+struct ProfileEntry {
+    name: String,
+    comment: Option<String>,
+    extends: Vec<String>,
+}
+
+/// Profiles of `list` sorted by name
+fn profile_entries(list: HashMap<String, Profile>) -> Vec<ProfileEntry> {
+    let mut entries = list
+        .into_iter()
+        .map(|(name, profile)| ProfileEntry {
+            name,
+            comment: profile.comment,
+            extends: profile.extends,
         })
-        .collect::<Vec<Vec<String>>>();
-    values.sort_by(|a, b| a.first().partial_cmp(&b.first()).unwrap());
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
 
-    items.append(&mut values);
+/// Table of `entries`
+fn profiles_table(entries: &[ProfileEntry]) -> String {
+    // Table header
+    let mut items = vec![vec![
+        String::from("PROFILE NAME"),
+        String::from("COMMENT"),
+        String::from("EXTENDS"),
+    ]];
+    items.extend(entries.iter().map(|entry| {
+        vec![
+            entry.name.clone(),
+            entry
+                .comment
+                .clone()
+                .unwrap_or_else(|| "No comment".to_owned()),
+            entry.extends.join(", "),
+        ]
+    }));
 
     let mut table = Builder::from(items).build();
     table
         .with(Style::modern_rounded())
         .with(Alignment::center())
         .modify(Rows::new(1..), Width::wrap(50).keep_words());
+    table.to_string()
+}
 
-    println!("{table}");
+pub fn profiles(opts: ProfilesOpts) -> Result<()> {
+    let entries = profile_entries(profiles_list(opts.profiles_path.as_ref())?);
+    match opts.format {
+        ListFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+        ListFormat::Table if entries.is_empty() => {
+            let profiles_path = config_dir().join("profiles.toml");
+            eprintln!("No profiles found! Check your profiles file ({profiles_path:?}) or set a ROGCAT_PROFILES environment variable.");
+        }
+        ListFormat::Table => println!("{}", profiles_table(&entries)),
+    }
     Ok(())
 }
+
+#[test]
+fn log_commands() {
+    assert_eq!(
+        log_command(&Level::Warn, "Tag", "it's done"),
+        "log -p w -t 'Tag' 'it'\\''s done'\n"
+    );
+    assert_eq!(
+        prefix("W/MyTag: message"),
+        Some((Level::Warn, "MyTag", "message"))
+    );
+    assert_eq!(prefix("E/Tag:a: b"), Some((Level::Error, "Tag", "a: b")));
+    assert_eq!(prefix("message"), None);
+    assert_eq!(prefix("X/Tag: message"), None);
+    assert_eq!(prefix("I/: message"), None);
+}
+
+#[test]
+fn config_edits() {
+    let mut document = "# Wide tags\nterminal_tag_width = 20 # columns\nfuture_key = \"kept\"\n"
+        .parse::<Document>()
+        .unwrap();
+    set_setting(&mut document, "terminal_tag_width", "30").unwrap();
+    set_setting(&mut document, "buffer", "main,crash").unwrap();
+    set_setting(&mut document, "tag_colors", "ActivityManager=red").unwrap();
+    assert!(set_setting(&mut document, "terminal_tag_width", "banana").is_err());
+    assert!(set_setting(&mut document, "future_key", "x").is_err());
+    assert_eq!(
+        document.to_string(),
+        "# Wide tags\nterminal_tag_width = 30 # columns\nfuture_key = \"kept\"\n\
+         buffer = [\"main\", \"crash\"]\ntag_colors = { ActivityManager = \"red\" }\n"
+    );
+    assert_eq!(setting_text(&document["future_key"]), "kept");
+    assert_eq!(setting_text(&document["buffer"]), "[\"main\", \"crash\"]");
+
+    document.remove("terminal_tag_width");
+    assert!(!document.to_string().contains("terminal_tag_width"));
+}
+
+#[test]
+fn parse_devices() {
+    let output = "* daemon not running; starting now at tcp:5037\n\
+                  * daemon started successfully\n\
+                  List of devices attached\n\
+                  emulator-5554          device product:sdk_gphone64_x86_64 model:sdk_gphone64_x86_64 device:emu64xa transport_id:1\n\
+                  R58M123ABC             unauthorized usb:1-1 transport_id:3\n\
+                  192.168.1.20:5555      offline transport_id:4\n\
+                  0123456789ABCDEF       no permissions (missing udev rules? user is in the plugdev group); see [http://developer.android.com/tools/device.html] usb:1-2\n\
+                  \n";
+    let devices = output.lines().filter_map(device_line).collect::<Vec<_>>();
+    assert_eq!(
+        devices,
+        [
+            Device {
+                serial: "emulator-5554".to_owned(),
+                state: "device".to_owned(),
+                product: Some("sdk_gphone64_x86_64".to_owned()),
+                model: Some("sdk_gphone64_x86_64".to_owned()),
+                transport_id: Some("1".to_owned()),
+            },
+            Device {
+                serial: "R58M123ABC".to_owned(),
+                state: "unauthorized".to_owned(),
+                transport_id: Some("3".to_owned()),
+                ..Default::default()
+            },
+            Device {
+                serial: "192.168.1.20:5555".to_owned(),
+                state: "offline".to_owned(),
+                transport_id: Some("4".to_owned()),
+                ..Default::default()
+            },
+            Device {
+                serial: "0123456789ABCDEF".to_owned(),
+                state: "no permissions".to_owned(),
+                ..Default::default()
+            },
+        ]
+    );
+    assert!(devices[0].ready());
+    assert!(!devices[1..].iter().any(Device::ready));
+
+    let table = devices_table(&devices);
+    assert!(table.contains("sdk_gphone64_x86_64"));
+    assert!(table.contains("UNAUTHORIZED"));
+    assert!(table.contains("OFFLINE"));
+
+    assert_eq!(
+        serde_json::to_value(&devices[1]).unwrap(),
+        serde_json::json!({
+            "serial": "R58M123ABC",
+            "state": "unauthorized",
+            "product": null,
+            "model": null,
+            "transport_id": "3"
+        })
+    );
+}
+
+#[test]
+fn list_profile_entries() {
+    let mut list = HashMap::new();
+    list.insert(
+        "b".to_owned(),
+        Profile {
+            comment: Some("Second".to_owned()),
+            extends: vec!["a".to_owned()],
+            ..Default::default()
+        },
+    );
+    list.insert("a".to_owned(), Profile::default());
+    let entries = profile_entries(list);
+    assert_eq!(
+        entries,
+        [
+            ProfileEntry {
+                name: "a".to_owned(),
+                comment: None,
+                extends: Vec::new(),
+            },
+            ProfileEntry {
+                name: "b".to_owned(),
+                comment: Some("Second".to_owned()),
+                extends: vec!["a".to_owned()],
+            },
+        ]
+    );
+    let table = profiles_table(&entries);
+    assert!(table.contains("No comment"));
+    assert!(table.contains("EXTENDS"));
+}
+
+#[test]
+fn report_zip_finish() {
+    use std::io::{self, Cursor, Read, SeekFrom};
+
+    /// Writer failing once after `limit` bytes like a full disk
+    struct Full {
+        inner: Cursor<Vec<u8>>,
+        limit: u64,
+        failed: bool,
+    }
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !self.failed && self.inner.position() + buf.len() as u64 > self.limit {
+                self.failed = true;
+                return Err(io::Error::other("No space left"));
+            }
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Full {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    let mut report = Report::new(Cursor::new(Vec::new()), Some("bugreport.txt")).unwrap();
+    report.write_all(b"== dumpstate ==\n").unwrap();
+    let archive = report.finish().unwrap().into_inner();
+    let size = archive.len() as u64;
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+    let mut content = String::new();
+    archive
+        .by_name("bugreport.txt")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "== dumpstate ==\n");
+
+    // Failures to write the end of the archive are returned
+    let full = Full {
+        inner: Cursor::new(Vec::new()),
+        limit: size - 1,
+        failed: false,
+    };
+    let mut report = Report::new(full, Some("bugreport.txt")).unwrap();
+    report.write_all(b"== dumpstate ==\n").unwrap();
+    assert!(report.finish().is_err());
+}
@@ -0,0 +1,291 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! User defined line layout like "{time} {level} {tag:<20} | {message}".
+//! Placeholders take an optional alignment and width like `:<20` or `:>8`
+//! and a maximum width like `:.30`. Braces are written as `{{` and `}}`.
+
+use crate::{record::Record, Error};
+use std::fmt::Write;
+use unicode_width::UnicodeWidthChar;
+
+/// Record values of the placeholders
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Time,
+    Date,
+    Level,
+    Tag,
+    Process,
+    Thread,
+    Message,
+    Raw,
+}
+
+const FIELDS: [(Field, &str); 8] = [
+    (Field::Time, "time"),
+    (Field::Date, "date"),
+    (Field::Level, "level"),
+    (Field::Tag, "tag"),
+    (Field::Process, "process"),
+    (Field::Thread, "thread"),
+    (Field::Message, "message"),
+    (Field::Raw, "raw"),
+];
+
+/// Placeholder with its layout
+#[derive(Clone, Debug, PartialEq)]
+struct Placeholder {
+    field: Field,
+    right_align: bool,
+    /// Minimum width in columns
+    width: usize,
+    /// Width in columns after which the value is cut
+    max_width: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// Parsed format template
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Parse `template`. Unknown placeholders and invalid layouts are
+    /// errors.
+    pub fn parse(template: &str) -> Result<Template, Error> {
+        let invalid = |reason: String| Error::Invalid(format!("Invalid format template: {reason}"));
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '}' => return Err(invalid("unmatched '}'. Write '}}' for a brace".into())),
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => {
+                                return Err(invalid(format!(
+                                    "unclosed placeholder {{{placeholder}"
+                                )))
+                            }
+                        }
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Placeholder(
+                        Placeholder::parse(&placeholder).map_err(invalid)?,
+                    ));
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Substitute the placeholders with the values of `record`
+    pub fn render(&self, record: &Record) -> String {
+        let mut line = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => line.push_str(text),
+                Part::Placeholder(placeholder) => placeholder.render(record, &mut line),
+            }
+        }
+        line
+    }
+}
+
+impl Placeholder {
+    fn parse(placeholder: &str) -> Result<Placeholder, String> {
+        let (name, layout) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+        let Some((field, _)) = FIELDS.iter().find(|(_, n)| *n == name.trim()) else {
+            let names = FIELDS.map(|(_, name)| name).join(", ");
+            return Err(format!(
+                "unknown placeholder {{{name}}}. Known placeholders are {names}"
+            ));
+        };
+        let invalid = || format!("invalid layout \"{layout}\" of {{{name}}}");
+        let (right_align, layout) = match layout.chars().next() {
+            Some('<') => (false, &layout[1..]),
+            Some('>') => (true, &layout[1..]),
+            _ => (false, layout),
+        };
+        let (width, max_width) = match layout.split_once('.') {
+            Some((width, max_width)) => (width, Some(max_width)),
+            None => (layout, None),
+        };
+        let width = match width {
+            "" => 0,
+            width => width.parse().map_err(|_| invalid())?,
+        };
+        let max_width = max_width
+            .map(|max_width| max_width.parse().map_err(|_| invalid()))
+            .transpose()?;
+        Ok(Placeholder {
+            field: *field,
+            right_align,
+            width,
+            max_width,
+        })
+    }
+
+    fn render(&self, record: &Record, line: &mut String) {
+        let time = record.time.as_deref().unwrap_or_default();
+        let level;
+        let value = match self.field {
+            Field::Time => time.split_once(' ').map_or(time, |(_, time)| time),
+            Field::Date => time.split_once(' ').map_or("", |(date, _)| date),
+            Field::Level => {
+                level = record.level.to_string();
+                &level
+            }
+            Field::Tag => &record.tag,
+            Field::Process => &record.process,
+            Field::Thread => &record.thread,
+            Field::Message => &record.message,
+            Field::Raw => &record.raw,
+        };
+
+        // Cut and pad by terminal columns. Wide chars take two.
+        let mut columns = 0;
+        let mut end = value.len();
+        for (i, c) in value.char_indices() {
+            let width = c.width().unwrap_or(0);
+            if self.max_width.is_some_and(|max| columns + width > max) {
+                end = i;
+                break;
+            }
+            columns += width;
+        }
+        let padding = self.width.saturating_sub(columns);
+        if self.right_align {
+            write!(line, "{:padding$}", "").ok();
+        }
+        line.push_str(&value[..end]);
+        if !self.right_align {
+            write!(line, "{:padding$}", "").ok();
+        }
+    }
+}
+
+#[cfg(test)]
+fn record() -> Record {
+    Record {
+        time: Some("01-10 23:02:59.028".to_owned()),
+        level: crate::record::Level::Warn,
+        tag: "ActivityManager".to_owned(),
+        process: "123".to_owned(),
+        thread: "456".to_owned(),
+        message: "Start proc".to_owned(),
+        raw: "raw line".to_owned(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn template_placeholders() {
+    let template =
+        Template::parse("{date} {time} {level} {tag} ({process} {thread}): {message} [{raw}]")
+            .unwrap();
+    assert_eq!(
+        template.render(&record()),
+        "01-10 23:02:59.028 W ActivityManager (123 456): Start proc [raw line]"
+    );
+
+    // Records without timestamp have empty dates and times
+    let record = Record {
+        time: None,
+        ..record()
+    };
+    assert_eq!(
+        Template::parse("[{date}|{time}]").unwrap().render(&record),
+        "[|]"
+    );
+}
+
+#[test]
+fn template_alignment() {
+    let render = |template: &str| Template::parse(template).unwrap().render(&record());
+    assert_eq!(render("{level:<3}|"), "W  |");
+    assert_eq!(render("{process:>6}|"), "   123|");
+    assert_eq!(render("{process:6}|"), "123   |");
+    assert_eq!(
+        render("{tag:<20} | {message}"),
+        "ActivityManager      | Start proc"
+    );
+    // Values wider than the width are not cut without a maximum
+    assert_eq!(render("{tag:<4}|"), "ActivityManager|");
+}
+
+#[test]
+fn template_truncation() {
+    let render = |template: &str, tag: &str| {
+        let record = Record {
+            tag: tag.to_owned(),
+            ..record()
+        };
+        Template::parse(template).unwrap().render(&record)
+    };
+    assert_eq!(render("{tag:.8}|", "ActivityManager"), "Activity|");
+    assert_eq!(render("{tag:<10.8}|", "ActivityManager"), "Activity  |");
+    assert_eq!(render("{tag:>10.8}|", "Short"), "     Short|");
+    // Wide chars take two columns and are not split
+    assert_eq!(render("{tag:.5}|", "日本語タグ"), "日本|");
+    assert_eq!(render("{tag:<6.5}|", "日本語タグ"), "日本  |");
+}
+
+#[test]
+fn template_braces() {
+    let template = Template::parse("{{{level}}} {{tag}} }}").unwrap();
+    assert_eq!(template.render(&record()), "{W} {tag} }");
+}
+
+#[test]
+fn template_errors() {
+    for invalid in [
+        "{foo}", "{tag", "tag}", "{tag:<x}", "{tag:.}", "{tag:^5}", "{}",
+    ] {
+        assert!(Template::parse(invalid).is_err(), "{invalid}");
+    }
+    let error = Template::parse("{time} {pid}").unwrap_err().to_string();
+    assert!(error.contains("unknown placeholder {pid}"), "{error}");
+}
@@ -20,29 +20,70 @@
 
 use crate::{
     cli::CliArguments,
-    profiles::Profile,
     utils::{config_get, terminal_width},
-    LogSink,
 };
-use failure::{format_err, Error};
+use anyhow::{anyhow, Result};
 use futures::{
-    sink::{Sink, SinkExt},
+    sink::Sink,
     task::{Context, Poll},
 };
 use regex::Regex;
-use rogcat::record::{Format, Level, Record};
+use rogcat::{
+    filter::{parse_duration, Processes, Timestamp},
+    profiles::Profile,
+    record::{Column, Format, Level, LevelStyle, Notice, Record},
+    sink::{FormatSink, HighlightSummary, Highlights},
+    template::Template,
+    Error, LogSink,
+};
 use std::{
+    borrow::Cow,
     cmp::{max, min},
-    convert::Into,
-    io::{stdout, BufWriter, Write},
+    collections::{HashMap, VecDeque},
+    io::{self, stdout, Write},
     pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 use termcolor::{Buffer, BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
+use tokio_util::sync::CancellationToken;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 const DIMM_COLOR: Color = Color::Ansi256(243);
 
-/// Construct a terminal sink for format from args with give profile
-pub fn try_from(args: &CliArguments, profile: &Profile) -> Result<LogSink, Error> {
+/// Dimm color of the light theme. The default one is barely visible on a
+/// light background.
+const LIGHT_DIMM_COLOR: Color = Color::Ansi256(240);
+
+/// Number of records after which an automatic column width shrinks back to
+/// the widest value seen meanwhile
+const COLUMN_SHRINK: usize = 1_000;
+
+/// Minimum width of the tag column if the terminal is narrow
+const MIN_TAG_WIDTH: usize = 8;
+
+/// Minimum number of message chars per line
+const MIN_PAYLOAD: usize = 20;
+
+/// Number of bytes of rendered records after which the output is printed
+/// even if the sink is not flushed
+const BATCH_SIZE: usize = 64 * 1024;
+
+/// Maximum number of records buffered while the output is paused
+const PAUSE_BACKLOG: usize = 100_000;
+
+/// Construct a terminal sink for format from args with give profile. The
+/// human format can be controlled with the keyboard if stdin is a terminal
+/// and not used as input. Quitting via keyboard cancels `shutdown`.
+pub fn try_from(
+    args: &CliArguments,
+    profile: &Profile,
+    processes: Processes,
+    shutdown: CancellationToken,
+) -> Result<LogSink> {
     let format = args
         .format
         .as_ref()
@@ -50,66 +91,597 @@ pub fn try_from(args: &CliArguments, profile: &Profile) -> Result<LogSink, Error
         .unwrap_or(Format::Human);
 
     if format == Format::Html {
-        return Err(format_err!("HTML format is only valid for file output"));
+        return Err(anyhow!("HTML format is only valid for file output"));
     }
 
-    let sink = Box::into_pin(match format {
-        Format::Human => Box::new(Human::from(args, profile, format)) as LogSink,
-        format => Box::new(FormatSink::new(format, stdout())) as LogSink,
-    });
+    if let Some(template) = args.format_template.as_deref() {
+        let template = Template::parse(template)?;
+        let color = color_choice(args);
+        return Ok(if args.template_color && color != ColorChoice::Never {
+            let sink = ColorFormatSink::new(Format::Raw, args, Colors::from(profile)?, color);
+            Box::new(sink.template(template))
+        } else {
+            Box::new(FormatSink::new(Format::Raw, stdout()).template(template))
+        });
+    }
+
+    Ok(match format {
+        Format::Human => {
+            let terminal = Terminal::from(args, profile, processes)?;
+            let interactive = atty::is(atty::Stream::Stdin)
+                && atty::is(atty::Stream::Stdout)
+                && args.command.as_deref() != Some("-");
+            if interactive {
+                keyboard::interactive(terminal, shutdown)?
+            } else {
+                Box::new(terminal) as LogSink
+            }
+        }
+        // Without colors the output is the same as in files
+        format if color_choice(args) == ColorChoice::Never => Box::new(
+            FormatSink::new(format, stdout())
+                .columns(args.columns.clone())
+                .level_style(args.level_style),
+        ),
+        format => Box::new(ColorFormatSink::new(
+            format,
+            args,
+            Colors::from(profile)?,
+            color_choice(args),
+        )),
+    })
+}
+
+/// Colors of tags and levels overriding the defaults
+#[derive(Clone, Debug, Default)]
+struct Colors {
+    tags: HashMap<String, Color>,
+    levels: HashMap<Level, Color>,
+}
+
+/// Parse the colors of `config_key` of the config file and of `profile`.
+/// The profile takes precedence.
+fn parse_colors(
+    config_key: &str,
+    profile: &HashMap<String, String>,
+) -> Result<HashMap<String, Color>, Error> {
+    let mut colors = config_get::<HashMap<String, String>>(config_key).unwrap_or_default();
+    colors.extend(profile.iter().map(|(k, v)| (k.clone(), v.clone())));
+    colors
+        .into_iter()
+        .map(|(key, value)| {
+            value
+                .trim()
+                .parse::<Color>()
+                .map(|color| (key.clone(), color))
+                .map_err(|e| Error::Invalid(format!("Invalid color of {key} in {config_key}: {e}")))
+        })
+        .collect()
+}
+
+impl Colors {
+    /// Colors of the `tag_colors` and `level_colors` settings of the config
+    /// file and `profile`. Colors are names like "red" or Ansi256 numbers.
+    fn from(profile: &Profile) -> Result<Colors, Error> {
+        let tags = parse_colors("tag_colors", &profile.tag_colors)?;
+        let levels = parse_colors("level_colors", &profile.level_colors)?
+            .into_iter()
+            .map(|(name, color)| {
+                // Levels are names like "warn" or letters like "W"
+                match name.parse() {
+                    Ok(level) => Ok((level, color)),
+                    Err(_) => Err(Error::Invalid(format!(
+                        "Invalid level {name} in level_colors"
+                    ))),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Colors { tags, levels })
+    }
+
+    /// Color of `tag` if overridden
+    fn tag(&self, tag: &str) -> Option<Color> {
+        self.tags.get(tag).copied()
+    }
+
+    /// Color of the records of `level`. Levels below debug are uncolored by
+    /// default.
+    fn level(&self, level: &Level) -> Option<Color> {
+        self.levels.get(level).copied().or(match level {
+            Level::Debug => Some(Color::Cyan),
+            Level::Info => Some(Color::Green),
+            Level::Warn => Some(Color::Yellow),
+            Level::Error | Level::Fatal | Level::Assert => Some(Color::Red),
+            _ => None,
+        })
+    }
+}
+
+/// Color choice of --color or the terminal_color setting. Auto colors only
+/// if stdout is a terminal.
+fn color_choice(args: &CliArguments) -> ColorChoice {
+    choose_color(
+        args.color.as_deref(),
+        no_color(),
+        config_get("terminal_color"),
+        atty::is(atty::Stream::Stdout),
+    )
+}
+
+/// True if the NO_COLOR environment variable is set to a non empty value
+pub(crate) fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// Color choice of the `flag` --color, the NO_COLOR convention and the
+/// `config` setting in this order. NO_COLOR is overruled by an explicit
+/// "--color always" only. Auto colors only if the output is a `tty`.
+pub(crate) fn choose_color(
+    flag: Option<&str>,
+    no_color: bool,
+    config: Option<&str>,
+    tty: bool,
+) -> ColorChoice {
+    let choice = match (flag, no_color) {
+        (Some("always"), _) => "always",
+        (_, true) => "never",
+        (Some(flag), false) => flag,
+        (None, false) => config.unwrap_or("auto"),
+    };
+    match choice {
+        "always" => ColorChoice::Always,
+        "never" => ColorChoice::Never,
+        "auto" if tty => ColorChoice::Auto,
+        "auto" => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    }
+}
+
+/// Background of the terminal the hashed colors are picked for
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Theme {
+    Dark,
+    Light,
+}
+
+/// Width of a column. Automatic widths grow with the values and shrink back
+/// if no value needed the width for `COLUMN_SHRINK` records. Values longer
+/// than a fixed width are truncated.
+#[derive(Clone, Default)]
+struct ColumnWidth {
+    width: usize,
+    fixed: bool,
+    unused: usize,
+    recent: usize,
+}
+
+impl ColumnWidth {
+    fn new(fixed: Option<usize>) -> ColumnWidth {
+        ColumnWidth {
+            width: fixed.unwrap_or_default(),
+            fixed: fixed.is_some(),
+            ..Default::default()
+        }
+    }
+
+    /// Start over with an automatic width
+    fn reset(&mut self) {
+        if !self.fixed {
+            *self = ColumnWidth::default();
+        }
+    }
+
+    /// Update the width with a value of `len` chars and return the width
+    fn update(&mut self, len: usize) -> usize {
+        if self.fixed {
+            return self.width;
+        }
+        if len >= self.width {
+            self.width = len;
+            self.unused = 0;
+            self.recent = 0;
+        } else {
+            self.unused += 1;
+            self.recent = max(self.recent, len);
+            if self.unused >= COLUMN_SHRINK {
+                self.width = self.recent;
+                self.unused = 0;
+                self.recent = 0;
+            }
+        }
+        self.width
+    }
+}
+
+/// Number of terminal columns `text` occupies
+fn text_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// True if every char of `text` is a printable ascii char that is one
+/// column wide
+fn is_printable_ascii(text: &str) -> bool {
+    text.bytes().all(|b| (b' '..=b'~').contains(&b))
+}
+
+/// Truncate `text` to `width` terminal columns. Grapheme clusters are not
+/// split.
+fn truncate(text: &str, width: usize) -> Cow<'_, str> {
+    if text.len() <= width && is_printable_ascii(text) {
+        return Cow::Borrowed(text);
+    }
+    let mut used = 0;
+    text.graphemes(true)
+        .take_while(|g| {
+            used += text_width(g);
+            used <= width
+        })
+        .collect::<String>()
+        .into()
+}
+
+/// Pad `text` with spaces to `width` terminal columns
+fn pad(text: &str, width: usize, right_align: bool) -> String {
+    let padding = " ".repeat(width.saturating_sub(text_width(text)));
+    if right_align {
+        padding + text
+    } else {
+        text.to_owned() + &padding
+    }
+}
+
+/// Milliseconds of a day for times without date
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Width of the column with the time since the previous record
+const DELTA_WIDTH: usize = 7;
+
+/// Width of the column with the time since the start of the process
+const UPTIME_WIDTH: usize = 8;
+
+/// Time of a record for measuring the time between records
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RecordTime {
+    /// "MM-DD HH:MM:SS.mmm"
+    Date(Timestamp),
+    /// "HH:MM:SS.mmm" without date that wraps at midnight
+    Time(Timestamp),
+    /// Milliseconds of "logcat -v monotonic" or "logcat -v epoch"
+    Seconds(i64),
+}
+
+impl RecordTime {
+    fn parse(time: &str) -> Option<RecordTime> {
+        let time = time.trim();
+        if let Some(timestamp) = Timestamp::parse(time) {
+            return Some(RecordTime::Date(timestamp));
+        }
+        if time.contains(':') {
+            return Timestamp::parse(&format!("01-01 {time}")).map(RecordTime::Time);
+        }
+        if !time.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            return None;
+        }
+        let seconds = time.parse::<f64>().ok()?;
+        Some(RecordTime::Seconds((seconds * 1000.0).round() as i64))
+    }
+
+    /// Milliseconds from `self` to `later`. Negative if `later` is before
+    /// `self`.
+    fn until(self, later: RecordTime) -> Option<i64> {
+        match (self, later) {
+            (RecordTime::Date(a), RecordTime::Date(b)) => Some(a.elapsed(b)),
+            (RecordTime::Time(a), RecordTime::Time(b)) => {
+                let elapsed = a.elapsed(b).rem_euclid(DAY_MS);
+                Some(if elapsed > DAY_MS / 2 {
+                    elapsed - DAY_MS
+                } else {
+                    elapsed
+                })
+            }
+            (RecordTime::Seconds(a), RecordTime::Seconds(b)) => Some(b - a),
+            _ => None,
+        }
+    }
+}
+
+/// Format the time since the previous record like "+123ms" or "+4.2s"
+fn format_delta(millis: i64) -> String {
+    let sign = if millis < 0 { '-' } else { '+' };
+    match millis.unsigned_abs() {
+        millis @ 0..=999 => format!("{sign}{millis}ms"),
+        millis @ 1000..=99_999 => format!("{sign}{:.1}s", millis as f64 / 1000.0),
+        millis => format!("{sign}{}s", millis / 1000),
+    }
+}
+
+/// Format the time since the start of a process like "+01.234s" and
+/// switch to coarser units like "+2m05s" or "+1h02m" to fit `UPTIME_WIDTH`
+fn format_uptime(millis: i64) -> String {
+    let millis = millis.max(0);
+    let seconds = millis / 1000;
+    match seconds {
+        0..=99 => format!("+{seconds:02}.{:03}s", millis % 1000),
+        100..=5_999 => format!("+{}m{:02}s", seconds / 60, seconds % 60),
+        6_000..=359_999 => format!("+{}h{:02}m", seconds / 3600, seconds / 60 % 60),
+        _ => format!("+{}d{:02}h", seconds / 86_400, seconds / 3600 % 24),
+    }
+}
+
+/// Milliseconds of a gap threshold like "500ms", "2s" or "1m"
+fn parse_threshold(value: &str) -> Result<u64, Error> {
+    parse_duration(value).ok_or_else(|| Error::Invalid(format!("Invalid gap threshold {value}")))
+}
+
+/// Maximum time between the records of a stack trace
+const TRACE_WINDOW_MS: u64 = 500;
+
+/// Packages of frames that are dimmed in grouped stack traces
+const FRAMEWORK_PACKAGES: [&str; 8] = [
+    "android.",
+    "androidx.",
+    "com.android.",
+    "dalvik.",
+    "java.",
+    "javax.",
+    "kotlin.",
+    "libcore.",
+];
+
+/// True if `record` is a line of a Java stack trace following `previous`
+fn continues_trace(previous: &Record, record: &Record) -> bool {
+    let message = record.message.trim_start();
+    let frame = message.starts_with("at ")
+        || message.starts_with("Caused by:")
+        || (message.starts_with("... ") && message.ends_with(" more"));
+    let close = match (
+        previous.time.as_deref().and_then(Timestamp::parse),
+        record.time.as_deref().and_then(Timestamp::parse),
+    ) {
+        (Some(a), Some(b)) => a.distance(b) <= TRACE_WINDOW_MS,
+        _ => previous.time == record.time,
+    };
+    frame
+        && close
+        && previous.process == record.process
+        && previous.thread == record.thread
+        && previous.tag == record.tag
+        && previous.level == record.level
+}
+
+/// True if `frame` is a stack frame of the platform
+fn is_framework_frame(frame: &str) -> bool {
+    frame
+        .strip_prefix("at ")
+        .is_some_and(|f| FRAMEWORK_PACKAGES.iter().any(|p| f.starts_with(p)))
+}
+
+/// Split `text` into chunks of at most `width` terminal columns. Returns
+/// the chunks with the index of their first char.
+fn chunks(text: &str, width: usize) -> Vec<(usize, &str)> {
+    // Chars and columns are bytes in printable ascii
+    if width > 0 && is_printable_ascii(text) {
+        if text.len() <= width {
+            return vec![(0, text)];
+        }
+        return (0..text.len())
+            .step_by(width)
+            .map(|start| (start, &text[start..min(start + width, text.len())]))
+            .collect();
+    }
+    let mut chunks = Vec::new();
+    let (mut start, mut start_char, mut chars, mut used) = (0, 0, 0, 0);
+    for (offset, grapheme) in text.grapheme_indices(true) {
+        // Lines of multi line messages start new chunks
+        if grapheme == "\n" || grapheme == "\r\n" {
+            chunks.push((start_char, &text[start..offset]));
+            start = offset + grapheme.len();
+            chars += grapheme.chars().count();
+            start_char = chars;
+            used = 0;
+            continue;
+        }
+        let w = text_width(grapheme);
+        if used + w > width && offset > start {
+            chunks.push((start_char, &text[start..offset]));
+            start = offset;
+            start_char = chars;
+            used = 0;
+        }
+        used += w;
+        chars += grapheme.chars().count();
+    }
+    if start < text.len() || chunks.is_empty() {
+        chunks.push((start_char, &text[start..]));
+    }
+    chunks
+}
+
+/// Style of highlighted matches
+#[derive(Clone)]
+enum HighlightStyle {
+    Bold,
+    Inverse,
+    Underline,
+}
 
-    Ok(Box::new(sink.sink_map_err(|e| {
-        failure::format_err!("Terminal error: {}", e)
-    })))
+/// Foreground color of messages
+#[derive(Clone, Debug, PartialEq)]
+enum MessageColor {
+    /// Color of the level
+    Level,
+    /// Default foreground color of the terminal
+    Default,
+    /// Dimm color
+    Dimm,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 enum DateFormat {
     Complete,
     Nothing,
     HourOnly,
     DateOnly,
 }
-/// Human readable terminal output
-struct Human {
-    writer: BufferWriter,
+/// Human readable record formatter
+#[derive(Clone)]
+pub(crate) struct Human {
+    buffer_width: ColumnWidth,
     date_format: DateFormat,
+    device_width: ColumnWidth,
     highlight: Vec<Regex>,
-    process_width: usize,
+    /// Records matched by each highlight regex
+    highlight_counts: Vec<usize>,
+    highlight_style: HighlightStyle,
+    message_color: MessageColor,
+    max_message_width: Option<usize>,
+    process_width: ColumnWidth,
     tag_width: Option<usize>,
-    thread_width: usize,
+    thread_width: ColumnWidth,
     dimm_color: Option<Color>,
     bright_colors: bool,
+    colors: Colors,
+    theme: Theme,
+    group_traces: bool,
+    /// Records of a stack trace rendered once the trace ends
+    trace: Vec<Record>,
+    /// Print a separator before records following the previous one after
+    /// more than this number of milliseconds
+    gap_threshold: Option<u64>,
+    show_delta: bool,
+    /// Show the time since the start of the process of each record
+    show_uptime: bool,
+    /// Time of the last rendered record with a parseable time
+    last_time: Option<RecordTime>,
+    /// Print a header before records of another process than the previous
+    /// record
+    group_by_process: bool,
+    /// Names of the processes shown in the headers
+    processes: Processes,
+    /// Device and pid of the last rendered record
+    last_process: Option<(Option<String>, String)>,
+}
+
+/// Human output on the terminal
+struct Terminal {
+    writer: BufferWriter,
+    /// Records rendered but not yet printed
+    pending: Buffer,
+    human: Human,
+    paused: Option<Paused>,
+    width: TerminalWidth,
+    /// Width used for the last record
+    last_width: Option<usize>,
+}
+
+/// Terminal width that is updated when the terminal is resized. Zero if
+/// the width is unknown.
+#[derive(Clone)]
+struct TerminalWidth(Arc<AtomicUsize>);
+
+impl TerminalWidth {
+    /// Query the terminal width and spawn a task that updates it on resize
+    fn watch() -> TerminalWidth {
+        let width = TerminalWidth(Arc::new(AtomicUsize::new(0)));
+        width.update();
+
+        let watched = width.clone();
+        #[cfg(unix)]
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+            if let Ok(mut resize) = signal(SignalKind::window_change()) {
+                while resize.recv().await.is_some() {
+                    watched.update();
+                }
+            }
+        });
+        #[cfg(not(unix))]
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                watched.update();
+            }
+        });
+
+        width
+    }
+
+    fn update(&self) {
+        self.0
+            .store(terminal_width().unwrap_or_default(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Option<usize> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            width => Some(width),
+        }
+    }
+}
+
+/// Records received while the output is paused
+#[derive(Default)]
+struct Paused {
+    backlog: VecDeque<Record>,
+    dropped: usize,
 }
 
 impl Human {
-    pub fn from(args: &CliArguments, profile: &Profile, _: Format) -> Human {
+    pub(crate) fn from(args: &CliArguments, profile: &Profile, _: Format) -> Result<Human, Error> {
         let mut hl = profile.highlight.to_owned();
         if !args.highlight.is_empty() {
             hl.extend(args.highlight.to_owned());
         }
-        let highlight = hl.iter().flat_map(|h| Regex::new(h)).collect();
-
-        let color = {
-            match args
-                .color
-                .as_deref()
-                .unwrap_or_else(|| config_get("terminal_color").unwrap_or("auto"))
-            {
-                "always" => ColorChoice::Always,
-                "never" => ColorChoice::Never,
-                "auto" => {
-                    if atty::is(atty::Stream::Stdout) {
-                        ColorChoice::Auto
-                    } else {
-                        ColorChoice::Never
-                    }
-                }
-                _ => ColorChoice::Auto,
+        let highlight = hl.iter().flat_map(|h| Regex::new(h)).collect::<Vec<_>>();
+        let highlight_style = match args
+            .highlight_style
+            .as_deref()
+            .unwrap_or_else(|| config_get("terminal_highlight_style").unwrap_or("inverse"))
+        {
+            "bold" => HighlightStyle::Bold,
+            "underline" => HighlightStyle::Underline,
+            _ => HighlightStyle::Inverse,
+        };
+        let message_color = match args
+            .message_color
+            .as_deref()
+            .unwrap_or_else(|| config_get("terminal_message_color").unwrap_or("level"))
+        {
+            "level" => MessageColor::Level,
+            "default" => MessageColor::Default,
+            "dimm" => MessageColor::Dimm,
+            color => {
+                return Err(Error::Invalid(format!(
+                    "Invalid terminal_message_color {color}"
+                )))
             }
         };
+
         let no_dimm = args.no_dimm || config_get("terminal_no_dimm").unwrap_or(false);
+        let theme = match args
+            .theme
+            .as_deref()
+            .unwrap_or_else(|| config_get("terminal_theme").unwrap_or("dark"))
+        {
+            "dark" => Theme::Dark,
+            "light" => Theme::Light,
+            theme => return Err(Error::Invalid(format!("Invalid terminal_theme {theme}"))),
+        };
+        let dimm_color = match config_get::<String>("terminal_dimm_color") {
+            Some(color) => color
+                .trim()
+                .parse::<Color>()
+                .map_err(|e| Error::Invalid(format!("Invalid terminal_dimm_color {color}: {e}")))?,
+            None if theme == Theme::Light => LIGHT_DIMM_COLOR,
+            None => DIMM_COLOR,
+        };
         let tag_width = config_get("terminal_tag_width");
+        let process_width = config_get("terminal_pid_width");
+        let thread_width = config_get("terminal_thread_width");
+        let max_message_width = args
+            .max_message_width
+            .or_else(|| config_get("terminal_max_message_width"));
         let hide_timestamp =
             args.hide_timestamp || config_get("terminal_hide_timestamp").unwrap_or(false);
         let show_date = args.show_date || config_get("terminal_show_date").unwrap_or(false);
@@ -127,22 +699,71 @@ impl Human {
 
         let bright_colors =
             args.bright_colors || config_get("terminal_bright_colors").unwrap_or(false);
+        let group_traces =
+            args.group_traces || config_get("terminal_group_traces").unwrap_or(false);
+        let gap_threshold = args
+            .show_gaps
+            .clone()
+            .or_else(|| config_get("terminal_show_gaps"))
+            .map(|threshold| parse_threshold(&threshold))
+            .transpose()?;
+        let show_delta = args.show_delta || config_get("terminal_show_delta").unwrap_or(false);
+        let show_uptime = args.show_uptime || config_get("terminal_show_uptime").unwrap_or(false);
+        let group_by_process = match args
+            .group_by
+            .clone()
+            .or_else(|| config_get("terminal_group_by"))
+            .as_deref()
+        {
+            None => false,
+            Some("process") => true,
+            Some(key) => return Err(Error::Invalid(format!("Invalid terminal_group_by {key}"))),
+        };
 
-        Human {
-            writer: BufferWriter::stdout(color),
-            dimm_color: if no_dimm { None } else { Some(DIMM_COLOR) },
+        Ok(Human {
+            dimm_color: if no_dimm { None } else { Some(dimm_color) },
+            highlight_counts: vec![0; highlight.len()],
             highlight,
+            highlight_style,
+            message_color,
+            buffer_width: ColumnWidth::default(),
             date_format,
+            device_width: ColumnWidth::default(),
+            max_message_width,
             tag_width,
-            process_width: 0,
-            thread_width: 0,
+            process_width: ColumnWidth::new(process_width),
+            thread_width: ColumnWidth::new(thread_width),
             bright_colors,
-        }
+            colors: Colors::from(profile)?,
+            theme,
+            group_traces,
+            trace: Vec::new(),
+            gap_threshold,
+            show_delta,
+            show_uptime,
+            last_time: None,
+            group_by_process,
+            processes: Processes::default(),
+            last_process: None,
+        })
+    }
+
+    /// Name the processes in the group headers with `processes`
+    pub(crate) fn processes(self, processes: Processes) -> Human {
+        Human { processes, ..self }
+    }
+
+    /// Let the automatic column widths start over after a terminal resize
+    fn resize(&mut self) {
+        self.buffer_width.reset();
+        self.device_width.reset();
+        self.process_width.reset();
+        self.thread_width.reset();
     }
 
     // Dynamic tag width estimation according to terminal width
-    fn tag_width(&self) -> usize {
-        self.tag_width.unwrap_or_else(|| match terminal_width() {
+    fn tag_width(&self, width: Option<usize>) -> usize {
+        self.tag_width.unwrap_or(match width {
             Some(n) if n <= 80 => 15,
             Some(n) if n <= 90 => 20,
             Some(n) if n <= 100 => 25,
@@ -152,41 +773,244 @@ impl Human {
     }
 
     #[cfg(target_os = "windows")]
-    fn hashed_color(i: &str) -> Color {
+    fn hashed_color(&self, i: &str) -> Color {
         let v = i.bytes().fold(42u8, |c, x| c ^ x) % 7;
-        match v {
-            0 => Color::Blue,
-            1 => Color::Green,
-            2 => Color::Red,
-            3 => Color::Cyan,
-            4 => Color::Magenta,
-            5 => Color::Yellow,
-            _ => Color::White,
+        match (v, self.theme) {
+            (0, _) => Color::Blue,
+            (1, _) => Color::Green,
+            (2, _) => Color::Red,
+            (3, _) => Color::Cyan,
+            (4, _) => Color::Magenta,
+            (5, Theme::Dark) => Color::Yellow,
+            (_, Theme::Dark) => Color::White,
+            (_, Theme::Light) => Color::Black,
         }
     }
 
     #[cfg(not(target_os = "windows"))]
-    fn hashed_color(i: &str) -> Color {
-        // Some colors are hard to read on (at least) dark terminals
-        // and I consider some others as ugly.
-        Color::Ansi256(match i.bytes().fold(42u8, |c, x| c ^ x) {
-            c @ 0..=1 => c + 2,
-            c @ 16..=21 => c + 6,
-            c @ 52..=55 | c @ 126..=129 => c + 4,
-            c @ 163..=165 | c @ 200..=201 => c + 3,
-            c @ 207 => c + 1,
-            c @ 232..=240 => c + 9,
-            c => c,
+    fn hashed_color(&self, i: &str) -> Color {
+        let c = i.bytes().fold(42u8, |c, x| c ^ x);
+        Color::Ansi256(match self.theme {
+            // Some colors are hard to read on (at least) dark terminals
+            // and I consider some others as ugly.
+            Theme::Dark => match c {
+                c @ 0..=1 => c + 2,
+                c @ 16..=21 => c + 6,
+                c @ 52..=55 | c @ 126..=129 => c + 4,
+                c @ 163..=165 | c @ 200..=201 => c + 3,
+                c @ 207 => c + 1,
+                c @ 232..=240 => c + 9,
+                c => c,
+            },
+            // Pale colors vanish on light terminals. The bright system
+            // colors are replaced by the normal ones, the cube colors are
+            // darkened and light grays are replaced by darker ones.
+            Theme::Light => match c {
+                7 | 15 => 8,
+                c @ 9..=14 => c - 8,
+                c @ 16..=231 => {
+                    let (r, g, b) = ((c - 16) / 36, (c - 16) / 6 % 6, (c - 16) % 6);
+                    16 + 36 * r.min(3) + 6 * g.min(3) + b.min(3)
+                }
+                c @ 244..=255 => c - 12,
+                c => c,
+            },
         })
     }
 
-    fn print(&mut self, record: &Record) -> Result<(), Error> {
+    /// Mark the chars of `text` that are matched by any highlight regex.
+    /// Returns an empty vector if nothing matches.
+    fn highlighted(&self, text: &str) -> Vec<bool> {
+        let mut matches = self
+            .highlight
+            .iter()
+            .flat_map(|r| r.find_iter(text))
+            .peekable();
+        if matches.peek().is_none() {
+            return Vec::new();
+        }
+        let mut bytes = vec![false; text.len()];
+        for m in matches {
+            bytes[m.range()].fill(true);
+        }
+        text.char_indices().map(|(i, _)| bytes[i]).collect()
+    }
+
+    /// Color spec for highlighted text based on `spec`
+    fn highlight_spec(&self, spec: &ColorSpec) -> ColorSpec {
+        let mut highlight = spec.clone();
+        match self.highlight_style {
+            HighlightStyle::Bold => highlight.set_bold(true),
+            HighlightStyle::Underline => highlight.set_underline(true),
+            HighlightStyle::Inverse => highlight
+                .set_bg(Some(spec.fg().copied().unwrap_or(Color::Yellow)))
+                .set_fg(Some(Color::Black)),
+        };
+        highlight
+    }
+
+    /// Render `record` or hold it back if stack traces are grouped. Held
+    /// back records are rendered by the next record that doesn't continue
+    /// the trace or by [`Human::finish`].
+    pub(crate) fn push(
+        &mut self,
+        record: &Record,
+        width: Option<usize>,
+        out: &mut dyn WriteColor,
+    ) -> io::Result<()> {
+        if !self.group_traces {
+            return self.render(record, width, out);
+        }
+        if !self
+            .trace
+            .last()
+            .is_some_and(|last| continues_trace(last, record))
+        {
+            self.finish(width, out)?;
+        }
+        self.trace.push(record.clone());
+        Ok(())
+    }
+
+    /// Render the held back records. The lines of a stack trace are
+    /// rendered as lines of the first record. Frames of the platform are
+    /// dimmed.
+    pub(crate) fn finish(
+        &mut self,
+        width: Option<usize>,
+        out: &mut dyn WriteColor,
+    ) -> io::Result<()> {
+        let trace = std::mem::take(&mut self.trace);
+        let Some((head, frames)) = trace.split_first() else {
+            return Ok(());
+        };
+        let mut message = head.message.clone();
+        let mut dimmed = vec![false; message.split('\n').count()];
+        for frame in frames {
+            let line = frame.message.trim();
+            message.push('\n');
+            if !line.starts_with("Caused by:") {
+                message.push_str("  ");
+            }
+            message.push_str(line);
+            dimmed.push(is_framework_frame(line));
+        }
+        let record = Record {
+            message,
+            ..head.clone()
+        };
+        self.render_lines(&record, width, out, &dimmed)
+    }
+
+    /// Milliseconds from the start of the process of `record` to `time`.
+    /// None if the start wasn't seen or is after `time`, e.g. if the dates
+    /// are missing or the year changed in between.
+    fn uptime(&self, record: &Record, time: RecordTime) -> Option<i64> {
+        let start = self
+            .processes
+            .start_time(record.device.as_deref(), &record.process)?;
+        let elapsed = RecordTime::parse(&start)?.until(time)?;
+        (elapsed >= 0).then_some(elapsed)
+    }
+
+    /// Print a dimmed header like "─── com.example (1234) ───" if the
+    /// process of `record` differs from the one of the previous record.
+    /// Records without pid, e.g. diagnostics, don't start a group.
+    fn process_header(&mut self, record: &Record, out: &mut dyn WriteColor) -> io::Result<()> {
+        if record.process.is_empty() {
+            return Ok(());
+        }
+        let process = (record.device.clone(), record.process.clone());
+        if self.last_process.as_ref() == Some(&process) {
+            return Ok(());
+        }
+        let mut header = match self
+            .processes
+            .name(record.device.as_deref(), &record.process)
+        {
+            Some(name) => format!("{name} ({})", record.process),
+            None => record.process.clone(),
+        };
+        if let Some(device) = record.device.as_deref() {
+            header = format!("{device} {header}");
+        }
+        out.set_color(ColorSpec::new().set_fg(self.dimm_color))?;
+        write!(out, "─── {header} ───")?;
+        out.reset()?;
+        out.write_all(b"\n")?;
+        self.last_process = Some(process);
+        Ok(())
+    }
+
+    /// Format a record for an output that is `width` chars wide. Messages
+    /// are wrapped if they don't fit.
+    pub(crate) fn render(
+        &mut self,
+        record: &Record,
+        width: Option<usize>,
+        out: &mut dyn WriteColor,
+    ) -> io::Result<()> {
+        if record.notice == Some(Notice::BufferBegin) {
+            let buffer = record.buffer.as_deref().unwrap_or(&record.message);
+            out.set_color(ColorSpec::new().set_fg(self.dimm_color))?;
+            write!(out, "─── {buffer} ───")?;
+            out.reset()?;
+            return out.write_all(b"\n");
+        }
+        self.render_lines(record, width, out, &[])
+    }
+
+    /// Render a record with the lines of the message flagged in `dimmed`
+    /// in the dimm color
+    fn render_lines(
+        &mut self,
+        record: &Record,
+        width: Option<usize>,
+        out: &mut dyn WriteColor,
+        dimmed: &[bool],
+    ) -> io::Result<()> {
+        // Records without parseable time don't show gaps and deltas
+        let time = record.time.as_deref().and_then(RecordTime::parse);
+        let elapsed = match (self.last_time, time) {
+            (Some(last), Some(time)) => last.until(time),
+            _ => None,
+        };
+        self.last_time = time.or(self.last_time);
+        if let (Some(threshold), Some(elapsed)) = (self.gap_threshold, elapsed) {
+            if elapsed > threshold as i64 {
+                out.set_color(ColorSpec::new().set_fg(self.dimm_color))?;
+                write!(out, "─── {:.1} s gap ───", elapsed as f64 / 1000.0)?;
+                out.reset()?;
+                out.write_all(b"\n")?;
+            }
+        }
+        if self.group_by_process {
+            self.process_header(record, out)?;
+        }
+        let delta = if self.show_delta {
+            let delta = elapsed.map(format_delta).unwrap_or_default();
+            pad(&delta, DELTA_WIDTH, true) + " "
+        } else {
+            String::new()
+        };
+        let uptime = if self.show_uptime {
+            let uptime = time
+                .and_then(|time| self.uptime(record, time))
+                .map(format_uptime)
+                .unwrap_or_default();
+            pad(&uptime, UPTIME_WIDTH, true) + " "
+        } else {
+            String::new()
+        };
+
         let timestamp = if self.date_format != DateFormat::Nothing {
-            let time = record.time.to_owned().unwrap_or_default();
-            match self.date_format {
-                DateFormat::Complete => Some(time),
-                DateFormat::DateOnly => time.get(0..5).map(str::to_string),
-                DateFormat::HourOnly => time.get(6..).map(str::to_string),
+            let time = record.time.as_deref().unwrap_or_default();
+            // Timestamps without date are seconds (logcat -v monotonic or epoch)
+            match (&self.date_format, time.split_once(' ')) {
+                (DateFormat::Complete, _) => Some(time),
+                (DateFormat::DateOnly, Some((date, _))) => Some(date),
+                (DateFormat::HourOnly, Some((_, hour))) => Some(hour),
+                (DateFormat::HourOnly, None) => Some(time),
                 _ => None,
             }
         } else {
@@ -194,69 +1018,119 @@ impl Human {
         }
         .unwrap_or_default();
 
-        let tag_width = self.tag_width();
-        let tag_chars = record.tag.chars().count();
-        let tag = format!(
-            "{:>width$}",
-            record
-                .tag
-                .chars()
-                .take(min(tag_width, tag_chars))
-                .collect::<String>(),
-            width = tag_width
-        );
+        // The device column is shown for records from sources with multiple
+        // devices or peers only
+        let device = match record.device.as_deref() {
+            Some(device) => {
+                let width = self.device_width.update(text_width(device));
+                pad(device, width, false) + " "
+            }
+            None => String::new(),
+        };
 
-        self.process_width = max(self.process_width, record.process.chars().count());
-        let pid = if record.process.is_empty() {
-            " ".repeat(self.process_width)
-        } else {
-            format!("{:<width$}", record.process, width = self.process_width)
+        // So is the buffer column for records of split buffers
+        let log_buffer = match record.buffer.as_deref() {
+            Some(buffer) => {
+                let width = self.buffer_width.update(text_width(buffer));
+                pad(buffer, width, false) + " "
+            }
+            None => String::new(),
         };
-        self.thread_width = max(self.thread_width, record.thread.chars().count());
-        let tid = if !record.thread.is_empty() {
-            format!(" {:>width$}", record.thread, width = self.thread_width)
-        } else if self.thread_width != 0 {
-            " ".repeat(self.thread_width + 1)
+
+        let process_width = self.process_width.update(text_width(&record.process));
+        let pid = pad(
+            &truncate(&record.process, process_width),
+            process_width,
+            false,
+        );
+        // Resolved thread names replace the thread id
+        let thread = record.thread_name.as_deref().unwrap_or(&record.thread);
+        let thread_width = self.thread_width.update(text_width(thread));
+        let tid = if !thread.is_empty() {
+            " ".to_owned() + &pad(&truncate(thread, thread_width), thread_width, true)
+        } else if thread_width != 0 {
+            " ".repeat(thread_width + 1)
         } else {
             String::new()
         };
 
-        let highlight = !self.highlight.is_empty()
-            && (self.highlight.iter().any(|r| r.is_match(&record.tag))
-                || self.highlight.iter().any(|r| r.is_match(&record.message)));
+        let mut highlight = false;
+        for (regex, count) in self.highlight.iter().zip(&mut self.highlight_counts) {
+            if regex.is_match(&record.tag) || regex.is_match(&record.message) {
+                highlight = true;
+                if !record.diagnostic {
+                    *count += 1;
+                }
+            }
+        }
 
-        let preamble_width = timestamp.chars().count()
+        let preamble_width = text_width(timestamp)
             + 1 // " "
-            + tag.chars().count()
+            + text_width(&delta)
+            + text_width(&uptime)
+            + text_width(&device)
+            + text_width(&log_buffer)
             + 2 // " ("
-            + pid.chars().count() + tid.chars().count()
+            + text_width(&pid) + text_width(&tid)
             + 2 // ") "
             + 3; // level
 
+        // Shrink the tag column first if the terminal is too narrow
+        let mut tag_width = self.tag_width(width);
+        if let Some(width) = width {
+            let available = width.saturating_sub(preamble_width + 3 + MIN_PAYLOAD);
+            tag_width = min(tag_width, max(available, MIN_TAG_WIDTH));
+        }
+        let tag = pad(&truncate(&record.tag, tag_width), tag_width, true);
+        let preamble_width = preamble_width + tag_width;
+
+        // Print the preamble and the message on separate lines if the
+        // terminal is still too narrow
+        let (payload_len, separate) = match width {
+            Some(width) if width < preamble_width + 3 + MIN_PAYLOAD => {
+                (max(width.saturating_sub(3), MIN_PAYLOAD), true)
+            }
+            Some(width) => (width - preamble_width - 3, false),
+            None => (usize::MAX, false),
+        };
+
         let timestamp_color = if highlight {
             Some(Color::Yellow)
         } else {
             self.dimm_color
         };
-        let tag_color = Self::hashed_color(&record.tag);
-        let pid_color = Self::hashed_color(&pid);
-        let tid_color = Self::hashed_color(&tid);
-        let level_color = match record.level {
-            Level::Debug => Some(Color::Cyan),
-            Level::Info => Some(Color::Green),
-            Level::Warn => Some(Color::Yellow),
-            Level::Error | Level::Fatal | Level::Assert => Some(Color::Red),
-            _ => self.dimm_color,
-        };
+        let device_color = self.hashed_color(&device);
+        let buffer_color = self.dimm_color;
+        let tag_color = self
+            .colors
+            .tag(&record.tag)
+            .unwrap_or_else(|| self.hashed_color(&record.tag));
+        let pid_color = self.hashed_color(&pid);
+        let tid_color = self.hashed_color(&tid);
+        let level_color = self.colors.level(&record.level).or(self.dimm_color);
+
+        let tag_marks = self.highlighted(&tag);
+        let tag_highlight = self.highlight_spec(ColorSpec::new().set_fg(Some(tag_color)));
 
-        let write_preamble = |buffer: &mut Buffer| -> Result<(), Error> {
+        let write_preamble = |buffer: &mut dyn WriteColor| -> io::Result<()> {
             let mut spec = ColorSpec::new();
             buffer.set_color(spec.set_fg(timestamp_color))?;
             buffer.write_all(timestamp.as_bytes())?;
             buffer.write_all(b" ")?;
+            buffer.write_all(delta.as_bytes())?;
+            buffer.write_all(uptime.as_bytes())?;
 
-            buffer.set_color(spec.set_fg(Some(tag_color)))?;
-            buffer.write_all(tag.as_bytes())?;
+            if !device.is_empty() {
+                buffer.set_color(spec.set_fg(Some(device_color)))?;
+                buffer.write_all(device.as_bytes())?;
+            }
+            if !log_buffer.is_empty() {
+                buffer.set_color(spec.set_fg(buffer_color))?;
+                buffer.write_all(log_buffer.as_bytes())?;
+            }
+
+            spec.set_fg(Some(tag_color));
+            write_highlighted(buffer, &tag, &tag_marks, &spec, &tag_highlight)?;
             buffer.set_color(spec.set_fg(None))?;
 
             buffer.write_all(b" (")?;
@@ -279,68 +1153,402 @@ impl Human {
             Ok(())
         };
 
-        let payload_len = terminal_width().unwrap_or(std::usize::MAX) - preamble_width - 3;
-        let message = record.message.replace('\t', "");
-        let message_len = message.chars().count();
-        let chunks = message_len / payload_len + 1;
+        let mut message = if record.message.contains('\t') {
+            Cow::Owned(record.message.replace('\t', ""))
+        } else {
+            Cow::Borrowed(record.message.as_str())
+        };
+        if let Some(width) = self.max_message_width {
+            // Truncate instead of wrapping
+            let width = min(width, payload_len);
+            if text_width(&message) > width {
+                message =
+                    Cow::Owned(truncate(&message, width.saturating_sub(1)).into_owned() + "…");
+            }
+        }
+        let chunks = chunks(&message, payload_len);
+        let message_marks = self.highlighted(&message);
+        let message_color = match self.message_color {
+            // Notices of logcat are no messages of the source
+            _ if record.notice.is_some() => self.dimm_color,
+            MessageColor::Level => level_color,
+            MessageColor::Default => None,
+            MessageColor::Dimm => self.dimm_color,
+        };
+        // Highlighted records without a visible match, e.g. one truncated
+        // away, are set apart with a bold message instead
+        let unmarked = !tag_marks.contains(&true) && !message_marks.contains(&true);
+        let mut message_spec = ColorSpec::new();
+        message_spec
+            .set_intense(self.bright_colors)
+            .set_fg(message_color)
+            .set_bold(highlight && unmarked);
+        let message_highlight = self.highlight_spec(&message_spec);
+        let mut dimmed_spec = message_spec.clone();
+        dimmed_spec.set_fg(self.dimm_color.or(message_color));
+        let dimmed_highlight = self.highlight_spec(&dimmed_spec);
+        // Index of the first char of each line
+        let line_starts = if dimmed.is_empty() {
+            Vec::new()
+        } else {
+            let newlines = message.chars().enumerate().filter(|(_, c)| *c == '\n');
+            std::iter::once(0)
+                .chain(newlines.map(|(i, _)| i + 1))
+                .collect::<Vec<_>>()
+        };
 
-        let mut buffer = self.writer.buffer();
+        if separate {
+            write_preamble(out)?;
+            out.write_all(b"\n")?;
+        }
 
-        for i in 0..chunks {
-            write_preamble(&mut buffer)?;
+        for (i, (first_char, chunk)) in chunks.iter().enumerate() {
+            if !separate {
+                write_preamble(out)?;
+            }
 
-            let c = if chunks == 1 {
+            let c = if chunks.len() == 1 {
                 "   "
             } else if i == 0 {
                 " ┌ "
-            } else if i == chunks - 1 {
+            } else if i == chunks.len() - 1 {
                 " └ "
             } else {
                 " ├ "
             };
 
-            buffer.write_all(c.as_bytes())?;
+            out.write_all(c.as_bytes())?;
 
-            let chunk = message
-                .chars()
-                .skip(i * payload_len)
-                .take(payload_len)
-                .collect::<String>();
-            buffer.set_color(
-                ColorSpec::new()
-                    .set_intense(self.bright_colors)
-                    .set_fg(level_color),
-            )?;
-            buffer.write_all(chunk.as_bytes())?;
-            buffer.write_all(b"\n")?;
+            // Matches spanning chunks are highlighted in every chunk
+            let marks = if message_marks.is_empty() {
+                &[][..]
+            } else {
+                let end = min(first_char + chunk.chars().count(), message_marks.len());
+                &message_marks[min(*first_char, end)..end]
+            };
+            let line = line_starts
+                .partition_point(|start| start <= first_char)
+                .saturating_sub(1);
+            if dimmed.get(line).copied().unwrap_or(false) {
+                write_highlighted(out, chunk, marks, &dimmed_spec, &dimmed_highlight)?;
+            } else {
+                write_highlighted(out, chunk, marks, &message_spec, &message_highlight)?;
+            }
+            out.reset()?;
+            out.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write `text` with `spec` and the chars marked in `marks` with `highlight`
+fn write_highlighted(
+    buffer: &mut dyn WriteColor,
+    text: &str,
+    marks: &[bool],
+    spec: &ColorSpec,
+    highlight: &ColorSpec,
+) -> io::Result<()> {
+    if marks.is_empty() {
+        buffer.set_color(spec)?;
+        buffer.write_all(text.as_bytes())?;
+        return Ok(());
+    }
+
+    let mut span = String::new();
+    let mut current = false;
+    for (c, marked) in text.chars().zip(marks.iter().copied()) {
+        if marked != current && !span.is_empty() {
+            buffer.set_color(if current { highlight } else { spec })?;
+            buffer.write_all(span.as_bytes())?;
+            span.clear();
         }
+        current = marked;
+        span.push(c);
+    }
+    buffer.set_color(if current { highlight } else { spec })?;
+    buffer.write_all(span.as_bytes())?;
+    Ok(())
+}
 
-        self.writer.print(&buffer).map_err(Into::into)
+impl Highlights for Human {
+    fn highlights(&self) -> HighlightSummary {
+        HighlightSummary {
+            counts: self
+                .highlight
+                .iter()
+                .zip(&self.highlight_counts)
+                .map(|(regex, count)| (regex.as_str().to_owned(), *count))
+                .collect(),
+        }
     }
 }
 
-impl Drop for Human {
+impl Terminal {
+    fn from(
+        args: &CliArguments,
+        profile: &Profile,
+        processes: Processes,
+    ) -> Result<Terminal, Error> {
+        let width = TerminalWidth::watch();
+        let writer = BufferWriter::stdout(color_choice(args));
+        Ok(Terminal {
+            pending: writer.buffer(),
+            writer,
+            human: Human::from(args, profile, Format::Human)?.processes(processes),
+            paused: None,
+            last_width: width.get(),
+            width,
+        })
+    }
+
+    /// Print record or buffer it if the output is paused
+    fn push(&mut self, record: Record) -> io::Result<()> {
+        match self.paused {
+            Some(ref mut paused) if paused.backlog.len() < PAUSE_BACKLOG => {
+                paused.backlog.push_back(record);
+                Ok(())
+            }
+            Some(ref mut paused) => {
+                paused.dropped += 1;
+                Ok(())
+            }
+            None => self.print(&record),
+        }
+    }
+
+    /// Pause or resume the output. Resuming prints the records received
+    /// while paused.
+    fn toggle_pause(&mut self) -> io::Result<()> {
+        match self.paused.take() {
+            Some(paused) => {
+                for record in paused.backlog {
+                    self.print(&record)?;
+                }
+                if paused.dropped > 0 {
+                    self.notice(&format!("{} records dropped while paused", paused.dropped))?;
+                }
+                self.flush()
+            }
+            None => {
+                self.paused = Some(Paused::default());
+                self.notice("Paused - press space to resume")
+            }
+        }
+    }
+
+    /// Print a line that is not a record
+    fn notice(&mut self, text: &str) -> io::Result<()> {
+        self.flush()?;
+        let mut buffer = self.writer.buffer();
+        buffer.set_color(ColorSpec::new().set_fg(self.human.dimm_color))?;
+        buffer.write_all(text.as_bytes())?;
+        buffer.reset()?;
+        buffer.write_all(b"\n")?;
+        self.writer.print(&buffer)
+    }
+
+    /// Clear the screen and move the cursor to the top left corner
+    fn clear(&mut self) -> io::Result<()> {
+        self.pending.clear();
+        let mut buffer = self.writer.buffer();
+        buffer.write_all(b"\x1b[2J\x1b[H")?;
+        self.writer.print(&buffer)
+    }
+
+    /// Render a record. The output is printed on flush or if the pending
+    /// output exceeds `BATCH_SIZE`.
+    fn print(&mut self, record: &Record) -> io::Result<()> {
+        let width = self.width.get();
+        if width != self.last_width {
+            self.last_width = width;
+            self.human.resize();
+        }
+        self.human.push(record, width, &mut self.pending)?;
+        if self.pending.len() >= BATCH_SIZE {
+            self.print_pending()?;
+        }
+        Ok(())
+    }
+
+    /// Render a held back stack trace and print the pending output
+    fn flush(&mut self) -> io::Result<()> {
+        self.human.finish(self.width.get(), &mut self.pending)?;
+        self.print_pending()
+    }
+
+    /// Flush and print the highlight counts in a dimmed footer. The footer
+    /// is printed only on close and not on drop. Failing to write it, e.g.
+    /// to a pipe that is already closed, is ignored.
+    fn close(&mut self) -> io::Result<()> {
+        self.flush()?;
+        if !self.human.highlight.is_empty() {
+            let footer = self.human.highlights().to_string();
+            self.notice(&footer).ok();
+        }
+        Ok(())
+    }
+
+    /// Print the pending output with a single write
+    fn print_pending(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            self.writer.print(&self.pending)?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Terminal {
     fn drop(&mut self) {
+        self.flush().ok();
         let mut buffer = self.writer.buffer();
         buffer.reset().and_then(|_| self.writer.print(&buffer)).ok();
     }
 }
 
-struct FormatSink<T: Write> {
+impl Highlights for Terminal {
+    fn highlights(&self) -> HighlightSummary {
+        self.human.highlights()
+    }
+}
+
+impl Sink<Record> for Terminal {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Record) -> Result<(), Self::Error> {
+        self.push(item).map_err(|e| Error::io("Terminal error", e))
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(self.flush().map_err(|e| Error::io("Terminal error", e)))
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(self.close().map_err(|e| Error::io("Terminal error", e)))
+    }
+}
+
+/// Sink that writes records in formats other than human or with a template
+/// to stdout. Lines are tinted by level and json keys are colored.
+struct ColorFormatSink {
     format: Format,
-    sink: BufWriter<T>,
+    columns: Option<Vec<Column>>,
+    level_style: LevelStyle,
+    template: Option<Template>,
+    colors: Colors,
+    writer: BufferWriter,
+    pending: Buffer,
 }
 
-impl<T: Write> FormatSink<T> {
-    fn new(format: Format, sink: T) -> FormatSink<T> {
-        FormatSink {
+impl ColorFormatSink {
+    fn new(
+        format: Format,
+        args: &CliArguments,
+        colors: Colors,
+        color: ColorChoice,
+    ) -> ColorFormatSink {
+        let writer = BufferWriter::stdout(color);
+        ColorFormatSink {
             format,
-            sink: BufWriter::new(sink),
+            columns: (!args.columns.is_empty()).then(|| args.columns.clone()),
+            level_style: args.level_style,
+            template: None,
+            colors,
+            pending: writer.buffer(),
+            writer,
+        }
+    }
+
+    /// Write the records with `template` instead of the format
+    fn template(mut self, template: Template) -> ColorFormatSink {
+        self.template = Some(template);
+        self
+    }
+
+    fn print(&mut self, record: &Record) -> Result<(), Error> {
+        let line = match &self.template {
+            Some(template) => template.render(record),
+            None => self
+                .format
+                .fmt_styled(record, self.columns.as_deref(), self.level_style)?,
+        };
+        let mut spec = ColorSpec::new();
+        spec.set_fg(self.colors.level(&record.level));
+        match self.format {
+            Format::Json | Format::JsonPretty => write_json(&mut self.pending, &line, &spec),
+            _ => self
+                .pending
+                .set_color(&spec)
+                .and_then(|_| self.pending.write_all(line.as_bytes())),
+        }
+        .and_then(|_| self.pending.reset())
+        .and_then(|_| self.pending.write_all(b"\n"))
+        .map_err(|e| Error::io("Terminal error", e))?;
+        if self.pending.len() >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if !self.pending.is_empty() {
+            self.writer
+                .print(&self.pending)
+                .map_err(|e| Error::io("Terminal error", e))?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Write `json` with `spec` and the keys of objects in blue
+fn write_json(out: &mut dyn WriteColor, json: &str, spec: &ColorSpec) -> io::Result<()> {
+    let mut key = ColorSpec::new();
+    key.set_fg(Some(Color::Blue));
+    let bytes = json.as_bytes();
+    // Start of the text not written yet
+    let mut plain = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'"' {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        i += 1;
+        while i < bytes.len() && bytes[i] != b'"' {
+            if bytes[i] == b'\\' {
+                i += 1;
+            }
+            i += 1;
+        }
+        let end = min(i + 1, bytes.len());
+        i = end;
+        if json[end..].trim_start().starts_with(':') {
+            out.set_color(spec)?;
+            out.write_all(&bytes[plain..start])?;
+            out.set_color(&key)?;
+            out.write_all(&bytes[start..end])?;
+            plain = end;
         }
     }
+    out.set_color(spec)?;
+    out.write_all(&bytes[plain..])
 }
 
-impl<T: Write + std::marker::Unpin> Sink<Record> for FormatSink<T> {
+impl Sink<Record> for ColorFormatSink {
     type Error = Error;
 
     fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
@@ -348,38 +1556,944 @@ impl<T: Write + std::marker::Unpin> Sink<Record> for FormatSink<T> {
     }
 
     fn start_send(self: Pin<&mut Self>, item: Record) -> Result<(), Self::Error> {
-        let this = self.get_mut();
-        this.sink
-            .write_all(this.format.fmt_record(&item)?.as_bytes())?;
-        this.sink.write_all(&[b'\n'])?;
-        Ok(())
+        self.get_mut().print(&item)
     }
 
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+        Poll::Ready(self.get_mut().flush())
     }
 
-    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
     }
 }
 
-impl Sink<Record> for Human {
-    type Error = Error;
+/// Keyboard control of the human terminal output: space pauses and resumes,
+/// `c` clears the screen and `q` quits.
+#[cfg(unix)]
+mod keyboard {
+    use super::Terminal;
+    use anyhow::{anyhow, Result};
+    use futures::{
+        sink::Sink,
+        task::{Context, Poll},
+    };
+    use rogcat::{record::Record, Error, LogSink};
+    use std::{
+        io::{stdin, Read},
+        os::unix::io::AsRawFd,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        thread,
+    };
+    use termios::{tcsetattr, Termios, ECHO, ICANON, TCSANOW, VMIN, VTIME};
+    use tokio_util::sync::CancellationToken;
 
-    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    /// Terminal output shared with the thread reading keys
+    struct Interactive {
+        terminal: Arc<Mutex<Terminal>>,
+        /// Terminal settings restored on drop
+        termios: Termios,
     }
 
-    fn start_send(mut self: Pin<&mut Self>, item: Record) -> Result<(), Self::Error> {
-        self.print(&item)
+    /// Disable line buffering and echo of stdin and spawn a thread that
+    /// handles key presses
+    pub(super) fn interactive(terminal: Terminal, shutdown: CancellationToken) -> Result<LogSink> {
+        let fd = stdin().as_raw_fd();
+        let termios =
+            Termios::from_fd(fd).map_err(|e| anyhow!("Failed to get terminal settings: {}", e))?;
+        let mut raw = termios;
+        raw.c_lflag &= !(ICANON | ECHO);
+        raw.c_cc[VMIN] = 1;
+        raw.c_cc[VTIME] = 0;
+        tcsetattr(fd, TCSANOW, &raw)
+            .map_err(|e| anyhow!("Failed to set terminal settings: {}", e))?;
+
+        let terminal = Arc::new(Mutex::new(terminal));
+        let keys = terminal.clone();
+        thread::spawn(move || {
+            let mut bytes = stdin().lock().bytes();
+            loop {
+                let result = match bytes.next() {
+                    Some(Ok(b' ')) => keys.lock().unwrap().toggle_pause(),
+                    Some(Ok(b'c')) => keys.lock().unwrap().clear(),
+                    Some(Ok(b'q')) => break,
+                    Some(Ok(_)) => Ok(()),
+                    // Without keys the output continues until the source
+                    // ends. Paused output is resumed because it can't be
+                    // resumed by a key anymore.
+                    None | Some(Err(_)) => {
+                        let mut terminal = keys.lock().unwrap();
+                        if terminal.paused.is_some() {
+                            terminal.toggle_pause().ok();
+                        }
+                        return;
+                    }
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+            shutdown.cancel();
+        });
+
+        Ok(Box::new(Interactive { terminal, termios }))
     }
 
-    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    impl Sink<Record> for Interactive {
+        type Error = Error;
+
+        fn poll_ready(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(self: Pin<&mut Self>, item: Record) -> Result<(), Self::Error> {
+            self.terminal
+                .lock()
+                .unwrap()
+                .push(item)
+                .map_err(|e| Error::io("Terminal error", e))
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(
+                self.terminal
+                    .lock()
+                    .unwrap()
+                    .flush()
+                    .map_err(|e| Error::io("Terminal error", e)),
+            )
+        }
+
+        fn poll_close(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(
+                self.terminal
+                    .lock()
+                    .unwrap()
+                    .close()
+                    .map_err(|e| Error::io("Terminal error", e)),
+            )
+        }
     }
 
-    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    impl Drop for Interactive {
+        fn drop(&mut self) {
+            tcsetattr(stdin().as_raw_fd(), TCSANOW, &self.termios).ok();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod keyboard {
+    use super::Terminal;
+    use anyhow::Result;
+    use rogcat::LogSink;
+    use tokio_util::sync::CancellationToken;
+
+    /// Keyboard control is not supported on this platform
+    pub(super) fn interactive(terminal: Terminal, _: CancellationToken) -> Result<LogSink> {
+        Ok(Box::new(terminal))
+    }
+}
+
+#[test]
+fn highlight_spans() {
+    let mut buffer = termcolor::Buffer::no_color();
+    let marks = [false, true, true, false, true];
+    write_highlighted(
+        &mut buffer,
+        "abcde",
+        &marks,
+        &ColorSpec::new(),
+        ColorSpec::new().set_bold(true),
+    )
+    .unwrap();
+    assert_eq!(buffer.as_slice(), b"abcde");
+
+    let mut buffer = termcolor::Buffer::ansi();
+    write_highlighted(
+        &mut buffer,
+        "abcde",
+        &marks,
+        &ColorSpec::new(),
+        ColorSpec::new().set_bold(true),
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(buffer.as_slice()),
+        "\x1b[0ma\x1b[0m\x1b[1mbc\x1b[0md\x1b[0m\x1b[1me"
+    );
+}
+
+#[cfg(test)]
+fn human() -> Human {
+    use clap::Parser;
+    let args = CliArguments::parse_from(["rogcat", "--hide-timestamp"]);
+    let mut human = Human::from(&args, &Profile::default(), Format::Human).unwrap();
+    human.tag_width = Some(3);
+    human
+}
+
+#[cfg(test)]
+fn render_record(human: &mut Human, record: &Record, width: Option<usize>) -> String {
+    let mut buffer = termcolor::Buffer::no_color();
+    human.render(record, width, &mut buffer).unwrap();
+    String::from_utf8(buffer.into_inner()).unwrap()
+}
+
+#[cfg(test)]
+fn render(human: &mut Human, process: &str, thread: &str, message: &str) -> String {
+    let record = Record {
+        tag: "tag".to_owned(),
+        process: process.to_owned(),
+        thread: thread.to_owned(),
+        message: message.to_owned(),
+        ..Default::default()
+    };
+    render_record(human, &record, None)
+}
+
+#[test]
+fn column_width_shrinks() {
+    let mut human = human();
+    assert_eq!(render(&mut human, "1", "2", "m"), " tag (1 2)  -    m\n");
+    assert_eq!(
+        render(&mut human, "com.example", "2", "m"),
+        " tag (com.example 2)  -    m\n"
+    );
+    assert_eq!(
+        render(&mut human, "1", "2", "m"),
+        " tag (1           2)  -    m\n"
+    );
+    for _ in 0..COLUMN_SHRINK {
+        render(&mut human, "12", "2", "m");
+    }
+    assert_eq!(render(&mut human, "1", "2", "m"), " tag (1  2)  -    m\n");
+}
+
+#[test]
+fn column_width_fixed() {
+    let mut human = human();
+    human.process_width = ColumnWidth::new(Some(4));
+    human.thread_width = ColumnWidth::new(Some(2));
+    assert_eq!(
+        render(&mut human, "1", "2", "m"),
+        " tag (1     2)  -    m\n"
+    );
+    assert_eq!(
+        render(&mut human, "com.example", "12345", "m"),
+        " tag (com. 12)  -    m\n"
+    );
+}
+
+#[test]
+fn thread_names() {
+    let mut human = human();
+    let record = Record {
+        tag: "tag".to_owned(),
+        process: "1".to_owned(),
+        thread: "2".to_owned(),
+        thread_name: Some("RenderThread".to_owned()),
+        message: "m".to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(
+        render_record(&mut human, &record, None),
+        " tag (1 RenderThread)  -    m\n"
+    );
+    human.thread_width = ColumnWidth::new(Some(6));
+    assert_eq!(
+        render_record(&mut human, &record, None),
+        " tag (1 Render)  -    m\n"
+    );
+}
+
+#[test]
+fn buffer_column() {
+    let mut human = human();
+    let record = |buffer: &str| Record {
+        tag: "tag".to_owned(),
+        process: "1".to_owned(),
+        thread: "2".to_owned(),
+        message: "m".to_owned(),
+        buffer: Some(buffer.to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(
+        render_record(&mut human, &record("events"), None),
+        " events tag (1 2)  -    m\n"
+    );
+    assert_eq!(
+        render_record(&mut human, &record("main"), None),
+        " main   tag (1 2)  -    m\n"
+    );
+}
+
+#[test]
+fn max_message_width() {
+    let mut human = human();
+    human.max_message_width = Some(5);
+    assert_eq!(
+        render(&mut human, "1", "2", "short"),
+        " tag (1 2)  -    short\n"
+    );
+    assert_eq!(
+        render(&mut human, "1", "2", "a longer message"),
+        " tag (1 2)  -    a lo…\n"
+    );
+}
+
+#[test]
+fn render_human() {
+    let mut human = human();
+    human.tag_width = None;
+    human.date_format = DateFormat::HourOnly;
+    let record = Record {
+        time: Some("01-10 23:56:50.101".to_owned()),
+        level: Level::Info,
+        tag: "ActivityManager".to_owned(),
+        process: "1234".to_owned(),
+        thread: "1235".to_owned(),
+        message: "Start proc".to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(
+        render_record(&mut human, &record, Some(80)),
+        "23:56:50.101 ActivityManager (1234 1235)  I    Start proc\n"
+    );
+
+    // Long tag is truncated
+    let record = Record {
+        tag: "VeryLongTagNameExceedingTheColumn".to_owned(),
+        ..record
+    };
+    assert_eq!(
+        render_record(&mut human, &record, Some(80)),
+        "23:56:50.101 VeryLongTagName (1234 1235)  I    Start proc\n"
+    );
+
+    // Empty thread keeps the column
+    let record = Record {
+        tag: "Tag".to_owned(),
+        thread: String::new(),
+        ..record
+    };
+    assert_eq!(
+        render_record(&mut human, &record, Some(80)),
+        "23:56:50.101             Tag (1234     )  I    Start proc\n"
+    );
+
+    // Multi chunk message
+    let record = Record {
+        thread: "1235".to_owned(),
+        message: "a".repeat(40),
+        ..record
+    };
+    assert_eq!(
+        render_record(&mut human, &record, Some(70)),
+        format!(
+            "23:56:50.101             Tag (1234 1235)  I  ┌ {}\n\
+             23:56:50.101             Tag (1234 1235)  I  └ {}\n",
+            "a".repeat(23),
+            "a".repeat(17)
+        )
+    );
+}
+
+#[test]
+fn render_narrow() {
+    let mut human = human();
+    human.tag_width = None;
+    human.date_format = DateFormat::HourOnly;
+    let record = Record {
+        time: Some("01-10 23:56:50.101".to_owned()),
+        level: Level::Info,
+        tag: "ActivityManager".to_owned(),
+        process: "1234".to_owned(),
+        thread: "1235".to_owned(),
+        message: "Start proc".to_owned(),
+        ..Default::default()
+    };
+
+    // The tag column shrinks
+    assert_eq!(
+        render_record(&mut human, &record, Some(60)),
+        "23:56:50.101 Activity (1234 1235)  I    Start proc\n"
+    );
+
+    // Preamble and message are printed on separate lines
+    assert_eq!(
+        render_record(&mut human, &record, Some(30)),
+        "23:56:50.101 Activity (1234 1235)  I \n   Start proc\n"
+    );
+    let record = Record {
+        message: "a".repeat(30),
+        ..record
+    };
+    assert_eq!(
+        render_record(&mut human, &record, Some(30)),
+        format!(
+            "23:56:50.101 Activity (1234 1235)  I \n ┌ {}\n └ {}\n",
+            "a".repeat(27),
+            "a".repeat(3)
+        )
+    );
+    assert!(!render_record(&mut human, &record, Some(0)).is_empty());
+}
+
+#[test]
+fn render_wide_chars() {
+    let mut human = human();
+    human.tag_width = None;
+    human.date_format = DateFormat::HourOnly;
+    let record = |tag: &str, message: &str| Record {
+        time: Some("01-10 23:56:50.101".to_owned()),
+        level: Level::Info,
+        tag: tag.to_owned(),
+        process: "1234".to_owned(),
+        thread: "1235".to_owned(),
+        message: message.to_owned(),
+        ..Default::default()
+    };
+    // Column of the pid in terminal columns
+    let pid_column = |line: &str| text_width(&line[..line.find(" (").unwrap()]);
+
+    for (tag, message) in [
+        ("Tag", "a".repeat(100)),
+        ("标签", "日志消息".repeat(20)),
+        ("Tag🚀", "mixed 日志 and emoji 🚀🚀 ".repeat(8)),
+        ("👩‍🚀👩‍🚀👩‍🚀👩‍🚀👩‍🚀👩‍🚀👩‍🚀👩‍🚀👩‍🚀", "e\u{301}".repeat(100)),
+    ] {
+        let rendered = render_record(&mut human, &record(tag, &message), Some(60));
+        let lines = rendered.lines().collect::<Vec<_>>();
+        assert!(lines.len() > 1);
+        for line in &lines {
+            assert!(text_width(line) <= 60, "{line} exceeds 60 columns");
+            assert_eq!(pid_column(line), pid_column(lines[0]));
+        }
+        // All but the last chunk fill the line up to a wide char
+        assert!(text_width(lines[0]) >= 59);
+        let payload = lines
+            .iter()
+            .map(|l| {
+                l.split_once(" ┌ ")
+                    .or(l.split_once(" ├ "))
+                    .or(l.split_once(" └ "))
+            })
+            .map(|l| l.unwrap().1)
+            .collect::<String>();
+        assert_eq!(payload, message);
+    }
+}
+
+#[test]
+fn truncate_wide_chars() {
+    assert_eq!(truncate("abc", 2), "ab");
+    assert_eq!(truncate("日志消息", 5), "日志");
+    assert_eq!(truncate("a🚀b", 2), "a");
+    assert_eq!(truncate("e\u{301}e\u{301}", 1), "e\u{301}");
+    assert_eq!(pad("日志", 6, true), "  日志");
+    assert_eq!(pad("日志", 6, false), "日志  ");
+}
+
+#[test]
+fn chunks_multi_line() {
+    assert_eq!(chunks("ab\ncdef", 2), [(0, "ab"), (3, "cd"), (5, "ef")]);
+    assert_eq!(chunks("a\n\nb", 10), [(0, "a"), (2, ""), (3, "b")]);
+    assert_eq!(chunks("a\r\nb", 10), [(0, "a"), (3, "b")]);
+    assert_eq!(chunks("abcde", 2), [(0, "ab"), (2, "cd"), (4, "e")]);
+    assert_eq!(chunks("a日志b", 3), [(0, "a日"), (2, "志b")]);
+}
+
+#[test]
+fn json_keys_colored() {
+    let mut buffer = Buffer::ansi();
+    let mut spec = ColorSpec::new();
+    spec.set_fg(Some(Color::Red));
+    let json = r#"{"message":"a \"b\": c","level":"E"}"#;
+    write_json(&mut buffer, json, &spec).unwrap();
+    let output = String::from_utf8(buffer.into_inner()).unwrap();
+    // Keys are blue, the rest red. Quotes within values are no keys.
+    assert!(output.contains("\x1b[0m\x1b[34m\"message\"\x1b[0m\x1b[31m:\"a \\\"b\\\": c\","));
+    assert!(output.contains("\x1b[34m\"level\""));
+
+    let mut buffer = Buffer::no_color();
+    write_json(&mut buffer, json, &spec).unwrap();
+    assert_eq!(buffer.as_slice(), json.as_bytes());
+}
+
+#[test]
+fn color_overrides() {
+    let profiles = rogcat::profiles::parse(
+        r#"
+        [profile.base]
+        tag_colors = { "Watchdog" = "red", "ActivityManager" = 202 }
+        level_colors = { warn = 208 }
+
+        [profile.team]
+        extends = ["base"]
+        tag_colors = { "Watchdog" = "magenta" }
+        level_colors = { E = "196" }
+        "#,
+    )
+    .unwrap();
+    let profile = rogcat::profiles::select(&profiles, &["team"]).unwrap();
+    let colors = Colors::from(&profile).unwrap();
+    // The extending profile takes precedence
+    assert_eq!(colors.tag("Watchdog"), Some(Color::Magenta));
+    assert_eq!(colors.tag("ActivityManager"), Some(Color::Ansi256(202)));
+    assert_eq!(colors.tag("Other"), None);
+    assert_eq!(colors.level(&Level::Warn), Some(Color::Ansi256(208)));
+    assert_eq!(colors.level(&Level::Error), Some(Color::Ansi256(196)));
+    assert_eq!(colors.level(&Level::Info), Some(Color::Green));
+    assert_eq!(colors.level(&Level::Verbose), None);
+
+    // Overrides take priority over the hashed tag color
+    use clap::Parser;
+    let args = CliArguments::parse_from(["rogcat", "--color", "always"]);
+    let mut human = Human::from(&args, &profile, Format::Human).unwrap();
+    let record = Record {
+        tag: "ActivityManager".to_owned(),
+        level: Level::Warn,
+        message: "m".to_owned(),
+        ..Default::default()
+    };
+    let mut buffer = Buffer::ansi();
+    human.render(&record, None, &mut buffer).unwrap();
+    let output = String::from_utf8(buffer.into_inner()).unwrap();
+    assert!(output.contains("\x1b[38;5;202m"));
+    assert!(output.contains("\x1b[48;5;208m"));
+
+    let invalid = |profile: &str| {
+        let profiles = rogcat::profiles::parse(profile).unwrap();
+        let profile = rogcat::profiles::select(&profiles, &["p"]).unwrap();
+        Colors::from(&profile).unwrap_err().to_string()
+    };
+    assert!(
+        invalid("[profile.p]\ntag_colors = { Tag = \"pink\" }").contains("Invalid color of Tag")
+    );
+    assert!(invalid("[profile.p]\nlevel_colors = { loud = 1 }").contains("Invalid level loud"));
+}
+
+#[test]
+fn group_traces() {
+    let mut human = human();
+    human.group_traces = true;
+    human.tag_width = Some(14);
+    let lines = [
+        "FATAL EXCEPTION: main",
+        "Process: com.example.app, PID: 4242",
+        "java.lang.IllegalStateException: Could not execute method for android:onClick",
+        "\tat android.view.View$DeclaredOnClickListener.onClick(View.java:6294)",
+        "\tat com.example.app.MainActivity.onSend(MainActivity.java:42)",
+        "Caused by: java.lang.reflect.InvocationTargetException",
+        "\tat java.lang.reflect.Method.invoke(Native Method)",
+        "\t... 11 more",
+    ];
+    let record = |message: &str| Record {
+        time: Some("01-10 23:56:50.101".to_owned()),
+        level: Level::Error,
+        tag: "AndroidRuntime".to_owned(),
+        process: "4242".to_owned(),
+        thread: "4242".to_owned(),
+        message: message.to_owned(),
+        ..Default::default()
+    };
+
+    let mut buffer = Buffer::no_color();
+    for line in lines {
+        human.push(&record(line), None, &mut buffer).unwrap();
+    }
+    // A record of another thread ends the trace
+    let other = Record {
+        thread: "4250".to_owned(),
+        ..record("at the end")
+    };
+    human.push(&other, None, &mut buffer).unwrap();
+    human.finish(None, &mut buffer).unwrap();
+    let preamble = " AndroidRuntime (4242 4242)  E ";
+    assert_eq!(
+        String::from_utf8(buffer.into_inner()).unwrap(),
+        format!(
+            "{preamble}   FATAL EXCEPTION: main\n\
+             {preamble}   Process: com.example.app, PID: 4242\n\
+             {preamble} ┌ java.lang.IllegalStateException: Could not execute method for android:onClick\n\
+             {preamble} ├   at android.view.View$DeclaredOnClickListener.onClick(View.java:6294)\n\
+             {preamble} ├   at com.example.app.MainActivity.onSend(MainActivity.java:42)\n\
+             {preamble} ├ Caused by: java.lang.reflect.InvocationTargetException\n\
+             {preamble} ├   at java.lang.reflect.Method.invoke(Native Method)\n\
+             {preamble} └   ... 11 more\n \
+             AndroidRuntime (4242 4250)  E    at the end\n"
+        )
+    );
+
+    // Platform frames are dimmed
+    let mut buffer = Buffer::ansi();
+    for line in &lines[2..5] {
+        human.push(&record(line), None, &mut buffer).unwrap();
+    }
+    human.finish(None, &mut buffer).unwrap();
+    let output = String::from_utf8(buffer.into_inner()).unwrap();
+    let color = |frame: &str| {
+        let end = output.find(frame).unwrap();
+        let start = output[..end].rfind("\x1b[").unwrap();
+        output[start..end].trim_end().to_owned()
+    };
+    assert_eq!(color("at android.view"), "\x1b[38;5;243m");
+    assert_eq!(color("at com.example"), "\x1b[31m");
+}
+
+#[test]
+fn color_choices() {
+    use ColorChoice::*;
+    // flag, NO_COLOR, terminal_color, tty, expected
+    let table = [
+        (None, false, None, true, Auto),
+        (None, false, None, false, Never),
+        (None, false, Some("always"), false, Always),
+        (None, false, Some("never"), true, Never),
+        (Some("never"), false, Some("always"), true, Never),
+        (Some("auto"), false, Some("never"), true, Auto),
+        (None, true, None, true, Never),
+        (None, true, Some("always"), true, Never),
+        (Some("auto"), true, None, true, Never),
+        (Some("always"), true, None, false, Always),
+    ];
+    for (flag, no_color, config, tty, expected) in table {
+        assert_eq!(
+            choose_color(flag, no_color, config, tty),
+            expected,
+            "{flag:?} {no_color} {config:?} {tty}"
+        );
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn light_theme_colors() {
+    let mut human = human();
+    human.theme = Theme::Light;
+    // A single byte hashes to every color once
+    for byte in 0..=255u8 {
+        let Color::Ansi256(c) = human.hashed_color(&char::from(byte).to_string()) else {
+            panic!("Not an Ansi256 color");
+        };
+        let pale = match c {
+            7 | 9..=15 | 244..=255 => true,
+            16..=231 => [(c - 16) / 36, (c - 16) / 6 % 6, (c - 16) % 6]
+                .iter()
+                .any(|component| *component > 3),
+            _ => false,
+        };
+        assert!(!pale, "Pale color {c}");
+    }
+}
+
+#[test]
+fn gaps_and_deltas() {
+    let mut human = human();
+    human.gap_threshold = Some(1000);
+    human.show_delta = true;
+    fn lines(human: &mut Human, time: Option<&str>) -> String {
+        let record = Record {
+            time: time.map(str::to_owned),
+            tag: "tag".to_owned(),
+            process: "1".to_owned(),
+            thread: "2".to_owned(),
+            message: "m".to_owned(),
+            ..Default::default()
+        };
+        render_record(human, &record, None)
+    }
+
+    assert_eq!(
+        lines(&mut human, Some("01-10 23:59:58.000")),
+        "         tag (1 2)  -    m\n"
+    );
+    assert_eq!(
+        lines(&mut human, Some("01-10 23:59:58.123")),
+        "  +123ms tag (1 2)  -    m\n"
+    );
+    // Records without time don't reset the previous time
+    assert_eq!(lines(&mut human, None), "         tag (1 2)  -    m\n");
+    assert_eq!(
+        lines(&mut human, Some("01-11 00:00:02.323")),
+        "─── 4.2 s gap ───\n   +4.2s tag (1 2)  -    m\n"
+    );
+    assert_eq!(
+        lines(&mut human, Some("01-11 00:00:02.300")),
+        "   -23ms tag (1 2)  -    m\n"
+    );
+
+    // Times without date wrap at midnight
+    human.last_time = None;
+    lines(&mut human, Some("23:59:59.500"));
+    assert_eq!(
+        lines(&mut human, Some("00:00:00.250")),
+        "  +750ms tag (1 2)  -    m\n"
+    );
+    assert_eq!(
+        lines(&mut human, Some("00:02:00.250")),
+        "─── 120.0 s gap ───\n   +120s tag (1 2)  -    m\n"
+    );
+
+    // Seconds of logcat -v monotonic
+    human.last_time = None;
+    lines(&mut human, Some("12.500"));
+    assert_eq!(
+        lines(&mut human, Some("13.000")),
+        "  +500ms tag (1 2)  -    m\n"
+    );
+}
+
+#[test]
+fn gap_thresholds() {
+    assert_eq!(parse_threshold("500ms").unwrap(), 500);
+    assert_eq!(parse_threshold("1.5").unwrap(), 1500);
+    assert_eq!(parse_threshold("2s").unwrap(), 2000);
+    assert_eq!(parse_threshold("1m").unwrap(), 60_000);
+    assert_eq!(parse_threshold("1h").unwrap(), 3_600_000);
+    assert!(parse_threshold("s").is_err());
+}
+
+#[test]
+fn group_by_process() {
+    let processes = Processes::default();
+    let mut human = human().processes(processes.clone());
+    human.group_by_process = true;
+    processes.observe(&Record {
+        tag: "am_proc_start".to_owned(),
+        message: "[0,100,10050,com.example.app,activity,{}]".to_owned(),
+        ..Default::default()
+    });
+
+    let output = [
+        ("100", "a"),
+        ("100", "b"),
+        ("200", "c"),
+        ("100", "d"),
+        ("", "diagnostic"),
+        ("100", "e"),
+        ("200", "f"),
+        ("200", "g"),
+    ]
+    .iter()
+    .map(|(process, message)| render(&mut human, process, "2", message))
+    .collect::<String>();
+    assert_eq!(
+        output,
+        "─── com.example.app (100) ───\n \
+         tag (100 2)  -    a\n \
+         tag (100 2)  -    b\n\
+         ─── 200 ───\n \
+         tag (200 2)  -    c\n\
+         ─── com.example.app (100) ───\n \
+         tag (100 2)  -    d\n \
+         tag (    2)  -    diagnostic\n \
+         tag (100 2)  -    e\n\
+         ─── 200 ───\n \
+         tag (200 2)  -    f\n \
+         tag (200 2)  -    g\n"
+    );
+
+    // Records of other devices start a group of their own
+    let record = Record {
+        device: Some("emulator-5554".to_owned()),
+        tag: "tag".to_owned(),
+        process: "200".to_owned(),
+        message: "h".to_owned(),
+        ..Default::default()
+    };
+    assert!(render_record(&mut human, &record, None).starts_with("─── emulator-5554 200 ───\n"));
+}
+
+#[test]
+fn uptime_format() {
+    assert_eq!(format_uptime(-500), "+00.000s");
+    assert_eq!(format_uptime(99_999), "+99.999s");
+    assert_eq!(format_uptime(100_000), "+1m40s");
+    assert_eq!(format_uptime(5_999_999), "+99m59s");
+    assert_eq!(format_uptime(3_720_000 * 2), "+2h04m");
+    assert_eq!(format_uptime(90_000_000), "+25h00m");
+    assert_eq!(format_uptime(360_000_000), "+4d04h");
+    for millis in [0, 99_999, 5_999_999, 359_999_999, 8_639_999_999] {
+        assert!(format_uptime(millis).len() <= UPTIME_WIDTH);
     }
 }
+
+#[test]
+fn uptime_column() {
+    let processes = Processes::default();
+    let mut human = human().processes(processes.clone());
+    human.show_uptime = true;
+    let start = |pid: &str, time: &str| {
+        processes.observe(&Record {
+            time: Some(time.to_owned()),
+            tag: "am_proc_start".to_owned(),
+            message: format!("[0,{pid},10050,com.example,activity,{{}}]"),
+            ..Default::default()
+        })
+    };
+    let mut lines = |process: &str, time: Option<&str>| {
+        let record = Record {
+            time: time.map(str::to_owned),
+            tag: "tag".to_owned(),
+            process: process.to_owned(),
+            thread: "2".to_owned(),
+            message: "m".to_owned(),
+            ..Default::default()
+        };
+        render_record(&mut human, &record, None)
+    };
+
+    start("100", "01-10 23:59:58.766");
+    assert_eq!(
+        lines("100", Some("01-11 00:00:00.000")),
+        " +01.234s tag (100 2)  -    m\n"
+    );
+    assert_eq!(
+        lines("100", Some("01-11 00:02:03.766")),
+        "   +2m05s tag (100 2)  -    m\n"
+    );
+    // Unknown processes, records before the start and records without time
+    // are blank
+    assert_eq!(
+        lines("200", Some("01-11 00:00:00.000")),
+        "          tag (200 2)  -    m\n"
+    );
+    assert_eq!(
+        lines("100", Some("01-10 23:59:58.000")),
+        "          tag (100 2)  -    m\n"
+    );
+    assert_eq!(lines("100", None), "          tag (100 2)  -    m\n");
+
+    // Times without date wrap at midnight
+    start("300", "23:59:59.500");
+    assert_eq!(
+        lines("300", Some("00:00:00.250")),
+        " +00.750s tag (300 2)  -    m\n"
+    );
+    // Times without date don't compare to dates
+    assert_eq!(
+        lines("300", Some("01-11 00:00:00.250")),
+        "          tag (300 2)  -    m\n"
+    );
+
+    // The start is forgotten when the process dies
+    processes.observe(&Record {
+        tag: "am_proc_died".to_owned(),
+        message: "[0,100,com.example]".to_owned(),
+        ..Default::default()
+    });
+    assert_eq!(
+        lines("100", Some("01-11 00:00:00.000")),
+        "          tag (100 2)  -    m\n"
+    );
+}
+
+#[test]
+fn highlight_counts() {
+    use clap::Parser;
+    let args = CliArguments::parse_from([
+        "rogcat",
+        "--highlight",
+        "ANR",
+        "--highlight",
+        "OutOfMemory",
+        "--highlight",
+        "StrictMode",
+    ]);
+    let mut human = Human::from(&args, &Profile::default(), Format::Human).unwrap();
+    for (tag, message) in [
+        ("ActivityManager", "ANR in com.example"),
+        ("art", "Throwing OutOfMemoryError"),
+        ("StrictMode", "policy violation; ANR risk"),
+        ("ActivityManager", "Start proc"),
+        ("ActivityManager", "ANR in com.other"),
+    ] {
+        let record = Record {
+            tag: tag.to_owned(),
+            message: message.to_owned(),
+            ..Default::default()
+        };
+        render_record(&mut human, &record, Some(80));
+    }
+    // Diagnostics of rogcat are not counted
+    render_record(
+        &mut human,
+        &Record::from_diagnostic(Level::Info, "ANR".to_owned()),
+        Some(80),
+    );
+    assert_eq!(
+        human.highlights().to_string(),
+        "highlights: ANR×3, OutOfMemory×1, StrictMode×1"
+    );
+}
+
+#[test]
+fn message_colors() {
+    use clap::Parser;
+    let record = Record {
+        tag: "tag".to_owned(),
+        level: Level::Info,
+        message: "message".to_owned(),
+        ..Default::default()
+    };
+    let message = |args: &[&str]| {
+        let args = CliArguments::parse_from(["rogcat", "--hide-timestamp"].iter().chain(args));
+        let mut human = Human::from(&args, &Profile::default(), Format::Human).unwrap();
+        let mut buffer = Buffer::ansi();
+        human.render(&record, None, &mut buffer).unwrap();
+        let output = String::from_utf8(buffer.into_inner()).unwrap();
+        let end = output.find("mess").unwrap();
+        let start = output[..end].rfind("\x1b[0m").unwrap() + 4;
+        output[start..end].to_owned()
+    };
+    // The level color is the default
+    assert_eq!(message(&[]), "\x1b[32m");
+    assert_eq!(message(&["--message-color", "level"]), "\x1b[32m");
+    assert_eq!(message(&["--message-color", "default"]), "");
+    assert_eq!(message(&["--message-color", "dimm"]), "\x1b[38;5;243m");
+    // Matches are highlighted where they are
+    assert_eq!(message(&["-h", "tag"]), "\x1b[32m");
+    assert_eq!(message(&["--message-color", "default", "-h", "tag"]), "");
+    // Highlighted records without a visible match render their message bold
+    assert_eq!(
+        message(&["--max-message-width", "5", "-h", "age$"]),
+        "\x1b[1m\x1b[32m"
+    );
+}
+
+#[test]
+fn render_notices() {
+    let mut human = human();
+    let divider = Record {
+        message: "--------- beginning of crash".to_owned(),
+        buffer: Some("crash".to_owned()),
+        notice: Some(Notice::BufferBegin),
+        ..Default::default()
+    };
+    assert_eq!(render_record(&mut human, &divider, None), "─── crash ───\n");
+
+    // Chatty notices are dimmed instead of colored by level
+    let chatty = Record {
+        tag: "chatty".to_owned(),
+        level: Level::Info,
+        message: "uid=1000(system) Binder:1234_5 expire 13 lines".to_owned(),
+        notice: Some(Notice::Expired),
+        ..Default::default()
+    };
+    let mut buffer = Buffer::ansi();
+    human.render(&chatty, None, &mut buffer).unwrap();
+    let output = String::from_utf8(buffer.into_inner()).unwrap();
+    assert!(output.contains("\x1b[38;5;243muid=1000(system)"));
+}
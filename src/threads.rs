@@ -0,0 +1,341 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Names of the threads of records read from adb. The names are read from
+//! /proc of the device in the background and cached. Records of threads that
+//! aren't resolved yet carry just the thread id.
+
+use crate::adb::adb_command;
+use futures::{
+    channel::mpsc::{unbounded, UnboundedSender},
+    future::BoxFuture,
+    StreamExt,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::time::timeout;
+
+/// Maximum number of cached names
+const CAPACITY: usize = 4096;
+
+/// Maximum number of threads resolved by one query
+const BATCH_SIZE: usize = 64;
+
+/// Maximum duration of a query
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time after which threads that could not be resolved are queried again,
+/// e.g. after the device was busy or reconnected
+const RETRY_UNRESOLVED: Duration = Duration::from_secs(60);
+
+/// Thread of a process on a device
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct ThreadId {
+    pub device: Option<String>,
+    pub pid: String,
+    pub tid: String,
+}
+
+impl ThreadId {
+    /// Thread of a record. Only numeric ids can be resolved.
+    pub fn new(device: Option<&str>, pid: &str, tid: &str) -> Option<ThreadId> {
+        let is_id = |id: &str| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit());
+        (is_id(pid) && is_id(tid)).then(|| ThreadId {
+            device: device.map(ToOwned::to_owned),
+            pid: pid.to_owned(),
+            tid: tid.to_owned(),
+        })
+    }
+}
+
+/// Resolves the names of threads
+pub trait Resolver: Send + 'static {
+    /// Names of `threads`. Threads that exited are missing.
+    fn resolve(&mut self, threads: Vec<ThreadId>) -> BoxFuture<'static, HashMap<ThreadId, String>>;
+}
+
+/// Reads /proc/<pid>/task/<tid>/comm with adb shell
+pub struct AdbResolver {
+    /// Device of records without device
+    device: Option<String>,
+}
+
+impl AdbResolver {
+    pub fn new(device: Option<String>) -> AdbResolver {
+        AdbResolver { device }
+    }
+}
+
+impl Resolver for AdbResolver {
+    fn resolve(&mut self, threads: Vec<ThreadId>) -> BoxFuture<'static, HashMap<ThreadId, String>> {
+        let default = self.device.clone();
+        Box::pin(async move {
+            let mut devices: HashMap<Option<String>, Vec<ThreadId>> = HashMap::new();
+            for thread in threads {
+                devices
+                    .entry(thread.device.clone())
+                    .or_default()
+                    .push(thread);
+            }
+            let mut names = HashMap::new();
+            for (device, threads) in devices {
+                let paths = threads
+                    .iter()
+                    .map(|t| format!("{}/task/{}", t.pid, t.tid))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let script = format!(
+                    "cd /proc && for t in {paths}; do echo \"$t $(cat $t/comm 2>/dev/null)\"; done"
+                );
+                let Ok(mut command) = adb_command(device.as_deref().or(default.as_deref())) else {
+                    continue;
+                };
+                command.args(["shell", &script]).kill_on_drop(true);
+                let Ok(Ok(output)) = timeout(RESOLVE_TIMEOUT, command.output()).await else {
+                    continue;
+                };
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                for (pid, tid, name) in stdout.lines().filter_map(parse_comm) {
+                    if let Some(thread) = ThreadId::new(device.as_deref(), pid, tid) {
+                        names.insert(thread, name.to_owned());
+                    }
+                }
+            }
+            names
+        })
+    }
+}
+
+/// Parse a line like "1234/task/1240 RenderThread"
+fn parse_comm(line: &str) -> Option<(&str, &str, &str)> {
+    let (path, name) = line.split_once(' ')?;
+    let (pid, tid) = path.split_once("/task/")?;
+    let name = name.trim();
+    (!name.is_empty()).then_some((pid, tid, name))
+}
+
+/// Least recently used names. Threads that could not be resolved are cached
+/// with an empty name to query them only every [`RETRY_UNRESOLVED`].
+#[derive(Default)]
+struct Cache {
+    /// Names, the clock of their last use and the time they were resolved
+    names: HashMap<ThreadId, (String, u64, Instant)>,
+    /// Threads being resolved
+    pending: HashSet<ThreadId>,
+    /// Incremented on every access
+    clock: u64,
+    capacity: usize,
+}
+
+impl Cache {
+    /// Name of `thread` at `now`. Unresolved threads expire.
+    fn get(&mut self, thread: &ThreadId, now: Instant) -> Option<&str> {
+        let expired = self.names.get(thread).is_some_and(|(name, _, resolved)| {
+            name.is_empty() && now.saturating_duration_since(*resolved) >= RETRY_UNRESOLVED
+        });
+        if expired {
+            self.names.remove(thread);
+        }
+        self.clock += 1;
+        let clock = self.clock;
+        self.names.get_mut(thread).map(|(name, used, _)| {
+            *used = clock;
+            name.as_str()
+        })
+    }
+
+    fn insert(&mut self, thread: ThreadId, name: String, resolved: Instant) {
+        if self.names.len() >= self.capacity && !self.names.contains_key(&thread) {
+            let oldest = self
+                .names
+                .iter()
+                .min_by_key(|(_, (_, used, _))| *used)
+                .map(|(thread, _)| thread.clone());
+            if let Some(oldest) = oldest {
+                self.names.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.names.insert(thread, (name, self.clock, resolved));
+    }
+}
+
+/// Cache of thread names that is filled in the background
+pub struct ThreadNames {
+    cache: Arc<Mutex<Cache>>,
+    requests: UnboundedSender<ThreadId>,
+}
+
+impl ThreadNames {
+    pub fn new<R: Resolver>(resolver: R) -> ThreadNames {
+        ThreadNames::with_capacity(resolver, CAPACITY)
+    }
+
+    /// Cache up to `capacity` names. The queries end when the cache is
+    /// dropped.
+    pub fn with_capacity<R: Resolver>(mut resolver: R, capacity: usize) -> ThreadNames {
+        let cache = Arc::new(Mutex::new(Cache {
+            capacity: capacity.max(1),
+            ..Default::default()
+        }));
+        let (requests, mut rx) = unbounded::<ThreadId>();
+        let resolved = cache.clone();
+        tokio::spawn(async move {
+            while let Some(first) = rx.next().await {
+                let mut batch = vec![first];
+                while batch.len() < BATCH_SIZE {
+                    match rx.try_recv() {
+                        Ok(thread) => batch.push(thread),
+                        Err(_) => break,
+                    }
+                }
+                let mut names = resolver.resolve(batch.clone()).await;
+                let now = Instant::now();
+                let mut cache = resolved.lock().expect("Poisoned lock");
+                for thread in batch {
+                    // Evicted while resolving
+                    if !cache.pending.remove(&thread) {
+                        continue;
+                    }
+                    let name = names.remove(&thread).unwrap_or_default();
+                    cache.insert(thread, name, now);
+                }
+            }
+        });
+        ThreadNames { cache, requests }
+    }
+
+    /// Name of `thread` if resolved. Unknown threads are resolved in the
+    /// background.
+    pub fn name(&self, thread: &ThreadId) -> Option<String> {
+        self.name_at(thread, Instant::now())
+    }
+
+    /// Name of `thread` at `now`. Threads that could not be resolved are
+    /// resolved again after [`RETRY_UNRESOLVED`].
+    fn name_at(&self, thread: &ThreadId, now: Instant) -> Option<String> {
+        let mut cache = self.cache.lock().expect("Poisoned lock");
+        if let Some(name) = cache.get(thread, now) {
+            return (!name.is_empty()).then(|| name.to_owned());
+        }
+        if cache.pending.insert(thread.clone()) {
+            self.requests.unbounded_send(thread.clone()).ok();
+        }
+        None
+    }
+
+    /// Forget the threads of the process `pid` that died
+    pub fn evict(&self, device: Option<&str>, pid: &str) {
+        let mut cache = self.cache.lock().expect("Poisoned lock");
+        let died = |thread: &ThreadId| thread.pid == pid && thread.device.as_deref() == device;
+        cache.names.retain(|thread, _| !died(thread));
+        cache.pending.retain(|thread| !died(thread));
+    }
+}
+
+#[test]
+fn comm_lines() {
+    assert_eq!(
+        parse_comm("1234/task/1240 RenderThread"),
+        Some(("1234", "1240", "RenderThread"))
+    );
+    assert_eq!(
+        parse_comm("1234/task/1241 Binder:1234_2"),
+        Some(("1234", "1241", "Binder:1234_2"))
+    );
+    assert_eq!(parse_comm("1234/task/1242 "), None);
+    assert_eq!(parse_comm("garbage"), None);
+    assert!(ThreadId::new(None, "1234", "1240").is_some());
+    assert!(ThreadId::new(None, "1234", "$(reboot)").is_none());
+    assert!(ThreadId::new(None, "", "1240").is_none());
+}
+
+#[tokio::test]
+async fn thread_name_cache() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Names threads "t<tid>" except tid 0 and counts the resolved threads
+    struct Mock(Arc<AtomicUsize>);
+
+    impl Resolver for Mock {
+        fn resolve(
+            &mut self,
+            threads: Vec<ThreadId>,
+        ) -> BoxFuture<'static, HashMap<ThreadId, String>> {
+            self.0.fetch_add(threads.len(), Ordering::Relaxed);
+            let names = threads
+                .into_iter()
+                .filter(|t| t.tid != "0")
+                .map(|t| {
+                    let name = format!("t{}", t.tid);
+                    (t, name)
+                })
+                .collect();
+            Box::pin(async move { names })
+        }
+    }
+
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let resolved = Arc::new(AtomicUsize::new(0));
+    let names = ThreadNames::with_capacity(Mock(resolved.clone()), 2);
+    let thread = |tid: &str| ThreadId::new(None, "1", tid).unwrap();
+
+    // Misses are resolved in the background and not twice
+    assert_eq!(names.name(&thread("10")), None);
+    assert_eq!(names.name(&thread("10")), None);
+    settle().await;
+    assert_eq!(names.name(&thread("10")).as_deref(), Some("t10"));
+    assert_eq!(resolved.load(Ordering::Relaxed), 1);
+
+    // Unknown threads are queried once until the retry
+    assert_eq!(names.name(&thread("0")), None);
+    settle().await;
+    assert_eq!(names.name(&thread("0")), None);
+    assert_eq!(resolved.load(Ordering::Relaxed), 2);
+    let later = Instant::now() + RETRY_UNRESOLVED;
+    assert_eq!(names.name_at(&thread("0"), later), None);
+    settle().await;
+    assert_eq!(resolved.load(Ordering::Relaxed), 3);
+    assert_eq!(names.name_at(&thread("0"), later), None);
+    settle().await;
+    assert_eq!(resolved.load(Ordering::Relaxed), 3);
+
+    // The least recently used name is dropped
+    names.name(&thread("10"));
+    names.name(&thread("11"));
+    settle().await;
+    assert_eq!(names.name(&thread("10")).as_deref(), Some("t10"));
+    assert_eq!(names.name(&thread("0")), None);
+    settle().await;
+    assert_eq!(resolved.load(Ordering::Relaxed), 5);
+
+    // Threads of a dead process are resolved again
+    names.evict(None, "1");
+    assert_eq!(names.name(&thread("10")), None);
+    settle().await;
+    assert_eq!(names.name(&thread("10")).as_deref(), Some("t10"));
+    assert_eq!(resolved.load(Ordering::Relaxed), 6);
+}
@@ -0,0 +1,188 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Run a command when a record matches a watch pattern
+
+use crate::diagnostics::Reporter;
+use anyhow::{anyhow, Result};
+use futures::{
+    ready,
+    sink::Sink,
+    task::{Context, Poll},
+    Future,
+};
+use regex::Regex;
+use rogcat::{record::Record, Error, LogSink};
+use std::{pin::Pin, process::Stdio, time::Duration};
+use tokio::{
+    process::{Child, Command},
+    time::Instant,
+};
+
+/// Minimum time between two invocations for the same pattern
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of commands running at the same time
+const MAX_RUNNING: usize = 4;
+
+struct Watch {
+    regex: Regex,
+    /// Time of the last invocation
+    last: Option<Instant>,
+}
+
+/// Sink that passes all records on and spawns a command for records with a
+/// tag or message matching a watch pattern
+struct TriggerSink {
+    sink: Pin<LogSink>,
+    watches: Vec<Watch>,
+    command: Vec<String>,
+    /// Run the command at most once per pattern
+    once: bool,
+    children: Vec<Child>,
+    /// Matches without invocation because of the rate limit
+    skipped: usize,
+    /// Failures of the command are diagnostics of rogcat
+    reporter: Reporter,
+}
+
+/// Wrap `sink` and run `command` for records matching any of `watches`.
+/// The fields of the record are passed in ROGCAT_* environment variables.
+pub fn wrap(
+    sink: Pin<LogSink>,
+    watches: &[String],
+    command: &str,
+    once: bool,
+    reporter: Reporter,
+) -> Result<Pin<LogSink>> {
+    let watches = watches
+        .iter()
+        .map(|w| {
+            Ok(Watch {
+                regex: Regex::new(w).map_err(|e| anyhow!("Invalid watch regex {w}: {e}"))?,
+                last: None,
+            })
+        })
+        .collect::<Result<_>>()?;
+    let command = command
+        .split_whitespace()
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    if command.is_empty() {
+        return Err(anyhow!("Invalid on-match command"));
+    }
+    Ok(Box::pin(TriggerSink {
+        sink,
+        watches,
+        command,
+        once,
+        children: Vec::new(),
+        skipped: 0,
+        reporter,
+    }))
+}
+
+impl TriggerSink {
+    /// Spawn the command for `record` if it matches a pattern and the rate
+    /// limit allows it. A record triggers at most one invocation.
+    fn trigger(&mut self, record: &Record) -> Result<(), Error> {
+        // Forget about the commands that exited
+        self.children
+            .retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+
+        let now = Instant::now();
+        let Some(watch) = self.watches.iter_mut().find(|w| {
+            (w.regex.is_match(&record.tag) || w.regex.is_match(&record.message))
+                && !(self.once && w.last.is_some())
+        }) else {
+            return Ok(());
+        };
+        if watch.last.is_some_and(|last| now - last < MIN_INTERVAL)
+            || self.children.len() >= MAX_RUNNING
+        {
+            self.skipped += 1;
+            return Ok(());
+        }
+        watch.last = Some(now);
+
+        let child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .env("ROGCAT_TAG", &record.tag)
+            .env("ROGCAT_MESSAGE", &record.message)
+            .env("ROGCAT_PID", &record.process)
+            .env("ROGCAT_TID", &record.thread)
+            .env("ROGCAT_LEVEL", record.level.to_string())
+            .env("ROGCAT_TIME", record.time.as_deref().unwrap_or_default())
+            .stdin(Stdio::null())
+            .spawn();
+        match child {
+            Ok(child) => self.children.push(child),
+            Err(e) => self
+                .reporter
+                .report(&format!("Failed to run on-match command: {e}"))
+                .map_err(|e| Error::io("Failed to write diagnostic", e))?,
+        }
+        Ok(())
+    }
+}
+
+impl Sink<Record> for TriggerSink {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sink.as_mut().poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Record) -> Result<(), Self::Error> {
+        self.trigger(&item)?;
+        self.sink.as_mut().start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sink.as_mut().poll_flush(cx)
+    }
+
+    /// Close the inner sink and wait for the running commands
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.sink.as_mut().poll_close(cx))?;
+        let this = self.get_mut();
+        while let Some(child) = this.children.last_mut() {
+            let result = {
+                let wait = child.wait();
+                futures::pin_mut!(wait);
+                ready!(wait.poll(cx))
+            };
+            if let Err(e) = result {
+                this.reporter
+                    .report(&format!("Failed to wait for on-match command: {e}"))
+                    .map_err(|e| Error::io("Failed to write diagnostic", e))?;
+            }
+            this.children.pop();
+        }
+        if this.skipped > 0 {
+            eprintln!(
+                "{} matches did not run the on-match command because of the rate limit",
+                this.skipped
+            );
+            this.skipped = 0;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
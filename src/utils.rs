@@ -18,25 +18,167 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use config::{Config, File};
-use failure::Error;
+use crate::diagnostics::Reporter;
+use anyhow::{anyhow, Result};
+use config::{builder::DefaultState, Config, ConfigBuilder, File, ValueKind};
 use lazy_static::lazy_static;
+use rogcat::config_dir;
 use serde::Deserialize;
-use std::{convert::Into, env, path::PathBuf, sync::RwLock};
-use which::which_in;
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
 
 lazy_static! {
-    static ref CONFIG: RwLock<Config> = RwLock::new({
-        Config::builder()
-            .add_source(File::from(config_dir().join("config.toml")).required(false))
-            .build()
-            .unwrap_or_default()
-    });
+    static ref CONFIG: RwLock<Config> = RwLock::new(Config::default());
 }
 
-/// Find adb binary
-pub fn adb() -> Result<PathBuf, Error> {
-    which_in("adb", env::var_os("PATH"), env::current_dir()?).map_err(Into::into)
+/// Type of the value of a setting
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Flag,
+    Number,
+    Text,
+    List,
+    Colors,
+}
+
+/// Settings of the configuration file. Profiles override them in their
+/// settings table.
+const SETTINGS: [(&str, Kind); 23] = [
+    ("buffer", Kind::List),
+    ("file_flush_interval", Kind::Number),
+    ("level_colors", Kind::Colors),
+    ("restart", Kind::Flag),
+    ("tag_colors", Kind::Colors),
+    ("terminal_bright_colors", Kind::Flag),
+    ("terminal_color", Kind::Text),
+    ("terminal_dimm_color", Kind::Text),
+    ("terminal_group_by", Kind::Text),
+    ("terminal_group_traces", Kind::Flag),
+    ("terminal_hide_timestamp", Kind::Flag),
+    ("terminal_highlight_style", Kind::Text),
+    ("terminal_max_message_width", Kind::Number),
+    ("terminal_message_color", Kind::Text),
+    ("terminal_no_dimm", Kind::Flag),
+    ("terminal_pid_width", Kind::Number),
+    ("terminal_show_date", Kind::Flag),
+    ("terminal_show_delta", Kind::Flag),
+    ("terminal_show_gaps", Kind::Text),
+    ("terminal_show_uptime", Kind::Flag),
+    ("terminal_tag_width", Kind::Number),
+    ("terminal_theme", Kind::Text),
+    ("terminal_thread_width", Kind::Number),
+];
+
+impl Kind {
+    /// Type of the setting `key`
+    fn of(key: &str) -> Result<Kind> {
+        SETTINGS
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, kind)| *kind)
+            .ok_or_else(|| {
+                let keys = SETTINGS.map(|(k, _)| k).join(", ");
+                anyhow!("Unknown setting {key}. Known settings are {keys}")
+            })
+    }
+
+    fn expected(self) -> &'static str {
+        match self {
+            Kind::Flag => "true or false",
+            Kind::Number => "a positive number",
+            Kind::Text => "a string",
+            Kind::List => "a comma separated list",
+            Kind::Colors => "comma separated NAME=COLOR pairs",
+        }
+    }
+
+    fn matches(self, value: &toml::Value) -> bool {
+        match (self, value) {
+            (Kind::Flag, toml::Value::Boolean(_)) | (Kind::Text, toml::Value::String(_)) => true,
+            (Kind::Number, toml::Value::Integer(n)) => *n >= 0,
+            (Kind::List, toml::Value::Array(items)) => items.iter().all(toml::Value::is_str),
+            (Kind::Colors, toml::Value::Table(colors)) => colors
+                .values()
+                .all(|c| c.is_str() || c.as_integer().is_some_and(|n| (0..=255).contains(&n))),
+            _ => false,
+        }
+    }
+}
+
+/// Location of the configuration file
+pub fn config_file() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+/// Parse `value` of the command line as value of the setting `key`
+pub fn parse_setting(key: &str, value: &str) -> Result<toml::Value> {
+    let kind = Kind::of(key)?;
+    let invalid = || {
+        anyhow!(
+            "Invalid value {value} for {key}: expected {}",
+            kind.expected()
+        )
+    };
+    let strings = || value.split(',').map(str::trim).filter(|s| !s.is_empty());
+    Ok(match kind {
+        Kind::Flag => toml::Value::Boolean(value.parse().map_err(|_| invalid())?),
+        Kind::Number => toml::Value::Integer(value.parse::<u32>().map_err(|_| invalid())?.into()),
+        Kind::Text => toml::Value::String(value.to_owned()),
+        Kind::List => toml::Value::Array(strings().map(|s| s.to_owned().into()).collect()),
+        Kind::Colors => {
+            let colors = strings()
+                .map(|pair| {
+                    let (name, color) = pair.split_once('=').ok_or_else(invalid)?;
+                    Ok((name.trim().to_owned(), color.trim().to_owned().into()))
+                })
+                .collect::<Result<_>>()?;
+            toml::Value::Table(colors)
+        }
+    })
+}
+
+/// Check the type of the value of the setting `key`
+pub fn check_setting(key: &str, value: &toml::Value) -> Result<()> {
+    let kind = Kind::of(key)?;
+    if kind.matches(value) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Invalid value for {key}: expected {}",
+            kind.expected()
+        ))
+    }
+}
+
+/// Value of the configuration crate
+fn config_value(value: &toml::Value) -> config::Value {
+    let kind = match value {
+        toml::Value::String(s) => ValueKind::String(s.clone()),
+        toml::Value::Integer(n) => ValueKind::I64(*n),
+        toml::Value::Float(f) => ValueKind::Float(*f),
+        toml::Value::Boolean(b) => ValueKind::Boolean(*b),
+        toml::Value::Datetime(d) => ValueKind::String(d.to_string()),
+        toml::Value::Array(items) => ValueKind::Array(items.iter().map(config_value).collect()),
+        toml::Value::Table(table) => ValueKind::Table(
+            table
+                .iter()
+                .map(|(k, v)| (k.clone(), config_value(v)))
+                .collect(),
+        ),
+    };
+    config::Value::new(None, kind)
+}
+
+/// True if `a` and `b` name the same existing file
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
 }
 
 pub fn terminal_width() -> Option<usize> {
@@ -48,20 +190,78 @@ pub fn terminal_width() -> Option<usize> {
     }
 }
 
-/// Detect configuration directory
-pub fn config_dir() -> PathBuf {
-    directories::BaseDirs::new()
-        .unwrap()
-        .config_dir()
-        .join("rogcat")
-}
-
 /// Read a value from the configuration file
 /// `config_dir/config.toml`
 pub fn config_get<'a, T: Deserialize<'a>>(key: &'a str) -> Option<T> {
     CONFIG.read().ok().and_then(|c| c.get::<T>(key).ok())
 }
 
-pub fn config_init() {
-    drop(CONFIG.read().expect("Failed to get config lock"));
+/// Let the settings of the selected profile take precedence over the
+/// configuration file. A malformed configuration file is already reported by
+/// `config_init` and leaves the defaults.
+pub fn config_profile(settings: &HashMap<String, toml::Value>) -> Result<()> {
+    for (key, value) in settings {
+        check_setting(key, value).map_err(|e| anyhow!("Invalid profile settings: {e}"))?;
+    }
+    if settings.is_empty() {
+        return Ok(());
+    }
+    let with_settings = |mut builder: ConfigBuilder<DefaultState>| {
+        for (key, value) in settings {
+            builder = builder.set_override(key, config_value(value))?;
+        }
+        builder.build()
+    };
+    let config =
+        with_settings(Config::builder().add_source(File::from(config_file()).required(false)))
+            .or_else(|_| with_settings(Config::builder()))?;
+    *CONFIG.write().expect("Failed to get config lock") = config;
+    Ok(())
+}
+
+/// Read the configuration file. A malformed file is reported and leaves the
+/// defaults.
+pub fn config_init(reporter: &mut Reporter) -> Result<()> {
+    let config = Config::builder()
+        .add_source(File::from(config_file()).required(false))
+        .build();
+    match config {
+        Ok(config) => *CONFIG.write().expect("Failed to get config lock") = config,
+        Err(e) => reporter.report(&format!(
+            "Failed to read the configuration: {e}. Using the defaults."
+        ))?,
+    }
+    Ok(())
+}
+
+#[test]
+fn settings() {
+    assert_eq!(
+        parse_setting("terminal_tag_width", "30").unwrap(),
+        toml::Value::Integer(30)
+    );
+    assert_eq!(
+        parse_setting("terminal_tag_width", "banana")
+            .unwrap_err()
+            .to_string(),
+        "Invalid value banana for terminal_tag_width: expected a positive number"
+    );
+    assert!(parse_setting("terminal_tag_width", "-1").is_err());
+    assert!(parse_setting("restart", "yes").is_err());
+    assert_eq!(
+        parse_setting("buffer", "main, crash").unwrap(),
+        toml::Value::Array(vec!["main".into(), "crash".into()])
+    );
+    let colors = parse_setting("tag_colors", "ActivityManager=red,Zygote=202").unwrap();
+    assert_eq!(colors["Zygote"].as_str(), Some("202"));
+    assert!(parse_setting("tag_colors", "red").is_err());
+    assert!(parse_setting("terminal_tag_wdith", "30")
+        .unwrap_err()
+        .to_string()
+        .starts_with("Unknown setting terminal_tag_wdith"));
+
+    assert!(check_setting("terminal_no_dimm", &toml::Value::Boolean(true)).is_ok());
+    assert!(check_setting("terminal_no_dimm", &toml::Value::String("true".into())).is_err());
+    assert!(check_setting("level_colors", &toml::from_str("E = 196").unwrap()).is_ok());
+    assert!(check_setting("level_colors", &toml::from_str("E = 1000").unwrap()).is_err());
 }
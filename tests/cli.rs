@@ -0,0 +1,1129 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// Create a temporary directory for a test
+fn tempdir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("rogcat-{}-{}", name, std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Write a file with n logcat lines
+fn input(dir: &Path, records: usize) -> PathBuf {
+    let file = dir.join("input.log");
+    let content = (0..records)
+        .map(|i| format!("01-10 23:56:50.101  1234  1235 I Tag: message {i}\n"))
+        .collect::<String>();
+    fs::write(&file, content).unwrap();
+    file
+}
+
+fn rogcat(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rogcat"))
+        .args(args)
+        .output()
+        .expect("Failed to run rogcat")
+}
+
+#[test]
+fn file_to_file() {
+    let dir = tempdir("file-to-file");
+    let input = input(&dir, 10_000);
+    let output = dir.join("output.log");
+
+    let result = rogcat(&[
+        "-i",
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+    ]);
+    assert!(result.status.success());
+    assert_eq!(
+        fs::read_to_string(&output).unwrap(),
+        fs::read_to_string(&input).unwrap()
+    );
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn head_exits() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rogcat"))
+        .args(["-", "-H", "10", "-f", "raw"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run rogcat");
+
+    // Keep stdin open to make sure rogcat stops on its own
+    let mut stdin = child.stdin.take().unwrap();
+    for i in 0..20 {
+        writeln!(stdin, "01-10 23:56:50.101  1234  1235 I Tag: message {i}").unwrap();
+    }
+    stdin.flush().unwrap();
+
+    let start = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "rogcat didn't exit after 10 records"
+        );
+        sleep(Duration::from_millis(10));
+    }
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 10);
+    assert_eq!(
+        stdout.lines().last(),
+        Some("01-10 23:56:50.101  1234  1235 I Tag: message 9")
+    );
+    drop(stdin);
+}
+
+#[test]
+fn output_command_exits() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rogcat"))
+        .args(["-", "--output-command", "head -n 5"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run rogcat");
+
+    // Keep stdin open to make sure rogcat stops when the command exits
+    let mut stdin = child.stdin.take().unwrap();
+    for i in 0..20 {
+        writeln!(stdin, "01-10 23:56:50.101  1234  1235 I Tag: message {i}").unwrap();
+    }
+    stdin.flush().unwrap();
+
+    let start = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "rogcat didn't exit after the command exited"
+        );
+        sleep(Duration::from_millis(10));
+    }
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 5);
+    assert_eq!(
+        stdout.lines().last(),
+        Some("01-10 23:56:50.101  1234  1235 I Tag: message 4")
+    );
+    drop(stdin);
+}
+
+#[test]
+fn output_command_fails() {
+    let dir = tempdir("output-command-fails");
+    let input = input(&dir, 10);
+
+    // A failing command is noted but isn't an error of rogcat
+    let result = rogcat(&["-i", input.to_str().unwrap(), "--output-command", "false"]);
+    assert!(result.status.success());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(
+        stderr.contains("[rogcat] Output command exited with exit status: 1"),
+        "{stderr}"
+    );
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn tee() {
+    let dir = tempdir("tee");
+    let input = input(&dir, 1000);
+    let output = dir.join("output.json");
+
+    let result = rogcat(&[
+        "-i",
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-f",
+        "json",
+        "--tee",
+        "--color",
+        "never",
+    ]);
+    assert!(result.status.success());
+    let json = fs::read_to_string(&output).unwrap();
+    assert_eq!(json.lines().count(), 1000);
+    assert!(json.lines().all(|line| line.starts_with('{')));
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 1000);
+    assert!(stdout.lines().all(|line| line.contains("message")));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn compressed_input() {
+    let dir = tempdir("compressed-input");
+    let input = input(&dir, 1000);
+    let compressed = dir.join("compressed.log.gz");
+    let output = dir.join("output.log");
+
+    let result = rogcat(&[
+        "-i",
+        input.to_str().unwrap(),
+        "-o",
+        compressed.to_str().unwrap(),
+    ]);
+    assert!(result.status.success());
+    assert_ne!(fs::read(&compressed).unwrap(), fs::read(&input).unwrap());
+
+    // Mix compressed and plain input
+    let result = rogcat(&[
+        "-i",
+        compressed.to_str().unwrap(),
+        "-i",
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+    ]);
+    assert!(result.status.success());
+    assert_eq!(
+        fs::read_to_string(&output).unwrap(),
+        fs::read_to_string(&input).unwrap().repeat(2)
+    );
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn corrupted_input() {
+    let dir = tempdir("corrupted-input");
+    let corrupted = dir.join("corrupted.log.gz");
+    fs::write(&corrupted, b"\x1f\x8bgarbage").unwrap();
+
+    let result = rogcat(&["-i", corrupted.to_str().unwrap(), "-f", "raw"]);
+    assert!(!result.status.success());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(stderr.contains("Failed to read"));
+    assert!(stderr.contains("corrupted.log.gz"));
+    assert!(!stderr.contains("panicked"));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn merge_by_time() {
+    let dir = tempdir("merge-by-time");
+    let line = |millis: usize, tag: &str| {
+        format!("01-10 23:56:50.{millis:03}  1234  1235 I {tag}: message {millis}\n")
+    };
+    let main = dir.join("main.log");
+    let kernel = dir.join("kernel.log");
+    fs::write(
+        &main,
+        (0..500)
+            .step_by(2)
+            .map(|i| line(i, "main"))
+            .collect::<String>(),
+    )
+    .unwrap();
+    fs::write(
+        &kernel,
+        (1..500)
+            .step_by(2)
+            .map(|i| line(i, "kernel"))
+            .collect::<String>(),
+    )
+    .unwrap();
+
+    let result = rogcat(&[
+        "-i",
+        main.to_str().unwrap(),
+        "-i",
+        kernel.to_str().unwrap(),
+        "--merge-by-time",
+        "-f",
+        "raw",
+    ]);
+    assert!(result.status.success());
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        (0..500)
+            .map(|i| line(i, if i % 2 == 0 { "main" } else { "kernel" }))
+            .collect::<String>()
+    );
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn count_by_closed_stdout() {
+    let dir = tempdir("count-by-closed");
+    // A table larger than the pipe buffer fails to write after the reader exits
+    let input = dir.join("input.log");
+    let content = (0..10_000)
+        .map(|i| format!("01-10 23:56:50.101  1234  1235 I Tag{i}: message {i}\n"))
+        .collect::<String>();
+    fs::write(&input, content).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rogcat"))
+        .args(["-i", input.to_str().unwrap(), "--count-by", "tag"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run rogcat");
+
+    // Read the first line of the table and close the pipe like head -1
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert!(!line.is_empty());
+    drop(stdout);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn count_exit_code() {
+    let dir = tempdir("count");
+    let input = input(&dir, 100);
+
+    let result = rogcat(&["-i", input.to_str().unwrap(), "--count"]);
+    assert!(result.status.success());
+    assert_eq!(String::from_utf8(result.stdout).unwrap(), "100\n");
+
+    let result = rogcat(&["-i", input.to_str().unwrap(), "--count", "-l", "error"]);
+    assert_eq!(result.status.code(), Some(1));
+    assert_eq!(String::from_utf8(result.stdout).unwrap(), "0\n");
+
+    let result = rogcat(&["-i", input.to_str().unwrap(), "--quiet", "-t", "^Tag$"]);
+    assert!(result.status.success());
+    assert!(result.stdout.is_empty());
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn fail_on_level() {
+    let dir = tempdir("fail-on-level");
+    let input = dir.join("input.log");
+    fs::write(
+        &input,
+        "01-10 23:56:50.101  1234  1235 I Tag: started\n\
+         01-10 23:56:50.102  1234  1235 E Tag: failed\n\
+         01-10 23:56:50.103  1234  1235 W Tag: retrying\n\
+         01-10 23:56:50.104  1234  1235 E Other: failed again\n",
+    )
+    .unwrap();
+    let input = input.to_str().unwrap();
+    let run = |args: &[&str]| {
+        let result = rogcat(&[&["-i", input, "-f", "raw"], args].concat());
+        (
+            result.status.code(),
+            String::from_utf8(result.stderr).unwrap(),
+        )
+    };
+
+    assert_eq!(
+        run(&["--fail-on-level", "error"]),
+        (Some(1), "2 records ≥ error\n".to_owned())
+    );
+    assert_eq!(
+        run(&["--fail-on-level", "fatal"]),
+        (Some(0), "0 records ≥ fatal\n".to_owned())
+    );
+    assert_eq!(
+        run(&["--fail-on-level", "W", "--max-count", "3"]),
+        (Some(0), "3 records ≥ warn\n".to_owned())
+    );
+    // Only records passing the filters count
+    assert_eq!(
+        run(&[
+            "--fail-on-level",
+            "error",
+            "--max-count",
+            "1",
+            "-t",
+            "^Tag$"
+        ]),
+        (Some(0), "1 records ≥ error\n".to_owned())
+    );
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn color_never_is_plain() {
+    let dir = tempdir("color-never");
+    let input = input(&dir, 100);
+
+    for format in ["raw", "json", "csv"] {
+        let output = dir.join(format!("output.{format}"));
+        let result = rogcat(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-f",
+            format,
+            "-o",
+            output.to_str().unwrap(),
+        ]);
+        assert!(result.status.success());
+
+        // Terminal output without colors is the same as file output except
+        // the csv header
+        let mut expected = fs::read_to_string(&output).unwrap();
+        if format == "csv" {
+            expected = expected.split_once('\n').unwrap().1.to_owned();
+        }
+        let result = rogcat(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-f",
+            format,
+            "--color",
+            "never",
+        ]);
+        assert!(result.status.success());
+        assert_eq!(String::from_utf8(result.stdout).unwrap(), expected);
+
+        let result = rogcat(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-f",
+            format,
+            "--color",
+            "always",
+        ]);
+        assert!(result.status.success());
+        assert!(result.stdout.starts_with(b"\x1b["));
+    }
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn format_template() {
+    let dir = tempdir("format-template");
+    let input = input(&dir, 3);
+    let output = dir.join("output.log");
+    let template = "{time} {level:<2}{tag:>5.2} | {message}";
+
+    let result = rogcat(&[
+        "-i",
+        input.to_str().unwrap(),
+        "--format-template",
+        template,
+        "-o",
+        output.to_str().unwrap(),
+    ]);
+    assert!(result.status.success());
+    let expected = "23:56:50.101 I    Ta | message 0\n\
+                    23:56:50.101 I    Ta | message 1\n\
+                    23:56:50.101 I    Ta | message 2\n";
+    assert_eq!(fs::read_to_string(&output).unwrap(), expected);
+
+    let result = rogcat(&["-i", input.to_str().unwrap(), "--format-template", template]);
+    assert!(result.status.success());
+    assert_eq!(String::from_utf8(result.stdout).unwrap(), expected);
+
+    let result = rogcat(&[
+        "-i",
+        input.to_str().unwrap(),
+        "--format-template",
+        template,
+        "--template-color",
+        "--color",
+        "always",
+    ]);
+    assert!(result.status.success());
+    assert!(result.stdout.starts_with(b"\x1b["));
+
+    // Unknown placeholders fail before any record is read
+    let result = rogcat(&["-i", input.to_str().unwrap(), "--format-template", "{pid}"]);
+    assert!(!result.status.success());
+    assert!(result.stdout.is_empty());
+    assert!(String::from_utf8(result.stderr)
+        .unwrap()
+        .contains("unknown placeholder {pid}"));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn convert() {
+    let dir = tempdir("convert");
+    let input = input(&dir, 2);
+    let csv = dir.join("old.csv");
+    let json = dir.join("new.json");
+
+    let result = rogcat(&[
+        "-i",
+        input.to_str().unwrap(),
+        "-f",
+        "csv",
+        "-o",
+        csv.to_str().unwrap(),
+    ]);
+    assert!(result.status.success());
+
+    // The format is taken from the extension
+    let result = rogcat(&[
+        "convert",
+        "-i",
+        csv.to_str().unwrap(),
+        "-o",
+        json.to_str().unwrap(),
+    ]);
+    assert!(result.status.success());
+    let expected = (0..2)
+        .map(|i| {
+            format!(
+                "{{\"time\":\"01-10 23:56:50.101\",\"message\":\"message {i}\",\"level\":\"Info\",\
+                 \"tag\":\"Tag\",\"process\":\"1234\",\"thread\":\"1235\",\"raw\":\"01-10 \
+                 23:56:50.101  1234  1235 I Tag: message {i}\"}}\n"
+            )
+        })
+        .collect::<String>();
+    assert_eq!(fs::read_to_string(&json).unwrap(), expected);
+
+    // Back to the original lines
+    let raw = dir.join("raw.log");
+    let result = rogcat(&[
+        "convert",
+        "-i",
+        json.to_str().unwrap(),
+        "-o",
+        raw.to_str().unwrap(),
+    ]);
+    assert!(result.status.success());
+    assert_eq!(
+        fs::read_to_string(&raw).unwrap(),
+        fs::read_to_string(&input).unwrap()
+    );
+
+    let result = rogcat(&[
+        "convert",
+        "-i",
+        csv.to_str().unwrap(),
+        "-o",
+        csv.to_str().unwrap(),
+    ]);
+    assert!(!result.status.success());
+
+    // Different paths of the same file are rejected as well
+    let alias = dir.join(".").join("old.csv");
+    let result = rogcat(&[
+        "convert",
+        "-i",
+        csv.to_str().unwrap(),
+        "-o",
+        alias.to_str().unwrap(),
+    ]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("in place"));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn diff() {
+    let dir = tempdir("diff");
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let old = fixtures.join("diff-old.log");
+    let new = fixtures.join("diff-new.log");
+    let output = dir.join("diff.txt");
+
+    let result = rogcat(&[
+        "diff",
+        "-C",
+        "0",
+        "--ignore-numbers",
+        "-o",
+        output.to_str().unwrap(),
+        old.to_str().unwrap(),
+        new.to_str().unwrap(),
+    ]);
+    assert_eq!(result.status.code(), Some(1));
+    let expected = format!(
+        "--- {}\n+++ {}\n\
+         @@ -4 +4 @@\n\
+         -01-10 23:56:50.407  4321  4330 E Network : Connection failed: timeout\n\
+         +01-11 09:12:01.251  4567  4580 I Network : Connected in 35ms\n\
+         @@ -5,0 +6 @@\n\
+         +01-11 09:12:01.420  4567  4581 I Sync    : Sync done\n",
+        old.display(),
+        new.display()
+    );
+    assert_eq!(fs::read_to_string(&output).unwrap(), expected);
+
+    // Outputs are not overwritten without --overwrite
+    let diff = |extra: &[&str]| {
+        let mut args = vec!["diff", "-C", "0", "--ignore-numbers"];
+        args.extend_from_slice(extra);
+        args.extend([old.to_str().unwrap(), new.to_str().unwrap()]);
+        rogcat(&args)
+    };
+    let result = diff(&["-o", output.to_str().unwrap()]);
+    assert_eq!(result.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Use overwrite flag to force!"));
+    let result = diff(&["-o", output.to_str().unwrap(), "--overwrite"]);
+    assert_eq!(result.status.code(), Some(1));
+    assert_eq!(fs::read_to_string(&output).unwrap(), expected);
+
+    // Inputs are never the output
+    let input = dir.join("new.log");
+    fs::copy(&new, &input).unwrap();
+    let result = rogcat(&[
+        "diff",
+        "-o",
+        input.to_str().unwrap(),
+        "--overwrite",
+        old.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+    assert_eq!(result.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Cannot write the diff to its input"));
+    assert_eq!(fs::read(&input).unwrap(), fs::read(&new).unwrap());
+
+    // Equal files print nothing
+    let result = rogcat(&["diff", old.to_str().unwrap(), old.to_str().unwrap()]);
+    assert!(result.status.success());
+    assert!(result.stdout.is_empty());
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn rewrite_rules() {
+    let dir = tempdir("rewrite");
+    let input = input(&dir, 3);
+    let input = input.to_str().unwrap();
+
+    // Filters see the rewritten messages
+    let result = rogcat(&[
+        "-i",
+        input,
+        "--rewrite",
+        r"s/message (\d)/msg ${1}x/",
+        "--rewrite",
+        "s/x/y/",
+        "-m",
+        "msg [12]y",
+        "--format",
+        "raw",
+        "--stats",
+    ]);
+    assert!(result.status.success());
+    assert_eq!(
+        String::from_utf8(result.stdout).unwrap(),
+        "01-10 23:56:50.101  1234  1235 I Tag: msg 1y\n\
+         01-10 23:56:50.101  1234  1235 I Tag: msg 2y\n"
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(stderr.contains("│ s/x/y/ "), "{stderr}");
+
+    // Invalid rules fail before any record is read
+    let result = rogcat(&["-i", input, "--rewrite", "s/(/x/"]);
+    assert!(!result.status.success());
+    assert!(result.stdout.is_empty());
+    assert!(String::from_utf8(result.stderr)
+        .unwrap()
+        .contains("Invalid rewrite rule"));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[cfg(unix)]
+#[test]
+fn on_match_command() {
+    let dir = tempdir("on-match");
+    let input = dir.join("input.log");
+    let content = (0..20)
+        .map(|i| {
+            let tag = if i % 2 == 0 { "Crash" } else { "Anr" };
+            format!("01-10 23:56:50.101  1234  1235 E {tag}: FATAL EXCEPTION {i}\n")
+        })
+        .collect::<String>();
+    fs::write(&input, content).unwrap();
+    let calls = dir.join("calls");
+    let script = dir.join("on-match.sh");
+    fs::write(
+        &script,
+        format!(
+            "echo \"$ROGCAT_TAG $ROGCAT_PID $ROGCAT_LEVEL $ROGCAT_MESSAGE\" >> {}\n",
+            calls.display()
+        ),
+    )
+    .unwrap();
+    let command = format!("sh {}", script.display());
+
+    // Each pattern triggers once, the records are printed anyway
+    let result = rogcat(&[
+        "-i",
+        input.to_str().unwrap(),
+        "--watch",
+        "^Crash$",
+        "--watch",
+        "^Anr$",
+        "--on-match",
+        &command,
+        "--once",
+        "-f",
+        "raw",
+    ]);
+    assert!(result.status.success());
+    assert_eq!(
+        String::from_utf8(result.stdout).unwrap().lines().count(),
+        20
+    );
+    let mut calls = fs::read_to_string(&calls)
+        .unwrap()
+        .lines()
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    calls.sort();
+    assert_eq!(
+        calls,
+        [
+            "Anr 1234 E FATAL EXCEPTION 1",
+            "Crash 1234 E FATAL EXCEPTION 0"
+        ]
+    );
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn list_profiles() {
+    let dir = tempdir("list-profiles");
+    let profiles = dir.join("profiles.toml");
+    fs::write(
+        &profiles,
+        "[profile.kernel]\nbuffer = [\"kernel\"]\n\n[profile.crash]\nbuffer = [\"crash\"]\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rogcat"))
+        .args(["completions", "--list", "profiles"])
+        .env("ROGCAT_PROFILES", &profiles)
+        .output()
+        .expect("Failed to run rogcat");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "crash\nkernel\n");
+
+    let output = rogcat(&[
+        "profiles",
+        "--format",
+        "json",
+        "-P",
+        profiles.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let profiles: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        profiles,
+        serde_json::json!([
+            {"name": "crash", "comment": null, "extends": []},
+            {"name": "kernel", "comment": null, "extends": []}
+        ])
+    );
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn malformed_config() {
+    let dir = tempdir("malformed-config");
+    let input = input(&dir, 1);
+    fs::create_dir_all(dir.join("rogcat")).unwrap();
+    fs::write(
+        dir.join("rogcat").join("config.toml"),
+        "terminal_tag_width = [\n",
+    )
+    .unwrap();
+    let profiles = dir.join("profiles.toml");
+    fs::write(
+        &profiles,
+        "[profile.wide.settings]\nterminal_tag_width = 40\n\n\
+         [profile.broken.settings]\nterminal_tag_width = \"banana\"\n",
+    )
+    .unwrap();
+
+    let run = |profile: Option<&str>| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_rogcat"));
+        command
+            .args([
+                "-i",
+                input.to_str().unwrap(),
+                "-P",
+                profiles.to_str().unwrap(),
+            ])
+            .env("XDG_CONFIG_HOME", &dir);
+        if let Some(profile) = profile {
+            command.args(["-p", profile]);
+        }
+        command.output().expect("Failed to run rogcat")
+    };
+
+    // The defaults apply with a warning
+    for profile in [None, Some("wide")] {
+        let output = run(profile);
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains("message 0"));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Using the defaults"));
+    }
+
+    let output = run(Some("broken"));
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid profile settings"));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+/// True if the process `pid` runs. Zombies waiting for init don't count.
+#[cfg(target_os = "linux")]
+fn running(pid: &str) -> bool {
+    fs::read_to_string(format!("/proc/{pid}/stat"))
+        .ok()
+        .and_then(|stat| {
+            // The state follows the command name in parentheses
+            let (_, rest) = stat.rsplit_once(')')?;
+            rest.split_whitespace().next().map(|state| state != "Z")
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn interrupt_terminates_process_group() {
+    let dir = tempdir("process-group");
+    let grandchild = dir.join("grandchild");
+    let spawns = dir.join("spawns");
+    let script = dir.join("spawn.sh");
+    fs::write(
+        &script,
+        format!(
+            "echo spawn >> {spawns}\n\
+             sleep 1000 &\n\
+             echo $! > {grandchild}.tmp\n\
+             mv {grandchild}.tmp {grandchild}\n\
+             echo '01-10 23:56:50.101  1234  1235 I Tag: started'\n\
+             wait\n",
+            spawns = spawns.display(),
+            grandchild = grandchild.display()
+        ),
+    )
+    .unwrap();
+    let command = format!("sh {}", script.display());
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rogcat"))
+        .args(["--restart", "-f", "raw", &command])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to run rogcat");
+
+    let start = Instant::now();
+    let pid = loop {
+        if let Ok(pid) = fs::read_to_string(&grandchild) {
+            break pid.trim().to_owned();
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "the script didn't start"
+        );
+        sleep(Duration::from_millis(10));
+    };
+    assert!(running(&pid));
+
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+    let start = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "rogcat didn't exit on SIGINT"
+        );
+        sleep(Duration::from_millis(10));
+    }
+
+    // The grandchild is terminated with the script and the script is not
+    // restarted
+    assert!(!running(&pid), "grandchild {pid} survived rogcat");
+    assert_eq!(fs::read_to_string(&spawns).unwrap(), "spawn\n");
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn logcat_notices() {
+    let dir = tempdir("notices");
+    let input = dir.join("notices.log");
+    fs::write(
+        &input,
+        "--------- beginning of main\n\
+         01-10 23:56:50.101  1234  1235 I Tag: message\n\
+         01-10 23:56:50.102  1036  1036 I chatty  : uid=10007(u0_a7) com.example.app expire 13 lines\n\
+         --------- beginning of system\n\
+         01-10 23:56:50.103  1234  1235 I Other: message\n\
+         logcat: Unexpected EOF!\n",
+    )
+    .unwrap();
+    let input = input.to_str().unwrap();
+
+    // Notices bypass the filters but the tag and message filters and aren't
+    // counted
+    let result = rogcat(&[
+        "-i", input, "--pid", "1234", "-t", "!Other", "--format", "raw", "--stats",
+    ]);
+    assert!(result.status.success());
+    assert_eq!(
+        String::from_utf8(result.stdout).unwrap(),
+        "--------- beginning of main\n\
+         01-10 23:56:50.101  1234  1235 I Tag: message\n\
+         01-10 23:56:50.102  1036  1036 I chatty  : uid=10007(u0_a7) com.example.app expire 13 lines\n\
+         --------- beginning of system\n\
+         logcat: Unexpected EOF!\n"
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(stderr.contains("1 records in"), "{stderr}");
+    assert!(
+        stderr.contains("chatty expired 13 lines: 13 of uid 10007(u0_a7)"),
+        "{stderr}"
+    );
+    let result = rogcat(&["-i", input, "-m", "!expire", "--format", "raw"]);
+    assert_eq!(
+        String::from_utf8(result.stdout).unwrap(),
+        "--------- beginning of main\n\
+         01-10 23:56:50.101  1234  1235 I Tag: message\n\
+         --------- beginning of system\n\
+         01-10 23:56:50.103  1234  1235 I Other: message\n\
+         logcat: Unexpected EOF!\n"
+    );
+
+    // Files keep the notices flagged
+    let output = dir.join("notices.json");
+    let result = rogcat(&["-i", input, "-o", output.to_str().unwrap(), "-f", "json"]);
+    assert!(result.status.success());
+    let json = fs::read_to_string(&output).unwrap();
+    assert!(
+        json.contains(r#""buffer":"main","notice":"buffer_begin""#),
+        "{json}"
+    );
+    assert!(json.contains(r#""notice":"expired""#), "{json}");
+    assert!(json.contains(r#""notice":"other""#), "{json}");
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn parallel() {
+    let dir = tempdir("parallel");
+    let input = input(&dir, 3000);
+    let input = input.to_str().unwrap();
+
+    let sequential = rogcat(&["-i", input, "-m", "message [12]", "--format", "csv"]);
+    assert!(sequential.status.success());
+    let result = rogcat(&[
+        "-i",
+        input,
+        "-m",
+        "message [12]",
+        "--format",
+        "csv",
+        "--parallel",
+        "4",
+    ]);
+    assert!(result.status.success());
+    assert!(!result.stdout.is_empty());
+    assert_eq!(result.stdout, sequential.stdout);
+
+    let result = rogcat(&["--parallel", "4", "adb logcat"]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8(result.stderr)
+        .unwrap()
+        .contains("--parallel requires input files or stdin"));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn level_style() {
+    let dir = tempdir("level-style");
+    let mixed = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/levels-mixed.csv");
+    let letters = dir.join("letters.csv");
+    let words = dir.join("words.csv");
+    let levels = |file: &Path| {
+        fs::read_to_string(file)
+            .unwrap()
+            .lines()
+            .map(|line| line.split(',').nth(2).unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+
+    let result = rogcat(&[
+        "-i",
+        mixed.to_str().unwrap(),
+        "-o",
+        letters.to_str().unwrap(),
+        "-f",
+        "csv",
+        "--level-style",
+        "letter",
+    ]);
+    assert!(result.status.success());
+    assert_eq!(levels(&letters), ["level", "I", "W", "E", "V", "-", "F"]);
+
+    // Letters are read back and written as words by default
+    let result = rogcat(&[
+        "-i",
+        letters.to_str().unwrap(),
+        "-o",
+        words.to_str().unwrap(),
+        "-f",
+        "csv",
+    ]);
+    assert!(result.status.success());
+    assert_eq!(
+        levels(&words),
+        ["level", "Info", "Warn", "Error", "Verbose", "None", "Fatal"]
+    );
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn input_progress() {
+    let dir = tempdir("input-progress");
+    let input = input(&dir, 100);
+    let input = input.to_str().unwrap();
+    let output = dir.join("output.log");
+    let output = output.to_str().unwrap();
+
+    // The progress is drawn on terminals only unless forced
+    let result = rogcat(&["-i", input, "-o", output]);
+    assert!(result.status.success());
+    assert!(!String::from_utf8_lossy(&result.stderr).contains("Parsed"));
+
+    let result = rogcat(&[
+        "-i",
+        input,
+        "-m",
+        "message 1",
+        "-o",
+        output,
+        "--overwrite",
+        "--progress",
+    ]);
+    assert!(result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("Parsed 100 records in "), "{stderr}");
+
+    let result = rogcat(&["--progress", "adb logcat"]);
+    assert!(!result.status.success());
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn control_port() {
+    let dir = tempdir("control-port");
+    let input = input(&dir, 10);
+    let input = input.to_str().unwrap();
+
+    // The port picked for port 0 is printed
+    let result = rogcat(&["-i", input, "--control-socket", "0", "--color", "never"]);
+    assert!(result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let port = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("[rogcat] Control socket listening on port "))
+        .unwrap_or_else(|| panic!("No port in {stderr}"));
+    assert_ne!(port.parse::<u16>().unwrap(), 0);
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn strict_parse_failures() {
+    let dir = tempdir("strict");
+    let input = input(&dir, 10);
+    let input = input.to_str().unwrap();
+
+    // Lines that aren't json are counted and reported as diagnostic
+    let result = rogcat(&[
+        "-i",
+        input,
+        "--input-format",
+        "json",
+        "--strict",
+        "--color",
+        "never",
+    ]);
+    assert!(!result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("[rogcat] 10 lines dropped because they could not be parsed"),
+        "{stderr}"
+    );
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn malformed_config_diagnostic() {
+    let dir = tempdir("malformed-config");
+    let input = input(&dir, 10);
+    let config = dir.join("rogcat");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(config.join("config.toml"), "buffer = [").unwrap();
+
+    let result = Command::new(env!("CARGO_BIN_EXE_rogcat"))
+        .args(["-i", input.to_str().unwrap(), "--color", "never"])
+        .env("XDG_CONFIG_HOME", &dir)
+        .output()
+        .expect("Failed to run rogcat");
+    assert!(result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.starts_with("[rogcat] Failed to read the configuration"),
+        "{stderr}"
+    );
+    assert_eq!(String::from_utf8_lossy(&result.stdout).lines().count(), 10);
+
+    fs::remove_dir_all(&dir).ok();
+}